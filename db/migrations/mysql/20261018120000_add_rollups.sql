@@ -0,0 +1,95 @@
+CREATE TABLE inputs_hourly (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  datalog VARCHAR(16) NOT NULL,
+  bucket_start BIGINT NOT NULL,
+  samples INTEGER NOT NULL,
+  p_pv_min DOUBLE NOT NULL DEFAULT 0,
+  p_pv_avg DOUBLE NOT NULL DEFAULT 0,
+  p_pv_max DOUBLE NOT NULL DEFAULT 0,
+  p_battery_min DOUBLE NOT NULL DEFAULT 0,
+  p_battery_avg DOUBLE NOT NULL DEFAULT 0,
+  p_battery_max DOUBLE NOT NULL DEFAULT 0,
+  p_charge_min DOUBLE NOT NULL DEFAULT 0,
+  p_charge_avg DOUBLE NOT NULL DEFAULT 0,
+  p_charge_max DOUBLE NOT NULL DEFAULT 0,
+  p_discharge_min DOUBLE NOT NULL DEFAULT 0,
+  p_discharge_avg DOUBLE NOT NULL DEFAULT 0,
+  p_discharge_max DOUBLE NOT NULL DEFAULT 0,
+  p_inv_min DOUBLE NOT NULL DEFAULT 0,
+  p_inv_avg DOUBLE NOT NULL DEFAULT 0,
+  p_inv_max DOUBLE NOT NULL DEFAULT 0,
+  p_rec_min DOUBLE NOT NULL DEFAULT 0,
+  p_rec_avg DOUBLE NOT NULL DEFAULT 0,
+  p_rec_max DOUBLE NOT NULL DEFAULT 0,
+  p_eps_min DOUBLE NOT NULL DEFAULT 0,
+  p_eps_avg DOUBLE NOT NULL DEFAULT 0,
+  p_eps_max DOUBLE NOT NULL DEFAULT 0,
+  p_grid_min DOUBLE NOT NULL DEFAULT 0,
+  p_grid_avg DOUBLE NOT NULL DEFAULT 0,
+  p_grid_max DOUBLE NOT NULL DEFAULT 0,
+  p_to_grid_min DOUBLE NOT NULL DEFAULT 0,
+  p_to_grid_avg DOUBLE NOT NULL DEFAULT 0,
+  p_to_grid_max DOUBLE NOT NULL DEFAULT 0,
+  p_to_user_min DOUBLE NOT NULL DEFAULT 0,
+  p_to_user_avg DOUBLE NOT NULL DEFAULT 0,
+  p_to_user_max DOUBLE NOT NULL DEFAULT 0,
+  e_pv_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_inv_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_rec_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_chg_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_dischg_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_eps_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_to_grid_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_to_user_all_delta DOUBLE NOT NULL DEFAULT 0
+);
+
+CREATE UNIQUE INDEX inputs_hourly_datalog_bucket_start ON inputs_hourly (datalog, bucket_start);
+
+CREATE TABLE inputs_daily (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  datalog VARCHAR(16) NOT NULL,
+  bucket_start BIGINT NOT NULL,
+  samples INTEGER NOT NULL,
+  p_pv_min DOUBLE NOT NULL DEFAULT 0,
+  p_pv_avg DOUBLE NOT NULL DEFAULT 0,
+  p_pv_max DOUBLE NOT NULL DEFAULT 0,
+  p_battery_min DOUBLE NOT NULL DEFAULT 0,
+  p_battery_avg DOUBLE NOT NULL DEFAULT 0,
+  p_battery_max DOUBLE NOT NULL DEFAULT 0,
+  p_charge_min DOUBLE NOT NULL DEFAULT 0,
+  p_charge_avg DOUBLE NOT NULL DEFAULT 0,
+  p_charge_max DOUBLE NOT NULL DEFAULT 0,
+  p_discharge_min DOUBLE NOT NULL DEFAULT 0,
+  p_discharge_avg DOUBLE NOT NULL DEFAULT 0,
+  p_discharge_max DOUBLE NOT NULL DEFAULT 0,
+  p_inv_min DOUBLE NOT NULL DEFAULT 0,
+  p_inv_avg DOUBLE NOT NULL DEFAULT 0,
+  p_inv_max DOUBLE NOT NULL DEFAULT 0,
+  p_rec_min DOUBLE NOT NULL DEFAULT 0,
+  p_rec_avg DOUBLE NOT NULL DEFAULT 0,
+  p_rec_max DOUBLE NOT NULL DEFAULT 0,
+  p_eps_min DOUBLE NOT NULL DEFAULT 0,
+  p_eps_avg DOUBLE NOT NULL DEFAULT 0,
+  p_eps_max DOUBLE NOT NULL DEFAULT 0,
+  p_grid_min DOUBLE NOT NULL DEFAULT 0,
+  p_grid_avg DOUBLE NOT NULL DEFAULT 0,
+  p_grid_max DOUBLE NOT NULL DEFAULT 0,
+  p_to_grid_min DOUBLE NOT NULL DEFAULT 0,
+  p_to_grid_avg DOUBLE NOT NULL DEFAULT 0,
+  p_to_grid_max DOUBLE NOT NULL DEFAULT 0,
+  p_to_user_min DOUBLE NOT NULL DEFAULT 0,
+  p_to_user_avg DOUBLE NOT NULL DEFAULT 0,
+  p_to_user_max DOUBLE NOT NULL DEFAULT 0,
+  e_pv_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_inv_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_rec_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_chg_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_dischg_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_eps_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_to_grid_all_delta DOUBLE NOT NULL DEFAULT 0,
+  e_to_user_all_delta DOUBLE NOT NULL DEFAULT 0
+);
+
+CREATE UNIQUE INDEX inputs_daily_datalog_bucket_start ON inputs_daily (datalog, bucket_start);
+
+CREATE INDEX inputs_created_at ON inputs (created_at);
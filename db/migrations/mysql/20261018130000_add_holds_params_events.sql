@@ -0,0 +1,32 @@
+CREATE TABLE holds (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  datalog VARCHAR(16) NOT NULL,
+  register INTEGER NOT NULL,
+  old_value INTEGER,
+  new_value INTEGER NOT NULL,
+  created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX holds_datalog_register ON holds (datalog, register);
+
+CREATE TABLE params (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  datalog VARCHAR(16) NOT NULL,
+  register INTEGER NOT NULL,
+  value INTEGER NOT NULL,
+  created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX params_datalog_register ON params (datalog, register);
+
+CREATE TABLE events (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  datalog VARCHAR(16) NOT NULL,
+  kind VARCHAR(16) NOT NULL,
+  code BIGINT,
+  detail TEXT NOT NULL,
+  origin VARCHAR(32),
+  created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX events_datalog_created_at ON events (datalog, created_at);
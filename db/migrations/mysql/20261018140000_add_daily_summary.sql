@@ -0,0 +1,18 @@
+CREATE TABLE daily_summary (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  datalog VARCHAR(16) NOT NULL,
+  day VARCHAR(10) NOT NULL,
+  pv DOUBLE NOT NULL,
+  consumption DOUBLE NOT NULL,
+  grid_import DOUBLE NOT NULL,
+  grid_export DOUBLE NOT NULL,
+  charge DOUBLE NOT NULL,
+  discharge DOUBLE NOT NULL,
+  battery_throughput DOUBLE NOT NULL,
+  net_import DOUBLE NOT NULL,
+  self_consumption DOUBLE,
+  self_sufficiency DOUBLE,
+  created_at TIMESTAMP NOT NULL
+);
+
+CREATE UNIQUE INDEX daily_summary_datalog_day ON daily_summary (datalog, day);
@@ -0,0 +1,19 @@
+CREATE TABLE tariff_costs (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  datalog VARCHAR(16) NOT NULL,
+  period VARCHAR(8) NOT NULL,
+  period_start VARCHAR(10) NOT NULL,
+  import_kwh DOUBLE NOT NULL,
+  export_kwh DOUBLE NOT NULL,
+  consumption_kwh DOUBLE NOT NULL,
+  import_cost DOUBLE NOT NULL,
+  export_revenue DOUBLE NOT NULL,
+  consumption_cost DOUBLE NOT NULL,
+  fixed_charges DOUBLE NOT NULL,
+  net_cost DOUBLE NOT NULL,
+  baseline_cost DOUBLE NOT NULL,
+  savings DOUBLE NOT NULL,
+  created_at TIMESTAMP NOT NULL
+);
+
+CREATE UNIQUE INDEX tariff_costs_datalog_period_start ON tariff_costs (datalog, period, period_start);
@@ -0,0 +1,15 @@
+CREATE TABLE writes (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  datalog VARCHAR(16) NOT NULL,
+  inverter VARCHAR(16),
+  device_function VARCHAR(16) NOT NULL,
+  register INTEGER NOT NULL,
+  old_value INTEGER,
+  new_value INTEGER,
+  origin VARCHAR(32),
+  source TEXT,
+  result TEXT NOT NULL,
+  created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX writes_datalog_register ON writes (datalog, register);
@@ -0,0 +1,18 @@
+CREATE TABLE battery_health (
+  id INT AUTO_INCREMENT PRIMARY KEY,
+  datalog VARCHAR(16) NOT NULL,
+  day VARCHAR(10) NOT NULL,
+  soh INTEGER NOT NULL,
+  cycle_count INTEGER NOT NULL,
+  soh_per_100_cycles DOUBLE,
+  max_cell_spread DOUBLE,
+  max_cell_temp_spread DOUBLE,
+  charge DOUBLE NOT NULL,
+  discharge DOUBLE NOT NULL,
+  efficiency DOUBLE,
+  lifetime_efficiency DOUBLE,
+  usable_capacity DOUBLE,
+  created_at TIMESTAMP NOT NULL
+);
+
+CREATE UNIQUE INDEX battery_health_datalog_day ON battery_health (datalog, day);
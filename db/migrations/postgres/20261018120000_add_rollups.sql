@@ -0,0 +1,95 @@
+CREATE TABLE inputs_hourly (
+  id SERIAL PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  bucket_start BIGINT NOT NULL,
+  samples INTEGER NOT NULL,
+  p_pv_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_pv_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_pv_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_battery_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_battery_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_battery_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_charge_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_charge_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_charge_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_discharge_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_discharge_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_discharge_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_inv_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_inv_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_inv_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_rec_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_rec_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_rec_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_eps_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_eps_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_eps_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_grid_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_grid_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_grid_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_grid_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_grid_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_grid_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_user_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_user_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_user_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_pv_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_inv_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_rec_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_chg_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_dischg_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_eps_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_to_grid_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_to_user_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0
+);
+
+CREATE UNIQUE INDEX inputs_hourly_datalog_bucket_start ON inputs_hourly (datalog, bucket_start);
+
+CREATE TABLE inputs_daily (
+  id SERIAL PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  bucket_start BIGINT NOT NULL,
+  samples INTEGER NOT NULL,
+  p_pv_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_pv_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_pv_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_battery_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_battery_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_battery_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_charge_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_charge_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_charge_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_discharge_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_discharge_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_discharge_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_inv_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_inv_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_inv_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_rec_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_rec_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_rec_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_eps_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_eps_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_eps_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_grid_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_grid_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_grid_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_grid_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_grid_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_grid_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_user_min DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_user_avg DOUBLE PRECISION NOT NULL DEFAULT 0,
+  p_to_user_max DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_pv_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_inv_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_rec_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_chg_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_dischg_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_eps_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_to_grid_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0,
+  e_to_user_all_delta DOUBLE PRECISION NOT NULL DEFAULT 0
+);
+
+CREATE UNIQUE INDEX inputs_daily_datalog_bucket_start ON inputs_daily (datalog, bucket_start);
+
+CREATE INDEX inputs_created_at ON inputs (created_at);
@@ -0,0 +1,18 @@
+CREATE TABLE daily_summary (
+  id SERIAL PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  day VARCHAR(10) NOT NULL,
+  pv DOUBLE PRECISION NOT NULL,
+  consumption DOUBLE PRECISION NOT NULL,
+  grid_import DOUBLE PRECISION NOT NULL,
+  grid_export DOUBLE PRECISION NOT NULL,
+  charge DOUBLE PRECISION NOT NULL,
+  discharge DOUBLE PRECISION NOT NULL,
+  battery_throughput DOUBLE PRECISION NOT NULL,
+  net_import DOUBLE PRECISION NOT NULL,
+  self_consumption DOUBLE PRECISION,
+  self_sufficiency DOUBLE PRECISION,
+  created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE UNIQUE INDEX daily_summary_datalog_day ON daily_summary (datalog, day);
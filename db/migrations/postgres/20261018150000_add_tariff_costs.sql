@@ -0,0 +1,19 @@
+CREATE TABLE tariff_costs (
+  id SERIAL PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  period VARCHAR(8) NOT NULL,
+  period_start VARCHAR(10) NOT NULL,
+  import_kwh DOUBLE PRECISION NOT NULL,
+  export_kwh DOUBLE PRECISION NOT NULL,
+  consumption_kwh DOUBLE PRECISION NOT NULL,
+  import_cost DOUBLE PRECISION NOT NULL,
+  export_revenue DOUBLE PRECISION NOT NULL,
+  consumption_cost DOUBLE PRECISION NOT NULL,
+  fixed_charges DOUBLE PRECISION NOT NULL,
+  net_cost DOUBLE PRECISION NOT NULL,
+  baseline_cost DOUBLE PRECISION NOT NULL,
+  savings DOUBLE PRECISION NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE UNIQUE INDEX tariff_costs_datalog_period_start ON tariff_costs (datalog, period, period_start);
@@ -0,0 +1,15 @@
+CREATE TABLE writes (
+  id SERIAL PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  inverter TEXT,
+  device_function VARCHAR(16) NOT NULL,
+  register INTEGER NOT NULL,
+  old_value INTEGER,
+  new_value INTEGER,
+  origin VARCHAR(32),
+  source TEXT,
+  result TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX writes_datalog_register ON writes (datalog, register);
@@ -0,0 +1,18 @@
+CREATE TABLE battery_health (
+  id SERIAL PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  day VARCHAR(10) NOT NULL,
+  soh INTEGER NOT NULL,
+  cycle_count INTEGER NOT NULL,
+  soh_per_100_cycles DOUBLE PRECISION,
+  max_cell_spread DOUBLE PRECISION,
+  max_cell_temp_spread DOUBLE PRECISION,
+  charge DOUBLE PRECISION NOT NULL,
+  discharge DOUBLE PRECISION NOT NULL,
+  efficiency DOUBLE PRECISION,
+  lifetime_efficiency DOUBLE PRECISION,
+  usable_capacity DOUBLE PRECISION,
+  created_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE UNIQUE INDEX battery_health_datalog_day ON battery_health (datalog, day);
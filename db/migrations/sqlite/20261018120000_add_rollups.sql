@@ -0,0 +1,95 @@
+CREATE TABLE inputs_hourly (
+  id INTEGER PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  bucket_start BIGINT NOT NULL,
+  samples INTEGER NOT NULL,
+  p_pv_min REAL NOT NULL DEFAULT 0,
+  p_pv_avg REAL NOT NULL DEFAULT 0,
+  p_pv_max REAL NOT NULL DEFAULT 0,
+  p_battery_min REAL NOT NULL DEFAULT 0,
+  p_battery_avg REAL NOT NULL DEFAULT 0,
+  p_battery_max REAL NOT NULL DEFAULT 0,
+  p_charge_min REAL NOT NULL DEFAULT 0,
+  p_charge_avg REAL NOT NULL DEFAULT 0,
+  p_charge_max REAL NOT NULL DEFAULT 0,
+  p_discharge_min REAL NOT NULL DEFAULT 0,
+  p_discharge_avg REAL NOT NULL DEFAULT 0,
+  p_discharge_max REAL NOT NULL DEFAULT 0,
+  p_inv_min REAL NOT NULL DEFAULT 0,
+  p_inv_avg REAL NOT NULL DEFAULT 0,
+  p_inv_max REAL NOT NULL DEFAULT 0,
+  p_rec_min REAL NOT NULL DEFAULT 0,
+  p_rec_avg REAL NOT NULL DEFAULT 0,
+  p_rec_max REAL NOT NULL DEFAULT 0,
+  p_eps_min REAL NOT NULL DEFAULT 0,
+  p_eps_avg REAL NOT NULL DEFAULT 0,
+  p_eps_max REAL NOT NULL DEFAULT 0,
+  p_grid_min REAL NOT NULL DEFAULT 0,
+  p_grid_avg REAL NOT NULL DEFAULT 0,
+  p_grid_max REAL NOT NULL DEFAULT 0,
+  p_to_grid_min REAL NOT NULL DEFAULT 0,
+  p_to_grid_avg REAL NOT NULL DEFAULT 0,
+  p_to_grid_max REAL NOT NULL DEFAULT 0,
+  p_to_user_min REAL NOT NULL DEFAULT 0,
+  p_to_user_avg REAL NOT NULL DEFAULT 0,
+  p_to_user_max REAL NOT NULL DEFAULT 0,
+  e_pv_all_delta REAL NOT NULL DEFAULT 0,
+  e_inv_all_delta REAL NOT NULL DEFAULT 0,
+  e_rec_all_delta REAL NOT NULL DEFAULT 0,
+  e_chg_all_delta REAL NOT NULL DEFAULT 0,
+  e_dischg_all_delta REAL NOT NULL DEFAULT 0,
+  e_eps_all_delta REAL NOT NULL DEFAULT 0,
+  e_to_grid_all_delta REAL NOT NULL DEFAULT 0,
+  e_to_user_all_delta REAL NOT NULL DEFAULT 0
+);
+
+CREATE UNIQUE INDEX inputs_hourly_datalog_bucket_start ON inputs_hourly (datalog, bucket_start);
+
+CREATE TABLE inputs_daily (
+  id INTEGER PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  bucket_start BIGINT NOT NULL,
+  samples INTEGER NOT NULL,
+  p_pv_min REAL NOT NULL DEFAULT 0,
+  p_pv_avg REAL NOT NULL DEFAULT 0,
+  p_pv_max REAL NOT NULL DEFAULT 0,
+  p_battery_min REAL NOT NULL DEFAULT 0,
+  p_battery_avg REAL NOT NULL DEFAULT 0,
+  p_battery_max REAL NOT NULL DEFAULT 0,
+  p_charge_min REAL NOT NULL DEFAULT 0,
+  p_charge_avg REAL NOT NULL DEFAULT 0,
+  p_charge_max REAL NOT NULL DEFAULT 0,
+  p_discharge_min REAL NOT NULL DEFAULT 0,
+  p_discharge_avg REAL NOT NULL DEFAULT 0,
+  p_discharge_max REAL NOT NULL DEFAULT 0,
+  p_inv_min REAL NOT NULL DEFAULT 0,
+  p_inv_avg REAL NOT NULL DEFAULT 0,
+  p_inv_max REAL NOT NULL DEFAULT 0,
+  p_rec_min REAL NOT NULL DEFAULT 0,
+  p_rec_avg REAL NOT NULL DEFAULT 0,
+  p_rec_max REAL NOT NULL DEFAULT 0,
+  p_eps_min REAL NOT NULL DEFAULT 0,
+  p_eps_avg REAL NOT NULL DEFAULT 0,
+  p_eps_max REAL NOT NULL DEFAULT 0,
+  p_grid_min REAL NOT NULL DEFAULT 0,
+  p_grid_avg REAL NOT NULL DEFAULT 0,
+  p_grid_max REAL NOT NULL DEFAULT 0,
+  p_to_grid_min REAL NOT NULL DEFAULT 0,
+  p_to_grid_avg REAL NOT NULL DEFAULT 0,
+  p_to_grid_max REAL NOT NULL DEFAULT 0,
+  p_to_user_min REAL NOT NULL DEFAULT 0,
+  p_to_user_avg REAL NOT NULL DEFAULT 0,
+  p_to_user_max REAL NOT NULL DEFAULT 0,
+  e_pv_all_delta REAL NOT NULL DEFAULT 0,
+  e_inv_all_delta REAL NOT NULL DEFAULT 0,
+  e_rec_all_delta REAL NOT NULL DEFAULT 0,
+  e_chg_all_delta REAL NOT NULL DEFAULT 0,
+  e_dischg_all_delta REAL NOT NULL DEFAULT 0,
+  e_eps_all_delta REAL NOT NULL DEFAULT 0,
+  e_to_grid_all_delta REAL NOT NULL DEFAULT 0,
+  e_to_user_all_delta REAL NOT NULL DEFAULT 0
+);
+
+CREATE UNIQUE INDEX inputs_daily_datalog_bucket_start ON inputs_daily (datalog, bucket_start);
+
+CREATE INDEX inputs_created_at ON inputs (created_at);
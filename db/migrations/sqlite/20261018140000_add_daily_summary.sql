@@ -0,0 +1,18 @@
+CREATE TABLE daily_summary (
+  id INTEGER PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  day VARCHAR(10) NOT NULL,
+  pv REAL NOT NULL,
+  consumption REAL NOT NULL,
+  grid_import REAL NOT NULL,
+  grid_export REAL NOT NULL,
+  charge REAL NOT NULL,
+  discharge REAL NOT NULL,
+  battery_throughput REAL NOT NULL,
+  net_import REAL NOT NULL,
+  self_consumption REAL,
+  self_sufficiency REAL,
+  created_at DATETIME NOT NULL
+);
+
+CREATE UNIQUE INDEX daily_summary_datalog_day ON daily_summary (datalog, day);
@@ -0,0 +1,19 @@
+CREATE TABLE tariff_costs (
+  id INTEGER PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  period VARCHAR(8) NOT NULL,
+  period_start VARCHAR(10) NOT NULL,
+  import_kwh REAL NOT NULL,
+  export_kwh REAL NOT NULL,
+  consumption_kwh REAL NOT NULL,
+  import_cost REAL NOT NULL,
+  export_revenue REAL NOT NULL,
+  consumption_cost REAL NOT NULL,
+  fixed_charges REAL NOT NULL,
+  net_cost REAL NOT NULL,
+  baseline_cost REAL NOT NULL,
+  savings REAL NOT NULL,
+  created_at DATETIME NOT NULL
+);
+
+CREATE UNIQUE INDEX tariff_costs_datalog_period_start ON tariff_costs (datalog, period, period_start);
@@ -0,0 +1,18 @@
+CREATE TABLE battery_health (
+  id INTEGER PRIMARY KEY,
+  datalog TEXT NOT NULL,
+  day VARCHAR(10) NOT NULL,
+  soh INTEGER NOT NULL,
+  cycle_count INTEGER NOT NULL,
+  soh_per_100_cycles REAL,
+  max_cell_spread REAL,
+  max_cell_temp_spread REAL,
+  charge REAL NOT NULL,
+  discharge REAL NOT NULL,
+  efficiency REAL,
+  lifetime_efficiency REAL,
+  usable_capacity REAL,
+  created_at DATETIME NOT NULL
+);
+
+CREATE UNIQUE INDEX battery_health_datalog_day ON battery_health (datalog, day);
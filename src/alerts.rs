@@ -0,0 +1,625 @@
+//! Fault and warning alerting.
+//!
+//! Each inverter is watched for a non-zero `fault_code` or `warning_code`, BMS fault and
+//! warning events (`bms_event_1`, `bms_event_2`), disconnects, and input data older than
+//! `stale_after` seconds. An alert is sent once when a condition starts or changes, again
+//! every `repeat` seconds while it lasts if set, and a recovery message once it clears.
+//!
+//! Every notification goes to the notifiers whose `min_severity` it reaches, except within
+//! quiet hours, when only those of `quiet_severity` are sent. The current state of each
+//! condition is also published retained on `{datalog}/alerts/{kind}`.
+
+use crate::prelude::*;
+use crate::config::{NotifierKind, Severity};
+use crate::eg4::packet::{FaultCodeString, ReadInputAll, WarningCodeString};
+
+use chrono::Timelike;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// How often stale data and repeats are checked for.
+const TICK: Duration = Duration::from_secs(10);
+
+/// How long a webhook, ntfy or Gotify request may take.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Fault,
+    Warning,
+    BmsFault,
+    BmsWarning,
+    Disconnected,
+    Stale,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fault => "fault",
+            Self::Warning => "warning",
+            Self::BmsFault => "bms_fault",
+            Self::BmsWarning => "bms_warning",
+            Self::Disconnected => "disconnected",
+            Self::Stale => "stale",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::Fault | Self::BmsFault => Severity::Critical,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum State {
+    Alert,
+    Recovery,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Notification {
+    pub datalog: Serial,
+    pub kind: Kind,
+    pub severity: Severity,
+    pub state: State,
+    /// fault, warning or BMS code, where there is one
+    pub code: Option<u32>,
+    pub message: String,
+    pub time: chrono::DateTime<chrono::Utc>,
+}
+
+impl Notification {
+    fn new(datalog: Serial, kind: Kind, state: State, code: Option<u32>, message: String) -> Self {
+        Self {
+            datalog,
+            kind,
+            severity: kind.severity(),
+            state,
+            code,
+            message,
+            time: Utils::utc(),
+        }
+    }
+
+    /// e.g. `[critical] 2222222222 fault`, or `[recovered] 2222222222 fault`
+    pub fn title(&self) -> String {
+        let tag = match self.state {
+            State::Alert => self.severity.as_str(),
+            State::Recovery => "recovered",
+        };
+        format!("[{}] {} {}", tag, self.datalog, self.kind.as_str())
+    }
+}
+
+struct Active {
+    code: Option<u32>,
+    message: String,
+    sent: Instant,
+}
+
+/// What is currently wrong with each inverter, and which notifications that calls for.
+pub struct Monitor {
+    config: config::Alerts,
+    active: HashMap<(Serial, Kind), Active>,
+    last_seen: HashMap<Serial, Instant>,
+}
+
+impl Monitor {
+    /// `datalogs` are reported stale if they never send any data.
+    pub fn new(config: config::Alerts, datalogs: Vec<Serial>, now: Instant) -> Self {
+        Self {
+            config,
+            active: HashMap::new(),
+            last_seen: datalogs.into_iter().map(|datalog| (datalog, now)).collect(),
+        }
+    }
+
+    pub fn inputs(&mut self, input: &ReadInputAll, now: Instant) -> Vec<Notification> {
+        let datalog = input.datalog;
+        self.last_seen.insert(datalog, now);
+
+        let conditions = [
+            (
+                Kind::Fault,
+                (input.fault_code != 0)
+                    .then(|| (Some(input.fault_code), FaultCodeString::from_value(input.fault_code).to_string())),
+            ),
+            (
+                Kind::Warning,
+                (input.warning_code != 0).then(|| {
+                    (Some(input.warning_code), WarningCodeString::from_value(input.warning_code).to_string())
+                }),
+            ),
+            (
+                Kind::BmsFault,
+                (input.bms_event_1 != 0).then(|| {
+                    (Some(u32::from(input.bms_event_1)), format!("BMS fault 0x{:04x}", input.bms_event_1))
+                }),
+            ),
+            (
+                Kind::BmsWarning,
+                (input.bms_event_2 != 0).then(|| {
+                    (Some(u32::from(input.bms_event_2)), format!("BMS warning 0x{:04x}", input.bms_event_2))
+                }),
+            ),
+            // data coming in means it is neither stale nor disconnected
+            (Kind::Stale, None),
+            (Kind::Disconnected, None),
+        ];
+
+        conditions
+            .into_iter()
+            .filter_map(|(kind, condition)| self.set(datalog, kind, condition, now))
+            .collect()
+    }
+
+    pub fn connected(&mut self, datalog: Serial, now: Instant) -> Vec<Notification> {
+        self.set(datalog, Kind::Disconnected, None, now).into_iter().collect()
+    }
+
+    pub fn disconnected(&mut self, datalog: Serial, now: Instant) -> Vec<Notification> {
+        let condition = Some((None, "inverter disconnected".to_string()));
+        self.set(datalog, Kind::Disconnected, condition, now).into_iter().collect()
+    }
+
+    /// Stale data and repeats of alerts still active.
+    pub fn tick(&mut self, now: Instant) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+
+        let stale_after = self.config.stale_after();
+        if stale_after > 0 {
+            let stale: Vec<Serial> = self
+                .last_seen
+                .iter()
+                .filter(|(_, seen)| now.duration_since(**seen) >= Duration::from_secs(stale_after))
+                .map(|(datalog, _)| *datalog)
+                .collect();
+            for datalog in stale {
+                let condition = Some((None, format!("no data for over {}s", stale_after)));
+                notifications.extend(self.set(datalog, Kind::Stale, condition, now));
+            }
+        }
+
+        let repeat = self.config.repeat();
+        if repeat > 0 {
+            for ((datalog, kind), active) in self.active.iter_mut() {
+                if now.duration_since(active.sent) >= Duration::from_secs(repeat) {
+                    active.sent = now;
+                    notifications.push(Notification::new(
+                        *datalog,
+                        *kind,
+                        State::Alert,
+                        active.code,
+                        active.message.clone(),
+                    ));
+                }
+            }
+        }
+
+        notifications
+    }
+
+    /// Move a condition to `condition`, None when it does not hold.
+    fn set(
+        &mut self,
+        datalog: Serial,
+        kind: Kind,
+        condition: Option<(Option<u32>, String)>,
+        now: Instant,
+    ) -> Option<Notification> {
+        let key = (datalog, kind);
+        let current = self.active.get(&key).map(|active| (active.code, active.message.clone()));
+
+        match (current, condition) {
+            (None, None) => None,
+            (Some(current), Some(condition)) if current == condition => None,
+            (_, Some((code, message))) => {
+                self.active.insert(
+                    key,
+                    Active {
+                        code,
+                        message: message.clone(),
+                        sent: now,
+                    },
+                );
+                Some(Notification::new(datalog, kind, State::Alert, code, message))
+            }
+            (Some((code, message)), None) => {
+                self.active.remove(&key);
+                let message = format!("cleared, was: {}", message);
+                Some(Notification::new(datalog, kind, State::Recovery, code, message))
+            }
+        }
+    }
+}
+
+/// Notifiers a notification goes to at `minute` of the local day.
+pub fn recipients<'a>(
+    config: &'a config::Alerts,
+    notification: &Notification,
+    minute: u32,
+) -> Vec<&'a config::Notifier> {
+    let quiet = config.quiet_hours().is_some_and(|(start, end)| {
+        if start <= end {
+            start <= minute && minute < end
+        } else {
+            minute >= start || minute < end
+        }
+    });
+    if quiet && notification.severity < config.quiet_severity() {
+        return Vec::new();
+    }
+
+    config
+        .notifiers()
+        .iter()
+        .filter(|notifier| notification.severity >= notifier.min_severity)
+        .collect()
+}
+
+/// Deliver a notification to one notifier.
+pub async fn send(http: &reqwest::Client, notifier: &config::Notifier, notification: &Notification) -> Result<()> {
+    use reqwest::header::CONTENT_TYPE;
+
+    let alert = notification.state == State::Alert;
+    let critical = notification.severity == Severity::Critical;
+
+    let request = match &notifier.kind {
+        NotifierKind::Webhook { url } => http
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(notification)?),
+        NotifierKind::Ntfy { url, token } => {
+            let priority = match (alert, critical) {
+                (true, true) => "urgent",
+                (true, false) => "high",
+                (false, _) => "default",
+            };
+            let request = http
+                .post(url)
+                .header("Title", notification.title())
+                .header("Priority", priority)
+                .header("Tags", notification.kind.as_str())
+                .body(notification.message.clone());
+            match token {
+                Some(token) => request.bearer_auth(token),
+                None => request,
+            }
+        }
+        NotifierKind::Gotify { url, token } => {
+            let priority = match (alert, critical) {
+                (true, true) => 8,
+                (true, false) => 5,
+                (false, _) => 2,
+            };
+            let body = serde_json::json!({
+                "title": notification.title(),
+                "message": notification.message,
+                "priority": priority,
+            });
+            http.post(url)
+                .header("X-Gotify-Key", token)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.to_string())
+        }
+        NotifierKind::Smtp {
+            host,
+            port,
+            tls,
+            username,
+            password,
+            from,
+            to,
+        } => {
+            let login = username.as_deref().zip(password.as_deref());
+            return smtp::send(host, *port, *tls, login, from, to, notification).await;
+        }
+    };
+
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        bail!("{} returned HTTP {}", notifier.kind.name(), response.status());
+    }
+    Ok(())
+}
+
+/// Just enough SMTP to hand a plain text message to a relay.
+mod smtp {
+    use super::Notification;
+    use crate::config::SmtpTls;
+    use crate::prelude::*;
+
+    use std::sync::Arc;
+    use tokio_rustls::rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+    use tokio::net::TcpStream;
+
+    /// How long connecting and the whole exchange may take.
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub async fn send(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        login: Option<(&str, &str)>,
+        from: &str,
+        to: &[String],
+        notification: &Notification,
+    ) -> Result<()> {
+        if tls == SmtpTls::None && login.is_some() {
+            bail!("not sending SMTP credentials to {} without TLS", host);
+        }
+        tokio::time::timeout(TIMEOUT, deliver(host, port, tls, login, from, to, notification))
+            .await
+            .map_err(|_| anyhow!("SMTP server {} timed out", host))?
+    }
+
+    async fn deliver(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        login: Option<(&str, &str)>,
+        from: &str,
+        to: &[String],
+        notification: &Notification,
+    ) -> Result<()> {
+        let stream = TcpStream::connect((host, port)).await?;
+
+        match tls {
+            SmtpTls::Tls => {
+                let mut stream = BufReader::new(connector()?.connect(server_name(host)?, stream).await?);
+                reply(&mut stream, 220).await?;
+                command(&mut stream, "EHLO eg4-bridge", 250).await?;
+                exchange(&mut stream, login, from, to, notification).await
+            }
+            SmtpTls::Starttls => {
+                let mut stream = BufReader::new(stream);
+                reply(&mut stream, 220).await?;
+                let extensions = command(&mut stream, "EHLO eg4-bridge", 250).await?;
+                if !extensions.iter().any(|e| e.eq_ignore_ascii_case("STARTTLS")) {
+                    bail!("SMTP server {} does not offer STARTTLS", host);
+                }
+                command(&mut stream, "STARTTLS", 220).await?;
+                let stream = connector()?.connect(server_name(host)?, stream.into_inner()).await?;
+                let mut stream = BufReader::new(stream);
+                command(&mut stream, "EHLO eg4-bridge", 250).await?;
+                exchange(&mut stream, login, from, to, notification).await
+            }
+            SmtpTls::None => {
+                let mut stream = BufReader::new(stream);
+                reply(&mut stream, 220).await?;
+                command(&mut stream, "EHLO eg4-bridge", 250).await?;
+                exchange(&mut stream, None, from, to, notification).await
+            }
+        }
+    }
+
+    /// Verifies servers against the platform's roots, as MQTT does without a `ca_file`.
+    fn connector() -> Result<TlsConnector> {
+        let native = rustls_native_certs::load_native_certs();
+        for e in &native.errors {
+            warn!("Failed to load a platform root certificate: {}", e);
+        }
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(native.certs);
+        if roots.is_empty() {
+            bail!("no platform root certificates to verify SMTP servers with");
+        }
+        let config = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+
+    fn server_name(host: &str) -> Result<ServerName<'static>> {
+        ServerName::try_from(host.to_string()).map_err(|e| anyhow!("invalid SMTP host {}: {}", host, e))
+    }
+
+    /// Everything after the greeting and EHLO, once the connection is as secure as it gets.
+    async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut BufReader<S>,
+        login: Option<(&str, &str)>,
+        from: &str,
+        to: &[String],
+        notification: &Notification,
+    ) -> Result<()> {
+        if let Some((username, password)) = login {
+            let token = base64(format!("\0{}\0{}", username, password).as_bytes());
+            command(stream, &format!("AUTH PLAIN {}", token), 235).await?;
+        }
+        command(stream, &format!("MAIL FROM:<{}>", from), 250).await?;
+        for recipient in to {
+            command(stream, &format!("RCPT TO:<{}>", recipient), 250).await?;
+        }
+        command(stream, "DATA", 354).await?;
+
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            from,
+            to.join(", "),
+            notification.title(),
+            notification.time.to_rfc2822()
+        );
+        for line in notification.message.lines() {
+            // a line of just "." would end the message early
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message.push_str(line);
+            message.push_str("\r\n");
+        }
+        message.push_str(".\r\n");
+        stream.write_all(message.as_bytes()).await?;
+        reply(stream, 250).await?;
+
+        command(stream, "QUIT", 221).await?;
+        Ok(())
+    }
+
+    /// Send one command and check its reply, returning the reply's text lines.
+    async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+        stream: &mut BufReader<S>,
+        line: &str,
+        expected: u16,
+    ) -> Result<Vec<String>> {
+        stream.write_all(format!("{}\r\n", line).as_bytes()).await?;
+        stream.flush().await?;
+        reply(stream, expected).await
+    }
+
+    /// Read a possibly multi-line reply, e.g. `250-...` lines up to a final `250 ...`.
+    async fn reply<S: AsyncRead + Unpin>(stream: &mut BufReader<S>, expected: u16) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await? == 0 {
+                bail!("SMTP server closed the connection");
+            }
+            let code: u16 = line.get(..3).and_then(|c| c.parse().ok()).unwrap_or_default();
+            if code != expected {
+                bail!("SMTP server replied {}", line.trim_end());
+            }
+            lines.push(line.get(4..).unwrap_or_default().trim_end().to_string());
+            if line.as_bytes().get(3) != Some(&b'-') {
+                return Ok(lines);
+            }
+        }
+    }
+
+    fn base64(input: &[u8]) -> String {
+        const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+        let mut output = String::new();
+        for chunk in input.chunks(3) {
+            let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+            let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    output.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+                } else {
+                    output.push('=');
+                }
+            }
+        }
+        output
+    }
+}
+
+pub struct Alerting {
+    config: ConfigWrapper,
+    channels: Channels,
+    http: reqwest::Client,
+    shutdown: CancellationToken,
+}
+
+impl Alerting {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        Self {
+            config,
+            channels,
+            http: reqwest::Client::builder()
+                .timeout(HTTP_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut inputs = self.channels.from_coordinator.subscribe();
+        let mut packets = self.channels.from_inverter.subscribe();
+        let mut ticker = tokio::time::interval(TICK);
+
+        let datalogs = self
+            .config
+            .enabled_inverters()
+            .iter()
+            .filter_map(|inverter| inverter.datalog())
+            .collect();
+        let mut monitor = Monitor::new(self.config.alerts(), datalogs, Instant::now());
+        info!("alerting started");
+
+        loop {
+            let notifications = tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                msg = inputs.recv() => match msg {
+                    Ok(coordinator::ChannelData::ReadInputAll(input)) => monitor.inputs(&input, Instant::now()),
+                    Ok(coordinator::ChannelData::Shutdown) => break,
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("alerting lagged, skipped {} messages", n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                msg = packets.recv() => match msg {
+                    Ok(eg4::inverter::ChannelData::Connected(datalog)) => monitor.connected(datalog, Instant::now()),
+                    Ok(eg4::inverter::ChannelData::Disconnect(datalog)) => monitor.disconnected(datalog, Instant::now()),
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("alerting lagged, skipped {} packets", n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = ticker.tick() => monitor.tick(Instant::now()),
+            };
+
+            for notification in notifications {
+                self.notify(notification);
+            }
+        }
+
+        info!("alerting exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    fn notify(&self, notification: Notification) {
+        match notification.state {
+            State::Alert => warn!("alert: {}: {}", notification.title(), notification.message),
+            State::Recovery => info!("alert: {}: {}", notification.title(), notification.message),
+        }
+
+        if self.config.mqtt().enabled() {
+            if let Ok(payload) = serde_json::to_string(&notification) {
+                let _ = self.channels.to_mqtt.send(mqtt::ChannelData::Message(mqtt::Message {
+                    topic: format!("{}/alerts/{}", notification.datalog, notification.kind.as_str()),
+                    retain: true,
+                    payload,
+                }));
+            }
+        }
+
+        let alerts = self.config.alerts();
+        let now = chrono::Local::now();
+        let recipients: Vec<config::Notifier> = recipients(&alerts, &notification, now.hour() * 60 + now.minute())
+            .into_iter()
+            .cloned()
+            .collect();
+        if recipients.is_empty() {
+            debug!("alert: {} not sent to any notifier", notification.title());
+            return;
+        }
+
+        // slow or unreachable notifiers must not hold up monitoring
+        let http = self.http.clone();
+        tokio::spawn(async move {
+            for notifier in recipients {
+                if let Err(e) = send(&http, &notifier, &notification).await {
+                    error!("Failed to send alert to {}: {}", notifier.kind.name(), e);
+                }
+            }
+        });
+    }
+}
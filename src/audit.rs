@@ -0,0 +1,275 @@
+//! Audit log of every write made to, or seen on, the inverters.
+//!
+//! Each write frame the bridge sends, or holds back in dry-run mode, is recorded with where
+//! it came from, the register value before and after, and the result: `SetHold`,
+//! `WriteParam`, the bit changes of `UpdateHold`, time slot writes and the clock sync. The
+//! source is set for a whole command with [`scope`], so the code building the frames does
+//! not need to be told who asked.
+//!
+//! Write replies that answer no frame of ours were asked for by another client of the
+//! datalog, such as the EG4 app or a second bridge; they are recorded with an `external`
+//! origin and the last value seen for the register.
+//!
+//! Records are appended as JSON lines to `audit.file` and stored in the `writes` table of
+//! every enabled database.
+
+use crate::prelude::*;
+use crate::command::{Origin, Source};
+use crate::coordinator::commands::dry_run::Frame;
+use eg4::packet::{DeviceFunction, TranslatedData};
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// How long a frame we sent is waited on; a matching reply after that counts as external.
+const ECHO_TIMEOUT: Duration = Duration::from_secs(30);
+
+tokio::task_local! {
+    static SOURCE: Source;
+}
+
+/// Run `future`, recording the writes it makes as coming from `source`.
+pub async fn scope<F: Future>(source: Source, future: F) -> F::Output {
+    SOURCE.scope(source, future).await
+}
+
+/// Source of the command being run, if any.
+pub fn current() -> Option<Source> {
+    SOURCE.try_with(|source| source.clone()).ok()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChannelData {
+    Record(Box<Record>),
+    Shutdown,
+}
+
+/// One write, as recorded.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Record {
+    pub time: chrono::DateTime<chrono::Utc>,
+    #[serde(flatten)]
+    pub frame: Frame,
+    pub origin: Option<Origin>,
+    /// MQTT topic, rule, controller or job that asked for the write
+    pub source: Option<String>,
+    /// `ok`, `dry run` or `failed: ...`
+    pub result: String,
+}
+
+impl Record {
+    pub fn new(frame: Frame, source: Option<Source>, result: impl Into<String>) -> Self {
+        Self {
+            time: Utils::utc(),
+            frame,
+            origin: source.as_ref().map(|s| s.origin),
+            source: source.map(|s| s.detail),
+            result: result.into(),
+        }
+    }
+}
+
+/// Record a write frame sent by the bridge, and how it went.
+pub fn record<T>(channels: &Channels, packet: &Packet, old_value: Option<u16>, result: &Result<T>) {
+    let outcome = match result {
+        Ok(_) => "ok".to_string(),
+        Err(e) => format!("failed: {}", e),
+    };
+    record_outcome(channels, packet, old_value, outcome);
+}
+
+pub fn record_outcome(channels: &Channels, packet: &Packet, old_value: Option<u16>, outcome: impl Into<String>) {
+    match Frame::new(packet, old_value) {
+        Ok(frame) => {
+            let record = Record::new(frame, current(), outcome);
+            // fails harmlessly when the audit log is disabled
+            let _ = channels.to_audit.send(ChannelData::Record(Box::new(record)));
+        }
+        Err(e) => warn!("audit: {}", e),
+    }
+}
+
+fn is_write(packet: &Packet) -> bool {
+    match packet {
+        Packet::TranslatedData(td) => {
+            matches!(td.device_function, DeviceFunction::WriteSingle | DeviceFunction::WriteMulti)
+        }
+        Packet::WriteParam(_) => true,
+        _ => false,
+    }
+}
+
+type Key = (Serial, String, u16);
+
+fn key(frame: &Frame) -> Key {
+    (frame.datalog, frame.device_function.clone(), frame.register)
+}
+
+/// Tells the replies to our own writes from those to other clients, keeping the hold
+/// values seen so an external write has a before value.
+#[derive(Default)]
+pub struct Tracker {
+    sent: HashMap<Key, Vec<Instant>>,
+    holds: HashMap<(Serial, u16), u16>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A frame going out to an inverter.
+    pub fn sent(&mut self, packet: &Packet, now: Instant) {
+        if !is_write(packet) {
+            return;
+        }
+        if let Ok(frame) = Frame::new(packet, None) {
+            self.sent.entry(key(&frame)).or_default().push(now);
+        }
+    }
+
+    /// A frame from an inverter; returns a record when it is the reply to a write made by
+    /// someone else.
+    pub fn received(&mut self, packet: &Packet, now: Instant) -> Option<Record> {
+        let record = if is_write(packet) {
+            Frame::new(packet, None).ok().and_then(|frame| self.external(frame, now))
+        } else {
+            None
+        };
+
+        if let Packet::TranslatedData(td @ TranslatedData {
+            device_function: DeviceFunction::ReadHold | DeviceFunction::WriteSingle | DeviceFunction::WriteMulti,
+            ..
+        }) = packet
+        {
+            for (register, value) in td.pairs() {
+                self.holds.insert((td.datalog, register), value);
+            }
+        }
+
+        record
+    }
+
+    fn external(&mut self, mut frame: Frame, now: Instant) -> Option<Record> {
+        if let Some(sent) = self.sent.get_mut(&key(&frame)) {
+            sent.retain(|at| now.duration_since(*at) < ECHO_TIMEOUT);
+            if !sent.is_empty() {
+                sent.remove(0);
+                return None;
+            }
+        }
+
+        // params are not tracked, so only hold registers get a before value
+        if frame.inverter.is_some() {
+            frame.old_value = self.holds.get(&(frame.datalog, frame.register)).copied();
+        }
+        Some(Record {
+            origin: Some(Origin::External),
+            ..Record::new(frame, None, "ok")
+        })
+    }
+}
+
+pub struct AuditLog {
+    config: ConfigWrapper,
+    channels: Channels,
+}
+
+impl AuditLog {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        Self { config, channels }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut records = self.channels.to_audit.subscribe();
+        let mut outgoing = self.channels.to_inverter.subscribe();
+        let mut incoming = self.channels.from_inverter.subscribe();
+        let mut tracker = Tracker::new();
+
+        let mut file = match self.config.audit().file() {
+            Some(path) => Some(
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| anyhow!("cannot open audit file {}: {}", path, e))?,
+            ),
+            None => None,
+        };
+        info!("audit log started");
+
+        loop {
+            // frames we send are seen before the replies to them
+            tokio::select! {
+                biased;
+
+                msg = outgoing.recv() => match msg {
+                    Ok(eg4::inverter::ChannelData::Packet(packet)) => tracker.sent(&packet, Instant::now()),
+                    // a single inverter stopping also sends this, so only `to_audit` ends the log
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("audit log lagged, skipped {} outgoing packets", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                msg = incoming.recv() => match msg {
+                    Ok(eg4::inverter::ChannelData::Packet(packet)) => {
+                        if let Some(record) = tracker.received(&packet, Instant::now()) {
+                            self.write(file.as_mut(), &record);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("audit log lagged, skipped {} incoming packets", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                msg = records.recv() => match msg {
+                    Ok(ChannelData::Record(record)) => self.write(file.as_mut(), &record),
+                    Ok(ChannelData::Shutdown) => break,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("audit log lagged, skipped {} writes", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+
+        info!("audit log exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        let _ = self.channels.to_audit.send(ChannelData::Shutdown);
+    }
+
+    fn write(&self, file: Option<&mut std::fs::File>, record: &Record) {
+        info!(
+            "audit: {} register {} on {}: {:?} -> {:?} by {} {} ({})",
+            record.frame.device_function,
+            record.frame.register,
+            record.frame.datalog,
+            record.frame.old_value,
+            record.frame.new_value,
+            record.origin.map(|o| o.to_string()).unwrap_or_else(|| "unknown".to_string()),
+            record.source.as_deref().unwrap_or_default(),
+            record.result
+        );
+
+        if let Some(file) = file {
+            let line = serde_json::to_string(record).map_err(Error::from);
+            if let Err(e) = line.and_then(|line| writeln!(file, "{}", line).map_err(Error::from)) {
+                error!("Failed to append to audit file: {}", e);
+            }
+        }
+
+        if self.config.have_enabled_database() {
+            let _ = self
+                .channels
+                .to_database
+                .send(database::ChannelData::Write(Box::new(record.clone())));
+        }
+    }
+}
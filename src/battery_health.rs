@@ -0,0 +1,436 @@
+//! Battery health analytics.
+//!
+//! The BMS values in complete input sets are tracked per inverter to give:
+//!
+//! * the cell voltage and temperature spread, with an alarm when the voltage spread
+//!   passes `cell_spread_alarm` that clears `cell_spread_hysteresis` below it;
+//! * usable capacity, estimated from the energy moved in or out of the battery while its
+//!   SOC changes by `capacity_soc_delta` percent;
+//! * a daily record of SOH, cycle count, the SOH lost per 100 cycles since the first
+//!   record, the worst spreads of the day, and round-trip efficiency from the
+//!   `e_chg_*`/`e_dischg_*` counters.
+//!
+//! Cells and capacity go to MQTT as they change; the daily record goes to MQTT and the
+//! `battery_health` table of the first database, which also provides the SOH and cycle
+//! baseline across restarts.
+
+use crate::prelude::*;
+use crate::database::{Database, Event, EventKind};
+use crate::eg4::packet::ReadInputAll;
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+
+/// Days charging less than this (kWh) give no meaningful efficiency.
+const MIN_EFFICIENCY_CHARGE: f64 = 1.0;
+
+/// Cell voltages and temperatures from one input set.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Cells {
+    pub datalog: Serial,
+    pub max_cell_voltage: f64,
+    pub min_cell_voltage: f64,
+    /// max_cell_voltage - min_cell_voltage, in volts
+    pub cell_spread: f64,
+    pub max_cell_temp: f64,
+    pub min_cell_temp: f64,
+    pub cell_temp_spread: f64,
+    /// Whether the cell spread alarm is raised
+    pub alarm: bool,
+}
+
+impl Cells {
+    fn from_inputs(input: &ReadInputAll) -> Option<Self> {
+        // no BMS data, e.g. lead-acid or a BMS that is not talking
+        if input.max_cell_voltage <= 0.0 || input.min_cell_voltage <= 0.0 {
+            return None;
+        }
+        Some(Self {
+            datalog: input.datalog,
+            max_cell_voltage: input.max_cell_voltage,
+            min_cell_voltage: input.min_cell_voltage,
+            cell_spread: round(input.max_cell_voltage - input.min_cell_voltage, 3),
+            max_cell_temp: input.max_cell_temp,
+            min_cell_temp: input.min_cell_temp,
+            cell_temp_spread: round(input.max_cell_temp - input.min_cell_temp, 1),
+            alarm: false,
+        })
+    }
+
+    pub fn mqtt_message(&self) -> Result<mqtt::Message> {
+        Ok(mqtt::Message {
+            topic: format!("{}/battery/cells", self.datalog),
+            retain: true,
+            payload: serde_json::to_string(self)?,
+        })
+    }
+}
+
+/// Usable capacity estimated over one SOC swing.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Capacity {
+    pub datalog: Serial,
+    pub soc_from: i8,
+    pub soc_to: i8,
+    /// Net energy into (charging) or out of (discharging) the battery over the swing, in kWh
+    pub energy: f64,
+    /// energy scaled up to a 0-100% swing, in kWh
+    pub capacity: f64,
+}
+
+impl Capacity {
+    pub fn mqtt_message(&self) -> Result<mqtt::Message> {
+        Ok(mqtt::Message {
+            topic: format!("{}/battery/capacity", self.datalog),
+            retain: true,
+            payload: serde_json::to_string(self)?,
+        })
+    }
+}
+
+/// One inverter-day of battery health.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Health {
+    pub datalog: Serial,
+    /// Local date the record belongs to
+    pub date: chrono::NaiveDate,
+    pub soh: i8,
+    pub cycle_count: u16,
+    /// SOH percentage points lost per 100 cycles since the first record; None until the
+    /// cycle count has moved
+    pub soh_per_100_cycles: Option<f64>,
+    /// Worst cell voltage spread of the day, in volts
+    pub max_cell_spread: Option<f64>,
+    pub max_cell_temp_spread: Option<f64>,
+    /// Energy into and out of the battery over the day, in kWh
+    pub charge: f64,
+    pub discharge: f64,
+    /// discharge / charge for the day as a percentage; None on days with little charging
+    pub efficiency: Option<f64>,
+    /// Same from the lifetime counters
+    pub lifetime_efficiency: Option<f64>,
+    /// Mean of the day's capacity estimates, in kWh
+    pub usable_capacity: Option<f64>,
+}
+
+impl Health {
+    pub fn mqtt_message(&self) -> Result<mqtt::Message> {
+        Ok(mqtt::Message {
+            topic: format!("{}/battery/health", self.datalog),
+            retain: true,
+            payload: serde_json::to_string(self)?,
+        })
+    }
+}
+
+/// What changed with one input set.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Update {
+    pub cells: Option<Cells>,
+    /// Some(true) when the cell spread alarm was raised, Some(false) when it cleared
+    pub alarm: Option<bool>,
+    pub capacity: Option<Capacity>,
+    /// The finished day, on the first input set of a new one
+    pub health: Option<Health>,
+}
+
+fn round(v: f64, places: i32) -> f64 {
+    let scale = 10f64.powi(places);
+    (v * scale).round() / scale
+}
+
+fn efficiency(charge: f64, discharge: f64) -> Option<f64> {
+    (charge >= MIN_EFFICIENCY_CHARGE).then(|| round(discharge / charge * 100.0, 1))
+}
+
+/// SOC and lifetime counters a capacity estimate starts from.
+#[derive(Clone, Copy)]
+struct Anchor {
+    soc: i8,
+    charge: f64,
+    discharge: f64,
+}
+
+impl Anchor {
+    fn new(input: &ReadInputAll) -> Self {
+        Self {
+            soc: input.soc,
+            charge: input.e_chg_all,
+            discharge: input.e_dischg_all,
+        }
+    }
+}
+
+struct State {
+    date: chrono::NaiveDate,
+    last: ReadInputAll,
+    max_cell_spread: Option<f64>,
+    max_cell_temp_spread: Option<f64>,
+    capacities: Vec<f64>,
+    anchor: Anchor,
+    alarm: bool,
+}
+
+impl State {
+    fn new(input: &ReadInputAll) -> Self {
+        Self {
+            date: local_date(input),
+            last: input.clone(),
+            max_cell_spread: None,
+            max_cell_temp_spread: None,
+            capacities: Vec::new(),
+            anchor: Anchor::new(input),
+            alarm: false,
+        }
+    }
+}
+
+fn local_date(input: &ReadInputAll) -> chrono::NaiveDate {
+    input.time.0.with_timezone(&chrono::Local).date_naive()
+}
+
+fn max(current: Option<f64>, v: f64) -> Option<f64> {
+    Some(current.map_or(v, |c| c.max(v)))
+}
+
+pub struct Tracker {
+    config: config::BatteryHealth,
+    states: HashMap<Serial, State>,
+    /// (soh, cycle_count) the trend is measured from
+    baselines: HashMap<Serial, (i8, u16)>,
+}
+
+impl Tracker {
+    pub fn new(config: config::BatteryHealth) -> Self {
+        Self {
+            config,
+            states: HashMap::new(),
+            baselines: HashMap::new(),
+        }
+    }
+
+    /// Set the SOH and cycle count the trend is measured from, unless one is already set.
+    pub fn seed(&mut self, datalog: Serial, soh: i8, cycle_count: u16) {
+        self.baselines.entry(datalog).or_insert((soh, cycle_count));
+    }
+
+    pub fn update(&mut self, input: &ReadInputAll) -> Update {
+        // a BMS that has not reported yet reads 0% SOH
+        if input.soh > 0 {
+            self.seed(input.datalog, input.soh, input.cycle_count);
+        }
+
+        let mut update = Update::default();
+        let state = self.states.entry(input.datalog).or_insert_with(|| State::new(input));
+
+        if local_date(input) != state.date {
+            let baseline = self.baselines.get(&input.datalog).copied();
+            update.health = Some(Self::health(state, baseline));
+            let alarm = state.alarm;
+            *state = State::new(input);
+            state.alarm = alarm;
+        }
+
+        if let Some(mut cells) = Cells::from_inputs(input) {
+            let config = &self.config;
+            if !state.alarm && cells.cell_spread >= config.cell_spread_alarm() {
+                state.alarm = true;
+                update.alarm = Some(true);
+            } else if state.alarm && cells.cell_spread <= config.cell_spread_alarm() - config.cell_spread_hysteresis() {
+                state.alarm = false;
+                update.alarm = Some(false);
+            }
+            cells.alarm = state.alarm;
+            state.max_cell_spread = max(state.max_cell_spread, cells.cell_spread);
+            state.max_cell_temp_spread = max(state.max_cell_temp_spread, cells.cell_temp_spread);
+            update.cells = Some(cells);
+        }
+
+        update.capacity = self.capacity(input);
+        if let Some(state) = self.states.get_mut(&input.datalog) {
+            if let Some(capacity) = &update.capacity {
+                state.capacities.push(capacity.capacity);
+            }
+            state.last = input.clone();
+        }
+
+        update
+    }
+
+    /// Estimate capacity once the SOC has moved far enough from the anchor, starting a new
+    /// swing from here.
+    fn capacity(&mut self, input: &ReadInputAll) -> Option<Capacity> {
+        let state = self.states.get_mut(&input.datalog)?;
+        let anchor = state.anchor;
+        let delta = i16::from(input.soc) - i16::from(anchor.soc);
+        if delta.unsigned_abs() < self.config.capacity_soc_delta() {
+            return None;
+        }
+        state.anchor = Anchor::new(input);
+
+        let net = (input.e_chg_all - anchor.charge) - (input.e_dischg_all - anchor.discharge);
+        let energy = if delta > 0 { net } else { -net };
+        // counters that went backwards, or a swing the energy does not account for
+        if energy <= 0.0 {
+            return None;
+        }
+
+        Some(Capacity {
+            datalog: input.datalog,
+            soc_from: anchor.soc,
+            soc_to: input.soc,
+            energy: round(energy, 1),
+            capacity: round(energy / (f64::from(delta.unsigned_abs()) / 100.0), 1),
+        })
+    }
+
+    fn health(state: &State, baseline: Option<(i8, u16)>) -> Health {
+        let last = &state.last;
+        let soh_per_100_cycles = baseline.and_then(|(soh, cycles)| {
+            let cycles = f64::from(last.cycle_count) - f64::from(cycles);
+            (cycles > 0.0).then(|| round((f64::from(soh) - f64::from(last.soh)) / cycles * 100.0, 2))
+        });
+        let usable_capacity = (!state.capacities.is_empty())
+            .then(|| round(state.capacities.iter().sum::<f64>() / state.capacities.len() as f64, 1));
+
+        Health {
+            datalog: last.datalog,
+            date: state.date,
+            soh: last.soh,
+            cycle_count: last.cycle_count,
+            soh_per_100_cycles,
+            max_cell_spread: state.max_cell_spread,
+            max_cell_temp_spread: state.max_cell_temp_spread,
+            charge: round(last.e_chg_day, 1),
+            discharge: round(last.e_dischg_day, 1),
+            efficiency: efficiency(last.e_chg_day, last.e_dischg_day),
+            lifetime_efficiency: efficiency(last.e_chg_all, last.e_dischg_all),
+            usable_capacity,
+        }
+    }
+}
+
+pub struct BatteryHealth {
+    config: ConfigWrapper,
+    channels: Channels,
+    database: Option<Arc<Database>>,
+    shutdown: CancellationToken,
+}
+
+impl BatteryHealth {
+    pub fn new(config: ConfigWrapper, channels: Channels, database: Option<Arc<Database>>) -> Self {
+        Self {
+            config,
+            channels,
+            database,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver = self.channels.from_coordinator.subscribe();
+        let mut tracker = Tracker::new(self.config.battery_health());
+        let mut seeded = HashSet::new();
+        info!("battery health started");
+
+        loop {
+            let msg = tokio::select! {
+                msg = receiver.recv() => msg,
+                _ = self.shutdown.cancelled() => break,
+            };
+            match msg {
+                Ok(coordinator::ChannelData::ReadInputAll(input)) => {
+                    if seeded.insert(input.datalog) {
+                        self.seed(&mut tracker, input.datalog).await;
+                    }
+                    let update = tracker.update(&input);
+                    if let Err(e) = self.publish(&update) {
+                        error!("Failed to publish battery health for {}: {}", input.datalog, e);
+                    }
+                }
+                Ok(coordinator::ChannelData::Shutdown) => break,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("battery health lagged, skipped {} messages", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+
+        info!("battery health exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Carry the SOH trend across restarts from the earliest stored record.
+    async fn seed(&self, tracker: &mut Tracker, datalog: Serial) {
+        let Some(database) = &self.database else {
+            return;
+        };
+        match database.battery_baseline(datalog).await {
+            Ok(Some((soh, cycle_count))) => tracker.seed(datalog, soh, cycle_count),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to read battery health baseline for {}: {}", datalog, e),
+        }
+    }
+
+    fn publish(&self, update: &Update) -> Result<()> {
+        let mqtt = self.config.mqtt().enabled();
+        let database = self.config.have_enabled_database();
+
+        if let (Some(alarm), Some(cells)) = (update.alarm, &update.cells) {
+            let detail = format!(
+                "cell spread {:.3} V ({:.3}-{:.3} V) {}",
+                cells.cell_spread,
+                cells.min_cell_voltage,
+                cells.max_cell_voltage,
+                if alarm { "raised" } else { "cleared" }
+            );
+            if alarm {
+                warn!("battery on {}: {}", cells.datalog, detail);
+            } else {
+                info!("battery on {}: {}", cells.datalog, detail);
+            }
+            if database {
+                self.channels.to_database.send(database::ChannelData::Event(Event::new(
+                    cells.datalog,
+                    EventKind::CellSpread,
+                    detail,
+                )))?;
+            }
+        }
+
+        if mqtt {
+            if let Some(cells) = &update.cells {
+                self.channels.to_mqtt.send(mqtt::ChannelData::Message(cells.mqtt_message()?))?;
+            }
+            if let Some(capacity) = &update.capacity {
+                self.channels
+                    .to_mqtt
+                    .send(mqtt::ChannelData::Message(capacity.mqtt_message()?))?;
+            }
+        }
+
+        if let Some(health) = &update.health {
+            info!(
+                "battery health for {} on {}: soh={}% cycles={} efficiency={:?}% capacity={:?} kWh",
+                health.datalog, health.date, health.soh, health.cycle_count, health.efficiency, health.usable_capacity
+            );
+            if mqtt {
+                self.channels.to_mqtt.send(mqtt::ChannelData::Message(health.mqtt_message()?))?;
+            }
+            if database {
+                self.channels
+                    .to_database
+                    .send(database::ChannelData::BatteryHealth(health.clone()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -14,6 +14,7 @@ pub struct Channels {
     pub to_register_cache: broadcast::Sender<register_cache::ChannelData>,
     pub from_coordinator: broadcast::Sender<CoordinatorChannelData>,
     pub to_coordinator: broadcast::Sender<CoordinatorChannelData>,
+    pub to_audit: broadcast::Sender<crate::audit::ChannelData>,
 }
 
 impl Default for Channels {
@@ -35,6 +36,7 @@ impl Channels {
             to_register_cache: Self::channel(),
             from_coordinator: Self::channel(),
             to_coordinator: Self::channel(),
+            to_audit: Self::channel(),
         }
     }
 
@@ -0,0 +1,325 @@
+//! Forecast-driven overnight AC charge planning.
+//!
+//! Each evening at `plan_time`, tomorrow's solar production is read from the forecast and
+//! compared with the average daily consumption of the last `history_days` daily summaries.
+//! The shortfall sets the AC charge SOC target, above `min_soc`, and the charge time slot is
+//! made just long enough to reach it at `charge_power`, ending at `window_end`. The charge
+//! starts from the SOC expected at `window_start`: the current SOC less the average load,
+//! spread evenly over the day, until the window opens. The plan is published retained on
+//! `{datalog}/charge_plan/preview`, then after `preview` seconds applied with the
+//! `AcChargeSocLimit` and `SetAcChargeTime` commands and published on `{datalog}/charge_plan`.
+//! Publishing anything to `cmd/{datalog}/charge_plan/cancel` while the plan is in preview
+//! drops it.
+//!
+//! The forecast is JSON, either one day or a list of days:
+//!
+//! ```json
+//! [{"date": "2026-10-20", "pv_kwh": 18.4}, {"date": "2026-10-21", "pv_kwh": 9.1}]
+//! ```
+//!
+//! An entry without a date is taken as tomorrow's.
+
+use crate::prelude::*;
+use crate::command::{Origin, Source};
+use crate::database::Database;
+use crate::rules::minute_of_day;
+
+use chrono::{NaiveDate, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// How long fetching the forecast may take.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Charge slots are whole multiples of this many minutes.
+const SLOT_GRANULARITY: u32 = 15;
+
+#[derive(Deserialize)]
+struct ForecastDay {
+    date: Option<NaiveDate>,
+    pv_kwh: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ForecastDocument {
+    Day(ForecastDay),
+    Days(Vec<ForecastDay>),
+}
+
+/// Forecast solar production in kWh for `date`.
+pub fn forecast_for(document: &str, date: NaiveDate) -> Result<f64> {
+    let days = match serde_json::from_str(document).map_err(|e| anyhow!("invalid forecast: {}", e))? {
+        ForecastDocument::Day(day) => vec![day],
+        ForecastDocument::Days(days) => days,
+    };
+    days.iter()
+        .find(|d| d.date.is_none_or(|d| d == date))
+        .map(|d| d.pv_kwh)
+        .ok_or_else(|| anyhow!("forecast has no entry for {}", date))
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Plan {
+    pub datalog: Serial,
+    /// day the charge is for
+    pub date: NaiveDate,
+    pub forecast_kwh: f64,
+    pub consumption_kwh: f64,
+    /// SOC expected when the charge window opens
+    pub soc_at_window_start: u16,
+    /// energy to add overnight
+    pub charge_kwh: f64,
+    pub target_soc: u16,
+    pub slot: u16,
+    /// `HH:MM`; equal to `end` when there is nothing to charge
+    pub start: String,
+    pub end: String,
+}
+
+impl Plan {
+    /// Plan for the night before `date`, from the battery's SOC at minute of day `now` if
+    /// known.
+    pub fn new(
+        config: &config::ChargePlanner,
+        datalog: Serial,
+        date: NaiveDate,
+        forecast_kwh: f64,
+        consumption_kwh: f64,
+        soc: Option<u16>,
+        now: u32,
+    ) -> Self {
+        let capacity = config.battery_capacity();
+        let (min_soc, max_soc) = (config.min_soc(), config.max_soc());
+
+        let shortfall = (consumption_kwh - forecast_kwh).max(0.0);
+        let target = (f64::from(min_soc) + shortfall / capacity * 100.0).ceil();
+        let target_soc = target.clamp(f64::from(min_soc), f64::from(max_soc)) as u16;
+
+        // the battery carries the load until the window opens, down to the reserve; an
+        // unknown SOC is taken to be at the reserve, which errs towards a longer slot
+        let window_start = minute_of_day(config.window_start()).unwrap_or_default();
+        let hours = f64::from((window_start + MINUTES_PER_DAY - now) % MINUTES_PER_DAY) / 60.0;
+        let drain = consumption_kwh / 24.0 * hours / capacity * 100.0;
+        let soc = soc.map_or(min_soc, |soc| {
+            (f64::from(soc) - drain).floor().max(f64::from(min_soc)) as u16
+        });
+        let charge_kwh = f64::from(target_soc.saturating_sub(soc)) / 100.0 * capacity;
+
+        let end = minute_of_day(config.window_end()).unwrap_or_default();
+        let window = match (end + MINUTES_PER_DAY - window_start) % MINUTES_PER_DAY {
+            0 => MINUTES_PER_DAY,
+            n => n,
+        };
+        let needed = (charge_kwh / config.charge_power() * 60.0).ceil() as u32;
+        let minutes = needed.div_ceil(SLOT_GRANULARITY) * SLOT_GRANULARITY;
+        let start = (end + MINUTES_PER_DAY - minutes.min(window)) % MINUTES_PER_DAY;
+
+        Self {
+            datalog,
+            date,
+            forecast_kwh,
+            consumption_kwh: (consumption_kwh * 1000.0).round() / 1000.0,
+            soc_at_window_start: soc,
+            charge_kwh: (charge_kwh * 1000.0).round() / 1000.0,
+            target_soc,
+            slot: config.slot(),
+            start: hhmm(start),
+            end: hhmm(end),
+        }
+    }
+
+    pub fn commands(&self, inverter: config::Inverter) -> Vec<Command> {
+        let (start, end) = (minute_of_day(&self.start), minute_of_day(&self.end));
+        let (start, end) = (start.unwrap_or_default(), end.unwrap_or_default());
+        let times = [
+            (start / 60) as u8,
+            (start % 60) as u8,
+            (end / 60) as u8,
+            (end % 60) as u8,
+        ];
+        vec![
+            Command::AcChargeSocLimit(inverter.clone(), self.target_soc),
+            Command::SetAcChargeTime(inverter, self.slot, times),
+        ]
+    }
+}
+
+fn hhmm(minute: u32) -> String {
+    format!("{:02}:{:02}", minute / 60, minute % 60)
+}
+
+#[derive(Clone)]
+pub struct ChargePlanner {
+    config: ConfigWrapper,
+    channels: Channels,
+    database: Option<Arc<Database>>,
+    http: reqwest::Client,
+    /// latest SOC of each inverter
+    soc: Arc<Mutex<HashMap<Serial, u16>>>,
+    /// inverters whose plan in preview was cancelled
+    cancelled: Arc<Mutex<HashSet<Serial>>>,
+    shutdown: CancellationToken,
+}
+
+impl ChargePlanner {
+    pub fn new(config: ConfigWrapper, channels: Channels, database: Option<Arc<Database>>) -> Self {
+        Self {
+            config,
+            channels,
+            database,
+            http: reqwest::Client::builder()
+                .timeout(HTTP_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            soc: Arc::new(Mutex::new(HashMap::new())),
+            cancelled: Arc::new(Mutex::new(HashSet::new())),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver = self.channels.from_coordinator.subscribe();
+        let mut clock = tokio::time::interval(std::time::Duration::from_secs(60));
+        let plan_time = minute_of_day(self.config.charge_planner().plan_time()).unwrap_or_default();
+        let mut planned: Option<NaiveDate> = None;
+        info!("charge planner started");
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                msg = receiver.recv() => match msg {
+                    Ok(coordinator::ChannelData::ReadInputAll(input)) => {
+                        if let Ok(mut soc) = self.soc.lock() {
+                            soc.insert(input.datalog, input.soc.max(0) as u16);
+                        }
+                    }
+                    Ok(coordinator::ChannelData::CancelChargePlan(datalog)) => {
+                        info!("charge plan for {} cancelled", datalog);
+                        if let Ok(mut cancelled) = self.cancelled.lock() {
+                            cancelled.insert(datalog);
+                        }
+                    }
+                    Ok(coordinator::ChannelData::Shutdown) => break,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("charge planner lagged, skipped {} messages", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = clock.tick() => {
+                    let now = chrono::Local::now();
+                    let today = now.date_naive();
+                    if planned != Some(today) && now.hour() * 60 + now.minute() >= plan_time {
+                        planned = Some(today);
+                        let planner = self.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = planner.run(today + chrono::Days::new(1)).await {
+                                error!("Charge planning failed: {}", e);
+                            }
+                        });
+                    }
+                }
+            }
+        }
+
+        info!("charge planner exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Plan the night before `date` for every inverter, preview, then apply.
+    async fn run(&self, date: NaiveDate) -> Result<()> {
+        let config = self.config.charge_planner();
+        let forecast_kwh = forecast_for(&self.forecast().await?, date)?;
+        let now = chrono::Local::now();
+        let now = now.hour() * 60 + now.minute();
+        // a cancellation only covers the plan it was sent for
+        if let Ok(mut cancelled) = self.cancelled.lock() {
+            cancelled.clear();
+        }
+
+        let mut plans = Vec::new();
+        for inverter in self.config.enabled_inverters() {
+            let Some(datalog) = inverter.datalog() else {
+                continue;
+            };
+            if config.datalog().is_some_and(|d| d != datalog) {
+                continue;
+            }
+
+            let consumption_kwh = self.consumption(datalog, &config).await;
+            let soc = self.soc.lock().ok().and_then(|soc| soc.get(&datalog).copied());
+            let plan = Plan::new(&config, datalog, date, forecast_kwh, consumption_kwh, soc, now);
+            info!(
+                "charge plan for {} on {}: forecast {} kWh, consumption {} kWh, charge to {}% {}-{}",
+                datalog, date, plan.forecast_kwh, plan.consumption_kwh, plan.target_soc, plan.start, plan.end
+            );
+            self.publish(&format!("{}/charge_plan/preview", datalog), &plan)?;
+            plans.push((inverter, plan));
+        }
+
+        if !config.apply() || plans.is_empty() {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_secs(config.preview())).await;
+
+        for (inverter, plan) in plans {
+            if self.cancelled.lock().is_ok_and(|cancelled| cancelled.contains(&plan.datalog)) {
+                info!("charge plan for {} was cancelled, not applying it", plan.datalog);
+                continue;
+            }
+            for command in plan.commands(inverter) {
+                self.channels
+                    .to_coordinator
+                    .send(coordinator::ChannelData::Command(command, Source::new(Origin::Planner, "charge_planner")))?;
+            }
+            self.publish(&format!("{}/charge_plan", plan.datalog), &plan)?;
+        }
+        Ok(())
+    }
+
+    async fn forecast(&self) -> Result<String> {
+        let config = self.config.charge_planner();
+        if let Some(url) = config.forecast_url() {
+            let response = self.http.get(url).send().await?.error_for_status()?;
+            return Ok(response.text().await?);
+        }
+        let file = config.forecast_file().unwrap_or_default();
+        std::fs::read_to_string(file).map_err(|e| anyhow!("cannot read forecast {}: {}", file, e))
+    }
+
+    /// Average daily consumption from the database, or the configured default.
+    async fn consumption(&self, datalog: Serial, config: &config::ChargePlanner) -> f64 {
+        let history = match &self.database {
+            Some(database) => database
+                .average_consumption(datalog, config.history_days())
+                .await
+                .map_err(|e| warn!("no consumption history for {}: {}", datalog, e))
+                .ok()
+                .flatten(),
+            None => None,
+        };
+        history.unwrap_or(config.default_consumption())
+    }
+
+    fn publish(&self, topic: &str, plan: &Plan) -> Result<()> {
+        if !self.config.mqtt().enabled() {
+            return Ok(());
+        }
+        self.channels.to_mqtt.send(mqtt::ChannelData::Message(mqtt::Message {
+            topic: topic.to_string(),
+            retain: true,
+            payload: serde_json::to_string(plan)?,
+        }))?;
+        Ok(())
+    }
+}
@@ -1,6 +1,69 @@
 use crate::prelude::*;
 
-#[derive(Debug, Clone)]
+use serde::Serialize;
+
+/// Where a command came from; recorded with every write in the database event log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    Mqtt,
+    Scheduler,
+    Rules,
+    Controller,
+    Planner,
+    /// A write by another client of the datalog, seen only by its reply
+    External,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Origin::Mqtt => write!(f, "mqtt"),
+            Origin::Scheduler => write!(f, "scheduler"),
+            Origin::Rules => write!(f, "rules"),
+            Origin::Controller => write!(f, "controller"),
+            Origin::Planner => write!(f, "planner"),
+            Origin::External => write!(f, "external"),
+        }
+    }
+}
+
+/// An origin and which one of its kind asked: the MQTT topic, the rule, the controller or
+/// the scheduled job.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub origin: Origin,
+    pub detail: String,
+    /// Puts back a setting a controller changed; the write policy lets it through without
+    /// waiting out `min_interval` or a confirmation.
+    pub restore: bool,
+}
+
+impl Source {
+    pub fn new(origin: Origin, detail: impl Into<String>) -> Self {
+        Self {
+            origin,
+            detail: detail.into(),
+            restore: false,
+        }
+    }
+
+    /// A fail-safe restore of a setting `origin` changed.
+    pub fn restore(origin: Origin, detail: impl Into<String>) -> Self {
+        Self {
+            restore: true,
+            ..Self::new(origin, detail)
+        }
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.origin, self.detail)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Command {
     ReadInputs(config::Inverter, u16),
     ReadInput(config::Inverter, u16, u16),
@@ -24,9 +87,58 @@ pub enum Command {
     AcChargeRate(config::Inverter, u16),
     AcChargeSocLimit(config::Inverter, u16),
     DischargeCutoffSocLimit(config::Inverter, u16),
+    /// Confirm the next write of a dangerous hold register with the policy's token
+    ConfirmHold(config::Inverter, u16, String),
+    /// Drop the charge plan in preview before it is applied
+    CancelChargePlan(config::Inverter),
 }
 
 impl Command {
+    /// True for commands that change inverter settings (and so honour read_only).
+    pub fn is_write(&self) -> bool {
+        use Command::*;
+
+        !matches!(
+            self,
+            ReadInputs(..)
+                | ReadInput(..)
+                | ReadHold(..)
+                | ReadParam(..)
+                | ReadAcChargeTime(..)
+                | ReadAcFirstTime(..)
+                | ReadChargePriorityTime(..)
+                | ReadForcedDischargeTime(..)
+                | ConfirmHold(..)
+                | CancelChargePlan(..)
+        )
+    }
+
+    /// Short form for logs and the event table, e.g. `BA12345678/set/hold/21 = 5`.
+    pub fn describe(&self) -> String {
+        use Command::*;
+
+        let path = self.to_result_topic().trim_start_matches("result/").to_string();
+        let value = match self {
+            SetHold(_, _, v)
+            | WriteParam(_, _, v)
+            | ChargeRate(_, v)
+            | DischargeRate(_, v)
+            | AcChargeRate(_, v)
+            | AcChargeSocLimit(_, v)
+            | DischargeCutoffSocLimit(_, v) => v.to_string(),
+            SetAcChargeTime(_, _, t)
+            | SetAcFirstTime(_, _, t)
+            | SetChargePriorityTime(_, _, t)
+            | SetForcedDischargeTime(_, _, t) => {
+                format!("{:02}:{:02}-{:02}:{:02}", t[0], t[1], t[2], t[3])
+            }
+            AcCharge(_, b) | ChargePriority(_, b) | ForcedDischarge(_, b) => b.to_string(),
+            _ => return path,
+        };
+
+        format!("{} = {}", path, value)
+    }
+
     pub fn to_result_topic(&self) -> String {
         use Command::*;
 
@@ -53,6 +165,8 @@ impl Command {
             AcChargeRate(inverter, _) => format!("{}/set/ac_charge_rate_pct", inverter.datalog().map(|s| s.to_string()).unwrap_or_default()),
             AcChargeSocLimit(inverter, _) => format!("{}/set/ac_charge_soc_limit_pct", inverter.datalog().map(|s| s.to_string()).unwrap_or_default()),
             DischargeCutoffSocLimit(inverter, _) => format!("{}/set/discharge_cutoff_soc_limit_pct", inverter.datalog().map(|s| s.to_string()).unwrap_or_default()),
+            ConfirmHold(inverter, register, _) => format!("{}/confirm/hold/{}", inverter.datalog().map(|s| s.to_string()).unwrap_or_default(), register),
+            CancelChargePlan(inverter) => format!("{}/charge_plan/cancel", inverter.datalog().map(|s| s.to_string()).unwrap_or_default()),
         };
 
         format!("result/{}", rest)
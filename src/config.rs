@@ -1,9 +1,10 @@
 use crate::prelude::*;
 use crate::register::RegisterParser;
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
 use serde_yaml;
+use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
 
 /// Main configuration structure that holds all settings for the EG4 bridge application.
@@ -14,6 +15,9 @@ use std::sync::{Arc, Mutex};
 pub struct Config {
     /// List of configured inverters to connect to
     pub inverters: Vec<Inverter>,
+    /// Inverters run in parallel, published and commanded as one
+    #[serde(default)]
+    pub groups: Vec<Group>,
     /// MQTT broker configuration for publishing data
     pub mqtt: Mqtt,
     /// InfluxDB configuration for time-series data storage
@@ -25,6 +29,42 @@ pub struct Config {
     /// Optional scheduler configuration for periodic tasks
     pub scheduler: Option<Scheduler>,
 
+    /// Optional tariff for cost and savings accounting
+    #[serde(default)]
+    pub tariff: Tariff,
+
+    /// Local automations on live input data
+    #[serde(default)]
+    pub rules: Rules,
+
+    /// Closed-loop cap on grid export
+    #[serde(default)]
+    pub export_limit: ExportLimit,
+
+    /// Battery discharge against grid import peaks
+    #[serde(default)]
+    pub peak_shaving: PeakShaving,
+
+    /// Overnight AC charge target from a solar forecast
+    #[serde(default)]
+    pub charge_planner: ChargePlanner,
+
+    /// Which hold registers may be written, and with what values
+    #[serde(default)]
+    pub write_policy: WritePolicy,
+
+    /// Record of every write made to, or seen on, the inverters
+    #[serde(default)]
+    pub audit: Audit,
+
+    /// Notifications of faults, warnings, disconnects and stale data
+    #[serde(default)]
+    pub alerts: Alerts,
+
+    /// Cell imbalance, SOH trend, round-trip efficiency and usable capacity
+    #[serde(default)]
+    pub battery_health: BatteryHealth,
+
     /// Logging level (default: "info")
     #[serde(default = "Config::default_loglevel")]
     pub loglevel: String,
@@ -32,6 +72,10 @@ pub struct Config {
     /// Global read-only mode flag
     pub read_only: bool,
 
+    /// Log and publish writes on `dryrun/...` instead of sending them
+    #[serde(default)]
+    pub dry_run: bool,
+
     /// Whether to enable Home Assistant integration
     #[serde(default = "Config::default_homeassistant_enabled")]
     pub homeassistant_enabled: bool,
@@ -65,6 +109,10 @@ pub struct Config {
     /// Timeout in seconds between sending read requests to inverters (default: 300)
     #[serde(default = "Config::default_inverter_timeout")]
     pub inverter_timeout: u64,
+
+    /// Whether to capture end-of-day energy totals and publish a daily summary (default: true)
+    #[serde(default = "Config::default_daily_summary")]
+    pub daily_summary: bool,
 }
 
 /// Configuration for a single EG4 inverter
@@ -99,6 +147,9 @@ pub struct Inverter {
     pub delay_ms: Option<u64>,
     /// Whether this inverter is in read-only mode
     pub read_only: Option<bool>,
+    /// Whether writes to this inverter are only logged and published; defaults to the
+    /// global `dry_run`
+    pub dry_run: Option<bool>,
     /// Interval in seconds between reading input registers (optional, overrides global setting)
     pub register_read_interval: Option<u64>,
 }
@@ -151,11 +202,42 @@ impl Inverter {
         self.read_only.unwrap_or(false)
     }
 
+    pub fn dry_run(&self) -> bool {
+        self.dry_run.unwrap_or(false)
+    }
+
     pub fn register_read_interval(&self) -> Option<u64> {
         self.register_read_interval
     }
 }
 
+// Group {{{
+/// Parallel inverters published as one virtual inverter on `{name}/inputs/all`, and
+/// commanded together on `cmd/{name}/...`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct Group {
+    pub name: String,
+    /// Datalogs of the member inverters
+    #[serde(deserialize_with = "de_serials")]
+    pub members: Vec<Serial>,
+    /// Seconds a member may go without reporting before the group is published without it
+    #[serde(default = "Config::default_group_stale_after")]
+    pub stale_after: u64,
+}
+impl Group {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn members(&self) -> &Vec<Serial> {
+        &self.members
+    }
+
+    pub fn stale_after(&self) -> u64 {
+        self.stale_after
+    }
+} // }}}
+
 // HomeAssistant {{{
 #[serde_as]
 #[derive(Clone, Debug, Deserialize)]
@@ -165,6 +247,14 @@ pub struct HomeAssistant {
 
     #[serde(default = "Config::default_mqtt_homeassistant_prefix")]
     pub prefix: String,
+
+    /// Home Assistant publishes `online` here when it starts; discovery is re-sent in response
+    #[serde(default = "Config::default_mqtt_homeassistant_status_topic")]
+    pub status_topic: String,
+
+    /// Delete retained discovery configs this bridge published for datalogs that are no longer enabled
+    #[serde(default)]
+    pub remove_stale: bool,
 }
 
 impl HomeAssistant {
@@ -175,6 +265,246 @@ impl HomeAssistant {
     pub fn prefix(&self) -> &str {
         &self.prefix
     }
+
+    pub fn status_topic(&self) -> &str {
+        &self.status_topic
+    }
+
+    pub fn remove_stale(&self) -> bool {
+        self.remove_stale
+    }
+} // }}}
+
+// MqttTls {{{
+#[derive(Clone, Debug, Deserialize)]
+pub struct MqttTls {
+    #[serde(default = "Config::default_enabled")]
+    pub enabled: bool,
+
+    /// Used instead of `mqtt.port` while TLS is enabled
+    #[serde(default = "Config::default_mqtt_tls_port")]
+    pub port: u16,
+
+    /// PEM CA bundle; the system roots are used when unset
+    pub ca_file: Option<String>,
+    /// PEM client certificate and key for brokers requiring client authentication
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+}
+
+impl MqttTls {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn ca_file(&self) -> Option<&str> {
+        self.ca_file.as_deref()
+    }
+
+    pub fn cert_file(&self) -> Option<&str> {
+        self.cert_file.as_deref()
+    }
+
+    pub fn key_file(&self) -> Option<&str> {
+        self.key_file.as_deref()
+    }
+} // }}}
+
+// MqttPublish {{{
+/// QoS and retain flag for one class of published message.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MqttPublish {
+    #[serde(default = "Config::default_mqtt_qos")]
+    pub qos: u8,
+
+    /// Overrides the retain flag each message is built with when set
+    pub retain: Option<bool>,
+}
+
+impl Default for MqttPublish {
+    fn default() -> Self {
+        Self {
+            qos: Config::default_mqtt_qos(),
+            retain: None,
+        }
+    }
+}
+
+impl MqttPublish {
+    pub fn qos(&self) -> u8 {
+        self.qos
+    }
+
+    pub fn retain(&self, built_with: bool) -> bool {
+        self.retain.unwrap_or(built_with)
+    }
+}
+
+/// Publish settings per message class.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct MqttPublishPolicy {
+    /// `input/*` and `inputs/*`
+    #[serde(default)]
+    pub inputs: MqttPublish,
+    /// `hold/*`, `param/*` and timeslots
+    #[serde(default)]
+    pub holds: MqttPublish,
+    /// Home Assistant discovery configs
+    #[serde(default)]
+    pub discovery: MqttPublish,
+    /// everything else: summaries, tariff, energy totals, command results
+    #[serde(default)]
+    pub other: MqttPublish,
+}
+
+impl MqttPublishPolicy {
+    fn all(&self) -> [(&'static str, &MqttPublish); 4] {
+        [
+            ("inputs", &self.inputs),
+            ("holds", &self.holds),
+            ("discovery", &self.discovery),
+            ("other", &self.other),
+        ]
+    }
+} // }}}
+
+// MqttOutbox {{{
+/// Messages held while the broker is unreachable, replayed in order on reconnect.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MqttOutbox {
+    #[serde(default = "Config::default_enabled")]
+    pub enabled: bool,
+
+    /// Oldest messages are dropped beyond this
+    #[serde(default = "Config::default_mqtt_outbox_max_messages")]
+    pub max_messages: usize,
+
+    /// `all` keeps every message, `latest` only the newest per topic
+    #[serde(default = "Config::default_mqtt_outbox_policy")]
+    pub policy: String,
+
+    /// Queued messages are kept here across restarts; memory only when unset
+    pub file: Option<String>,
+}
+
+impl Default for MqttOutbox {
+    fn default() -> Self {
+        Self {
+            enabled: Config::default_enabled(),
+            max_messages: Config::default_mqtt_outbox_max_messages(),
+            policy: Config::default_mqtt_outbox_policy(),
+            file: None,
+        }
+    }
+}
+
+impl MqttOutbox {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn max_messages(&self) -> usize {
+        self.max_messages
+    }
+
+    pub fn latest_only(&self) -> bool {
+        self.policy == "latest"
+    }
+
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+} // }}}
+
+// MqttDeadband {{{
+/// How far a value must move before it is republished; the larger of the two applies.
+/// Zero for both publishes any change.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Deadband {
+    #[serde(default)]
+    pub absolute: f64,
+    /// Of the last published value
+    #[serde(default)]
+    pub percent: f64,
+}
+
+impl Deadband {
+    pub fn exceeded(&self, old: f64, new: f64) -> bool {
+        let threshold = self.absolute.max(old.abs() * self.percent / 100.0);
+        (new - old).abs() > threshold || (threshold == 0.0 && new != old)
+    }
+}
+
+/// Report-by-exception for register topics.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MqttDeadband {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Seconds after which an unchanged value is republished anyway
+    #[serde(default = "Config::default_mqtt_deadband_max_age")]
+    pub max_age: u64,
+
+    #[serde(default)]
+    pub power: Deadband,
+    #[serde(default)]
+    pub voltage: Deadband,
+    #[serde(default)]
+    pub energy: Deadband,
+    #[serde(default)]
+    pub status: Deadband,
+    /// currents, temperatures, frequencies and anything unclassified
+    #[serde(default)]
+    pub other: Deadband,
+}
+
+impl Default for MqttDeadband {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_age: Config::default_mqtt_deadband_max_age(),
+            power: Deadband::default(),
+            voltage: Deadband::default(),
+            energy: Deadband::default(),
+            status: Deadband::default(),
+            other: Deadband::default(),
+        }
+    }
+}
+
+impl MqttDeadband {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn max_age(&self) -> u64 {
+        self.max_age
+    }
+
+    pub fn band(&self, class: crate::deadband::Class) -> &Deadband {
+        use crate::deadband::Class;
+        match class {
+            Class::Power => &self.power,
+            Class::Voltage => &self.voltage,
+            Class::Energy => &self.energy,
+            Class::Status => &self.status,
+            Class::Other => &self.other,
+        }
+    }
+
+    fn all(&self) -> [(&'static str, &Deadband); 5] {
+        [
+            ("power", &self.power),
+            ("voltage", &self.voltage),
+            ("energy", &self.energy),
+            ("status", &self.status),
+            ("other", &self.other),
+        ]
+    }
 } // }}}
 
 // Mqtt {{{
@@ -196,6 +526,39 @@ pub struct Mqtt {
     pub homeassistant: HomeAssistant,
 
     pub publish_individual_input: Option<bool>,
+
+    /// Also publish every input field and named hold register to its own scaled topic
+    #[serde(default)]
+    pub publish_fields: bool,
+
+    /// Must be unique per broker; two clients with one id disconnect each other
+    #[serde(default = "Config::default_mqtt_client_id")]
+    pub client_id: String,
+
+    pub tls: Option<MqttTls>,
+
+    #[serde(default = "Config::default_mqtt_keepalive")]
+    pub keepalive: u64,
+
+    #[serde(default = "Config::default_enabled")]
+    pub clean_session: bool,
+
+    #[serde(default)]
+    pub publish: MqttPublishPolicy,
+
+    /// `3.1.1` or `5`; v5 adds response topics and correlation data for command results
+    #[serde(default = "Config::default_mqtt_protocol")]
+    pub protocol: String,
+
+    /// Publish each command's result on `result/...` as JSON, not just `FAIL` on failure
+    #[serde(default)]
+    pub json_results: bool,
+
+    #[serde(default)]
+    pub outbox: MqttOutbox,
+
+    #[serde(default)]
+    pub deadband: MqttDeadband,
 }
 impl Mqtt {
     pub fn enabled(&self) -> bool {
@@ -229,6 +592,56 @@ impl Mqtt {
     pub fn publish_individual_input(&self) -> bool {
         self.publish_individual_input == Some(true)
     }
+
+    pub fn publish_fields(&self) -> bool {
+        self.publish_fields
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// TLS settings, if TLS is enabled.
+    pub fn tls(&self) -> Option<&MqttTls> {
+        self.tls.as_ref().filter(|t| t.enabled())
+    }
+
+    /// Port to connect to, allowing for TLS.
+    pub fn connect_port(&self) -> u16 {
+        self.tls().map(|t| t.port()).unwrap_or(self.port)
+    }
+
+    pub fn keepalive(&self) -> u64 {
+        self.keepalive
+    }
+
+    pub fn clean_session(&self) -> bool {
+        self.clean_session
+    }
+
+    pub fn publish(&self) -> &MqttPublishPolicy {
+        &self.publish
+    }
+
+    pub fn protocol(&self) -> &str {
+        &self.protocol
+    }
+
+    pub fn v5(&self) -> bool {
+        self.protocol == "5"
+    }
+
+    pub fn json_results(&self) -> bool {
+        self.json_results
+    }
+
+    pub fn outbox(&self) -> &MqttOutbox {
+        &self.outbox
+    }
+
+    pub fn deadband(&self) -> &MqttDeadband {
+        &self.deadband
+    }
 } // }}}
 
 // Influx {{{
@@ -241,7 +654,36 @@ pub struct Influx {
     pub username: Option<String>,
     pub password: Option<String>,
 
+    /// v1 database name; also used as the bucket for v2/v3 when `bucket` is unset
+    #[serde(default)]
     pub database: String,
+
+    /// Write API to use: 1 (`/write?db=`) or 2/3 (`/api/v2/write` with token auth)
+    #[serde(default = "Config::default_influx_api_version")]
+    pub api_version: u8,
+    pub org: Option<String>,
+    pub bucket: Option<String>,
+    pub token: Option<String>,
+
+    /// Timestamp precision sent to the server: s, ms, us or ns
+    #[serde(default = "Config::default_influx_precision")]
+    pub precision: String,
+
+    /// Compress write bodies with gzip
+    #[serde(default)]
+    pub gzip: bool,
+
+    /// Number of points to buffer before writing (1 writes every message immediately)
+    #[serde(default = "Config::default_influx_batch_size")]
+    pub batch_size: usize,
+
+    /// Maximum seconds a partial batch is held before being flushed
+    #[serde(default = "Config::default_influx_flush_interval")]
+    pub flush_interval: u64,
+
+    /// Extra tags added to every point, e.g. `site: home`
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
 }
 impl Influx {
     pub fn enabled(&self) -> bool {
@@ -263,6 +705,43 @@ impl Influx {
     pub fn database(&self) -> &str {
         &self.database
     }
+
+    pub fn api_version(&self) -> u8 {
+        self.api_version
+    }
+
+    pub fn org(&self) -> &Option<String> {
+        &self.org
+    }
+
+    /// Bucket for v2/v3 writes, falling back to `database`.
+    pub fn bucket(&self) -> &str {
+        self.bucket.as_deref().unwrap_or(&self.database)
+    }
+
+    pub fn token(&self) -> &Option<String> {
+        &self.token
+    }
+
+    pub fn precision(&self) -> &str {
+        &self.precision
+    }
+
+    pub fn gzip(&self) -> bool {
+        self.gzip
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size.max(1)
+    }
+
+    pub fn flush_interval(&self) -> u64 {
+        self.flush_interval
+    }
+
+    pub fn tags(&self) -> &BTreeMap<String, String> {
+        &self.tags
+    }
 } // }}}
 
 // Database {{{
@@ -272,6 +751,22 @@ pub struct Database {
     pub enabled: bool,
 
     pub url: String,
+
+    /// Number of rows to buffer before inserting them in one transaction (1 inserts immediately)
+    #[serde(default = "Config::default_database_batch_size")]
+    pub batch_size: usize,
+    /// Maximum seconds a partial batch is held before being inserted
+    #[serde(default = "Config::default_database_flush_interval")]
+    pub flush_interval: u64,
+
+    /// Delete raw `inputs` rows older than this many days (unset keeps everything)
+    pub retention_days: Option<u32>,
+    /// Maintain hourly and daily aggregate tables
+    #[serde(default)]
+    pub rollups: bool,
+    /// Seconds between retention/rollup runs
+    #[serde(default = "Config::default_database_maintenance_interval")]
+    pub maintenance_interval: u64,
 }
 impl Database {
     pub fn enabled(&self) -> bool {
@@ -281,6 +776,26 @@ impl Database {
     pub fn url(&self) -> &str {
         &self.url
     }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size.max(1)
+    }
+
+    pub fn flush_interval(&self) -> u64 {
+        self.flush_interval
+    }
+
+    pub fn retention_days(&self) -> Option<u32> {
+        self.retention_days
+    }
+
+    pub fn rollups(&self) -> bool {
+        self.rollups
+    }
+
+    pub fn maintenance_interval(&self) -> u64 {
+        self.maintenance_interval
+    }
 } // }}}
 
 // Scheduler {{{
@@ -301,78 +816,928 @@ impl Scheduler {
     }
 } // }}}
 
-#[derive(Clone)]
-pub struct ConfigWrapper(Arc<Mutex<Config>>);
+// Tariff {{{
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Tariff {
+    #[serde(default)]
+    pub enabled: bool,
 
-impl ConfigWrapper {
-    pub fn new(file: String) -> Result<Self> {
-        let config = Config::new(file)?;
-        Ok(Self(Arc::new(Mutex::new(config))))
+    /// Fixed charge added once per day
+    #[serde(default)]
+    pub fixed_daily: f64,
+    /// Fixed charge added once per month
+    #[serde(default)]
+    pub fixed_monthly: f64,
+
+    /// Time-of-use prices; the first rate matching the weekday and hour applies
+    #[serde(default)]
+    pub rates: Vec<TariffRate>,
+}
+impl Tariff {
+    pub fn enabled(&self) -> bool {
+        self.enabled
     }
 
-    pub fn from_config(config: Config) -> Self {
-        Self(Arc::new(Mutex::new(config)))
+    pub fn fixed_daily(&self) -> f64 {
+        self.fixed_daily
     }
 
-    pub fn register_read_interval(&self) -> Option<u64> {
-        self.0.lock().unwrap().register_read_interval.into()
+    pub fn fixed_monthly(&self) -> f64 {
+        self.fixed_monthly
     }
 
-    pub fn inverter_timeout(&self) -> u64 {
-        self.0.lock().unwrap().inverter_timeout
+    pub fn rates(&self) -> &Vec<TariffRate> {
+        &self.rates
     }
+}
 
-    pub fn inverters(&self) -> Vec<Inverter> {
-        self.0.lock().unwrap().inverters.clone()
+#[derive(Clone, Debug, Deserialize)]
+pub struct TariffRate {
+    /// Weekdays this rate applies to (mon, tue, ...); empty means every day
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// First hour (0-23) this rate applies to
+    #[serde(default)]
+    pub start_hour: u32,
+    /// Hour (1-24) this rate stops applying, exclusive
+    #[serde(default = "Config::default_tariff_end_hour")]
+    pub end_hour: u32,
+
+    /// Price per kWh imported from the grid
+    pub import: f64,
+    /// Price per kWh exported to the grid
+    #[serde(default)]
+    pub export: f64,
+}
+impl TariffRate {
+    /// Whether this rate covers the given local weekday and hour.
+    pub fn applies(&self, weekday: chrono::Weekday, hour: u32) -> bool {
+        let day_matches = self.days.is_empty()
+            || self
+                .days
+                .iter()
+                .any(|d| chrono::Weekday::from_str(d).map(|d| d == weekday).unwrap_or(false));
+
+        day_matches && self.start_hour <= hour && hour < self.end_hour
     }
+} // }}}
 
-    pub fn set_inverters(&self, new: Vec<Inverter>) {
-        self.0.lock().unwrap().inverters = new;
+// ExportLimit {{{
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExportLimit {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Only control this inverter; every enabled inverter when unset
+    #[serde(default, deserialize_with = "de_serial")]
+    pub datalog: Option<Serial>,
+
+    /// Export ceiling in watts; 0 for zero export
+    #[serde(default)]
+    pub max_export: u32,
+    /// Watts below the ceiling to aim for once limiting
+    #[serde(default = "Config::default_export_limit_margin")]
+    pub margin: u32,
+
+    /// `power_limit` lowers a hold register in watts, `charge_rate` raises the charge rate
+    #[serde(default = "Config::default_export_limit_method")]
+    pub method: String,
+    /// Hold register written by the `power_limit` method, in W or % in the register map
+    pub register: Option<u16>,
+    /// Inverter output in watts at 100%, for a `power_limit` register in %
+    #[serde(default)]
+    pub rated_power: u32,
+    /// Battery charge power at a 100% charge rate, for the `charge_rate` method
+    #[serde(default = "Config::default_export_limit_max_charge_power")]
+    pub max_charge_power: u32,
+
+    /// Smallest change in watts worth a write
+    #[serde(default = "Config::default_export_limit_min_step")]
+    pub min_step: u32,
+    /// Minimum seconds between writes to the inverter
+    #[serde(default = "Config::default_export_limit_min_write_interval")]
+    pub min_write_interval: u64,
+    /// Seconds without input data before the original setting is restored
+    #[serde(default = "Config::default_export_limit_data_timeout")]
+    pub data_timeout: u64,
+}
+impl Default for ExportLimit {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            datalog: None,
+            max_export: 0,
+            margin: Config::default_export_limit_margin(),
+            method: Config::default_export_limit_method(),
+            register: None,
+            rated_power: 0,
+            max_charge_power: Config::default_export_limit_max_charge_power(),
+            min_step: Config::default_export_limit_min_step(),
+            min_write_interval: Config::default_export_limit_min_write_interval(),
+            data_timeout: Config::default_export_limit_data_timeout(),
+        }
+    }
+}
+impl ExportLimit {
+    pub fn enabled(&self) -> bool {
+        self.enabled
     }
 
-    pub fn enabled_inverters(&self) -> Vec<Inverter> {
-        self.inverters().into_iter().filter(|i| i.enabled()).collect()
+    pub fn datalog(&self) -> Option<Serial> {
+        self.datalog
     }
 
-    pub fn inverter_with_host(&self, host: &str) -> Option<Inverter> {
-        self.inverters().into_iter().find(|i| i.host() == host)
+    pub fn max_export(&self) -> u32 {
+        self.max_export
     }
 
-    pub fn enabled_inverter_with_datalog(&self, datalog: Serial) -> Option<Inverter> {
-        self.enabled_inverters()
-            .into_iter()
-            .find(|i| i.datalog() == Some(datalog))
+    pub fn margin(&self) -> u32 {
+        self.margin
     }
 
-    pub fn enabled_inverter_with_serial(&self, serial: Serial) -> Option<Inverter> {
-        self.enabled_inverters()
-            .into_iter()
-            .find(|i| i.serial() == Some(serial))
+    pub fn charge_rate(&self) -> bool {
+        self.method == "charge_rate"
     }
 
-    pub fn inverters_for_message(&self, message: &mqtt::Message) -> Result<Vec<Inverter>> {
-        let (target_inverter, _) = message.split_cmd_topic()?;
-        let inverters = self.enabled_inverters();
+    pub fn register(&self) -> Option<u16> {
+        self.register
+    }
 
-        match target_inverter {
-            mqtt::TargetInverter::All => Ok(inverters),
-            mqtt::TargetInverter::Serial(datalog) => Ok(inverters
-                .into_iter()
-                .filter(|i| i.datalog() == Some(datalog))
-                .collect()),
-        }
+    pub fn rated_power(&self) -> u32 {
+        self.rated_power
     }
 
-    pub fn mqtt(&self) -> Mqtt {
-        self.0.lock().unwrap().mqtt.clone()
+    pub fn max_charge_power(&self) -> u32 {
+        self.max_charge_power
     }
 
-    pub fn influx(&self) -> Influx {
-        self.0.lock().unwrap().influx.clone()
+    pub fn min_step(&self) -> u32 {
+        self.min_step
     }
 
-    pub fn databases(&self) -> Vec<Database> {
-        self.0.lock().unwrap().databases.clone()
+    pub fn min_write_interval(&self) -> u64 {
+        self.min_write_interval
+    }
+
+    pub fn data_timeout(&self) -> u64 {
+        self.data_timeout
+    }
+} // }}}
+
+// PeakShaving {{{
+#[derive(Clone, Debug, Deserialize)]
+pub struct PeakShaving {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Only control this inverter; every enabled inverter when unset
+    #[serde(default, deserialize_with = "de_serial")]
+    pub datalog: Option<Serial>,
+
+    /// `discharge_rate` raises the discharge rate, `forced_discharge` enables forced discharge
+    #[serde(default = "Config::default_peak_shaving_method")]
+    pub method: String,
+    /// Discharge rate (%) while shaving, for the `discharge_rate` method
+    #[serde(default = "Config::default_peak_shaving_discharge_rate")]
+    pub discharge_rate: u16,
+
+    /// Start shaving once import is within this many watts of the threshold
+    #[serde(default = "Config::default_peak_shaving_approach")]
+    pub approach: u32,
+    /// Stop once import would stay this many watts further below without the battery
+    #[serde(default = "Config::default_peak_shaving_hysteresis")]
+    pub hysteresis: u32,
+
+    #[serde(default = "Config::default_peak_shaving_max_writes_per_hour")]
+    pub max_writes_per_hour: u32,
+
+    /// Import thresholds by time of day; the first matching window applies
+    #[serde(default)]
+    pub windows: Vec<PeakWindow>,
+    /// Off-peak windows to recharge from AC in
+    #[serde(default)]
+    pub recharge: Vec<TimeWindow>,
+}
+impl Default for PeakShaving {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            datalog: None,
+            method: Config::default_peak_shaving_method(),
+            discharge_rate: Config::default_peak_shaving_discharge_rate(),
+            approach: Config::default_peak_shaving_approach(),
+            hysteresis: Config::default_peak_shaving_hysteresis(),
+            max_writes_per_hour: Config::default_peak_shaving_max_writes_per_hour(),
+            windows: Vec::new(),
+            recharge: Vec::new(),
+        }
+    }
+}
+impl PeakShaving {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn datalog(&self) -> Option<Serial> {
+        self.datalog
+    }
+
+    pub fn forced_discharge(&self) -> bool {
+        self.method == "forced_discharge"
+    }
+
+    pub fn discharge_rate(&self) -> u16 {
+        self.discharge_rate
+    }
+
+    pub fn approach(&self) -> u32 {
+        self.approach
+    }
+
+    pub fn hysteresis(&self) -> u32 {
+        self.hysteresis
+    }
+
+    pub fn max_writes_per_hour(&self) -> u32 {
+        self.max_writes_per_hour
+    }
+
+    pub fn windows(&self) -> &Vec<PeakWindow> {
+        &self.windows
+    }
+
+    pub fn recharge(&self) -> &Vec<TimeWindow> {
+        &self.recharge
+    }
+}
+
+/// Weekdays and a local time range, `HH:MM`; the end is exclusive and may be past midnight.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TimeWindow {
+    /// Weekdays (mon, tue, ...); empty means every day
+    #[serde(default)]
+    pub days: Vec<String>,
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, at: chrono::DateTime<chrono::Local>) -> bool {
+        use chrono::{Datelike, Timelike};
+
+        crate::rules::in_window(
+            &self.days,
+            self.start_time.as_deref(),
+            self.end_time.as_deref(),
+            at.weekday(),
+            at.hour() * 60 + at.minute(),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PeakWindow {
+    #[serde(flatten)]
+    pub window: TimeWindow,
+    /// Grid import in watts not to exceed
+    pub threshold: u32,
+} // }}}
+
+// ChargePlanner {{{
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChargePlanner {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Only plan for this inverter; every enabled inverter when unset
+    #[serde(default, deserialize_with = "de_serial")]
+    pub datalog: Option<Serial>,
+
+    /// JSON solar forecast, read from a file or fetched over HTTP
+    pub forecast_file: Option<String>,
+    pub forecast_url: Option<String>,
+
+    /// Local time (`HH:MM`) the plan for the night is made
+    #[serde(default = "Config::default_charge_planner_plan_time")]
+    pub plan_time: String,
+    /// Seconds the plan is previewed on MQTT before it is applied
+    #[serde(default = "Config::default_charge_planner_preview")]
+    pub preview: u64,
+    /// Apply plans; when false they are only previewed
+    #[serde(default = "Config::default_enabled")]
+    pub apply: bool,
+
+    /// Usable battery capacity in kWh
+    #[serde(default)]
+    pub battery_capacity: f64,
+    /// AC charge power in kW
+    #[serde(default = "Config::default_charge_planner_charge_power")]
+    pub charge_power: f64,
+    #[serde(default = "Config::default_charge_planner_min_soc")]
+    pub min_soc: u16,
+    #[serde(default = "Config::default_charge_planner_max_soc")]
+    pub max_soc: u16,
+
+    /// Off-peak window the charge slot is placed in, ending at `window_end`
+    #[serde(default = "Config::default_charge_planner_window_start")]
+    pub window_start: String,
+    #[serde(default = "Config::default_charge_planner_window_end")]
+    pub window_end: String,
+    /// AC charge time slot (1-3) the plan writes
+    #[serde(default = "Config::default_charge_planner_slot")]
+    pub slot: u16,
+
+    /// Days of daily summaries averaged for expected consumption
+    #[serde(default = "Config::default_charge_planner_history_days")]
+    pub history_days: u32,
+    /// Expected daily consumption in kWh when there is no history
+    #[serde(default)]
+    pub default_consumption: f64,
+}
+impl Default for ChargePlanner {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            datalog: None,
+            forecast_file: None,
+            forecast_url: None,
+            plan_time: Config::default_charge_planner_plan_time(),
+            preview: Config::default_charge_planner_preview(),
+            apply: true,
+            battery_capacity: 0.0,
+            charge_power: Config::default_charge_planner_charge_power(),
+            min_soc: Config::default_charge_planner_min_soc(),
+            max_soc: Config::default_charge_planner_max_soc(),
+            window_start: Config::default_charge_planner_window_start(),
+            window_end: Config::default_charge_planner_window_end(),
+            slot: Config::default_charge_planner_slot(),
+            history_days: Config::default_charge_planner_history_days(),
+            default_consumption: 0.0,
+        }
+    }
+}
+impl ChargePlanner {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn datalog(&self) -> Option<Serial> {
+        self.datalog
+    }
+
+    pub fn forecast_file(&self) -> Option<&str> {
+        self.forecast_file.as_deref()
+    }
+
+    pub fn forecast_url(&self) -> Option<&str> {
+        self.forecast_url.as_deref()
+    }
+
+    pub fn plan_time(&self) -> &str {
+        &self.plan_time
+    }
+
+    pub fn preview(&self) -> u64 {
+        self.preview
+    }
+
+    pub fn apply(&self) -> bool {
+        self.apply
+    }
+
+    pub fn battery_capacity(&self) -> f64 {
+        self.battery_capacity
+    }
+
+    pub fn charge_power(&self) -> f64 {
+        self.charge_power
+    }
+
+    pub fn min_soc(&self) -> u16 {
+        self.min_soc
+    }
+
+    pub fn max_soc(&self) -> u16 {
+        self.max_soc
+    }
+
+    pub fn window_start(&self) -> &str {
+        &self.window_start
+    }
+
+    pub fn window_end(&self) -> &str {
+        &self.window_end
+    }
+
+    pub fn slot(&self) -> u16 {
+        self.slot
+    }
+
+    pub fn history_days(&self) -> u32 {
+        self.history_days
+    }
+
+    pub fn default_consumption(&self) -> f64 {
+        self.default_consumption
+    }
+} // }}}
+
+// Rules {{{
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Rules {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Evaluate and log every rule without sending any commands
+    #[serde(default)]
+    pub dry_run: bool,
+
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+impl Rules {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn rules(&self) -> &Vec<Rule> {
+        &self.rules
+    }
+}
+
+/// Fires `actions` when every condition holds, and `release_actions` once they stop holding.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    /// Used in logs and MQTT topics
+    pub name: String,
+
+    #[serde(default = "Config::default_enabled")]
+    pub enabled: bool,
+
+    /// Only evaluate for this inverter; every enabled inverter when unset
+    #[serde(default, deserialize_with = "de_serial")]
+    pub datalog: Option<Serial>,
+
+    /// Thresholds on input fields, all of which must hold
+    #[serde(default)]
+    pub conditions: Vec<RuleCondition>,
+
+    /// Weekdays (mon, tue, ...) the rule may fire on; empty means every day
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Local time window, `HH:MM`; the end is exclusive and may be past midnight
+    pub start_time: Option<String>,
+    pub end_time: Option<String>,
+
+    /// How far past its threshold a field must go back before an active rule releases
+    #[serde(default)]
+    pub hysteresis: f64,
+
+    /// Minimum seconds between two firings
+    #[serde(default)]
+    pub cooldown: u64,
+
+    /// Evaluate and log this rule without sending its commands
+    #[serde(default)]
+    pub dry_run: bool,
+
+    pub actions: Vec<crate::json_command::JsonCommand>,
+    #[serde(default)]
+    pub release_actions: Vec<crate::json_command::JsonCommand>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RuleCondition {
+    /// A field of the complete input set, e.g. `soc` or `t_bat`
+    pub field: String,
+    pub above: Option<f64>,
+    pub below: Option<f64>,
+} // }}}
+
+// WritePolicy {{{
+#[derive(Clone, Debug, Deserialize)]
+pub struct WritePolicy {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Only these hold registers may be written; every register when empty
+    #[serde(default)]
+    pub allow: Vec<RegisterRange>,
+    /// Hold registers that may never be written
+    #[serde(default)]
+    pub deny: Vec<RegisterRange>,
+
+    /// Hold registers whose writes must first be confirmed with `confirm_token`
+    #[serde(default = "Config::default_write_policy_dangerous")]
+    pub dangerous: Vec<RegisterRange>,
+    /// Token expected on `cmd/{datalog}/confirm/hold/{register}`; dangerous writes are
+    /// always rejected without one
+    pub confirm_token: Option<String>,
+    /// Seconds a confirmation stays good for
+    #[serde(default = "Config::default_write_policy_confirm_timeout")]
+    pub confirm_timeout: u64,
+
+    /// Minimum seconds between writes of one register on one inverter; 0 for no limit
+    #[serde(default)]
+    pub min_interval: u64,
+
+    /// Bounds replacing, or adding to, those of the register map
+    #[serde(default)]
+    pub limits: Vec<RegisterLimit>,
+}
+impl Default for WritePolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allow: Vec::new(),
+            deny: Vec::new(),
+            dangerous: Config::default_write_policy_dangerous(),
+            confirm_token: None,
+            confirm_timeout: Config::default_write_policy_confirm_timeout(),
+            min_interval: 0,
+            limits: Vec::new(),
+        }
+    }
+}
+impl WritePolicy {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn allowed(&self, register: u16) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|r| r.contains(register)))
+            && !self.deny.iter().any(|r| r.contains(register))
+    }
+
+    pub fn dangerous(&self, register: u16) -> bool {
+        self.dangerous.iter().any(|r| r.contains(register))
+    }
+
+    pub fn confirm_token(&self) -> Option<&str> {
+        self.confirm_token.as_deref()
+    }
+
+    pub fn confirm_timeout(&self) -> u64 {
+        self.confirm_timeout
+    }
+
+    pub fn min_interval(&self) -> u64 {
+        self.min_interval
+    }
+
+    pub fn limits(&self) -> &Vec<RegisterLimit> {
+        &self.limits
+    }
+}
+
+// Audit {{{
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Audit {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Append-only JSON lines file of writes; they go to the databases either way
+    pub file: Option<String>,
+}
+impl Audit {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+} // }}}
+
+// Alerts {{{
+#[derive(Clone, Debug, Deserialize)]
+pub struct Alerts {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Seconds without input data before an inverter is reported stale; 0 to never
+    #[serde(default = "Config::default_alerts_stale_after")]
+    pub stale_after: u64,
+    /// Seconds before a still-active alert is sent again; 0 to send it once
+    #[serde(default)]
+    pub repeat: u64,
+
+    /// Local time window, `HH:MM`, in which only alerts of `quiet_severity` are sent; the end
+    /// is exclusive and may be past midnight
+    pub quiet_start: Option<String>,
+    pub quiet_end: Option<String>,
+    #[serde(default = "Config::default_alerts_quiet_severity")]
+    pub quiet_severity: Severity,
+
+    #[serde(default)]
+    pub notifiers: Vec<Notifier>,
+}
+impl Default for Alerts {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            stale_after: Config::default_alerts_stale_after(),
+            repeat: 0,
+            quiet_start: None,
+            quiet_end: None,
+            quiet_severity: Config::default_alerts_quiet_severity(),
+            notifiers: Vec::new(),
+        }
+    }
+}
+impl Alerts {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn stale_after(&self) -> u64 {
+        self.stale_after
+    }
+
+    pub fn repeat(&self) -> u64 {
+        self.repeat
+    }
+
+    /// Quiet hours as minutes of the day, when both ends are set
+    pub fn quiet_hours(&self) -> Option<(u32, u32)> {
+        let start = crate::rules::minute_of_day(self.quiet_start.as_deref()?)?;
+        let end = crate::rules::minute_of_day(self.quiet_end.as_deref()?)?;
+        Some((start, end))
+    }
+
+    pub fn quiet_severity(&self) -> Severity {
+        self.quiet_severity
+    }
+
+    pub fn notifiers(&self) -> &Vec<Notifier> {
+        &self.notifiers
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Warning,
+    Critical,
+}
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Warning => "warning",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Notifier {
+    #[serde(flatten)]
+    pub kind: NotifierKind,
+    /// Least severe alert sent to this notifier
+    #[serde(default = "Config::default_alerts_min_severity")]
+    pub min_severity: Severity,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierKind {
+    /// POSTs each alert as JSON
+    Webhook { url: String },
+    /// ntfy topic URL, e.g. `https://ntfy.sh/my-inverter`
+    Ntfy { url: String, token: Option<String> },
+    /// Gotify message URL, e.g. `https://gotify.example.com/message`
+    Gotify { url: String, token: String },
+    /// SMTP, secured with STARTTLS unless `tls` says otherwise
+    Smtp {
+        host: String,
+        #[serde(default = "Config::default_alerts_smtp_port")]
+        port: u16,
+        #[serde(default = "Config::default_alerts_smtp_tls")]
+        tls: SmtpTls,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+/// How an SMTP notifier secures its connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Upgrade a plain connection with STARTTLS, which the server must offer
+    Starttls,
+    /// TLS from the start, usually on port 465
+    Tls,
+    /// No TLS, and so no login
+    None,
+}
+
+impl NotifierKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Webhook { .. } => "webhook",
+            Self::Ntfy { .. } => "ntfy",
+            Self::Gotify { .. } => "gotify",
+            Self::Smtp { .. } => "smtp",
+        }
+    }
+} // }}}
+
+// BatteryHealth {{{
+#[derive(Clone, Debug, Deserialize)]
+pub struct BatteryHealth {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Spread between the highest and lowest cell voltage, in volts, that raises the alarm
+    #[serde(default = "Config::default_battery_health_cell_spread_alarm")]
+    pub cell_spread_alarm: f64,
+    /// How far below `cell_spread_alarm` the spread must fall to clear the alarm
+    #[serde(default = "Config::default_battery_health_cell_spread_hysteresis")]
+    pub cell_spread_hysteresis: f64,
+
+    /// SOC change, in percent, over which usable capacity is estimated
+    #[serde(default = "Config::default_battery_health_capacity_soc_delta")]
+    pub capacity_soc_delta: u16,
+}
+impl Default for BatteryHealth {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cell_spread_alarm: Config::default_battery_health_cell_spread_alarm(),
+            cell_spread_hysteresis: Config::default_battery_health_cell_spread_hysteresis(),
+            capacity_soc_delta: Config::default_battery_health_capacity_soc_delta(),
+        }
+    }
+}
+impl BatteryHealth {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn cell_spread_alarm(&self) -> f64 {
+        self.cell_spread_alarm
+    }
+
+    pub fn cell_spread_hysteresis(&self) -> f64 {
+        self.cell_spread_hysteresis
+    }
+
+    pub fn capacity_soc_delta(&self) -> u16 {
+        self.capacity_soc_delta
+    }
+} // }}}
+
+/// One register, `25`, or an inclusive range, `"25-53"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RegisterRangeSpec")]
+pub struct RegisterRange {
+    pub first: u16,
+    pub last: u16,
+}
+impl RegisterRange {
+    pub fn contains(&self, register: u16) -> bool {
+        (self.first..=self.last).contains(&register)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RegisterRangeSpec {
+    One(u16),
+    Range(String),
+}
+impl TryFrom<RegisterRangeSpec> for RegisterRange {
+    type Error = String;
+
+    fn try_from(spec: RegisterRangeSpec) -> Result<Self, Self::Error> {
+        let (first, last) = match spec {
+            RegisterRangeSpec::One(register) => (register, register),
+            RegisterRangeSpec::Range(s) => {
+                let parse = |r: &str| r.trim().parse::<u16>().map_err(|_| format!("invalid register range {}", s));
+                match s.split_once('-') {
+                    Some((first, last)) => (parse(first)?, parse(last)?),
+                    None => (parse(&s)?, parse(&s)?),
+                }
+            }
+        };
+        if first > last {
+            return Err(format!("register range {}-{} is backwards", first, last));
+        }
+        Ok(Self { first, last })
+    }
+}
+
+/// Bounds of one hold register, in the units of the register map (raw values for
+/// registers it does not describe).
+#[derive(Clone, Debug, Deserialize)]
+pub struct RegisterLimit {
+    pub register: u16,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Values must be a whole number of steps above `min` (or 0)
+    pub step: Option<f64>,
+} // }}}
+
+#[derive(Clone)]
+pub struct ConfigWrapper(Arc<Mutex<Config>>);
+
+impl ConfigWrapper {
+    pub fn new(file: String) -> Result<Self> {
+        let config = Config::new(file)?;
+        Ok(Self(Arc::new(Mutex::new(config))))
+    }
+
+    pub fn from_config(config: Config) -> Self {
+        Self(Arc::new(Mutex::new(config)))
+    }
+
+    pub fn register_read_interval(&self) -> Option<u64> {
+        self.0.lock().unwrap().register_read_interval.into()
+    }
+
+    pub fn inverter_timeout(&self) -> u64 {
+        self.0.lock().unwrap().inverter_timeout
+    }
+
+    pub fn inverters(&self) -> Vec<Inverter> {
+        self.0.lock().unwrap().inverters.clone()
+    }
+
+    pub fn set_inverters(&self, new: Vec<Inverter>) {
+        self.0.lock().unwrap().inverters = new;
+    }
+
+    pub fn enabled_inverters(&self) -> Vec<Inverter> {
+        self.inverters().into_iter().filter(|i| i.enabled()).collect()
+    }
+
+    pub fn groups(&self) -> Vec<Group> {
+        self.0.lock().unwrap().groups.clone()
+    }
+
+    pub fn group(&self, name: &str) -> Option<Group> {
+        self.groups().into_iter().find(|g| g.name() == name)
+    }
+
+    pub fn inverter_with_host(&self, host: &str) -> Option<Inverter> {
+        self.inverters().into_iter().find(|i| i.host() == host)
+    }
+
+    pub fn enabled_inverter_with_datalog(&self, datalog: Serial) -> Option<Inverter> {
+        self.enabled_inverters()
+            .into_iter()
+            .find(|i| i.datalog() == Some(datalog))
+    }
+
+    pub fn enabled_inverter_with_serial(&self, serial: Serial) -> Option<Inverter> {
+        self.enabled_inverters()
+            .into_iter()
+            .find(|i| i.serial() == Some(serial))
+    }
+
+    pub fn inverters_for_message(&self, message: &mqtt::Message) -> Result<Vec<Inverter>> {
+        let (target_inverter, _) = message.split_cmd_topic()?;
+        let inverters = self.enabled_inverters();
+
+        match target_inverter {
+            mqtt::TargetInverter::All => Ok(inverters),
+            mqtt::TargetInverter::Serial(datalog) => Ok(inverters
+                .into_iter()
+                .filter(|i| i.datalog() == Some(datalog))
+                .collect()),
+            // every member or none, so a group is never left half changed
+            mqtt::TargetInverter::Group(name) => {
+                let group = self.group(&name).ok_or_else(|| anyhow!("unknown inverter group {}", name))?;
+                group
+                    .members()
+                    .iter()
+                    .map(|datalog| {
+                        inverters
+                            .iter()
+                            .find(|i| i.datalog() == Some(*datalog))
+                            .cloned()
+                            .ok_or_else(|| anyhow!("member {} of group {} is not enabled", datalog, name))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    pub fn mqtt(&self) -> Mqtt {
+        self.0.lock().unwrap().mqtt.clone()
+    }
+
+    pub fn influx(&self) -> Influx {
+        self.0.lock().unwrap().influx.clone()
+    }
+
+    pub fn databases(&self) -> Vec<Database> {
+        self.0.lock().unwrap().databases.clone()
     }
 
     pub fn set_databases(&self, new: Vec<Database>) {
@@ -431,28 +1796,68 @@ impl ConfigWrapper {
         Err(anyhow::anyhow!("config.rs:Inverter with datalog {} not found", old_datalog))
     }
 
-    pub fn homeassistant_enabled(&self) -> bool {
-        self.0.lock().unwrap().homeassistant_enabled
+    pub fn homeassistant_enabled(&self) -> bool {
+        self.0.lock().unwrap().homeassistant_enabled
+    }
+
+    pub fn datalog_file(&self) -> Option<String> {
+        self.0.lock().unwrap().datalog_file.clone()
+    }
+
+    pub fn strict_data_check(&self) -> bool {
+        self.0.lock().unwrap().strict_data_check
+    }
+
+    pub fn register_file(&self) -> Option<String> {
+        self.0.lock().unwrap().register_file.clone()
+    }
+
+    pub fn verbose(&self) -> bool {
+        self.0.lock().unwrap().verbose
+    }
+
+    pub fn human_timestamps(&self) -> bool {
+        self.0.lock().unwrap().human_timestamps
+    }
+
+    pub fn daily_summary(&self) -> bool {
+        self.0.lock().unwrap().daily_summary
+    }
+
+    pub fn tariff(&self) -> Tariff {
+        self.0.lock().unwrap().tariff.clone()
     }
 
-    pub fn datalog_file(&self) -> Option<String> {
-        self.0.lock().unwrap().datalog_file.clone()
+    pub fn rules(&self) -> Rules {
+        self.0.lock().unwrap().rules.clone()
     }
 
-    pub fn strict_data_check(&self) -> bool {
-        self.0.lock().unwrap().strict_data_check
+    pub fn export_limit(&self) -> ExportLimit {
+        self.0.lock().unwrap().export_limit.clone()
     }
 
-    pub fn register_file(&self) -> Option<String> {
-        self.0.lock().unwrap().register_file.clone()
+    pub fn peak_shaving(&self) -> PeakShaving {
+        self.0.lock().unwrap().peak_shaving.clone()
     }
 
-    pub fn verbose(&self) -> bool {
-        self.0.lock().unwrap().verbose
+    pub fn charge_planner(&self) -> ChargePlanner {
+        self.0.lock().unwrap().charge_planner.clone()
     }
 
-    pub fn human_timestamps(&self) -> bool {
-        self.0.lock().unwrap().human_timestamps
+    pub fn write_policy(&self) -> WritePolicy {
+        self.0.lock().unwrap().write_policy.clone()
+    }
+
+    pub fn audit(&self) -> Audit {
+        self.0.lock().unwrap().audit.clone()
+    }
+
+    pub fn alerts(&self) -> Alerts {
+        self.0.lock().unwrap().alerts.clone()
+    }
+
+    pub fn battery_health(&self) -> BatteryHealth {
+        self.0.lock().unwrap().battery_health.clone()
     }
 
     pub fn show_unknown(&self) -> bool {
@@ -483,7 +1888,12 @@ impl Config {
         let content = std::fs::read_to_string(&file)
             .map_err(|err| anyhow!("config.rs:error reading {}: {}", file, err))?;
 
-        let config: Self = serde_yaml::from_str(&content)?;
+        let mut config: Self = serde_yaml::from_str(&content)?;
+
+        // inverters without their own dry_run follow the global one
+        for inverter in config.inverters.iter_mut() {
+            inverter.dry_run = Some(inverter.dry_run.unwrap_or(config.dry_run));
+        }
         
         // Log configuration details
         info!("Configuration loaded successfully:");
@@ -503,6 +1913,11 @@ impl Config {
             info!("      Register Block Size: {}", inv.register_block_size.unwrap_or(40));
             info!("      Delay MS: {}ms", inv.delay_ms.unwrap_or(1000));
             info!("      Read Only: {}", inv.read_only.unwrap_or(false));
+            info!("      Dry Run: {}", inv.dry_run());
+        }
+        for group in &config.groups {
+            let members: Vec<String> = group.members.iter().map(|s| s.to_string()).collect();
+            info!("  Group {}: {}", group.name, members.join(", "));
         }
 
         info!("  MQTT: {}", if config.mqtt.enabled { "enabled" } else { "disabled" });
@@ -510,13 +1925,45 @@ impl Config {
             info!("    Host: {}", config.mqtt.host);
             info!("    Port: {}", config.mqtt.port);
             info!("    Namespace: {}", config.mqtt.namespace);
+            info!("    Client ID: {}", config.mqtt.client_id);
+            info!("    Protocol: {}", config.mqtt.protocol);
+            info!("    TLS: {}", if config.mqtt.tls().is_some() { "enabled" } else { "disabled" });
+            info!("    Keepalive: {}s", config.mqtt.keepalive);
+            info!("    Clean Session: {}", config.mqtt.clean_session);
+            info!("    Publish Fields: {}", config.mqtt.publish_fields);
+            if config.mqtt.outbox.enabled {
+                info!(
+                    "    Outbox: {} messages, keep {}{}",
+                    config.mqtt.outbox.max_messages,
+                    config.mqtt.outbox.policy,
+                    config.mqtt.outbox.file.as_deref().map(|f| format!(", in {}", f)).unwrap_or_default()
+                );
+            } else {
+                info!("    Outbox: disabled");
+            }
+            if config.mqtt.deadband.enabled {
+                info!("    Deadband: enabled, max age {}s", config.mqtt.deadband.max_age);
+            }
             info!("    Home Assistant: {}", if config.mqtt.homeassistant.enabled { "enabled" } else { "disabled" });
+            if config.mqtt.homeassistant.enabled {
+                info!("      Status Topic: {}", config.mqtt.homeassistant.status_topic);
+                info!("      Remove Stale: {}", config.mqtt.homeassistant.remove_stale);
+            }
         }
 
         info!("  InfluxDB: {}", if config.influx.enabled { "enabled" } else { "disabled" });
         if config.influx.enabled {
             info!("    URL: {}", config.influx.url);
-            info!("    Database: {}", config.influx.database);
+            info!("    API Version: {}", config.influx.api_version);
+            if config.influx.api_version == 1 {
+                info!("    Database: {}", config.influx.database);
+            } else {
+                info!("    Org: {}", config.influx.org.as_deref().unwrap_or_default());
+                info!("    Bucket: {}", config.influx.bucket());
+            }
+            info!("    Precision: {}", config.influx.precision);
+            info!("    Gzip: {}", config.influx.gzip);
+            info!("    Batch Size: {}", config.influx.batch_size());
         }
 
         info!("  Databases: {} configured, {} enabled",
@@ -527,6 +1974,9 @@ impl Config {
             info!("    Database[{}]:", i);
             info!("      Enabled: {}", db.enabled);
             info!("      URL: {}", db.url);
+            info!("      Batch Size: {}", db.batch_size());
+            info!("      Retention: {}", db.retention_days.map(|d| format!("{} days", d)).unwrap_or_else(|| "forever".to_string()));
+            info!("      Rollups: {}", db.rollups);
         }
 
         info!("  Scheduler: {}", if config.scheduler.is_some() { "enabled" } else { "disabled" });
@@ -537,7 +1987,81 @@ impl Config {
             }
         }
 
+        info!("  Daily Summary: {}", if config.daily_summary { "enabled" } else { "disabled" });
+        info!("  Tariff: {}", if config.tariff.enabled { "enabled" } else { "disabled" });
+        if config.tariff.enabled {
+            info!("    Rates: {}", config.tariff.rates.len());
+            info!("    Fixed Daily: {}", config.tariff.fixed_daily);
+            info!("    Fixed Monthly: {}", config.tariff.fixed_monthly);
+        }
+
+        info!("  Rules: {}", if config.rules.enabled { "enabled" } else { "disabled" });
+        if config.rules.enabled {
+            info!("    Rules: {}", config.rules.rules.len());
+            info!("    Dry Run: {}", config.rules.dry_run);
+        }
+
+        info!("  Export Limit: {}", if config.export_limit.enabled { "enabled" } else { "disabled" });
+        if config.export_limit.enabled {
+            info!("    Max Export: {} W", config.export_limit.max_export);
+            info!("    Method: {}", config.export_limit.method);
+            info!("    Min Write Interval: {}s", config.export_limit.min_write_interval);
+            info!("    Data Timeout: {}s", config.export_limit.data_timeout);
+        }
+
+        info!("  Peak Shaving: {}", if config.peak_shaving.enabled { "enabled" } else { "disabled" });
+        if config.peak_shaving.enabled {
+            info!("    Method: {}", config.peak_shaving.method);
+            info!("    Windows: {}", config.peak_shaving.windows.len());
+            info!("    Recharge Windows: {}", config.peak_shaving.recharge.len());
+            info!("    Max Writes Per Hour: {}", config.peak_shaving.max_writes_per_hour);
+        }
+
+        info!("  Charge Planner: {}", if config.charge_planner.enabled { "enabled" } else { "disabled" });
+        if config.charge_planner.enabled {
+            let cp = &config.charge_planner;
+            info!(
+                "    Forecast: {}",
+                cp.forecast_url.as_deref().or(cp.forecast_file.as_deref()).unwrap_or_default()
+            );
+            info!("    Plan Time: {}", cp.plan_time);
+            info!("    Window: {}-{}", cp.window_start, cp.window_end);
+            info!("    Apply: {}", cp.apply);
+        }
+
+        info!("  Write Policy: {}", if config.write_policy.enabled { "enabled" } else { "disabled" });
+        if config.write_policy.enabled {
+            let wp = &config.write_policy;
+            info!("    Allow: {:?}", wp.allow);
+            info!("    Deny: {:?}", wp.deny);
+            info!("    Dangerous: {:?}", wp.dangerous);
+            info!("    Confirm Token: {}", if wp.confirm_token.is_some() { "set" } else { "unset" });
+            info!("    Min Interval: {}s", wp.min_interval);
+        }
+        info!("  Audit: {}", if config.audit.enabled { "enabled" } else { "disabled" });
+        if config.audit.enabled {
+            info!("    File: {}", config.audit.file.as_deref().unwrap_or("none"));
+        }
+        info!("  Alerts: {}", if config.alerts.enabled { "enabled" } else { "disabled" });
+        if config.alerts.enabled {
+            let alerts = &config.alerts;
+            info!("    Stale After: {}s", alerts.stale_after);
+            info!("    Repeat: {}s", alerts.repeat);
+            if let (Some(start), Some(end)) = (&alerts.quiet_start, &alerts.quiet_end) {
+                info!("    Quiet Hours: {}-{} ({:?} only)", start, end, alerts.quiet_severity);
+            }
+            for notifier in &alerts.notifiers {
+                info!("    Notifier: {} ({:?} and above)", notifier.kind.name(), notifier.min_severity);
+            }
+        }
+        info!("  Battery Health: {}", if config.battery_health.enabled { "enabled" } else { "disabled" });
+        if config.battery_health.enabled {
+            let bh = &config.battery_health;
+            info!("    Cell Spread Alarm: {} V (hysteresis {} V)", bh.cell_spread_alarm, bh.cell_spread_hysteresis);
+            info!("    Capacity SOC Delta: {}%", bh.capacity_soc_delta);
+        }
         info!("  Global Read Only: {}", config.read_only);
+        info!("  Global Dry Run: {}", config.dry_run);
         info!("  Log Level: {}", config.loglevel);
 
         config.validate()?;
@@ -553,6 +2077,45 @@ impl Config {
             if self.mqtt.host.is_empty() {
                 return Err(anyhow!("config.rs:MQTT host cannot be empty"));
             }
+            if self.mqtt.client_id.is_empty() {
+                return Err(anyhow!("config.rs:MQTT client_id cannot be empty"));
+            }
+            if !matches!(self.mqtt.protocol.as_str(), "3.1.1" | "5") {
+                bail!("mqtt.protocol={} is invalid; must be 3.1.1 or 5", self.mqtt.protocol);
+            }
+            if self.mqtt.keepalive < 5 {
+                bail!("mqtt.keepalive={} is invalid; must be at least 5 seconds", self.mqtt.keepalive);
+            }
+            if let Some(tls) = self.mqtt.tls() {
+                if tls.port == 0 {
+                    bail!("mqtt.tls.port must be between 1 and 65535");
+                }
+                if tls.cert_file.is_some() != tls.key_file.is_some() {
+                    bail!("mqtt.tls.cert_file and mqtt.tls.key_file must be set together");
+                }
+                if tls.cert_file.is_some() && tls.ca_file.is_none() {
+                    bail!("mqtt.tls.ca_file is required with a client certificate");
+                }
+            }
+            if !matches!(self.mqtt.outbox.policy.as_str(), "all" | "latest") {
+                bail!("mqtt.outbox.policy={} is invalid; must be all or latest", self.mqtt.outbox.policy);
+            }
+            if self.mqtt.outbox.max_messages == 0 {
+                bail!("mqtt.outbox.max_messages must be at least 1");
+            }
+            if self.mqtt.deadband.max_age == 0 {
+                bail!("mqtt.deadband.max_age must be at least 1 second");
+            }
+            for (class, band) in self.mqtt.deadband.all() {
+                if band.absolute < 0.0 || band.percent < 0.0 {
+                    bail!("mqtt.deadband.{} must not be negative", class);
+                }
+            }
+            for (class, publish) in self.mqtt.publish.all() {
+                if publish.qos > 2 {
+                    bail!("mqtt.publish.{}.qos={} is invalid; must be 0, 1 or 2", class, publish.qos);
+                }
+            }
         }
 
         // Validate InfluxDB configuration
@@ -560,8 +2123,24 @@ impl Config {
             if let Err(e) = url::Url::parse(&self.influx.url) {
                 return Err(anyhow!("config.rs:Invalid InfluxDB URL: {}", e));
             }
-            if self.influx.database.is_empty() {
-                return Err(anyhow!("config.rs:InfluxDB database name cannot be empty"));
+            match self.influx.api_version {
+                1 => {
+                    if self.influx.database.is_empty() {
+                        return Err(anyhow!("config.rs:InfluxDB database name cannot be empty"));
+                    }
+                }
+                2 | 3 => {
+                    if self.influx.bucket().is_empty() {
+                        return Err(anyhow!("config.rs:InfluxDB bucket (or database) cannot be empty"));
+                    }
+                    if self.influx.api_version == 2 && self.influx.org.as_deref().unwrap_or_default().is_empty() {
+                        return Err(anyhow!("config.rs:InfluxDB org is required for api_version 2"));
+                    }
+                }
+                v => bail!("influx.api_version={} is invalid; must be 1, 2 or 3", v),
+            }
+            if !["s", "ms", "us", "ns"].contains(&self.influx.precision.as_str()) {
+                bail!("influx.precision={} is invalid; must be one of s, ms, us, ns", self.influx.precision);
             }
         }
 
@@ -571,6 +2150,9 @@ impl Config {
                 if let Err(e) = url::Url::parse(db.url()) {
                     return Err(anyhow!("config.rs:Invalid database URL: {}", e));
                 }
+                if db.retention_days == Some(0) {
+                    return Err(anyhow!("config.rs:Database retention_days must be at least 1"));
+                }
             }
         }
 
@@ -609,6 +2191,246 @@ impl Config {
             }
         }
 
+        // Validate tariff configuration
+        if self.tariff.enabled {
+            if self.tariff.rates.is_empty() {
+                bail!("tariff.rates must have at least one rate when the tariff is enabled");
+            }
+            for (i, rate) in self.tariff.rates.iter().enumerate() {
+                if rate.start_hour >= rate.end_hour || rate.end_hour > 24 {
+                    bail!(
+                        "tariff.rates[{}] hours {}-{} are invalid; need 0 <= start_hour < end_hour <= 24",
+                        i, rate.start_hour, rate.end_hour
+                    );
+                }
+                for day in &rate.days {
+                    if chrono::Weekday::from_str(day).is_err() {
+                        bail!("tariff.rates[{}].days contains invalid weekday {}", i, day);
+                    }
+                }
+            }
+        }
+
+        // Validate rules
+        if self.rules.enabled {
+            let mut names = std::collections::HashSet::new();
+            for rule in &self.rules.rules {
+                if rule.name.is_empty() || rule.name.contains(['/', '+', '#']) {
+                    bail!("rules: name {:?} is invalid; must be non-empty without / + #", rule.name);
+                }
+                if !names.insert(&rule.name) {
+                    bail!("rules: duplicate rule name {}", rule.name);
+                }
+                if rule.actions.is_empty() {
+                    bail!("rules.{}: needs at least one action", rule.name);
+                }
+                for day in &rule.days {
+                    if chrono::Weekday::from_str(day).is_err() {
+                        bail!("rules.{}.days contains invalid weekday {}", rule.name, day);
+                    }
+                }
+                if rule.start_time.is_some() != rule.end_time.is_some() {
+                    bail!("rules.{}: start_time and end_time must be set together", rule.name);
+                }
+                for time in rule.start_time.iter().chain(rule.end_time.iter()) {
+                    if crate::rules::minute_of_day(time).is_none() {
+                        bail!("rules.{}: time {} is invalid, use HH:MM", rule.name, time);
+                    }
+                }
+                for condition in &rule.conditions {
+                    if condition.above.is_none() && condition.below.is_none() {
+                        bail!("rules.{}: condition on {} needs above or below", rule.name, condition.field);
+                    }
+                }
+                if rule.hysteresis < 0.0 {
+                    bail!("rules.{}.hysteresis must not be negative", rule.name);
+                }
+            }
+        }
+
+        // Validate export limit
+        if self.export_limit.enabled {
+            match self.export_limit.method.as_str() {
+                "power_limit" => {
+                    let Some(register) = self.export_limit.register else {
+                        bail!("export_limit.register is required for the power_limit method");
+                    };
+                    let file = self.register_file.clone().unwrap_or_else(|| "doc/eg4_registers.json".to_string());
+                    let registers = RegisterParser::new(&file)?;
+                    let unit = registers
+                        .registers_of_type("hold")
+                        .into_iter()
+                        .find(|r| r.register_number == register)
+                        .map(|r| r.unit.clone());
+                    match unit.as_deref() {
+                        Some("W") => {}
+                        Some("%") if self.export_limit.rated_power == 0 => {
+                            bail!("export_limit.rated_power is required for register {}, which is in %", register);
+                        }
+                        Some("%") => {}
+                        _ => bail!(
+                            "export_limit.register {} is not a hold register in W or % in {}",
+                            register,
+                            file
+                        ),
+                    }
+                }
+                "charge_rate" => {
+                    if self.export_limit.max_charge_power == 0 {
+                        bail!("export_limit.max_charge_power must be greater than 0");
+                    }
+                }
+                method => bail!(
+                    "export_limit.method {} is invalid; must be power_limit or charge_rate",
+                    method
+                ),
+            }
+            if self.export_limit.data_timeout == 0 {
+                bail!("export_limit.data_timeout must be greater than 0");
+            }
+        }
+
+        // Validate charge planner
+        if self.charge_planner.enabled {
+            let cp = &self.charge_planner;
+            if cp.forecast_file.is_none() == cp.forecast_url.is_none() {
+                bail!("charge_planner needs exactly one of forecast_file or forecast_url");
+            }
+            for time in [&cp.plan_time, &cp.window_start, &cp.window_end] {
+                if crate::rules::minute_of_day(time).is_none() {
+                    bail!("charge_planner: time {} is invalid, use HH:MM", time);
+                }
+            }
+            if cp.battery_capacity <= 0.0 || cp.charge_power <= 0.0 {
+                bail!("charge_planner.battery_capacity and charge_power must be greater than 0");
+            }
+            if cp.min_soc > cp.max_soc || cp.max_soc > 100 {
+                bail!("charge_planner: need min_soc <= max_soc <= 100");
+            }
+            if !(1..=3).contains(&cp.slot) {
+                bail!("charge_planner.slot must be 1-3, got {}", cp.slot);
+            }
+        }
+
+        // Validate write policy
+        if self.write_policy.enabled {
+            for limit in &self.write_policy.limits {
+                if let (Some(min), Some(max)) = (limit.min, limit.max) {
+                    if min > max {
+                        bail!("write_policy: register {} has min {} above max {}", limit.register, min, max);
+                    }
+                }
+                if limit.step.is_some_and(|step| step <= 0.0) {
+                    bail!("write_policy: register {} step must be greater than 0", limit.register);
+                }
+            }
+            if self.write_policy.confirm_token.as_deref() == Some("") {
+                bail!("write_policy.confirm_token must not be empty");
+            }
+        }
+
+        if self.audit.file.as_deref() == Some("") {
+            bail!("audit.file must not be empty");
+        }
+
+        // Validate groups
+        let mut names = std::collections::HashSet::new();
+        for group in &self.groups {
+            let name = &group.name;
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                bail!("group name '{}' must be letters, digits, _ or -", name);
+            }
+            // cmd/{name}/... would be taken for a datalog, or every inverter
+            if name.len() == 10 || name == "all" {
+                bail!("group name '{}' must not be \"all\" or 10 characters long", name);
+            }
+            if !names.insert(name) {
+                bail!("group '{}' is defined more than once", name);
+            }
+            if group.members.len() < 2 {
+                bail!("group '{}' needs at least two members", name);
+            }
+            let mut members = std::collections::HashSet::new();
+            for member in &group.members {
+                if !members.insert(member) {
+                    bail!("group '{}' lists {} more than once", name, member);
+                }
+                if !self.inverters.iter().any(|i| i.datalog == Some(*member)) {
+                    bail!("group '{}' member {} is not the datalog of a configured inverter", name, member);
+                }
+            }
+        }
+
+        // Validate battery health
+        if self.battery_health.enabled {
+            let bh = &self.battery_health;
+            if bh.cell_spread_alarm <= 0.0 {
+                bail!("battery_health.cell_spread_alarm must be greater than 0");
+            }
+            if bh.cell_spread_hysteresis < 0.0 || bh.cell_spread_hysteresis >= bh.cell_spread_alarm {
+                bail!("battery_health.cell_spread_hysteresis must be at least 0 and below cell_spread_alarm");
+            }
+            if !(1..=100).contains(&bh.capacity_soc_delta) {
+                bail!("battery_health.capacity_soc_delta must be between 1 and 100");
+            }
+        }
+
+        // Validate alerts
+        if self.alerts.enabled {
+            let alerts = &self.alerts;
+            if alerts.quiet_start.is_some() != alerts.quiet_end.is_some() {
+                bail!("alerts needs both quiet_start and quiet_end, or neither");
+            }
+            for time in alerts.quiet_start.iter().chain(alerts.quiet_end.iter()) {
+                if crate::rules::minute_of_day(time).is_none() {
+                    bail!("alerts: time {} is invalid, use HH:MM", time);
+                }
+            }
+            for notifier in &alerts.notifiers {
+                if let NotifierKind::Smtp { tls, username, to, .. } = &notifier.kind {
+                    if to.is_empty() {
+                        bail!("alerts: smtp notifier needs at least one to address");
+                    }
+                    if *tls == SmtpTls::None && username.is_some() {
+                        bail!("alerts: smtp notifier only logs in over TLS; set tls to starttls or tls");
+                    }
+                }
+            }
+        }
+
+        // Validate peak shaving
+        if self.peak_shaving.enabled {
+            let ps = &self.peak_shaving;
+            if !matches!(ps.method.as_str(), "discharge_rate" | "forced_discharge") {
+                bail!(
+                    "peak_shaving.method {} is invalid; must be discharge_rate or forced_discharge",
+                    ps.method
+                );
+            }
+            if ps.discharge_rate > 100 {
+                bail!("peak_shaving.discharge_rate must be 0-100, got {}", ps.discharge_rate);
+            }
+            if ps.max_writes_per_hour == 0 {
+                bail!("peak_shaving.max_writes_per_hour must be greater than 0");
+            }
+            let windows = ps.windows.iter().map(|w| &w.window).chain(ps.recharge.iter());
+            for (i, window) in windows.enumerate() {
+                for day in &window.days {
+                    if chrono::Weekday::from_str(day).is_err() {
+                        bail!("peak_shaving: window {} contains invalid weekday {}", i, day);
+                    }
+                }
+                if window.start_time.is_some() != window.end_time.is_some() {
+                    bail!("peak_shaving: window {} needs both start_time and end_time", i);
+                }
+                for time in window.start_time.iter().chain(window.end_time.iter()) {
+                    if crate::rules::minute_of_day(time).is_none() {
+                        bail!("peak_shaving: window {} time {} is invalid, use HH:MM", i, time);
+                    }
+                }
+            }
+        }
+
         Ok(())
     }
 
@@ -619,10 +2441,44 @@ impl Config {
         "lxp".to_string()
     }
 
+    fn default_mqtt_protocol() -> String {
+        "3.1.1".to_string()
+    }
+
+    fn default_mqtt_client_id() -> String {
+        "lxp-bridge".to_string()
+    }
+
+    fn default_mqtt_tls_port() -> u16 {
+        8883
+    }
+
+    fn default_mqtt_keepalive() -> u64 {
+        60
+    }
+
+    fn default_mqtt_qos() -> u8 {
+        1
+    }
+
+    fn default_mqtt_outbox_max_messages() -> usize {
+        10000
+    }
+
+    fn default_mqtt_outbox_policy() -> String {
+        "all".to_string()
+    }
+
+    fn default_mqtt_deadband_max_age() -> u64 {
+        300
+    }
+
     fn default_mqtt_homeassistant() -> HomeAssistant {
         HomeAssistant {
             enabled: Self::default_enabled(),
             prefix: Self::default_mqtt_homeassistant_prefix(),
+            status_topic: Self::default_mqtt_homeassistant_status_topic(),
+            remove_stale: false,
         }
     }
 
@@ -630,6 +2486,10 @@ impl Config {
         "homeassistant".to_string()
     }
 
+    fn default_mqtt_homeassistant_status_topic() -> String {
+        "homeassistant/status".to_string()
+    }
+
     fn default_enabled() -> bool {
         true
     }
@@ -665,6 +2525,177 @@ impl Config {
     fn default_inverter_timeout() -> u64 {
         300
     }
+
+    fn default_tariff_end_hour() -> u32 {
+        24
+    }
+
+    fn default_export_limit_margin() -> u32 {
+        100
+    }
+
+    fn default_export_limit_method() -> String {
+        "power_limit".to_string()
+    }
+
+    fn default_export_limit_max_charge_power() -> u32 {
+        5000
+    }
+
+    fn default_export_limit_min_step() -> u32 {
+        100
+    }
+
+    fn default_export_limit_min_write_interval() -> u64 {
+        60
+    }
+
+    fn default_export_limit_data_timeout() -> u64 {
+        60
+    }
+
+    fn default_charge_planner_plan_time() -> String {
+        "21:00".to_string()
+    }
+
+    fn default_charge_planner_preview() -> u64 {
+        300
+    }
+
+    fn default_charge_planner_charge_power() -> f64 {
+        3.0
+    }
+
+    fn default_charge_planner_min_soc() -> u16 {
+        20
+    }
+
+    fn default_charge_planner_max_soc() -> u16 {
+        100
+    }
+
+    fn default_charge_planner_window_start() -> String {
+        "23:00".to_string()
+    }
+
+    fn default_charge_planner_window_end() -> String {
+        "06:00".to_string()
+    }
+
+    fn default_charge_planner_slot() -> u16 {
+        1
+    }
+
+    fn default_charge_planner_history_days() -> u32 {
+        7
+    }
+
+    fn default_write_policy_dangerous() -> Vec<RegisterRange> {
+        // grid protection settings
+        vec![RegisterRange { first: 25, last: 53 }]
+    }
+
+    fn default_battery_health_cell_spread_alarm() -> f64 {
+        0.1
+    }
+
+    fn default_battery_health_cell_spread_hysteresis() -> f64 {
+        0.02
+    }
+
+    fn default_battery_health_capacity_soc_delta() -> u16 {
+        20
+    }
+
+    fn default_alerts_stale_after() -> u64 {
+        300
+    }
+
+    fn default_group_stale_after() -> u64 {
+        300
+    }
+
+    fn default_alerts_quiet_severity() -> Severity {
+        Severity::Critical
+    }
+
+    fn default_alerts_min_severity() -> Severity {
+        Severity::Warning
+    }
+
+    fn default_alerts_smtp_port() -> u16 {
+        25
+    }
+
+    fn default_alerts_smtp_tls() -> SmtpTls {
+        SmtpTls::Starttls
+    }
+
+    fn default_write_policy_confirm_timeout() -> u64 {
+        60
+    }
+
+    fn default_peak_shaving_method() -> String {
+        "discharge_rate".to_string()
+    }
+
+    fn default_peak_shaving_discharge_rate() -> u16 {
+        100
+    }
+
+    fn default_peak_shaving_approach() -> u32 {
+        500
+    }
+
+    fn default_peak_shaving_hysteresis() -> u32 {
+        500
+    }
+
+    fn default_peak_shaving_max_writes_per_hour() -> u32 {
+        6
+    }
+
+    fn default_daily_summary() -> bool {
+        true
+    }
+
+    fn default_influx_api_version() -> u8 {
+        1
+    }
+
+    fn default_influx_precision() -> String {
+        "s".to_string()
+    }
+
+    fn default_influx_batch_size() -> usize {
+        1
+    }
+
+    fn default_influx_flush_interval() -> u64 {
+        10
+    }
+
+    fn default_database_batch_size() -> usize {
+        1
+    }
+
+    fn default_database_flush_interval() -> u64 {
+        10
+    }
+
+    fn default_database_maintenance_interval() -> u64 {
+        300
+    }
+}
+
+fn de_serials<'de, D>(deserializer: D) -> Result<Vec<Serial>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|s| Serial::from_str(s).map_err(serde::de::Error::custom))
+        .collect()
 }
 
 fn de_serial<'de, D>(deserializer: D) -> Result<Option<Serial>, D::Error>
@@ -0,0 +1,394 @@
+//! Export limiting.
+//!
+//! Each complete input set compares grid export with `max_export`. Once it goes over, the
+//! controller steers the net export towards `margin` watts below the ceiling, either by
+//! lowering a power limit hold register (`power_limit`) or by raising the battery charge
+//! rate (`charge_rate`), and hands the setting back once the limit is no longer needed. A
+//! power limit register is scaled by its unit in the register map: watts, or a percentage
+//! of `rated_power`.
+//!
+//! Writes wear the inverter's EEPROM, so they are at least `min_write_interval` seconds
+//! apart and only made for changes of `min_step` watts or more. The setting in force before
+//! the first write is read back from the inverter and restored when export falls away, when
+//! input data stops for `data_timeout` seconds, and on shutdown.
+
+use crate::prelude::*;
+use crate::command::{Origin, Source};
+use crate::coordinator::commands::write_inverter::CHARGE_RATE_REGISTER;
+use crate::eg4::packet::{DeviceFunction, Packet, ReadInputAll};
+use crate::register::RegisterParser;
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+/// How often to check for inverters that stopped sending data.
+const EXPIRY_CHECK: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Read the controlled register, to learn the setting to restore
+    Read(Serial),
+    /// Write the controlled register
+    Write(Serial, u16),
+}
+
+/// Controller state for one inverter, as published.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Status {
+    /// whether the controller currently holds the setting changed
+    pub limiting: bool,
+    /// setting found before the controller first wrote
+    pub original: Option<u16>,
+    /// value last written while limiting
+    pub setting: Option<u16>,
+    /// net export in watts at the last input set
+    pub export: i64,
+}
+
+#[derive(Default)]
+struct State {
+    status: Status,
+    last_action: Option<Instant>,
+    last_input: Option<Instant>,
+}
+
+pub struct Limiter {
+    config: config::ExportLimit,
+    /// watts per raw step of the power limit register
+    watts_per_step: f64,
+    state: HashMap<Serial, State>,
+}
+
+impl Limiter {
+    /// The power limit register's unit and scaling are taken from `registers`; without
+    /// them it is taken as whole watts.
+    pub fn new(config: config::ExportLimit, registers: Option<&RegisterParser>) -> Self {
+        let register = registers.and_then(|registers| {
+            registers
+                .registers_of_type("hold")
+                .into_iter()
+                .find(|r| Some(r.register_number) == config.register())
+        });
+        let watts_per_step = match register {
+            Some(r) if r.unit == "%" => r.scaling * f64::from(config.rated_power()) / 100.0,
+            Some(r) => r.scaling,
+            None => 1.0,
+        };
+        Self {
+            config,
+            watts_per_step,
+            state: HashMap::new(),
+        }
+    }
+
+    /// The hold register the controller writes.
+    pub fn register(&self) -> u16 {
+        if self.config.charge_rate() {
+            CHARGE_RATE_REGISTER
+        } else {
+            self.config.register().unwrap_or_default()
+        }
+    }
+
+    pub fn status(&self, datalog: Serial) -> Option<&Status> {
+        self.state.get(&datalog).map(|s| &s.status)
+    }
+
+    /// Note a value of the controlled register read from the inverter. It is taken as the
+    /// setting to restore unless the controller is holding it changed.
+    pub fn observe(&mut self, datalog: Serial, value: u16) {
+        if !self.applies(datalog) {
+            return;
+        }
+        let status = &mut self.state.entry(datalog).or_default().status;
+        if !status.limiting {
+            status.original = Some(value);
+        }
+    }
+
+    /// Act on a complete input set.
+    pub fn update(&mut self, input: &ReadInputAll, now: Instant) -> Option<Action> {
+        if !self.applies(input.datalog) {
+            return None;
+        }
+
+        let export = i64::from(input.p_to_grid) - i64::from(input.p_to_user);
+        let target = i64::from(self.config.max_export()) - i64::from(self.config.margin());
+        let over = i64::from(input.p_to_grid) > i64::from(self.config.max_export());
+        let min_interval = Duration::from_secs(self.config.min_write_interval());
+
+        let known = self.state.get(&input.datalog);
+        let current = known.and_then(|s| s.status.setting);
+        let value = known
+            .and_then(|s| s.status.original)
+            .map(|original| (original, self.desired(input, original, export - target)));
+        let small_step = match (value, current) {
+            (Some((_, value)), Some(current)) => {
+                (self.watts(value) - self.watts(current)).abs() < i64::from(self.config.min_step())
+            }
+            _ => false,
+        };
+
+        let state = self.state.entry(input.datalog).or_default();
+        state.last_input = Some(now);
+        state.status.export = export;
+
+        if !state.status.limiting && !over {
+            return None;
+        }
+        if state.last_action.is_some_and(|t| now.duration_since(t) < min_interval) {
+            return None;
+        }
+        let Some((original, value)) = value else {
+            state.last_action = Some(now);
+            return Some(Action::Read(input.datalog));
+        };
+
+        let limiting = value != original;
+        if limiting && state.status.limiting && small_step {
+            return None;
+        }
+        if !limiting && !state.status.limiting {
+            return None;
+        }
+
+        state.last_action = Some(now);
+        state.status.limiting = limiting;
+        state.status.setting = limiting.then_some(value);
+        Some(Action::Write(input.datalog, value))
+    }
+
+    /// Restore the setting of inverters with no input data for `data_timeout` seconds.
+    pub fn expire(&mut self, now: Instant) -> Vec<Action> {
+        let timeout = Duration::from_secs(self.config.data_timeout());
+        let mut r = Vec::new();
+        for (datalog, state) in self.state.iter_mut() {
+            let stale = state.last_input.is_none_or(|t| now.duration_since(t) >= timeout);
+            if stale && state.status.limiting {
+                warn!("no input data from {} for {}s, restoring export limit setting", datalog, timeout.as_secs());
+                r.extend(Self::release(*datalog, state, now));
+            }
+        }
+        r
+    }
+
+    /// Restore the setting of every inverter the controller is limiting.
+    pub fn restore_all(&mut self, now: Instant) -> Vec<Action> {
+        self.state
+            .iter_mut()
+            .filter_map(|(datalog, state)| Self::release(*datalog, state, now))
+            .collect()
+    }
+
+    pub fn command(&self, inverter: config::Inverter, action: Action) -> Command {
+        match action {
+            Action::Read(_) => Command::ReadHold(inverter, self.register(), 1),
+            Action::Write(_, value) if self.config.charge_rate() => Command::ChargeRate(inverter, value),
+            Action::Write(_, value) => Command::SetHold(inverter, self.register(), value),
+        }
+    }
+
+    fn applies(&self, datalog: Serial) -> bool {
+        self.config.datalog().is_none_or(|d| d == datalog)
+    }
+
+    fn release(datalog: Serial, state: &mut State, now: Instant) -> Option<Action> {
+        if !state.status.limiting {
+            return None;
+        }
+        state.status.limiting = false;
+        state.status.setting = None;
+        state.last_action = Some(now);
+        state.status.original.map(|original| Action::Write(datalog, original))
+    }
+
+    /// Setting that would move net export down by `excess` watts, never less restrictive
+    /// than the original.
+    fn desired(&self, input: &ReadInputAll, original: u16, excess: i64) -> u16 {
+        if self.config.charge_rate() {
+            let max = i64::from(self.config.max_charge_power());
+            let charge = i64::from(input.p_charge) + excess;
+            let percent = (charge * 100 + max - 1).div_euclid(max);
+            percent.clamp(i64::from(original.min(100)), 100) as u16
+        } else {
+            let output = (i64::from(input.p_inv) - excess).max(0);
+            let steps = (output as f64 / self.watts_per_step).floor() as i64;
+            steps.clamp(0, i64::from(original)) as u16
+        }
+    }
+
+    /// A setting in watts.
+    fn watts(&self, value: u16) -> i64 {
+        if self.config.charge_rate() {
+            i64::from(value) * i64::from(self.config.max_charge_power()) / 100
+        } else {
+            (f64::from(value) * self.watts_per_step).round() as i64
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ExportLimit {
+    config: ConfigWrapper,
+    channels: Channels,
+    limiter: Arc<Mutex<Limiter>>,
+    shutdown: CancellationToken,
+}
+
+impl ExportLimit {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        let export_limit = config.export_limit();
+        // only the power limit method needs the register map
+        let registers = if export_limit.charge_rate() {
+            None
+        } else {
+            let file = config.register_file().unwrap_or_else(|| "doc/eg4_registers.json".to_string());
+            RegisterParser::new(&file)
+                .map_err(|e| warn!("export limit takes the register as watts, cannot load {}: {}", file, e))
+                .ok()
+        };
+        let limiter = Limiter::new(export_limit, registers.as_ref());
+        Self {
+            config,
+            channels,
+            limiter: Arc::new(Mutex::new(limiter)),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut inputs = self.channels.from_coordinator.subscribe();
+        let mut packets = self.channels.from_inverter.subscribe();
+        let mut expiry = tokio::time::interval(EXPIRY_CHECK);
+        info!("export limit started");
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                msg = inputs.recv() => match msg {
+                    Ok(coordinator::ChannelData::ReadInputAll(input)) => {
+                        let action = self.lock()?.update(&input, Instant::now());
+                        if let Some(action) = action {
+                            self.send(action, Source::new(Origin::Controller, "export_limit"));
+                        }
+                        if let Err(e) = self.publish(input.datalog) {
+                            warn!("Failed to publish export limit status: {}", e);
+                        }
+                    }
+                    Ok(coordinator::ChannelData::Shutdown) => break,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("export limit lagged, skipped {} messages", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                msg = packets.recv() => match msg {
+                    Ok(eg4::inverter::ChannelData::Packet(Packet::TranslatedData(td))) => {
+                        if matches!(td.device_function, DeviceFunction::ReadHold | DeviceFunction::WriteSingle) {
+                            let mut limiter = self.lock()?;
+                            let register = limiter.register();
+                            if let Some((_, value)) = td.pairs().into_iter().find(|(r, _)| *r == register) {
+                                limiter.observe(td.datalog, value);
+                            }
+                        }
+                    }
+                    Ok(eg4::inverter::ChannelData::Shutdown) => break,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("export limit lagged, skipped {} packets", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = expiry.tick() => {
+                    let actions = self.lock()?.expire(Instant::now());
+                    for action in actions {
+                        self.send(action, Source::restore(Origin::Controller, "export_limit"));
+                    }
+                }
+            }
+        }
+
+        for command in self.restore_commands() {
+            self.send_command(command, Source::restore(Origin::Controller, "export_limit"));
+        }
+        info!("export limit exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Commands restoring the original setting wherever the controller changed it.
+    pub fn restore_commands(&self) -> Vec<Command> {
+        let Ok(mut limiter) = self.lock() else {
+            return Vec::new();
+        };
+        limiter
+            .restore_all(Instant::now())
+            .into_iter()
+            .filter_map(|action| self.command(&limiter, action))
+            .collect()
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Limiter>> {
+        self.limiter
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock export limiter"))
+    }
+
+    fn command(&self, limiter: &Limiter, action: Action) -> Option<Command> {
+        let datalog = match action {
+            Action::Read(datalog) | Action::Write(datalog, _) => datalog,
+        };
+        match self.config.enabled_inverter_with_datalog(datalog) {
+            Some(inverter) => Some(limiter.command(inverter, action)),
+            None => {
+                warn!("export limit: no enabled inverter with datalog {}", datalog);
+                None
+            }
+        }
+    }
+
+    fn send(&self, action: Action, source: Source) {
+        let command = match self.lock() {
+            Ok(limiter) => self.command(&limiter, action),
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        };
+        if let Some(command) = command {
+            self.send_command(command, source);
+        }
+    }
+
+    fn send_command(&self, command: Command, source: Source) {
+        info!("export limit: {}", command.describe());
+        if let Err(e) = self
+            .channels
+            .to_coordinator
+            .send(coordinator::ChannelData::Command(command, source))
+        {
+            error!("Failed to send export limit command: {}", e);
+        }
+    }
+
+    fn publish(&self, datalog: Serial) -> Result<()> {
+        if !self.config.mqtt().enabled() {
+            return Ok(());
+        }
+        let Some(status) = self.lock()?.status(datalog).cloned() else {
+            return Ok(());
+        };
+        self.channels.to_mqtt.send(mqtt::ChannelData::Message(mqtt::Message {
+            topic: format!("{}/export_limit", datalog),
+            retain: true,
+            payload: serde_json::to_string(&status)?,
+        }))?;
+        Ok(())
+    }
+}
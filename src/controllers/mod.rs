@@ -0,0 +1,73 @@
+//! Closed-loop controllers that adjust inverter settings from live readings.
+//!
+//! A controller writes hold registers on its own, so it remembers the setting it found
+//! before its first write and hands back the commands that put it back. On shutdown the
+//! coordinator runs those before it stops processing commands.
+
+pub mod export_limit;
+pub mod peak_shaving;
+
+use crate::prelude::*;
+
+#[derive(Clone, Default)]
+pub struct Controllers {
+    pub export_limit: Option<export_limit::ExportLimit>,
+    pub peak_shaving: Option<peak_shaving::PeakShaving>,
+}
+
+impl Controllers {
+    pub fn new(config: &ConfigWrapper, channels: &Channels) -> Self {
+        let export_limit = config
+            .export_limit()
+            .enabled()
+            .then(|| export_limit::ExportLimit::new(config.clone(), channels.clone()));
+
+        let peak_shaving = config
+            .peak_shaving()
+            .enabled()
+            .then(|| peak_shaving::PeakShaving::new(config.clone(), channels.clone()));
+
+        Self {
+            export_limit,
+            peak_shaving,
+        }
+    }
+
+    pub fn start(&self) {
+        if let Some(export_limit) = self.export_limit.clone() {
+            tokio::spawn(async move {
+                if let Err(e) = export_limit.start().await {
+                    error!("Export limit task failed: {}", e);
+                }
+            });
+        }
+        if let Some(peak_shaving) = self.peak_shaving.clone() {
+            tokio::spawn(async move {
+                if let Err(e) = peak_shaving.start().await {
+                    error!("Peak shaving task failed: {}", e);
+                }
+            });
+        }
+    }
+
+    pub fn stop(&self) {
+        if let Some(export_limit) = &self.export_limit {
+            export_limit.stop();
+        }
+        if let Some(peak_shaving) = &self.peak_shaving {
+            peak_shaving.stop();
+        }
+    }
+
+    /// Commands restoring every setting a controller currently holds changed.
+    pub fn restore_commands(&self) -> Vec<Command> {
+        let mut r = Vec::new();
+        if let Some(export_limit) = &self.export_limit {
+            r.extend(export_limit.restore_commands());
+        }
+        if let Some(peak_shaving) = &self.peak_shaving {
+            r.extend(peak_shaving.restore_commands());
+        }
+        r
+    }
+}
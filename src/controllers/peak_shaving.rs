@@ -0,0 +1,474 @@
+//! Peak shaving.
+//!
+//! Inside a configured window, grid import (`p_to_user`) is held under the window's
+//! threshold by discharging the battery: once import comes within `approach` watts of the
+//! threshold the discharge rate is raised (`discharge_rate`) or forced discharge is enabled
+//! (`forced_discharge`). Shaving stops once import plus the battery's discharge, i.e. what
+//! would be imported without it, is `hysteresis` watts further below. In off-peak
+//! `recharge` windows AC charging is enabled, and put back when the window ends.
+//!
+//! Changes are sent to the coordinator as commands, like export limiting. Before its first
+//! change the controller reads the setting in force, the discharge rate or the AC charge
+//! and forced discharge bits of register 21, and that is what it puts back when shaving or
+//! recharging ends and on shutdown. A change counts as made once a reply from the inverter
+//! shows it; one not seen by the next input set is sent again, and the setting before it is
+//! only forgotten once its restore is seen. At most `max_writes_per_hour` writes are made per
+//! inverter; changes beyond that wait for the next input set after the budget frees up.
+
+use crate::prelude::*;
+use crate::command::{Origin, Source};
+use crate::coordinator::commands::write_inverter::DISCHARGE_RATE_REGISTER;
+use crate::eg4::packet::{DeviceFunction, Packet, ReadInputAll, Register, RegisterBit};
+
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+const HOUR: Duration = Duration::from_secs(3600);
+const REGISTER_21: u16 = Register::Register21 as u16;
+const AC_CHARGE: u16 = RegisterBit::AcChargeEnable as u16;
+const FORCED_DISCHARGE: u16 = RegisterBit::ForcedDischargeEnable as u16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    /// Start (true) or stop shaving
+    Shave(bool),
+    /// Enable AC charging, or put it back as it was
+    AcCharge(bool),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Read a hold register, to learn the setting to restore
+    Read(Serial, u16),
+    /// Make a change
+    Change(Serial, Change),
+}
+
+/// Controller state for one inverter, as published.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Status {
+    pub shaving: bool,
+    pub recharging: bool,
+    /// import threshold of the window in force
+    pub threshold: Option<u32>,
+    /// grid import in watts at the last input set
+    pub import: u32,
+    pub writes_last_hour: usize,
+    /// discharge rate found before shaving, restored afterwards
+    pub original_discharge_rate: Option<u16>,
+    /// AC charge bit found before recharging, restored afterwards
+    pub original_ac_charge: Option<bool>,
+    /// forced discharge bit found before shaving, restored afterwards
+    pub original_forced_discharge: Option<bool>,
+}
+
+#[derive(Default)]
+struct State {
+    status: Status,
+    writes: VecDeque<Instant>,
+    /// changes sent but not yet seen in a reply from the inverter
+    pending: Vec<Change>,
+}
+
+pub struct Shaver {
+    config: config::PeakShaving,
+    state: HashMap<Serial, State>,
+}
+
+impl Shaver {
+    pub fn new(config: config::PeakShaving) -> Self {
+        Self {
+            config,
+            state: HashMap::new(),
+        }
+    }
+
+    pub fn status(&self, datalog: Serial) -> Option<&Status> {
+        self.state.get(&datalog).map(|s| &s.status)
+    }
+
+    /// Note a hold register value read from, or written to, the inverter. A pending change
+    /// it shows is taken as made, and a setting the controller does not hold changed is
+    /// taken as the one to restore if none is known yet.
+    pub fn observe(&mut self, datalog: Serial, register: u16, value: u16) {
+        if self.config.datalog().is_some_and(|d| d != datalog) {
+            return;
+        }
+        let forced_discharge = self.config.forced_discharge();
+        let discharge_rate = self.config.discharge_rate();
+        let state = self.state.entry(datalog).or_default();
+
+        let status = &mut state.status;
+        state.pending.retain(|change| {
+            let Some((r, mask, expected)) = Self::expected(forced_discharge, discharge_rate, status, *change) else {
+                return true;
+            };
+            if r != register || value & mask != expected & mask {
+                return true;
+            }
+            Self::done(forced_discharge, status, *change);
+            false
+        });
+
+        let restoring = |change| state.pending.contains(&change);
+        if register == REGISTER_21 {
+            if !status.recharging && !restoring(Change::AcCharge(false)) {
+                status.original_ac_charge.get_or_insert(value & AC_CHARGE != 0);
+            }
+            if forced_discharge && !status.shaving && !restoring(Change::Shave(false)) {
+                status.original_forced_discharge.get_or_insert(value & FORCED_DISCHARGE != 0);
+            }
+        } else if register == DISCHARGE_RATE_REGISTER
+            && !forced_discharge
+            && !status.shaving
+            && !restoring(Change::Shave(false))
+        {
+            status.original_discharge_rate.get_or_insert(value);
+        }
+    }
+
+    /// Actions for a complete input set, within the write budget. Changes are taken as made
+    /// until a reply shows otherwise.
+    pub fn update(&mut self, input: &ReadInputAll, now: Instant) -> Vec<Action> {
+        if self.config.datalog().is_some_and(|d| d != input.datalog) {
+            return Vec::new();
+        }
+
+        let local = input.time.0.with_timezone(&chrono::Local);
+        let threshold = self
+            .config
+            .windows()
+            .iter()
+            .find(|w| w.window.contains(local))
+            .map(|w| w.threshold);
+        let off_peak = self.config.recharge().iter().any(|w| w.contains(local));
+        let approach = self.config.approach();
+        let hysteresis = self.config.hysteresis();
+        let max_writes = self.config.max_writes_per_hour() as usize;
+        let register = self.register();
+
+        let state = self.state.entry(input.datalog).or_default();
+        while state.writes.front().is_some_and(|t| now.duration_since(*t) >= HOUR) {
+            state.writes.pop_front();
+        }
+        let import = u32::from(input.p_to_user);
+        state.status.import = import;
+        state.status.threshold = threshold;
+
+        let shave = match threshold {
+            Some(threshold) if state.status.shaving => {
+                // what import would be with the battery idle
+                import + u32::from(input.p_discharge) + approach + hysteresis >= threshold
+            }
+            Some(threshold) => import + approach >= threshold,
+            None => false,
+        };
+        let recharge = off_peak && !shave;
+
+        // changes not seen by now are sent again, then stop charging before discharging
+        let mut wanted = std::mem::take(&mut state.pending);
+        for change in [Change::AcCharge(recharge), Change::Shave(shave)] {
+            if Self::current(&state.status, change) != change {
+                // a change not seen yet is overtaken by its undoing
+                wanted.retain(|c| *c != Self::opposite(change));
+                wanted.push(change);
+            }
+        }
+
+        let mut r = Vec::new();
+        let mut reads = Vec::new();
+        let mut deferred = false;
+        for change in wanted {
+            // learn what to put back before changing anything
+            let original_known = match change {
+                Change::Shave(_) if register == REGISTER_21 => state.status.original_forced_discharge.is_some(),
+                Change::Shave(_) => state.status.original_discharge_rate.is_some(),
+                Change::AcCharge(_) => state.status.original_ac_charge.is_some(),
+            };
+            if !original_known {
+                let reg = if matches!(change, Change::AcCharge(_)) { REGISTER_21 } else { register };
+                if !reads.contains(&reg) {
+                    reads.push(reg);
+                    r.push(Action::Read(input.datalog, reg));
+                }
+            } else if deferred || state.writes.len() >= max_writes {
+                if !deferred {
+                    warn!(
+                        "peak shaving on {}: {} writes in the last hour, deferring {:?}",
+                        input.datalog,
+                        state.writes.len(),
+                        change
+                    );
+                }
+                deferred = true;
+            } else {
+                state.writes.push_back(now);
+                Self::apply(&mut state.status, change);
+                state.pending.push(change);
+                r.push(Action::Change(input.datalog, change));
+                continue;
+            }
+            // a change already taken as made stays due
+            if Self::current(&state.status, change) == change {
+                state.pending.push(change);
+            }
+        }
+        state.status.writes_last_hour = state.writes.len();
+        r
+    }
+
+    /// Take a change as made without a reply, for an inverter whose writes are held back.
+    pub fn made(&mut self, datalog: Serial, change: Change) {
+        let forced_discharge = self.config.forced_discharge();
+        if let Some(state) = self.state.get_mut(&datalog) {
+            if state.pending.contains(&change) {
+                state.pending.retain(|c| *c != change);
+                Self::done(forced_discharge, &mut state.status, change);
+            }
+        }
+    }
+
+    /// Changes putting every inverter back as the controller found it.
+    pub fn restore_all(&mut self) -> Vec<(Serial, Change)> {
+        let mut r = Vec::new();
+        for (datalog, state) in self.state.iter_mut() {
+            for change in [Change::Shave(false), Change::AcCharge(false)] {
+                if Self::opposite(change) == Self::current(&state.status, change) {
+                    Self::apply(&mut state.status, change);
+                    state.pending.retain(|c| *c != Self::opposite(change));
+                    state.pending.push(change);
+                    r.push((*datalog, change));
+                }
+            }
+        }
+        r
+    }
+
+    /// The command making a change; None if the setting to restore is unknown.
+    pub fn command(&self, inverter: config::Inverter, change: Change) -> Option<Command> {
+        let status = self.status(inverter.datalog()?)?;
+        let r = match change {
+            Change::Shave(true) if self.config.forced_discharge() => Command::ForcedDischarge(inverter, true),
+            Change::Shave(false) if self.config.forced_discharge() => {
+                Command::ForcedDischarge(inverter, status.original_forced_discharge?)
+            }
+            Change::Shave(true) => Command::DischargeRate(inverter, self.config.discharge_rate()),
+            Change::Shave(false) => Command::DischargeRate(inverter, status.original_discharge_rate?),
+            Change::AcCharge(true) => Command::AcCharge(inverter, true),
+            Change::AcCharge(false) => Command::AcCharge(inverter, status.original_ac_charge?),
+        };
+        Some(r)
+    }
+
+    /// The hold register shaving changes.
+    fn register(&self) -> u16 {
+        if self.config.forced_discharge() {
+            REGISTER_21
+        } else {
+            DISCHARGE_RATE_REGISTER
+        }
+    }
+
+    /// Register, mask and value a reply shows once `change` is made.
+    fn expected(forced_discharge: bool, discharge_rate: u16, status: &Status, change: Change) -> Option<(u16, u16, u16)> {
+        let bit = |mask: u16, set: bool| (REGISTER_21, mask, if set { mask } else { 0 });
+        let r = match change {
+            Change::Shave(true) if forced_discharge => bit(FORCED_DISCHARGE, true),
+            Change::Shave(false) if forced_discharge => bit(FORCED_DISCHARGE, status.original_forced_discharge?),
+            Change::Shave(true) => (DISCHARGE_RATE_REGISTER, u16::MAX, discharge_rate),
+            Change::Shave(false) => (DISCHARGE_RATE_REGISTER, u16::MAX, status.original_discharge_rate?),
+            Change::AcCharge(true) => bit(AC_CHARGE, true),
+            Change::AcCharge(false) => bit(AC_CHARGE, status.original_ac_charge?),
+        };
+        Some(r)
+    }
+
+    /// A change was seen made; a restore leaves nothing to put back.
+    fn done(forced_discharge: bool, status: &mut Status, change: Change) {
+        match change {
+            Change::Shave(false) if forced_discharge => status.original_forced_discharge = None,
+            Change::Shave(false) => status.original_discharge_rate = None,
+            Change::AcCharge(false) => status.original_ac_charge = None,
+            Change::Shave(true) | Change::AcCharge(true) => {}
+        }
+    }
+
+    fn apply(status: &mut Status, change: Change) {
+        match change {
+            Change::Shave(enable) => status.shaving = enable,
+            Change::AcCharge(enable) => status.recharging = enable,
+        }
+    }
+
+    fn current(status: &Status, change: Change) -> Change {
+        match change {
+            Change::Shave(_) => Change::Shave(status.shaving),
+            Change::AcCharge(_) => Change::AcCharge(status.recharging),
+        }
+    }
+
+    fn opposite(change: Change) -> Change {
+        match change {
+            Change::Shave(enable) => Change::Shave(!enable),
+            Change::AcCharge(enable) => Change::AcCharge(!enable),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PeakShaving {
+    config: ConfigWrapper,
+    channels: Channels,
+    shaver: Arc<Mutex<Shaver>>,
+    shutdown: CancellationToken,
+}
+
+impl PeakShaving {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        let shaver = Shaver::new(config.peak_shaving());
+        Self {
+            config,
+            channels,
+            shaver: Arc::new(Mutex::new(shaver)),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut inputs = self.channels.from_coordinator.subscribe();
+        let mut packets = self.channels.from_inverter.subscribe();
+        info!("peak shaving started");
+
+        loop {
+            tokio::select! {
+                _ = self.shutdown.cancelled() => break,
+                msg = inputs.recv() => match msg {
+                    Ok(coordinator::ChannelData::ReadInputAll(input)) => {
+                        let actions = self.lock()?.update(&input, Instant::now());
+                        for action in actions {
+                            self.send(action, Source::new(Origin::Controller, "peak_shaving"));
+                        }
+                        if let Err(e) = self.publish(input.datalog) {
+                            warn!("Failed to publish peak shaving status: {}", e);
+                        }
+                    }
+                    Ok(coordinator::ChannelData::Shutdown) => break,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("peak shaving lagged, skipped {} messages", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                msg = packets.recv() => match msg {
+                    Ok(eg4::inverter::ChannelData::Packet(Packet::TranslatedData(td))) => {
+                        if matches!(td.device_function, DeviceFunction::ReadHold | DeviceFunction::WriteSingle) {
+                            let mut shaver = self.lock()?;
+                            for (register, value) in td.pairs() {
+                                shaver.observe(td.datalog, register, value);
+                            }
+                        }
+                    }
+                    Ok(eg4::inverter::ChannelData::Shutdown) => break,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("peak shaving lagged, skipped {} packets", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            }
+        }
+
+        for command in self.restore_commands() {
+            self.send_command(command, Source::restore(Origin::Controller, "peak_shaving"));
+        }
+        info!("peak shaving exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Commands undoing whatever the controller currently holds changed.
+    pub fn restore_commands(&self) -> Vec<Command> {
+        let Ok(mut shaver) = self.lock() else {
+            return Vec::new();
+        };
+        shaver
+            .restore_all()
+            .into_iter()
+            .filter_map(|(datalog, change)| {
+                let inverter = self.config.enabled_inverter_with_datalog(datalog)?;
+                shaver.command(inverter, change)
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Shaver>> {
+        self.shaver
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock peak shaver"))
+    }
+
+    fn send(&self, action: Action, source: Source) {
+        let datalog = match action {
+            Action::Read(datalog, _) | Action::Change(datalog, _) => datalog,
+        };
+        let Some(inverter) = self.config.enabled_inverter_with_datalog(datalog) else {
+            warn!("peak shaving: no enabled inverter with datalog {}", datalog);
+            return;
+        };
+        let command = match action {
+            Action::Read(_, register) => Some(Command::ReadHold(inverter, register, 1)),
+            Action::Change(_, change) => match self.lock() {
+                Ok(mut shaver) => {
+                    // held-back writes never show up in a reply
+                    if inverter.dry_run() {
+                        shaver.made(datalog, change);
+                    }
+                    let command = shaver.command(inverter, change);
+                    if command.is_none() {
+                        warn!("peak shaving on {}: setting to restore for {:?} is unknown", datalog, change);
+                    }
+                    command
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    None
+                }
+            },
+        };
+        if let Some(command) = command {
+            self.send_command(command, source);
+        }
+    }
+
+    fn send_command(&self, command: Command, source: Source) {
+        info!("peak shaving: {}", command.describe());
+        if let Err(e) = self
+            .channels
+            .to_coordinator
+            .send(coordinator::ChannelData::Command(command, source))
+        {
+            error!("Failed to send peak shaving command: {}", e);
+        }
+    }
+
+    fn publish(&self, datalog: Serial) -> Result<()> {
+        if !self.config.mqtt().enabled() {
+            return Ok(());
+        }
+        let Some(status) = self.lock()?.status(datalog).cloned() else {
+            return Ok(());
+        };
+        self.channels.to_mqtt.send(mqtt::ChannelData::Message(mqtt::Message {
+            topic: format!("{}/peak_shaving", datalog),
+            retain: true,
+            payload: serde_json::to_string(&status)?,
+        }))?;
+        Ok(())
+    }
+}
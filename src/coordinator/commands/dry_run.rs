@@ -0,0 +1,73 @@
+use crate::prelude::*;
+
+use eg4::packet::{Packet, TranslatedData};
+use serde::Serialize;
+
+/// A write frame, as held back in dry-run mode and as audited.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Frame {
+    pub datalog: Serial,
+    pub inverter: Option<Serial>,
+    pub device_function: String,
+    pub register: u16,
+    /// raw values of the frame, as they would have been sent
+    pub values: Vec<u8>,
+    /// register value before the write, where known
+    pub old_value: Option<u16>,
+    /// value the register would have been set to, for single register writes
+    pub new_value: Option<u16>,
+}
+
+impl Frame {
+    pub fn new(packet: &Packet, old_value: Option<u16>) -> Result<Self> {
+        let (datalog, inverter, device_function, register, values) = match packet {
+            Packet::TranslatedData(TranslatedData {
+                datalog,
+                inverter,
+                device_function,
+                register,
+                values,
+            }) => (*datalog, Some(*inverter), format!("{:?}", device_function), *register, values.clone()),
+            Packet::WriteParam(wp) => (wp.datalog, None, "WriteParam".to_string(), wp.register, wp.values.clone()),
+            _ => bail!("not a write frame: {:?}", packet),
+        };
+        let new_value = match values[..] {
+            [low, high] => Some(u16::from_le_bytes([low, high])),
+            _ => None,
+        };
+
+        Ok(Self {
+            datalog,
+            inverter,
+            device_function,
+            register,
+            values,
+            old_value,
+            new_value,
+        })
+    }
+
+    /// Relative topic, e.g. `dryrun/2222222222/hold/21`.
+    pub fn topic(&self) -> String {
+        let kind = if self.inverter.is_some() { "hold" } else { "param" };
+        format!("dryrun/{}/{}/{}", self.datalog, kind, self.register)
+    }
+}
+
+/// Log, publish and audit a write frame instead of sending it.
+pub fn publish(channels: &Channels, packet: &Packet, old_value: Option<u16>) -> Result<()> {
+    let frame = Frame::new(packet, old_value)?;
+    crate::audit::record_outcome(channels, packet, old_value, "dry run");
+    info!(
+        "[dry run] not sending {} to register {} on {}: {:?} -> {:?} ({:?})",
+        frame.device_function, frame.register, frame.datalog, frame.old_value, frame.new_value, frame.values
+    );
+
+    // fails harmlessly when MQTT is disabled
+    let _ = channels.to_mqtt.send(mqtt::ChannelData::Message(mqtt::Message {
+        topic: frame.topic(),
+        retain: false,
+        payload: serde_json::to_string(&frame)?,
+    }));
+    Ok(())
+}
@@ -1,3 +1,4 @@
+pub mod dry_run;
 pub mod parse_hold;
 pub mod parse_input;
 pub mod read_hold;
@@ -40,10 +40,22 @@ impl SetHold {
             values: self.value.to_le_bytes().to_vec(),
         });
 
+        let old_value = RegisterCache::cached(&self.channels, packet.datalog(), self.register).await;
+        if self.inverter.dry_run() {
+            super::dry_run::publish(&self.channels, &packet, old_value)?;
+            return Ok(packet);
+        }
+
+        let result = self.send(&packet).await;
+        crate::audit::record(&self.channels, &packet, old_value, &result);
+        result
+    }
+
+    async fn send(&self, packet: &Packet) -> Result<Packet> {
         let mut receiver = self.channels.from_inverter.subscribe();
 
         // Log the packet being sent
-        if let Packet::TranslatedData(td) = &packet {
+        if let Packet::TranslatedData(td) = packet {
             info!("[set_hold] Sending TranslatedData packet to inverter - function: {:?}, register: {}, datalog: {}", 
                 td.device_function, self.register, td.datalog);
         }
@@ -52,7 +64,7 @@ impl SetHold {
             bail!("Failed to send packet to coordinator: {}", e);
         }
 
-        let packet = receiver.wait_for_reply(&packet).await?;
+        let packet = receiver.wait_for_reply(packet).await?;
         if packet.value() != self.value {
             bail!(
                 "failed to set register {}, got back value {} (wanted {})",
@@ -28,7 +28,14 @@ pub enum Action {
 }
 
 impl Action {
-    fn register(&self) -> Result<u16> {
+    /// Whether `register` holds the start or end of a time slot, as hour and minute bytes.
+    pub(crate) fn is_time_register(register: u16) -> bool {
+        [68..=73, 76..=81, 84..=89, 152..=157]
+            .iter()
+            .any(|slots| slots.contains(&register))
+    }
+
+    pub(crate) fn register(&self) -> Result<u16> {
         use Action::*;
         match self {
             AcCharge(1) => Ok(68),
@@ -185,10 +192,21 @@ impl SetTimeRegister {
             register,
         });
 
+        let old_value = RegisterCache::cached(&self.channels, packet.datalog(), register).await;
+        if self.inverter.dry_run() {
+            return super::dry_run::publish(&self.channels, &packet, old_value);
+        }
+
+        let result = self.send(&packet, register, values).await;
+        crate::audit::record(&self.channels, &packet, old_value, &result);
+        result
+    }
+
+    async fn send(&self, packet: &Packet, register: u16, values: &[u8]) -> Result<()> {
         let mut receiver = self.channels.from_inverter.subscribe();
 
         // Log packet details
-        if let Packet::TranslatedData(td) = &packet {
+        if let Packet::TranslatedData(td) = packet {
             info!("[set_register] Sending TranslatedData packet to inverter - function: {:?}, register: {}, datalog: {}", 
                 td.device_function, register, td.datalog);
         }
@@ -202,7 +220,7 @@ impl SetTimeRegister {
             bail!("send(to_inverter) failed - channel closed?");
         }
 
-        let reply = receiver.wait_for_reply(&packet).await?;
+        let reply = receiver.wait_for_reply(packet).await?;
         if let Packet::TranslatedData(td) = reply {
             if td.values != values {
                 bail!(
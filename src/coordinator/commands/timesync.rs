@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use crate::command::Origin;
 
 use chrono::TimeZone;
 
@@ -103,16 +104,40 @@ impl TimeSync {
                 // Create and send the time update packet
                 let packet = self.set_time_packet(now);
 
+                if self.inverter.dry_run() {
+                    return super::dry_run::publish(&self.channels, &packet, None);
+                }
+
                 if let Err(e) = self.channels.to_coordinator.send(crate::coordinator::ChannelData::SendPacket(packet.clone())) {
                     bail!("Failed to send packet to coordinator: {}", e);
                 }
 
                 // Wait for confirmation of the time update
-                if let Packet::TranslatedData(_) = receiver.wait_for_reply(&packet).await? {
+                let reply = receiver.wait_for_reply(&packet).await;
+                crate::audit::record(&self.channels, &packet, None, &reply);
+                let confirmed = matches!(reply?, Packet::TranslatedData(_));
+                if confirmed {
                     debug!("time set ok");
                 } else {
                     warn!("time set didn't get confirmation reply!");
                 }
+
+                // Record the write in the database event log (no-op without databases)
+                if let Some(datalog) = self.inverter.datalog() {
+                    let _ = self.channels.to_database.send(database::ChannelData::Event(database::Event {
+                        origin: Some(Origin::Scheduler),
+                        ..database::Event::new(
+                            datalog,
+                            database::EventKind::Write,
+                            format!(
+                                "{}/set/time = {} {}",
+                                datalog,
+                                now.format("%Y-%m-%d %H:%M:%S"),
+                                if confirmed { "ok" } else { "unconfirmed" }
+                            ),
+                        )
+                    }));
+                }
             } else if time_diff.abs() > max_limit {
                 // Log a warning if the time difference is too large
                 // This might indicate a problem that needs manual intervention
@@ -4,6 +4,7 @@ use crate::eg4::{
     packet::{Packet, RegisterBit, DeviceFunction, TranslatedData},
     inverter::ChannelData,
 };
+use crate::write_policy::WritePolicy;
 
 pub struct UpdateHold {
     channels: Channels,
@@ -11,6 +12,7 @@ pub struct UpdateHold {
     register: u16,
     bit: RegisterBit,
     enable: bool,
+    policy: Option<WritePolicy>,
 }
 
 impl UpdateHold {
@@ -20,6 +22,7 @@ impl UpdateHold {
         register: u16,
         bit: RegisterBit,
         enable: bool,
+        policy: Option<WritePolicy>,
     ) -> Self {
         Self {
             channels,
@@ -27,6 +30,7 @@ impl UpdateHold {
             register,
             bit,
             enable,
+            policy,
         }
     }
 
@@ -54,6 +58,9 @@ impl UpdateHold {
         } else {
             current_value & !(self.bit.clone() as u16)
         };
+        if let Some(policy) = &self.policy {
+            policy.check(self.inverter.datalog().expect("datalog must be set"), self.register, Some(new_value))?;
+        }
 
         // Now write the new value
         let write_packet = Packet::TranslatedData(TranslatedData {
@@ -64,12 +71,23 @@ impl UpdateHold {
             values: new_value.to_le_bytes().to_vec(),
         });
 
+        // the read above still goes out, so the old value is the live one
+        if self.inverter.dry_run() {
+            return super::dry_run::publish(&self.channels, &write_packet, Some(current_value));
+        }
+
+        let result = self.write(&mut receiver, &write_packet, new_value).await;
+        crate::audit::record(&self.channels, &write_packet, Some(current_value), &result);
+        result
+    }
+
+    async fn write(&self, receiver: &mut eg4::inverter::Receiver, write_packet: &Packet, new_value: u16) -> Result<()> {
         self.channels
             .to_inverter
             .send(ChannelData::Packet(write_packet.clone()))
             .map_err(|e| anyhow!("send(to_inverter) failed: {}", e))?;
 
-        let write_packet = receiver.wait_for_reply(&write_packet).await?;
+        let write_packet = receiver.wait_for_reply(write_packet).await?;
         if write_packet.value() != new_value {
             bail!(
                 "failed to update register {:?}, got back value {} (wanted {})",
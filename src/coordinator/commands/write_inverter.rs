@@ -4,22 +4,34 @@ use crate::coordinator::commands::time_register_ops;
 use crate::coordinator::commands::set_hold::SetHold;
 use crate::coordinator::commands::write_param::WriteParam;
 use crate::coordinator::commands::time_register_ops::SetTimeRegister;
+use crate::coordinator::commands::read_hold::ReadHold;
+use crate::coordinator::commands::update_hold::UpdateHold;
+use crate::eg4::packet::{Register, RegisterBit};
+use crate::write_policy::WritePolicy;
+
+/// Holding register behind the charge rate.
+pub const CHARGE_RATE_REGISTER: u16 = 0x0100;
+/// Holding register behind the discharge rate.
+pub const DISCHARGE_RATE_REGISTER: u16 = 0x0101;
 
 /// WriteInverter handles all direct inverter operations.
 /// The read_only check only applies to write operations (set_* functions).
 /// Read operations are always allowed regardless of read_only setting.
+/// Hold register writes are also checked against the write policy.
 pub struct WriteInverter {
     channels: Channels,
     inverter: config::Inverter,
     config: ConfigWrapper,
+    policy: WritePolicy,
 }
 
 impl WriteInverter {
-    pub fn new(channels: Channels, inverter: config::Inverter, config: ConfigWrapper) -> Self {
+    pub fn new(channels: Channels, inverter: config::Inverter, config: ConfigWrapper, policy: WritePolicy) -> Self {
         Self {
             channels,
             inverter,
             config,
+            policy,
         }
     }
 
@@ -35,6 +47,15 @@ impl WriteInverter {
         }
     }
 
+    /// Records an acknowledged write against the write policy. A dry run only checks the
+    /// policy, so held-back writes never use up an interval or a confirmation.
+    fn record(&self, datalog: Serial, register: u16) -> Result<()> {
+        if self.inverter.dry_run() {
+            return Ok(());
+        }
+        self.policy.record(datalog, register)
+    }
+
     /// Write operation: Sets AC charge rate
     /// Blocked by read_only setting
     pub async fn set_ac_charge_rate(&self, value: u16) -> Result<()> {
@@ -80,7 +101,7 @@ impl WriteInverter {
     pub async fn set_charge_rate(&self, value: u16) -> Result<()> {
         info!("Setting charge rate to {} for inverter {}", value, self.inverter.datalog().unwrap_or_default());
         self.check_read_only()?;
-        self.set_hold(0x0100_u16, value).await
+        self.set_hold(CHARGE_RATE_REGISTER, value).await
     }
 
     /// Write operation: Sets discharge cutoff SOC limit
@@ -96,7 +117,7 @@ impl WriteInverter {
     pub async fn set_discharge_rate(&self, value: u16) -> Result<()> {
         info!("Setting discharge rate to {} for inverter {}", value, self.inverter.datalog().unwrap_or_default());
         self.check_read_only()?;
-        self.set_hold(0x0101_u16, value).await
+        self.set_hold(DISCHARGE_RATE_REGISTER, value).await
     }
 
     /// Write operation: Sets forced discharge time
@@ -116,6 +137,8 @@ impl WriteInverter {
         let reg = register.clone().into();
         info!("Setting hold register 0x{:04X} to {} for inverter {}", reg, value, self.inverter.datalog().unwrap_or_default());
         self.check_read_only()?;
+        let datalog = self.inverter.datalog().unwrap_or_default();
+        self.policy.check(datalog, reg, Some(value))?;
         SetHold::new(
             self.channels.clone(),
             self.inverter.clone(),
@@ -124,6 +147,7 @@ impl WriteInverter {
         )
         .run()
         .await?;
+        self.record(datalog, reg)?;
         info!("Successfully set hold register 0x{:04X} to {}", reg, value);
         Ok(())
     }
@@ -159,6 +183,15 @@ impl WriteInverter {
         info!("Setting time register with values {:?} for inverter {}", 
             values, self.inverter.datalog().unwrap_or_default());
         self.check_read_only()?;
+        let datalog = self.inverter.datalog().unwrap_or_default();
+        let register = action.register()?;
+        let slot = [
+            (register, u16::from_le_bytes([values[0], values[1]])),
+            (register + 1, u16::from_le_bytes([values[2], values[3]])),
+        ];
+        for (register, value) in slot {
+            self.policy.check(datalog, register, Some(value))?;
+        }
         SetTimeRegister::new(
             self.channels.clone(),
             self.inverter.clone(),
@@ -168,7 +201,58 @@ impl WriteInverter {
         )
         .run()
         .await?;
+        for register in [register, register + 1] {
+            self.record(datalog, register)?;
+        }
         info!("Successfully set time register");
         Ok(())
     }
-} 
\ No newline at end of file
+
+    /// Write operation: Sets or clears one bit of a holding register
+    /// Blocked by read_only setting
+    pub async fn update_hold_bit(&self, register: Register, bit: RegisterBit, enable: bool) -> Result<()> {
+        info!("Setting {:?} to {} for inverter {}", bit, enable, self.inverter.datalog().unwrap_or_default());
+        self.check_read_only()?;
+        let datalog = self.inverter.datalog().unwrap_or_default();
+        let register = u16::from(register);
+        // checked once the current value is read and the new one known
+        UpdateHold::new(self.channels.clone(), self.inverter.clone(), register, bit, enable, Some(self.policy.clone()))
+            .run()
+            .await?;
+        self.record(datalog, register)
+    }
+
+    /// Read operation: Reads the current value of one holding register
+    pub async fn read_hold<U>(&self, register: U) -> Result<u16>
+    where
+        U: Into<u16>,
+    {
+        let packet = ReadHold::new(self.channels.clone(), self.inverter.clone(), register, 1)
+            .run()
+            .await?;
+        Ok(packet.value())
+    }
+
+    /// Read operation: Reads a holding register back and checks the bits in `mask` hold `expected`
+    pub async fn verify_hold<U>(&self, register: U, mask: u16, expected: u16) -> Result<()>
+    where
+        U: Into<u16>,
+    {
+        let register = register.into();
+        // nothing was written to read back
+        if self.inverter.dry_run() {
+            return Ok(());
+        }
+        let value = self.read_hold(register).await?;
+        if value & mask != expected & mask {
+            bail!(
+                "hold register {} read back as {} after writing (wanted {} under mask 0x{:04X})",
+                register,
+                value,
+                expected,
+                mask
+            );
+        }
+        Ok(())
+    }
+}
@@ -42,6 +42,18 @@ impl WriteParam {
             values: self.value.to_le_bytes().to_vec(),
         });
 
+        // params are not cached, so there is no old value to show
+        if self.inverter.dry_run() {
+            super::dry_run::publish(&self.channels, &packet, None)?;
+            return Ok(packet);
+        }
+
+        let result = self.send(&packet).await;
+        crate::audit::record(&self.channels, &packet, None, &result);
+        result
+    }
+
+    async fn send(&self, packet: &Packet) -> Result<Packet> {
         let mut receiver = self.channels.from_inverter.subscribe();
 
         info!("Sending write param packet to coordinator");
@@ -50,7 +62,7 @@ impl WriteParam {
         }
 
         info!("Waiting for reply from inverter");
-        let packet = receiver.wait_for_reply(&packet).await?;
+        let packet = receiver.wait_for_reply(packet).await?;
         // WriteParam packets seem to reply with 0 on success, very odd
         if packet.value() != 0 {
             error!("Failed to set register {} - received non-zero response: {}", self.register, packet.value());
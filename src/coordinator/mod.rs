@@ -2,8 +2,11 @@ pub mod commands;
 
 use crate::prelude::*;
 use crate::eg4::packet::{Register, RegisterBit};
-use crate::command::Command;
+use crate::command::{Command, Origin, Source};
+use crate::database::{Event, EventKind};
 use crate::datalog_writer::DatalogWriter;
+use crate::register::RegisterParser;
+use crate::write_policy::WritePolicy;
 
 use crate::eg4::{
     packet::{DeviceFunction, ReadInput, TranslatedData, Packet},
@@ -13,11 +16,21 @@ use commands::time_register_ops::{Action, ReadTimeRegister};
 
 use std::sync::{Arc, Mutex};
 
-#[derive(Eq, PartialEq, Debug, Clone)]
+/// How long shutdown waits for controller settings to be restored.
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(15);
+
+#[derive(PartialEq, Debug, Clone)]
 pub enum ChannelData {
     Shutdown,
     Packet(crate::eg4::packet::Packet),
     SendPacket(crate::eg4::packet::Packet),
+    /// A complete set of input registers, published on `from_coordinator` for consumers
+    /// such as the daily summary.
+    ReadInputAll(Box<crate::eg4::packet::ReadInputAll>),
+    /// A command raised inside the bridge, such as by a rule, run like one from MQTT.
+    Command(Command, Source),
+    /// Drop the charge plan in preview for this datalog, published on `from_coordinator`.
+    CancelChargePlan(Serial),
 }
 
 pub type InputsStore = std::collections::HashMap<Serial, crate::eg4::packet::ReadInputs>;
@@ -44,6 +57,8 @@ pub struct PacketStats {
     pub register_cache_errors: u64,
     // Other stats
     pub mqtt_messages_sent: u64,
+    pub mqtt_outbox_size: u64,
+    pub mqtt_outbox_dropped: u64,
     pub influx_writes: u64,
     pub database_writes: u64,
     pub register_cache_writes: u64,
@@ -77,6 +92,8 @@ impl PacketStats {
         info!("    Register cache errors: {}", self.register_cache_errors);
         info!("  MQTT:");
         info!("    Messages sent: {}", self.mqtt_messages_sent);
+        info!("    Outbox size: {}", self.mqtt_outbox_size);
+        info!("    Outbox dropped: {}", self.mqtt_outbox_dropped);
         info!("  InfluxDB:");
         info!("    Writes: {}", self.influx_writes);
         info!("  Database:");
@@ -123,6 +140,8 @@ impl PacketStats {
         self.database_errors = other.database_errors;
         self.register_cache_errors = other.register_cache_errors;
         self.mqtt_messages_sent = other.mqtt_messages_sent;
+        self.mqtt_outbox_size = other.mqtt_outbox_size;
+        self.mqtt_outbox_dropped = other.mqtt_outbox_dropped;
         self.influx_writes = other.influx_writes;
         self.database_writes = other.database_writes;
         self.register_cache_writes = other.register_cache_writes;
@@ -143,6 +162,18 @@ pub struct Coordinator {
     mqtt: Option<Arc<Mqtt>>,
     databases: Vec<Arc<Database>>,
     register_cache: Option<Arc<RegisterCache>>,
+    /// Register map for per-field hold topics, loaded only when they are enabled
+    field_registers: Option<Arc<RegisterParser>>,
+    controllers: crate::controllers::Controllers,
+    write_policy: WritePolicy,
+    daily_summary: Option<Arc<crate::daily_summary::DailySummary>>,
+    tariff: Option<Arc<crate::tariff::Tariff>>,
+    energy_totals: Option<Arc<crate::energy_totals::EnergyTotals>>,
+    rules: Option<Arc<crate::rules::Rules>>,
+    charge_planner: Option<Arc<crate::charge_planner::ChargePlanner>>,
+    alerting: Option<Arc<crate::alerts::Alerting>>,
+    battery_health: Option<Arc<crate::battery_health::BatteryHealth>>,
+    groups: Option<Arc<crate::groups::Groups>>,
 }
 
 /// Manages all application components and their lifecycle
@@ -217,6 +248,17 @@ impl Components {
 impl Coordinator {
     pub fn new(config: Arc<ConfigWrapper>, channels: Channels) -> Self {
         let shared_stats = Arc::new(Mutex::new(PacketStats::default()));
+        let field_registers = if config.mqtt().publish_fields() {
+            let file = config.register_file().unwrap_or_else(|| "doc/eg4_registers.json".to_string());
+            RegisterParser::new(&file)
+                .map_err(|e| warn!("no per-field hold topics, cannot load {}: {}", file, e))
+                .ok()
+                .map(Arc::new)
+        } else {
+            None
+        };
+        let write_policy = WritePolicy::new((*config).clone(), channels.clone());
+        let controllers = crate::controllers::Controllers::new(&config, &channels);
         Self {
             config,
             channels,
@@ -227,18 +269,66 @@ impl Coordinator {
             mqtt: None,
             databases: Vec::new(),
             register_cache: None,
+            field_registers,
+            controllers,
+            write_policy,
+            daily_summary: None,
+            tariff: None,
+            energy_totals: None,
+            rules: None,
+            charge_planner: None,
+            alerting: None,
+            battery_health: None,
+            groups: None,
         }
     }
 
     pub fn stop(&self) {
         info!("Stopping coordinator...");
 
+        // Components started with their own shutdown token
+        if let Some(daily_summary) = &self.daily_summary {
+            daily_summary.stop();
+        }
+
+        if let Some(tariff) = &self.tariff {
+            tariff.stop();
+        }
+
+        if let Some(energy_totals) = &self.energy_totals {
+            energy_totals.stop();
+        }
+
+        if let Some(rules) = &self.rules {
+            rules.stop();
+        }
+
+        self.controllers.stop();
+
+        if let Some(planner) = &self.charge_planner {
+            planner.stop();
+        }
+
+        if let Some(alerting) = &self.alerting {
+            alerting.stop();
+        }
+
+        if let Some(battery_health) = &self.battery_health {
+            battery_health.stop();
+        }
+
+        if let Some(groups) = &self.groups {
+            groups.stop();
+        }
+
         // Send shutdown signals through channels first
         let _ = self.channels.to_inverter.send(crate::eg4::inverter::ChannelData::Shutdown);
         let _ = self.channels.to_mqtt.send(mqtt::ChannelData::Shutdown);
         let _ = self.channels.to_influx.send(influx::ChannelData::Shutdown);
         let _ = self.channels.to_database.send(database::ChannelData::Shutdown);
         let _ = self.channels.to_register_cache.send(register_cache::ChannelData::Shutdown);
+        let _ = self.channels.from_coordinator.send(ChannelData::Shutdown);
+        let _ = self.channels.to_audit.send(crate::audit::ChannelData::Shutdown);
     }
 
     pub async fn start(&mut self) -> Result<()> {
@@ -273,6 +363,16 @@ impl Coordinator {
             info!("Datalog writer initialized successfully");
         }
         
+        // Record every write, ours and those of other clients
+        if self.config.audit().enabled() {
+            let audit = crate::audit::AuditLog::new((*self.config).clone(), self.channels.clone());
+            tokio::spawn(async move {
+                if let Err(e) = audit.start().await {
+                    error!("Audit log task failed: {}", e);
+                }
+            });
+        }
+
         // Initialize MQTT client if enabled (integration tests can set EG4_TEST_SKIP_MQTT_BROKER=1
         // to exercise `to_mqtt` / `from_mqtt` without a real broker).
         if self.config.mqtt().enabled() {
@@ -333,6 +433,109 @@ impl Coordinator {
             });
         }
         
+        // Capture end-of-day totals from complete input sets
+        if self.config.daily_summary() {
+            let daily_summary = Arc::new(crate::daily_summary::DailySummary::new((*self.config).clone(), self.channels.clone()));
+            self.daily_summary = Some(daily_summary.clone());
+            tokio::spawn(async move {
+                if let Err(e) = daily_summary.start().await {
+                    error!("Daily summary task failed: {}", e);
+                }
+            });
+        }
+
+        // Publish lifetime energy counters with reconnect glitches filtered out
+        if self.config.mqtt().enabled() {
+            let energy_totals = Arc::new(crate::energy_totals::EnergyTotals::new(self.channels.clone()));
+            self.energy_totals = Some(energy_totals.clone());
+            tokio::spawn(async move {
+                if let Err(e) = energy_totals.start().await {
+                    error!("Energy totals task failed: {}", e);
+                }
+            });
+        }
+
+        // Publish parallel inverters as one
+        if self.config.mqtt().enabled() && !self.config.groups().is_empty() {
+            let groups = Arc::new(crate::groups::Groups::new((*self.config).clone(), self.channels.clone()));
+            self.groups = Some(groups.clone());
+            tokio::spawn(async move {
+                if let Err(e) = groups.start().await {
+                    error!("Inverter groups task failed: {}", e);
+                }
+            });
+        }
+
+        // Price energy flows against the configured tariff
+        if self.config.tariff().enabled() {
+            let tariff = Arc::new(crate::tariff::Tariff::new(
+                (*self.config).clone(),
+                self.channels.clone(),
+                self.databases.first().cloned(),
+            ));
+            self.tariff = Some(tariff.clone());
+            tokio::spawn(async move {
+                if let Err(e) = tariff.start().await {
+                    error!("Tariff task failed: {}", e);
+                }
+            });
+        }
+
+        // Closed-loop control of inverter settings
+        self.controllers.start();
+
+        // Plan the overnight AC charge from the solar forecast
+        if self.config.charge_planner().enabled() {
+            let planner = Arc::new(crate::charge_planner::ChargePlanner::new(
+                (*self.config).clone(),
+                self.channels.clone(),
+                self.databases.first().cloned(),
+            ));
+            self.charge_planner = Some(planner.clone());
+            tokio::spawn(async move {
+                if let Err(e) = planner.start().await {
+                    error!("Charge planner task failed: {}", e);
+                }
+            });
+        }
+
+        // Track cell balance, SOH, round-trip efficiency and usable capacity
+        if self.config.battery_health().enabled() {
+            let battery_health = Arc::new(crate::battery_health::BatteryHealth::new(
+                (*self.config).clone(),
+                self.channels.clone(),
+                self.databases.first().cloned(),
+            ));
+            self.battery_health = Some(battery_health.clone());
+            tokio::spawn(async move {
+                if let Err(e) = battery_health.start().await {
+                    error!("Battery health task failed: {}", e);
+                }
+            });
+        }
+
+        // Notify of faults, warnings, disconnects and stale data
+        if self.config.alerts().enabled() {
+            let alerting = Arc::new(crate::alerts::Alerting::new((*self.config).clone(), self.channels.clone()));
+            self.alerting = Some(alerting.clone());
+            tokio::spawn(async move {
+                if let Err(e) = alerting.start().await {
+                    error!("Alerting task failed: {}", e);
+                }
+            });
+        }
+
+        // Run local automations on live input data
+        if self.config.rules().enabled() {
+            let rules = Arc::new(crate::rules::Rules::new((*self.config).clone(), self.channels.clone()));
+            self.rules = Some(rules.clone());
+            tokio::spawn(async move {
+                if let Err(e) = rules.start().await {
+                    error!("Rules task failed: {}", e);
+                }
+            });
+        }
+
         // Verify subscribers are ready
         info!("Verifying subscribers...");
         
@@ -427,6 +630,7 @@ impl Coordinator {
                         }
                         Ok(eg4::inverter::ChannelData::Disconnect(datalog)) => {
                             info!("Inverter {} disconnected", datalog);
+                            self.send_event(Event::new(datalog, EventKind::Disconnected, "inverter disconnected".to_string()));
                         }
                         Ok(eg4::inverter::ChannelData::Shutdown) => {
                             info!("Received shutdown signal from inverter");
@@ -473,6 +677,13 @@ impl Coordinator {
                             info!("Received shutdown signal");
                             break;
                         }
+                        Ok(ChannelData::ReadInputAll(_)) | Ok(ChannelData::CancelChargePlan(_)) => {}
+                        Ok(ChannelData::Command(command, source)) => {
+                            let description = command.describe();
+                            if let Err(e) = self.process_command(command, source.clone()).await {
+                                error!("{} command {} failed: {}", source, description, e);
+                            }
+                        }
                         Err(e) => {
                             error!("Error receiving from coordinator channel: {}", e);
                             break;
@@ -484,10 +695,16 @@ impl Coordinator {
                 msg = from_mqtt_rx.recv() => {
                     match msg {
                         Ok(mqtt::ChannelData::Message(message)) => {
-                            if let Err(e) = self.process_message(message).await {
+                            if let Err(e) = self.process_message(message, mqtt::ReplyTo::default()).await {
+                                error!("Failed to process MQTT command message: {}", e);
+                            }
+                        }
+                        Ok(mqtt::ChannelData::Request(message, reply_to)) => {
+                            if let Err(e) = self.process_message(message, reply_to).await {
                                 error!("Failed to process MQTT command message: {}", e);
                             }
                         }
+                        Ok(mqtt::ChannelData::Reply(..)) => {}
                         Ok(mqtt::ChannelData::Shutdown) => {
                             debug!("from_mqtt shutdown");
                         }
@@ -591,13 +808,19 @@ impl Coordinator {
                     }
                 }
 
-                // Send to databases (e.g. PostgreSQL) when input blocks can be decoded.
-                if let Err(e) = self.send_to_database(&td, parsed_input.as_ref()) {
-                    error!("Failed to send data to database channel: {}", e);
+                // Aggregate decoded input blocks and fan complete sets out to databases
+                // and `from_coordinator` subscribers.
+                if let Err(e) = self.send_input_all(&td, parsed_input.as_ref()) {
+                    error!("Failed to send complete input set: {}", e);
+                }
+
+                // Hold reads and single-register write echoes feed the hold history
+                if matches!(td.device_function, DeviceFunction::ReadHold | DeviceFunction::WriteSingle) {
+                    self.send_database(database::ChannelData::HoldData(td.datalog, td.pairs()));
                 }
 
                 // Cache register values
-                if let Err(e) = self.cache_register(td.register, td.values.clone()) {
+                if let Err(e) = self.cache_register(td.datalog, td.register, td.values.clone()) {
                     error!("Failed to cache register {}: {}", td.register, e);
                 }
 
@@ -607,8 +830,10 @@ impl Coordinator {
                 }
             }
             Packet::ReadParam(rp) => {
+                self.send_database(database::ChannelData::ParamData(rp.datalog, rp.pairs()));
+
                 // Cache register values
-                if let Err(e) = self.cache_register(rp.register, rp.values.clone()) {
+                if let Err(e) = self.cache_register(rp.datalog, rp.register, rp.values.clone()) {
                     error!("Failed to cache register {}: {}", rp.register, e);
                 }
             }
@@ -623,7 +848,7 @@ impl Coordinator {
                 }
 
                 // Cache register values
-                if let Err(e) = self.cache_register(wp.register, wp.values.clone()) {
+                if let Err(e) = self.cache_register(wp.datalog, wp.register, wp.values.clone()) {
                     error!("Failed to cache register {}: {}", wp.register, e);
                 }
             }
@@ -643,31 +868,64 @@ impl Coordinator {
         self.channels.read_register_cache.subscribe().is_closed()
     }
 
-    async fn process_message(&self, message: mqtt::Message) -> Result<()> {
+    async fn process_message(&self, mut message: mqtt::Message, reply_to: mqtt::ReplyTo) -> Result<()> {
         // If MQTT is disabled, don't process any messages
         if !self.config.mqtt().enabled() {
             return Ok(());
         }
 
-        for inverter in self.config.inverters_for_message(&message)? {
-            match message.to_command(inverter) {
-                Ok(command) => {
-                    info!("parsed command {:?}", command);
-                    let result = self.process_command(command.clone()).await;
-                    if result.is_err() {
-                    let topic_reply = command.to_result_topic();
-                    let reply = mqtt::ChannelData::Message(mqtt::Message {
-                        topic: topic_reply,
-                        retain: false,
-                            payload: "FAIL".to_string(),
-                    });
-                    if self.channels.to_mqtt.send(reply).is_err() {
-                        bail!("send(to_mqtt) failed - channel closed?");
-                        }
+        let id = message.take_envelope_id();
+
+        let inverters = match self.config.inverters_for_message(&message) {
+            Ok(inverters) => inverters,
+            Err(err) => {
+                self.send_command_result(None, &reply_to, &mqtt::CommandResult::new(id, &Err(anyhow!("{}", err))))?;
+                return Err(err);
+            }
+        };
+
+        // only JSON documents can name registers, so only load the map for them
+        let registers = if message.is_json_command() {
+            let file = self
+                .config
+                .register_file()
+                .unwrap_or_else(|| "doc/eg4_registers.json".to_string());
+            RegisterParser::new(&file)
+                .map_err(|e| warn!("register names unavailable: {}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        let parsed: Vec<Result<Vec<Command>>> = inverters
+            .into_iter()
+            .map(|inverter| message.to_commands(inverter, registers.as_ref()))
+            .collect();
+
+        // a group command is only sent once it is valid for every member
+        if matches!(message.split_cmd_topic(), Ok((mqtt::TargetInverter::Group(_), _))) {
+            if let Some(Err(err)) = parsed.iter().find(|p| p.is_err()) {
+                let err = anyhow!("not sent to any member of the group: {}", err);
+                error!("{:?}", err);
+                self.send_command_result(None, &reply_to, &mqtt::CommandResult::new(id, &Err(err)))?;
+                return Ok(());
+            }
+        }
+
+        let source = Source::new(Origin::Mqtt, message.topic.clone());
+        for commands in parsed {
+            match commands {
+                Ok(commands) => {
+                    for command in commands {
+                        info!("parsed command {:?}", command);
+                        let result = self.process_command(command.clone(), source.clone()).await;
+                        let result = mqtt::CommandResult::new(id.clone(), &result);
+                        self.send_command_result(Some(command.to_result_topic()), &reply_to, &result)?;
                     }
                 }
                 Err(err) => {
                     error!("{:?}", err);
+                    self.send_command_result(None, &reply_to, &mqtt::CommandResult::new(id.clone(), &Err(err)))?;
                 }
             }
         }
@@ -675,10 +933,82 @@ impl Coordinator {
         Ok(())
     }
 
+    /// Publish a command result to its `result/...` topic, if known, and to the requester's
+    /// response topic, if one was given. `result/...` only gets `FAIL` on failure unless
+    /// `mqtt.json_results` is set; the response topic always gets the JSON.
+    fn send_command_result(
+        &self,
+        result_topic: Option<String>,
+        reply_to: &mqtt::ReplyTo,
+        result: &mqtt::CommandResult,
+    ) -> Result<()> {
+        let payload = serde_json::to_string(result)?;
+
+        let result_payload = if self.config.mqtt().json_results() {
+            Some(payload.clone())
+        } else if result.error.is_some() {
+            Some("FAIL".to_string())
+        } else {
+            None
+        };
+        if let (Some(topic), Some(payload)) = (result_topic, result_payload) {
+            let reply = mqtt::ChannelData::Message(mqtt::Message {
+                topic,
+                retain: false,
+                payload,
+            });
+            if self.channels.to_mqtt.send(reply).is_err() {
+                bail!("send(to_mqtt) failed - channel closed?");
+            }
+        }
+
+        if let Some(topic) = &reply_to.response_topic {
+            let reply = mqtt::ChannelData::Reply(
+                mqtt::Message {
+                    topic: topic.clone(),
+                    retain: false,
+                    payload,
+                },
+                reply_to.clone(),
+            );
+            if self.channels.to_mqtt.send(reply).is_err() {
+                bail!("send(to_mqtt) failed - channel closed?");
+            }
+        }
+
+        Ok(())
+    }
+
     /// Process a command received from MQTT or other sources
-    /// This function routes commands to appropriate read/write handlers
-    async fn process_command(&self, command: Command) -> Result<()> {
-        let inverter = match &command {
+    /// This function routes commands to appropriate read/write handlers;
+    /// writes are recorded in the database event log along with their origin, and each
+    /// frame they send in the audit log along with their source
+    async fn process_command(&self, command: Command, source: Source) -> Result<Vec<(u16, u16)>> {
+        let is_write = command.is_write();
+        let description = command.describe();
+        let datalog = self.command_inverter(&command).datalog();
+        let dry_run = self.command_inverter(&command).dry_run();
+
+        let origin = source.origin;
+        let result = crate::audit::scope(source, self.run_command(command)).await;
+
+        if let (true, Some(datalog)) = (is_write, datalog) {
+            let outcome = match &result {
+                Ok(_) if dry_run => "dry run".to_string(),
+                Ok(_) => "ok".to_string(),
+                Err(e) => format!("failed: {}", e),
+            };
+            self.send_event(Event {
+                origin: Some(origin),
+                ..Event::new(datalog, EventKind::Write, format!("{} {}", description, outcome))
+            });
+        }
+
+        result
+    }
+
+    fn command_inverter<'a>(&self, command: &'a Command) -> &'a config::Inverter {
+        match command {
             Command::ChargeRate(inv, _) |
             Command::DischargeRate(inv, _) |
             Command::AcChargeRate(inv, _) |
@@ -700,65 +1030,70 @@ impl Coordinator {
             Command::ReadForcedDischargeTime(inv, _) |
             Command::AcCharge(inv, _) |
             Command::ChargePriority(inv, _) |
-            Command::ForcedDischarge(inv, _) => inv.clone(),
-        };
+            Command::ForcedDischarge(inv, _) |
+            Command::ConfirmHold(inv, _, _) |
+            Command::CancelChargePlan(inv) => inv,
+        }
+    }
+
+    /// Returns the register values read back, if the command was a read.
+    async fn run_command(&self, command: Command) -> Result<Vec<(u16, u16)>> {
+        let inverter = self.command_inverter(&command).clone();
 
         let write_inverter = commands::write_inverter::WriteInverter::new(
             self.channels.clone(),
             inverter.clone(),
             (*self.config).clone(),
+            self.write_policy.clone(),
         );
 
         match command {
             // Write operations - these are blocked by read_only mode
-            Command::ChargeRate(_, value) => write_inverter.set_charge_rate(value).await,
-            Command::DischargeRate(_, value) => write_inverter.set_discharge_rate(value).await,
-            Command::AcChargeRate(_, value) => write_inverter.set_ac_charge_rate(value).await,
-            Command::AcChargeSocLimit(_, value) => write_inverter.set_ac_charge_soc_limit(value).await,
-            Command::DischargeCutoffSocLimit(_, value) => write_inverter.set_discharge_cutoff_soc_limit(value).await,
-            Command::SetHold(_, register, value) => write_inverter.set_hold(register, value).await,
-            Command::WriteParam(_, register, value) => write_inverter.set_param(register, value).await,
-            Command::SetAcChargeTime(_, _, values) => write_inverter.set_ac_charge_time(values).await,
-            Command::SetAcFirstTime(_, _, values) => write_inverter.set_ac_first_time(values).await,
-            Command::SetChargePriorityTime(_, _, values) => write_inverter.set_charge_priority_time(values).await,
-            Command::SetForcedDischargeTime(_, _, values) => write_inverter.set_forced_discharge_time(values).await,
+            Command::ChargeRate(_, value) => write_inverter.set_charge_rate(value).await?,
+            Command::DischargeRate(_, value) => write_inverter.set_discharge_rate(value).await?,
+            Command::AcChargeRate(_, value) => write_inverter.set_ac_charge_rate(value).await?,
+            Command::AcChargeSocLimit(_, value) => write_inverter.set_ac_charge_soc_limit(value).await?,
+            Command::DischargeCutoffSocLimit(_, value) => write_inverter.set_discharge_cutoff_soc_limit(value).await?,
+            Command::SetHold(_, register, value) => write_inverter.set_hold(register, value).await?,
+            Command::WriteParam(_, register, value) => write_inverter.set_param(register, value).await?,
+            Command::SetAcChargeTime(_, _, values) => write_inverter.set_ac_charge_time(values).await?,
+            Command::SetAcFirstTime(_, _, values) => write_inverter.set_ac_first_time(values).await?,
+            Command::SetChargePriorityTime(_, _, values) => write_inverter.set_charge_priority_time(values).await?,
+            Command::SetForcedDischargeTime(_, _, values) => write_inverter.set_forced_discharge_time(values).await?,
             
             // Read operations - these are always allowed regardless of read_only mode
-            Command::ReadInputs(_, block) => self.read_input_block(&inverter, block * 40, inverter.register_block_size()).await,
-            Command::ReadInput(_, register, count) => self.read_input_registers(&inverter, register, count).await,
-            Command::ReadHold(_, register, count) => self.read_hold_registers(&inverter, register, count).await,
-            Command::ReadParam(_, register) => self.read_param_register(&inverter, register).await,
-            Command::ReadAcChargeTime(_, num) => self.read_ac_charge_time(&inverter, num).await,
-            Command::ReadAcFirstTime(_, num) => self.read_ac_first_time(&inverter, num).await,
-            Command::ReadChargePriorityTime(_, num) => self.read_charge_priority_time(&inverter, num).await,
-            Command::ReadForcedDischargeTime(_, num) => self.read_forced_discharge_time(&inverter, num).await,
+            Command::ReadInputs(_, block) => return self.read_input_block(&inverter, block * 40, inverter.register_block_size()).await.map(packet_values),
+            Command::ReadInput(_, register, count) => return self.read_input_registers(&inverter, register, count).await.map(packet_values),
+            Command::ReadHold(_, register, count) => return self.read_hold_registers(&inverter, register, count).await.map(packet_values),
+            Command::ReadParam(_, register) => return self.read_param_register(&inverter, register).await.map(packet_values),
+            Command::ReadAcChargeTime(_, num) => self.read_ac_charge_time(&inverter, num).await?,
+            Command::ReadAcFirstTime(_, num) => self.read_ac_first_time(&inverter, num).await?,
+            Command::ReadChargePriorityTime(_, num) => self.read_charge_priority_time(&inverter, num).await?,
+            Command::ReadForcedDischargeTime(_, num) => self.read_forced_discharge_time(&inverter, num).await?,
             
             // Enable/Disable operations - these are blocked by read_only mode
             Command::AcCharge(_, enable) => {
-                self.update_hold(
-                    inverter,
-                    Register::Register21,
-                    RegisterBit::AcChargeEnable,
-                    enable,
-                ).await
+                write_inverter.update_hold_bit(Register::Register21, RegisterBit::AcChargeEnable, enable).await?
             },
             Command::ChargePriority(_, enable) => {
-                self.update_hold(
-                    inverter,
-                    Register::Register21,
-                    RegisterBit::ChargePriorityEnable,
-                    enable,
-                ).await
+                write_inverter.update_hold_bit(Register::Register21, RegisterBit::ChargePriorityEnable, enable).await?
             },
             Command::ForcedDischarge(_, enable) => {
-                self.update_hold(
-                    inverter,
-                    Register::Register21,
-                    RegisterBit::ForcedDischargeEnable,
-                    enable,
-                ).await
+                write_inverter.update_hold_bit(Register::Register21, RegisterBit::ForcedDischargeEnable, enable).await?
+            },
+
+            // Confirmations only arm the write policy; nothing is sent to the inverter
+            Command::ConfirmHold(_, register, token) => {
+                self.write_policy.confirm(inverter.datalog().unwrap_or_default(), register, &token)?
+            },
+            Command::CancelChargePlan(_) => {
+                if let Some(datalog) = inverter.datalog() {
+                    self.channels.from_coordinator.send(ChannelData::CancelChargePlan(datalog))?;
+                }
             },
         }
+
+        Ok(Vec::new())
     }
 
     /// Read a block of input registers from the inverter
@@ -768,11 +1103,11 @@ impl Coordinator {
         inverter: &config::Inverter,
         register: U,
         count: u16,
-    ) -> Result<()>
+    ) -> Result<Packet>
     where
         U: Into<u16>,
     {
-        commands::read_inputs::ReadInputs::new(self.channels.clone(), inverter.clone(), register, count)
+        let packet = commands::read_inputs::ReadInputs::new(self.channels.clone(), inverter.clone(), register, count)
         .run()
         .await?;
 
@@ -781,16 +1116,16 @@ impl Coordinator {
             info!("read_input_block sleeping {} ms", inverter.delay_ms().unwrap_or(0));
             tokio::time::sleep(std::time::Duration::from_millis(inverter.delay_ms().unwrap_or(0))).await;
         }
-        Ok(())
+        Ok(packet)
     }
 
     /// Read specific input registers from the inverter
     /// This operation is always allowed regardless of read_only mode
-    async fn read_input_registers<U>(&self, inverter: &config::Inverter, register: U, count: u16) -> Result<()>
+    async fn read_input_registers<U>(&self, inverter: &config::Inverter, register: U, count: u16) -> Result<Packet>
     where
         U: Into<u16>,
     {
-        commands::read_inputs::ReadInputs::new(self.channels.clone(), inverter.clone(), register, count)
+        let packet = commands::read_inputs::ReadInputs::new(self.channels.clone(), inverter.clone(), register, count)
         .run()
         .await?;
 
@@ -799,16 +1134,16 @@ impl Coordinator {
             info!("read_input_registers sleeping {} ms", inverter.delay_ms().unwrap_or(0));
             tokio::time::sleep(std::time::Duration::from_millis(inverter.delay_ms().unwrap_or(0))).await;
         }
-        Ok(())
+        Ok(packet)
     }
 
     /// Read holding registers from the inverter
     /// This operation is always allowed regardless of read_only mode
-    async fn read_hold_registers<U>(&self, inverter: &config::Inverter, register: U, count: u16) -> Result<()>
+    async fn read_hold_registers<U>(&self, inverter: &config::Inverter, register: U, count: u16) -> Result<Packet>
     where
         U: Into<u16>,
     {
-        commands::read_hold::ReadHold::new(self.channels.clone(), inverter.clone(), register, count)
+        let packet = commands::read_hold::ReadHold::new(self.channels.clone(), inverter.clone(), register, count)
         .run()
         .await?;
 
@@ -817,16 +1152,16 @@ impl Coordinator {
             info!("read_hold_registers sleeping {} ms", inverter.delay_ms().unwrap_or(0));
             tokio::time::sleep(std::time::Duration::from_millis(inverter.delay_ms().unwrap_or(0))).await;
         }
-        Ok(())
+        Ok(packet)
     }
 
     /// Read a parameter register from the inverter
     /// This operation is always allowed regardless of read_only mode
-    async fn read_param_register<U>(&self, inverter: &config::Inverter, register: U) -> Result<()>
+    async fn read_param_register<U>(&self, inverter: &config::Inverter, register: U) -> Result<Packet>
     where
         U: Into<u16>,
     {
-        commands::read_param::ReadParam::new(self.channels.clone(), inverter.clone(), register)
+        let packet = commands::read_param::ReadParam::new(self.channels.clone(), inverter.clone(), register)
             .run()
             .await?;
 
@@ -835,7 +1170,7 @@ impl Coordinator {
             info!("read_param_register sleeping {} ms", inverter.delay_ms().unwrap_or(0));
             tokio::time::sleep(std::time::Duration::from_millis(inverter.delay_ms().unwrap_or(0))).await;
         }
-        Ok(())
+        Ok(packet)
     }
 
     /// Read AC charge time settings from the inverter
@@ -870,9 +1205,24 @@ impl Coordinator {
 
     async fn inverter_connected(&mut self, datalog: Serial) -> Result<()> {
         info!("Inverter {} connected", datalog);
+        self.send_event(Event::new(datalog, EventKind::Connected, "inverter connected".to_string()));
         Ok(())
     }
 
+    /// Best-effort send to the database tasks; a no-op when none are configured.
+    fn send_database(&self, data: database::ChannelData) {
+        if self.databases.is_empty() {
+            return;
+        }
+        if let Err(e) = self.channels.to_database.send(data) {
+            error!("Failed to send data to database channel: {}", e);
+        }
+    }
+
+    fn send_event(&self, event: Event) {
+        self.send_database(database::ChannelData::Event(event));
+    }
+
     async fn send_to_influx(&self, data: &TranslatedData) -> Result<()> {
         if self.influx.is_none() {
             debug!("InfluxDB client not initialized, skipping send");
@@ -912,7 +1262,7 @@ impl Coordinator {
         }
     }
 
-    fn cache_register(&self, register: u16, values: Vec<u8>) -> Result<()> {
+    fn cache_register(&self, datalog: Serial, register: u16, values: Vec<u8>) -> Result<()> {
         // Wire format matches `TranslatedData::pairs` / `Utils::u16ify` (little-endian).
         let values_u16: Vec<u16> = values
             .chunks(2)
@@ -928,7 +1278,7 @@ impl Coordinator {
         // Send each value to the register cache
         for (i, value) in values_u16.into_iter().enumerate() {
             let reg = register + i as u16;
-            self.channels.to_register_cache.send(register_cache::ChannelData::RegisterData(reg, value))?;
+            self.channels.to_register_cache.send(register_cache::ChannelData::RegisterData(datalog, reg, value))?;
         }
         Ok(())
     }
@@ -939,7 +1289,11 @@ impl Coordinator {
         }
         let messages = match data.device_function {
             DeviceFunction::ReadHold | DeviceFunction::ReadHoldError => {
-                mqtt::Message::for_hold(data.clone())?
+                let mut messages = mqtt::Message::for_hold(data.clone())?;
+                if let Some(registers) = &self.field_registers {
+                    messages.extend(crate::fields::for_holds(data, registers));
+                }
+                messages
             }
             _ => mqtt::Message::for_input_with_parsed(
                 data.clone(),
@@ -966,12 +1320,7 @@ impl Coordinator {
         Ok(entry.to_input_all())
     }
 
-    fn send_to_database(&self, data: &TranslatedData, parsed_input: Option<&ReadInput>) -> Result<()> {
-        if self.databases.is_empty() {
-            debug!("No databases configured, skipping send");
-            return Ok(());
-        }
-
+    fn send_input_all(&self, data: &TranslatedData, parsed_input: Option<&ReadInput>) -> Result<()> {
         if data.device_function != DeviceFunction::ReadInput {
             return Ok(());
         }
@@ -990,17 +1339,36 @@ impl Coordinator {
             ReadInput::ReadInput6(r6) => self.update_inputs_store(data.datalog, |entry| entry.set_read_input_6(r6))?,
         };
 
-        if let Some(input_all) = maybe_input_all {
-            let _ = self
-                .inputs_store
-                .lock()
-                .map_err(|_| anyhow!("Failed to lock input store"))?
-                .remove(&data.datalog);
-            self.channels
-                .to_database
-                .send(database::ChannelData::ReadInputAll(Box::new(input_all)))
-                .map_err(|e| anyhow!("Failed to send data to database channel: {}", e))?;
+        let Some(input_all) = maybe_input_all else {
+            return Ok(());
+        };
+
+        let _ = self
+            .inputs_store
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock input store"))?
+            .remove(&data.datalog);
+
+        if self.config.mqtt().enabled() && self.config.mqtt().publish_fields() {
+            for message in crate::fields::for_inputs(&input_all)? {
+                self.channels.to_mqtt.send(mqtt::ChannelData::Message(message))?;
+            }
+        }
+
+        // No subscribers is not an error; nothing may be interested in complete sets
+        let _ = self
+            .channels
+            .from_coordinator
+            .send(ChannelData::ReadInputAll(Box::new(input_all.clone())));
+
+        if self.databases.is_empty() {
+            debug!("No databases configured, skipping send");
+            return Ok(());
         }
+        self.channels
+            .to_database
+            .send(database::ChannelData::ReadInputAll(Box::new(input_all)))
+            .map_err(|e| anyhow!("Failed to send data to database channel: {}", e))?;
 
         Ok(())
     }
@@ -1009,17 +1377,6 @@ impl Coordinator {
         self.read_time_register(inverter, Action::ForcedDischarge(num)).await
     }
 
-    async fn update_hold(&self, inverter: config::Inverter, register: Register, bit: RegisterBit, enable: bool) -> Result<()> {
-        let update_hold = commands::update_hold::UpdateHold::new(
-            self.channels.clone(),
-            inverter,
-            register.into(),
-            bit,
-            enable,
-        );
-        update_hold.run().await
-    }
-
     async fn read_time_register(&self, inverter: &config::Inverter, action: Action) -> Result<()> {
         ReadTimeRegister::new(
             self.channels.clone(),
@@ -1031,9 +1388,41 @@ impl Coordinator {
         .await
     }
 
-    pub async fn app(_shutdown_rx: broadcast::Receiver<()>, config: Arc<ConfigWrapper>) -> Result<()> {
+    pub async fn app(mut shutdown_rx: broadcast::Receiver<()>, config: Arc<ConfigWrapper>) -> Result<()> {
         let channels = Channels::new();
-        let mut coordinator = Self::new(config, channels);
-        coordinator.start().await
+        let mut coordinator = Self::new(config, channels.clone());
+        let controllers = coordinator.controllers.clone();
+
+        let run = coordinator.start();
+        tokio::pin!(run);
+        tokio::select! {
+            result = &mut run => return result,
+            Ok(()) = shutdown_rx.recv() => {}
+        }
+
+        // keep processing commands until whatever the controllers changed is put back
+        info!("Shutting down, restoring controlled settings");
+        for command in controllers.restore_commands() {
+            let source = Source::restore(Origin::Controller, "restore on shutdown");
+            let _ = channels.to_coordinator.send(ChannelData::Command(command, source));
+        }
+        let _ = channels.to_coordinator.send(ChannelData::Shutdown);
+        match tokio::time::timeout(SHUTDOWN_TIMEOUT, run).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!("Timed out restoring controlled settings");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Register values carried by a reply packet.
+fn packet_values(packet: Packet) -> Vec<(u16, u16)> {
+    match packet {
+        Packet::TranslatedData(td) => td.pairs(),
+        Packet::ReadParam(rp) => rp.pairs(),
+        Packet::WriteParam(wp) => wp.pairs(),
+        Packet::Heartbeat(_) => Vec::new(),
     }
 }
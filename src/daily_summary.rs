@@ -0,0 +1,248 @@
+//! End-of-day energy totals.
+//!
+//! The inverter's `e_*_day` counters reset at its own midnight and nothing else keeps the
+//! final value. Complete input sets arrive on `from_coordinator`; when an inverter's day
+//! counters drop sharply and either the local date has changed or the drop is still there in
+//! the next input set, we treat it as a rollover. A single glitched set, such as a partial
+//! read or an inverter restart, is ignored. On a rollover the last totals seen before it are
+//! published, together with a few derived metrics, to MQTT, the databases and InfluxDB.
+
+use crate::prelude::*;
+use crate::eg4::packet::ReadInputAll;
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+static MEASUREMENT: &str = "eg4_daily_summary";
+
+/// Totals for one inverter-day, in kWh unless noted.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Summary {
+    pub datalog: Serial,
+    /// Local date the totals belong to
+    pub date: chrono::NaiveDate,
+    pub pv: f64,
+    pub consumption: f64,
+    pub grid_import: f64,
+    pub grid_export: f64,
+    pub charge: f64,
+    pub discharge: f64,
+    /// charge + discharge
+    pub battery_throughput: f64,
+    /// grid_import - grid_export; negative when the day was a net export
+    pub net_import: f64,
+    /// Percentage of PV used on site rather than exported; None without PV
+    pub self_consumption: Option<f64>,
+    /// Percentage of consumption not met by grid import; None without consumption
+    pub self_sufficiency: Option<f64>,
+}
+
+impl Summary {
+    pub fn from_inputs(input: &ReadInputAll) -> Self {
+        let pv = input.e_pv_day;
+        let import = input.e_to_user_day;
+        let export = input.e_to_grid_day;
+        let charge = input.e_chg_day;
+        let discharge = input.e_dischg_day;
+        let consumption = consumption(input);
+
+        let self_consumption = (pv > 0.0).then(|| percent(pv - export, pv));
+        let self_sufficiency = (consumption > 0.0).then(|| percent(consumption - import, consumption));
+
+        Self {
+            datalog: input.datalog,
+            date: input.time.0.with_timezone(&chrono::Local).date_naive(),
+            pv: round(pv),
+            consumption: round(consumption),
+            grid_import: round(import),
+            grid_export: round(export),
+            charge: round(charge),
+            discharge: round(discharge),
+            battery_throughput: round(charge + discharge),
+            net_import: round(import - export),
+            self_consumption,
+            self_sufficiency,
+        }
+    }
+
+    pub fn mqtt_message(&self) -> Result<mqtt::Message> {
+        Ok(mqtt::Message {
+            topic: format!("{}/summary/daily", self.datalog),
+            retain: true,
+            payload: serde_json::to_string(self)?,
+        })
+    }
+
+    /// JSON for `influx::ChannelData::Measurement`, timestamped at local midnight starting the day.
+    pub fn influx_value(&self) -> Result<serde_json::Value> {
+        let start = self
+            .date
+            .and_hms_opt(0, 0, 0)
+            .and_then(|t| t.and_local_timezone(chrono::Local).earliest())
+            .ok_or_else(|| anyhow!("no local midnight for {}", self.date))?;
+
+        let mut value = serde_json::to_value(self)?;
+        if let Some(obj) = value.as_object_mut() {
+            obj.insert("time".to_string(), serde_json::Value::from(start.timestamp()));
+        }
+        Ok(value)
+    }
+}
+
+/// Energy used on site today: inverter output plus grid import, less what went back out to
+/// the grid or was rectified into the battery.
+pub fn consumption(input: &ReadInputAll) -> f64 {
+    (input.e_inv_day + input.e_to_user_day - input.e_to_grid_day - input.e_rec_day).max(0.0)
+}
+
+fn round(v: f64) -> f64 {
+    (v * 10.0).round() / 10.0
+}
+
+fn percent(part: f64, whole: f64) -> f64 {
+    round((part / whole * 100.0).clamp(0.0, 100.0))
+}
+
+/// Remembers the latest input set per inverter and spots day rollovers.
+#[derive(Default)]
+pub struct Tracker {
+    last: HashMap<Serial, ReadInputAll>,
+    /// Inverters whose last input set dropped on the same local date, awaiting a second one
+    dropped: HashSet<Serial>,
+}
+
+impl Tracker {
+    /// Feed a complete input set; returns the finished day's summary once the inverter has
+    /// reset its day counters.
+    pub fn update(&mut self, input: &ReadInputAll) -> Option<Summary> {
+        let Some(previous) = self.last.get(&input.datalog) else {
+            self.last.insert(input.datalog, input.clone());
+            return None;
+        };
+
+        if !Self::dropped(previous, input) {
+            self.dropped.remove(&input.datalog);
+            self.last.insert(input.datalog, input.clone());
+            return None;
+        }
+
+        // keep the totals from before the drop until it is confirmed
+        if Self::date(input) == Self::date(previous) && self.dropped.insert(input.datalog) {
+            return None;
+        }
+
+        self.dropped.remove(&input.datalog);
+        let previous = self.last.insert(input.datalog, input.clone())?;
+        Some(Summary::from_inputs(&previous))
+    }
+
+    fn dropped(previous: &ReadInputAll, current: &ReadInputAll) -> bool {
+        let before = Self::day_total(previous);
+        // Counters only grow during a day, but require a real drop so a rounding wobble
+        // isn't mistaken for midnight.
+        before > 0.0 && Self::day_total(current) < before / 2.0
+    }
+
+    fn date(input: &ReadInputAll) -> chrono::NaiveDate {
+        input.time.0.with_timezone(&chrono::Local).date_naive()
+    }
+
+    fn day_total(input: &ReadInputAll) -> f64 {
+        input.e_pv_day
+            + input.e_inv_day
+            + input.e_rec_day
+            + input.e_chg_day
+            + input.e_dischg_day
+            + input.e_eps_day
+            + input.e_to_grid_day
+            + input.e_to_user_day
+    }
+}
+
+#[derive(Clone)]
+pub struct DailySummary {
+    config: ConfigWrapper,
+    channels: Channels,
+    tracker: Arc<Mutex<Tracker>>,
+    shutdown: CancellationToken,
+}
+
+impl DailySummary {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        Self {
+            config,
+            channels,
+            tracker: Arc::new(Mutex::new(Tracker::default())),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver = self.channels.from_coordinator.subscribe();
+        info!("daily summary started");
+
+        loop {
+            let msg = tokio::select! {
+                msg = receiver.recv() => msg,
+                _ = self.shutdown.cancelled() => break,
+            };
+            match msg {
+                Ok(coordinator::ChannelData::ReadInputAll(input)) => {
+                    let summary = self
+                        .tracker
+                        .lock()
+                        .map_err(|_| anyhow!("Failed to lock daily summary tracker"))?
+                        .update(&input);
+                    if let Some(summary) = summary {
+                        if let Err(e) = self.publish(&summary) {
+                            error!("Failed to publish daily summary for {}: {}", summary.datalog, e);
+                        }
+                    }
+                }
+                Ok(coordinator::ChannelData::Shutdown) => break,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("daily summary lagged, skipped {} messages", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+
+        info!("daily summary exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    fn publish(&self, summary: &Summary) -> Result<()> {
+        info!(
+            "daily summary for {} on {}: pv={} kWh consumption={} kWh net_import={} kWh",
+            summary.datalog, summary.date, summary.pv, summary.consumption, summary.net_import
+        );
+
+        if self.config.mqtt().enabled() {
+            self.channels
+                .to_mqtt
+                .send(mqtt::ChannelData::Message(summary.mqtt_message()?))?;
+        }
+
+        if self.config.have_enabled_database() {
+            self.channels
+                .to_database
+                .send(database::ChannelData::DailySummary(summary.clone()))?;
+        }
+
+        if self.config.influx().enabled() {
+            self.channels.to_influx.send(influx::ChannelData::Measurement(
+                MEASUREMENT.to_string(),
+                summary.influx_value()?,
+            ))?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,5 +1,9 @@
 use crate::prelude::*;
-use sqlx::{postgres::PgConnectOptions, postgres::PgPoolOptions, PgPool};
+use crate::command::Origin;
+use crate::eg4::packet::{FaultCodeString, WarningCodeString};
+use sqlx::any::{AnyArguments, AnyPoolOptions};
+use sqlx::{Any, AnyPool, Row};
+use std::collections::HashMap;
 use std::sync::RwLock;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -8,23 +12,146 @@ use crate::coordinator::PacketStats;
 #[derive(Debug, Clone, PartialEq)]
 pub enum ChannelData {
     ReadInputAll(Box<eg4::packet::ReadInputAll>),
+    /// (register, value) pairs read from or written to holding registers
+    HoldData(Serial, Vec<(u16, u16)>),
+    /// (register, value) pairs from a ReadParam reply
+    ParamData(Serial, Vec<(u16, u16)>),
+    Event(Event),
+    DailySummary(crate::daily_summary::Summary),
+    TariffCosts(crate::tariff::Costs),
+    BatteryHealth(crate::battery_health::Health),
+    Write(Box<crate::audit::Record>),
     Shutdown,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Fault,
+    Warning,
+    Connected,
+    Disconnected,
+    Write,
+    /// A write refused by the write policy
+    Rejected,
+    /// Battery cell voltage spread alarm raised or cleared
+    CellSpread,
+}
+
+impl EventKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Fault => "fault",
+            EventKind::Warning => "warning",
+            EventKind::Connected => "connected",
+            EventKind::Disconnected => "disconnected",
+            EventKind::Write => "write",
+            EventKind::Rejected => "rejected",
+            EventKind::CellSpread => "cell_spread",
+        }
+    }
+}
+
+/// A row for the `events` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub datalog: Serial,
+    pub kind: EventKind,
+    pub code: Option<u32>,
+    pub detail: String,
+    pub origin: Option<Origin>,
+}
+
+impl Event {
+    pub fn new(datalog: Serial, kind: EventKind, detail: String) -> Self {
+        Self {
+            datalog,
+            kind,
+            code: None,
+            detail,
+            origin: None,
+        }
+    }
+}
+
 pub type Sender = broadcast::Sender<ChannelData>;
 
+type Query<'q> = sqlx::query::Query<'q, Any, AnyArguments<'q>>;
+
+/// Columns written to `inputs`, in bind order. `created_at` must stay last (see `values`).
+const INPUT_COLUMNS: &[&str] = &[
+    "status", "v_pv_1", "v_pv_2", "v_pv_3", "v_bat", "soc", "soh", "internal_fault", "p_pv",
+    "p_pv_1", "p_pv_2", "p_pv_3", "p_battery", "p_charge", "p_discharge", "v_ac_r", "v_ac_s",
+    "v_ac_t", "f_ac", "p_inv", "p_rec", "pf", "v_eps_r", "v_eps_s", "v_eps_t", "f_eps", "p_eps",
+    "s_eps", "p_grid", "p_to_grid", "p_to_user", "e_pv_day", "e_pv_day_1", "e_pv_day_2",
+    "e_pv_day_3", "e_inv_day", "e_rec_day", "e_chg_day", "e_dischg_day", "e_eps_day",
+    "e_to_grid_day", "e_to_user_day", "v_bus_1", "v_bus_2", "e_pv_all", "e_pv_all_1",
+    "e_pv_all_2", "e_pv_all_3", "e_inv_all", "e_rec_all", "e_chg_all", "e_dischg_all",
+    "e_eps_all", "e_to_grid_all", "e_to_user_all", "fault_code", "warning_code", "t_inner",
+    "t_rad_1", "t_rad_2", "t_bat", "runtime", "max_chg_curr", "max_dischg_curr",
+    "charge_volt_ref", "dischg_cut_volt", "bat_status_0", "bat_status_1", "bat_status_2",
+    "bat_status_3", "bat_status_4", "bat_status_5", "bat_status_6", "bat_status_7",
+    "bat_status_8", "bat_status_9", "bat_status_inv", "bat_count", "bat_capacity",
+    "bat_current", "bms_event_1", "bms_event_2", "max_cell_voltage", "min_cell_voltage",
+    "max_cell_temp", "min_cell_temp", "bms_fw_update_state", "cycle_count", "vbat_inv",
+    "datalog", "created_at",
+];
+
+/// Power columns rolled up as min/avg/max into `inputs_hourly` and `inputs_daily`.
+const ROLLUP_POWERS: &[&str] = &[
+    "p_pv", "p_battery", "p_charge", "p_discharge", "p_inv", "p_rec", "p_eps", "p_grid",
+    "p_to_grid", "p_to_user",
+];
+
+/// Lifetime energy counters rolled up as a delta (max - min) over the bucket.
+const ROLLUP_ENERGIES: &[&str] = &[
+    "e_pv_all", "e_inv_all", "e_rec_all", "e_chg_all", "e_dischg_all", "e_eps_all",
+    "e_to_grid_all", "e_to_user_all",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum DatabaseType {
     MySQL,
     Postgres,
     SQLite,
 }
 
+impl DatabaseType {
+    /// Bind placeholder for the `n`th (1-based) parameter.
+    fn placeholder(&self, n: usize) -> String {
+        match self {
+            DatabaseType::Postgres => format!("${}", n),
+            _ => "?".to_string(),
+        }
+    }
+
+    /// Most bind parameters one statement may carry.
+    fn max_binds(&self) -> usize {
+        match self {
+            DatabaseType::SQLite => 32766,
+            _ => 65535,
+        }
+    }
+
+    /// SQL expression converting Unix seconds to the dialect's `created_at` type.
+    fn unixtime(&self, expr: &str) -> String {
+        match self {
+            DatabaseType::Postgres => format!("to_timestamp({})", expr),
+            DatabaseType::MySQL => format!("FROM_UNIXTIME({})", expr),
+            DatabaseType::SQLite => format!("datetime({}, 'unixepoch')", expr),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Database {
     config: config::Database,
     channels: Channels,
-    pool: Arc<RwLock<Option<PgPool>>>,
+    pool: Arc<RwLock<Option<AnyPool>>>,
     shared_stats: Arc<Mutex<PacketStats>>,
+    /// Last known value of each holding register, to record only changes
+    holds: Arc<Mutex<HashMap<(Serial, u16), u16>>>,
+    /// Last seen (fault_code, warning_code) per datalog
+    codes: Arc<Mutex<HashMap<Serial, (u32, u32)>>>,
 }
 
 impl Database {
@@ -34,6 +161,8 @@ impl Database {
             channels,
             pool: Arc::new(RwLock::new(None)),
             shared_stats,
+            holds: Arc::new(Mutex::new(HashMap::new())),
+            codes: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
@@ -43,8 +172,8 @@ impl Database {
         // Connect and migrate before starting the inserter
         self.connect().await?;
         self.migrate().await?;
-        
-        futures::try_join!(self.inserter())?;
+
+        futures::try_join!(self.inserter(), self.maintainer())?;
 
         info!("database loop exiting");
 
@@ -60,33 +189,30 @@ impl Database {
         match prefix[0] {
             "sqlite" => Ok(DatabaseType::SQLite),
             "mysql" => Ok(DatabaseType::MySQL),
-            "postgres" => Ok(DatabaseType::Postgres),
+            "postgres" | "postgresql" => Ok(DatabaseType::Postgres),
             _ => Err(anyhow!("database.rs:unsupported database {}", self.config.url())),
         }
     }
 
     async fn connect(&self) -> Result<()> {
         info!("Connecting to database: {}", self.config.url());
-        
-        // For now, only support PostgreSQL
-        match self.database()? {
-            DatabaseType::Postgres => {
-                let options = PgConnectOptions::from_str(self.config.url())?;
-                let pool = PgPoolOptions::new()
-                    .max_connections(5)
-                    .min_connections(1)
-                    .acquire_timeout(std::time::Duration::from_secs(30))
-                    .connect_with(options)
-                    .await?;
-                info!("Database connected successfully");
-                *self.pool.write().map_err(|_| anyhow::anyhow!("Failed to acquire write lock"))? = Some(pool);
-                Ok(())
-            }
-            _ => Err(anyhow::anyhow!("Only PostgreSQL is currently supported"))
-        }
+
+        // fail early on unsupported URLs rather than inside the pool
+        self.database()?;
+        sqlx::any::install_default_drivers();
+
+        let pool = AnyPoolOptions::new()
+            .max_connections(5)
+            .min_connections(1)
+            .acquire_timeout(std::time::Duration::from_secs(30))
+            .connect(self.config.url())
+            .await?;
+        info!("Database connected successfully");
+        *self.pool.write().map_err(|_| anyhow::anyhow!("Failed to acquire write lock"))? = Some(pool);
+        Ok(())
     }
 
-    pub async fn connection(&self) -> Result<PgPool> {
+    pub async fn connection(&self) -> Result<AnyPool> {
         match &*self.pool.read().map_err(|_| anyhow::anyhow!("Failed to acquire read lock"))? {
             Some(pool) => Ok(pool.clone()),
             None => Err(anyhow::anyhow!("database.rs:Database not connected"))
@@ -97,8 +223,11 @@ impl Database {
         info!("Starting database migration");
         let pool = self.connection().await?;
 
-        // Use PostgreSQL migrations
-        let migrator = sqlx::migrate!("db/migrations/postgres");
+        let migrator = match self.database()? {
+            DatabaseType::Postgres => sqlx::migrate!("db/migrations/postgres"),
+            DatabaseType::MySQL => sqlx::migrate!("db/migrations/mysql"),
+            DatabaseType::SQLite => sqlx::migrate!("db/migrations/sqlite"),
+        };
 
         info!("Running database migrations");
         migrator.run(&pool).await?;
@@ -110,66 +239,491 @@ impl Database {
     async fn inserter(&self) -> Result<()> {
         let mut receiver = self.channels.to_database.subscribe();
 
-        // wait for database to be ready
-        self.connect().await?;
+        // one row binds every input column, so a batch must fit the backend's bind limit
+        let max_rows = self.database()?.max_binds() / INPUT_COLUMNS.len();
+        let batch_size = self.config.batch_size().min(max_rows);
+        if batch_size < self.config.batch_size() {
+            warn!(
+                "database batch_size {} is over the {} rows one insert can hold, using {}",
+                self.config.batch_size(),
+                max_rows,
+                batch_size
+            );
+        }
+        let mut flush_timer = tokio::time::interval(std::time::Duration::from_secs(
+            self.config.flush_interval().max(1),
+        ));
+        flush_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
-        let query = format!(
-            "INSERT INTO inputs ({}) VALUES {}",
-            self.columns(),
-            match self.database()? {
-                DatabaseType::MySQL => Database::values_for_mysql(),
-                _ => Database::values_for_not_mysql(),
-            }
-        );
+        let mut batch: Vec<eg4::packet::ReadInputAll> = Vec::new();
 
         loop {
             use ChannelData::*;
 
-            match receiver.recv().await? {
-                Shutdown => break,
-                ReadInputAll(data) => {
-                    let mut retry_count = 0;
-                    let max_retries = 3;
-                    let mut backoff = 1;
-                    
-                    while retry_count < max_retries {
-                        match self.insert(&query, &data).await {
-                            Ok(_) => {
-                                // Increment stats after successful insert
-                                if let Ok(mut stats) = self.shared_stats.lock() {
-                                    stats.database_writes += 1;
-                                    debug!("Incremented database writes counter to {}", stats.database_writes);
-                                }
-                                break;
-                            }
-                            Err(err) => {
-                                error!("INSERT failed: {:?} - retrying in {}s", err, backoff);
-                                if let Ok(mut stats) = self.shared_stats.lock() {
-                                    stats.database_errors += 1;
-                                }
-                                tokio::time::sleep(std::time::Duration::from_secs(backoff)).await;
-                                retry_count += 1;
-                                backoff *= 2;
-                            }
+            tokio::select! {
+                msg = receiver.recv() => match msg {
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("database lagged, skipped {} messages", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) | Ok(Shutdown) => break,
+                    Ok(ReadInputAll(data)) => {
+                        for event in self.code_transitions(&data)? {
+                            self.record(self.insert_event(&event).await);
                         }
+                        batch.push(*data);
+                        if batch.len() >= batch_size {
+                            self.flush(std::mem::take(&mut batch)).await;
+                        }
+                    }
+                    Ok(HoldData(datalog, pairs)) => self.record(self.insert_holds(datalog, pairs).await),
+                    Ok(ParamData(datalog, pairs)) => self.record(self.insert_params(datalog, pairs).await),
+                    Ok(Event(event)) => self.record(self.insert_event(&event).await),
+                    Ok(DailySummary(summary)) => self.record(self.insert_daily_summary(&summary).await),
+                    Ok(BatteryHealth(health)) => self.record(self.insert_battery_health(&health).await),
+                    Ok(TariffCosts(costs)) => self.record(self.insert_tariff_costs(&costs).await),
+                    Ok(Write(record)) => self.record(self.insert_write(&record).await),
+                },
+                _ = flush_timer.tick() => {
+                    if !batch.is_empty() {
+                        self.flush(std::mem::take(&mut batch)).await;
+                    }
+                }
+            }
+        }
+
+        if !batch.is_empty() {
+            self.flush(batch).await;
+        }
+
+        Ok(())
+    }
+
+    async fn flush(&self, rows: Vec<eg4::packet::ReadInputAll>) {
+        let mut retry_count = 0;
+        let max_retries = 3;
+        let mut backoff = 1;
+
+        while retry_count < max_retries {
+            match self.insert(&rows).await {
+                Ok(_) => {
+                    // Increment stats after successful insert
+                    if let Ok(mut stats) = self.shared_stats.lock() {
+                        stats.database_writes += rows.len() as u64;
+                        debug!("Incremented database writes counter to {}", stats.database_writes);
                     }
-                    
-                    if retry_count == max_retries {
-                        error!("Failed to insert data after {} retries", max_retries);
+                    break;
+                }
+                Err(err) => {
+                    error!("INSERT failed: {:?} - retrying in {}s", err, backoff);
+                    if let Ok(mut stats) = self.shared_stats.lock() {
+                        stats.database_errors += 1;
                     }
+                    tokio::time::sleep(std::time::Duration::from_secs(backoff)).await;
+                    retry_count += 1;
+                    backoff *= 2;
                 }
             }
         }
 
+        if retry_count == max_retries {
+            error!("Failed to insert {} rows after {} retries", rows.len(), max_retries);
+        }
+    }
+
+    /// Count the outcome of a one-shot insert; these are not retried.
+    fn record(&self, result: Result<()>) {
+        if let Ok(mut stats) = self.shared_stats.lock() {
+            match result {
+                Ok(_) => stats.database_writes += 1,
+                Err(err) => {
+                    error!("INSERT failed: {:?}", err);
+                    stats.database_errors += 1;
+                }
+            }
+        }
+    }
+
+    /// Fault/warning code changes since the last row seen for this datalog.
+    fn code_transitions(&self, data: &eg4::packet::ReadInputAll) -> Result<Vec<Event>> {
+        let (old_fault, old_warning) = self
+            .codes
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock fault code store"))?
+            .insert(data.datalog, (data.fault_code, data.warning_code))
+            .unwrap_or((0, 0));
+
+        let mut events = Vec::new();
+        if data.fault_code != old_fault {
+            events.push(Event {
+                code: Some(data.fault_code),
+                ..Event::new(
+                    data.datalog,
+                    EventKind::Fault,
+                    format!(
+                        "{} (was {})",
+                        FaultCodeString::from_value(data.fault_code),
+                        FaultCodeString::from_value(old_fault)
+                    ),
+                )
+            });
+        }
+        if data.warning_code != old_warning {
+            events.push(Event {
+                code: Some(data.warning_code),
+                ..Event::new(
+                    data.datalog,
+                    EventKind::Warning,
+                    format!(
+                        "{} (was {})",
+                        WarningCodeString::from_value(data.warning_code),
+                        WarningCodeString::from_value(old_warning)
+                    ),
+                )
+            });
+        }
+
+        Ok(events)
+    }
+
+    /// Record holding registers whose value differs from the last one seen. The first
+    /// value seen after startup is stored as a snapshot with a NULL old_value.
+    async fn insert_holds(&self, datalog: Serial, pairs: Vec<(u16, u16)>) -> Result<()> {
+        let changes: Vec<(u16, Option<u16>, u16)> = {
+            let mut holds = self
+                .holds
+                .lock()
+                .map_err(|_| anyhow!("Failed to lock hold store"))?;
+            pairs
+                .into_iter()
+                .filter_map(|(register, value)| {
+                    let old = holds.insert((datalog, register), value);
+                    (old != Some(value)).then_some((register, old, value))
+                })
+                .collect()
+        };
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let sql = format!(
+            "INSERT INTO holds (datalog, register, old_value, new_value, created_at) VALUES {}",
+            self.values(5, changes.len())?
+        );
+        let now = Utils::utc().timestamp();
+        let mut query = sqlx::query(&sql);
+        for (register, old, new) in changes {
+            query = query
+                .bind(datalog.to_string())
+                .bind(register as i64)
+                .bind(old.map(i64::from))
+                .bind(new as i64)
+                .bind(now);
+        }
+
+        let pool = self.connection().await?;
+        query.execute(&pool).await?;
         Ok(())
     }
 
-    async fn insert(&self, query: &str, data: &eg4::packet::ReadInputAll) -> Result<()> {
+    async fn insert_params(&self, datalog: Serial, pairs: Vec<(u16, u16)>) -> Result<()> {
+        if pairs.is_empty() {
+            return Ok(());
+        }
+
+        let sql = format!(
+            "INSERT INTO params (datalog, register, value, created_at) VALUES {}",
+            self.values(4, pairs.len())?
+        );
+        let now = Utils::utc().timestamp();
+        let mut query = sqlx::query(&sql);
+        for (register, value) in pairs {
+            query = query
+                .bind(datalog.to_string())
+                .bind(register as i64)
+                .bind(value as i64)
+                .bind(now);
+        }
+
         let pool = self.connection().await?;
-        let mut conn = pool.acquire().await?;
+        query.execute(&pool).await?;
+        Ok(())
+    }
+
+    async fn insert_event(&self, event: &Event) -> Result<()> {
+        let sql = format!(
+            "INSERT INTO events (datalog, kind, code, detail, origin, created_at) VALUES {}",
+            self.values(6, 1)?
+        );
+
+        let pool = self.connection().await?;
+        sqlx::query(&sql)
+            .bind(event.datalog.to_string())
+            .bind(event.kind.as_str())
+            .bind(event.code.map(i64::from))
+            .bind(event.detail.clone())
+            .bind(event.origin.map(|o| o.to_string()))
+            .bind(Utils::utc().timestamp())
+            .execute(&pool)
+            .await?;
+        Ok(())
+    }
 
+    async fn insert_write(&self, record: &crate::audit::Record) -> Result<()> {
+        let sql = format!(
+            "INSERT INTO writes (datalog, inverter, device_function, register, old_value, new_value, origin, source, result, created_at) VALUES {}",
+            self.values(10, 1)?
+        );
+
+        let frame = &record.frame;
+        let pool = self.connection().await?;
+        sqlx::query(&sql)
+            .bind(frame.datalog.to_string())
+            .bind(frame.inverter.map(|s| s.to_string()))
+            .bind(frame.device_function.clone())
+            .bind(frame.register as i64)
+            .bind(frame.old_value.map(i64::from))
+            .bind(frame.new_value.map(i64::from))
+            .bind(record.origin.map(|o| o.to_string()))
+            .bind(record.source.clone())
+            .bind(record.result.clone())
+            .bind(record.time.timestamp())
+            .execute(&pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Store one inverter-day, replacing any earlier row for the same day.
+    async fn insert_daily_summary(&self, summary: &crate::daily_summary::Summary) -> Result<()> {
+        let db = self.database()?;
+        let delete = format!(
+            "DELETE FROM daily_summary WHERE datalog = {} AND day = {}",
+            db.placeholder(1),
+            db.placeholder(2)
+        );
+        let insert = format!(
+            "INSERT INTO daily_summary (datalog, day, pv, consumption, grid_import, grid_export, \
+             charge, discharge, battery_throughput, net_import, self_consumption, self_sufficiency, \
+             created_at) VALUES {}",
+            self.values(13, 1)?
+        );
+        let day = summary.date.to_string();
+
+        let pool = self.connection().await?;
+        let mut tx = pool.begin().await?;
+        sqlx::query(&delete)
+            .bind(summary.datalog.to_string())
+            .bind(day.clone())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&insert)
+            .bind(summary.datalog.to_string())
+            .bind(day)
+            .bind(summary.pv)
+            .bind(summary.consumption)
+            .bind(summary.grid_import)
+            .bind(summary.grid_export)
+            .bind(summary.charge)
+            .bind(summary.discharge)
+            .bind(summary.battery_throughput)
+            .bind(summary.net_import)
+            .bind(summary.self_consumption)
+            .bind(summary.self_sufficiency)
+            .bind(Utils::utc().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Store one inverter-day of battery health, replacing any earlier row for the same day.
+    async fn insert_battery_health(&self, health: &crate::battery_health::Health) -> Result<()> {
+        let db = self.database()?;
+        let delete = format!(
+            "DELETE FROM battery_health WHERE datalog = {} AND day = {}",
+            db.placeholder(1),
+            db.placeholder(2)
+        );
+        let insert = format!(
+            "INSERT INTO battery_health (datalog, day, soh, cycle_count, soh_per_100_cycles, max_cell_spread, \
+             max_cell_temp_spread, charge, discharge, efficiency, lifetime_efficiency, usable_capacity, \
+             created_at) VALUES {}",
+            self.values(13, 1)?
+        );
+        let day = health.date.to_string();
+
+        let pool = self.connection().await?;
+        let mut tx = pool.begin().await?;
+        sqlx::query(&delete)
+            .bind(health.datalog.to_string())
+            .bind(day.clone())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&insert)
+            .bind(health.datalog.to_string())
+            .bind(day)
+            .bind(health.soh as i64)
+            .bind(health.cycle_count as i64)
+            .bind(health.soh_per_100_cycles)
+            .bind(health.max_cell_spread)
+            .bind(health.max_cell_temp_spread)
+            .bind(health.charge)
+            .bind(health.discharge)
+            .bind(health.efficiency)
+            .bind(health.lifetime_efficiency)
+            .bind(health.usable_capacity)
+            .bind(Utils::utc().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// SOH and cycle count of the earliest battery health row, the start of the SOH trend.
+    pub async fn battery_baseline(&self, datalog: Serial) -> Result<Option<(i8, u16)>> {
+        let db = self.database()?;
+        let query = format!(
+            "SELECT soh, cycle_count FROM battery_health WHERE datalog = {} ORDER BY day LIMIT 1",
+            db.placeholder(1)
+        );
+
+        let pool = self.connection().await?;
+        let row: Option<(i64, i64)> = sqlx::query_as(&query)
+            .bind(datalog.to_string())
+            .fetch_optional(&pool)
+            .await?;
+        Ok(row.map(|(soh, cycle_count)| (soh as i8, cycle_count as u16)))
+    }
+
+    /// Mean daily consumption (kWh) over the most recent `days` daily summaries, if any.
+    pub async fn average_consumption(&self, datalog: Serial, days: u32) -> Result<Option<f64>> {
+        let db = self.database()?;
+        let query = format!(
+            "SELECT consumption FROM daily_summary WHERE datalog = {} ORDER BY day DESC LIMIT {}",
+            db.placeholder(1),
+            days
+        );
+
+        let pool = self.connection().await?;
+        let rows: Vec<f64> = sqlx::query_scalar(&query)
+            .bind(datalog.to_string())
+            .fetch_all(&pool)
+            .await?;
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(rows.iter().sum::<f64>() / rows.len() as f64))
+    }
+
+    /// The stored running totals for a day or month, if any.
+    pub async fn tariff_costs(
+        &self,
+        datalog: Serial,
+        period: crate::tariff::Period,
+        period_start: &str,
+    ) -> Result<Option<crate::tariff::Costs>> {
+        let db = self.database()?;
+        let query = format!(
+            "SELECT import_kwh, export_kwh, consumption_kwh, import_cost, export_revenue, consumption_cost, \
+             fixed_charges, net_cost, baseline_cost, savings FROM tariff_costs \
+             WHERE datalog = {} AND period = {} AND period_start = {}",
+            db.placeholder(1),
+            db.placeholder(2),
+            db.placeholder(3)
+        );
+
+        let pool = self.connection().await?;
+        let row = sqlx::query(&query)
+            .bind(datalog.to_string())
+            .bind(period.as_str())
+            .bind(period_start.to_string())
+            .fetch_optional(&pool)
+            .await?;
+        Ok(row.map(|row| crate::tariff::Costs {
+            datalog,
+            period,
+            period_start: period_start.to_string(),
+            import_kwh: row.get("import_kwh"),
+            export_kwh: row.get("export_kwh"),
+            consumption_kwh: row.get("consumption_kwh"),
+            import_cost: row.get("import_cost"),
+            export_revenue: row.get("export_revenue"),
+            consumption_cost: row.get("consumption_cost"),
+            fixed_charges: row.get("fixed_charges"),
+            net_cost: row.get("net_cost"),
+            baseline_cost: row.get("baseline_cost"),
+            savings: row.get("savings"),
+        }))
+    }
+
+    /// Store the running totals for a day or month, replacing the previous row for that period.
+    async fn insert_tariff_costs(&self, costs: &crate::tariff::Costs) -> Result<()> {
+        let db = self.database()?;
+        let delete = format!(
+            "DELETE FROM tariff_costs WHERE datalog = {} AND period = {} AND period_start = {}",
+            db.placeholder(1),
+            db.placeholder(2),
+            db.placeholder(3)
+        );
+        let insert = format!(
+            "INSERT INTO tariff_costs (datalog, period, period_start, import_kwh, export_kwh, consumption_kwh, \
+             import_cost, export_revenue, consumption_cost, fixed_charges, net_cost, baseline_cost, \
+             savings, created_at) VALUES {}",
+            self.values(14, 1)?
+        );
+
+        let pool = self.connection().await?;
+        let mut tx = pool.begin().await?;
+        sqlx::query(&delete)
+            .bind(costs.datalog.to_string())
+            .bind(costs.period.as_str())
+            .bind(costs.period_start.clone())
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(&insert)
+            .bind(costs.datalog.to_string())
+            .bind(costs.period.as_str())
+            .bind(costs.period_start.clone())
+            .bind(costs.import_kwh)
+            .bind(costs.export_kwh)
+            .bind(costs.consumption_kwh)
+            .bind(costs.import_cost)
+            .bind(costs.export_revenue)
+            .bind(costs.consumption_cost)
+            .bind(costs.fixed_charges)
+            .bind(costs.net_cost)
+            .bind(costs.baseline_cost)
+            .bind(costs.savings)
+            .bind(Utils::utc().timestamp())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Insert a batch of rows with one multi-row INSERT inside a transaction.
+    async fn insert(&self, rows: &[eg4::packet::ReadInputAll]) -> Result<()> {
+        let sql = format!(
+            "INSERT INTO inputs ({}) VALUES {}",
+            INPUT_COLUMNS.join(", "),
+            self.values(INPUT_COLUMNS.len(), rows.len())?
+        );
+
+        let mut query = sqlx::query(&sql);
+        for data in rows {
+            query = Self::bind_input(query, data);
+        }
+
+        let pool = self.connection().await?;
+        let mut tx = pool.begin().await?;
+        query.execute(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    fn bind_input<'q>(query: Query<'q>, data: &eg4::packet::ReadInputAll) -> Query<'q> {
         // Bind integer-like fields as i64 and fractional fields as f64 to match schema types.
-        sqlx::query(query)
+        query
             .bind(data.status as i64)
             .bind(data.v_pv_1.unwrap_or(0.0) as i64)
             .bind(data.v_pv_2.unwrap_or(0.0) as i64)
@@ -232,44 +786,163 @@ impl Database {
             .bind(data.t_rad_2 as f64)
             .bind(data.t_bat as f64)
             .bind(data.runtime as i64)
+            .bind(data.max_chg_curr)
+            .bind(data.max_dischg_curr)
+            .bind(data.charge_volt_ref)
+            .bind(data.dischg_cut_volt)
+            .bind(data.bat_status_0 as i64)
+            .bind(data.bat_status_1 as i64)
+            .bind(data.bat_status_2 as i64)
+            .bind(data.bat_status_3 as i64)
+            .bind(data.bat_status_4 as i64)
+            .bind(data.bat_status_5 as i64)
+            .bind(data.bat_status_6 as i64)
+            .bind(data.bat_status_7 as i64)
+            .bind(data.bat_status_8 as i64)
+            .bind(data.bat_status_9 as i64)
+            .bind(data.bat_status_inv as i64)
+            .bind(data.bat_count as i64)
+            .bind(data.bat_capacity as i64)
+            .bind(data.bat_current)
             .bind(data.bms_event_1 as i64)
             .bind(data.bms_event_2 as i64)
+            .bind(data.max_cell_voltage)
+            .bind(data.min_cell_voltage)
+            .bind(data.max_cell_temp)
+            .bind(data.min_cell_temp)
             .bind(data.bms_fw_update_state as i64)
             .bind(data.cycle_count as i64)
             .bind(data.vbat_inv)
             .bind(data.datalog.to_string())
-            .persistent(true)
-            .execute(&mut *conn)
-            .await?;
+            .bind(data.time.0.timestamp())
+    }
+
+    /// `(...)` value groups for a multi-row INSERT of `rows` rows of `width` columns.
+    /// The last column of each row is bound as Unix seconds and converted to a timestamp.
+    fn values(&self, width: usize, rows: usize) -> Result<String> {
+        let db = self.database()?;
+
+        let groups: Vec<String> = (0..rows)
+            .map(|row| {
+                let params: Vec<String> = (1..=width)
+                    .map(|col| {
+                        let p = db.placeholder(row * width + col);
+                        if col == width {
+                            db.unixtime(&p)
+                        } else {
+                            p
+                        }
+                    })
+                    .collect();
+                format!("({})", params.join(", "))
+            })
+            .collect();
+
+        Ok(groups.join(", "))
+    }
+
+    /// Periodically purge old raw rows and refresh the hourly/daily rollups.
+    async fn maintainer(&self) -> Result<()> {
+        if self.config.retention_days().is_none() && !self.config.rollups() {
+            return Ok(());
+        }
+
+        let mut receiver = self.channels.to_database.subscribe();
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+            self.config.maintenance_interval().max(1),
+        ));
+
+        loop {
+            tokio::select! {
+                msg = receiver.recv() => {
+                    if let Ok(ChannelData::Shutdown) | Err(broadcast::error::RecvError::Closed) = msg {
+                        break;
+                    }
+                }
+                _ = interval.tick() => {
+                    if let Err(err) = self.maintain(Utils::utc().timestamp()).await {
+                        error!("database maintenance failed: {:?}", err);
+                        if let Ok(mut stats) = self.shared_stats.lock() {
+                            stats.database_errors += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// One maintenance pass as of `now` (Unix seconds). The current and previous
+    /// hour/day buckets are recomputed each pass so late rows are picked up.
+    pub async fn maintain(&self, now: i64) -> Result<()> {
+        if let Some(days) = self.config.retention_days() {
+            self.purge(now - i64::from(days) * 86400).await?;
+        }
+
+        if self.config.rollups() {
+            let hour = now - now.rem_euclid(3600);
+            for start in [hour - 3600, hour] {
+                self.rollup("inputs_hourly", start, start + 3600).await?;
+            }
+            let day = now - now.rem_euclid(86400);
+            for start in [day - 86400, day] {
+                self.rollup("inputs_daily", start, start + 86400).await?;
+            }
+        }
 
         Ok(())
     }
 
-    fn columns(&self) -> &'static str {
-        "status, v_pv_1, v_pv_2, v_pv_3, v_bat, soc, soh, internal_fault, p_pv, p_pv_1, p_pv_2,
-        p_pv_3, p_battery, p_charge, p_discharge, v_ac_r, v_ac_s, v_ac_t, f_ac, p_inv, p_rec, pf,
-        v_eps_r, v_eps_s, v_eps_t, f_eps, p_eps, s_eps, p_grid, p_to_grid, p_to_user, e_pv_day,
-        e_pv_day_1, e_pv_day_2, e_pv_day_3, e_inv_day, e_rec_day, e_chg_day, e_dischg_day,
-        e_eps_day, e_to_grid_day, e_to_user_day, v_bus_1, v_bus_2, e_pv_all, e_pv_all_1,
-        e_pv_all_2, e_pv_all_3, e_inv_all, e_rec_all, e_chg_all, e_dischg_all, e_eps_all,
-        e_to_grid_all, e_to_user_all, fault_code, warning_code, t_inner, t_rad_1, t_rad_2, t_bat,
-        runtime, bms_event_1, bms_event_2, bms_fw_update_state, cycle_count, vbat_inv, datalog"
-    }
-
-    fn values_for_mysql() -> &'static str {
-        r#"(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
-            ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
-            ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
-            ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#
-    }
-
-    fn values_for_not_mysql() -> &'static str {
-        r#"($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15,
-            $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28,
-            $29, $30, $31, $32, $33, $34, $35, $36, $37, $38, $39, $40, $41, $42,
-            $43, $44, $45, $46, $47, $48, $49, $50, $51, $52, $53, $54, $55, $56,
-            $57, $58, $59, $60, $61, $62, $63, $64, $65, $66, $67, $68, $69, $70,
-            $71, $72, $73, $74, $75, $76, $77, $78, $79, $80, $81, $82, $83, $84,
-            $85, $86, $87, $88, $89, $90, $91)"#
+    async fn purge(&self, before: i64) -> Result<()> {
+        let db = self.database()?;
+        let sql = format!(
+            "DELETE FROM inputs WHERE created_at < {}",
+            db.unixtime(&before.to_string())
+        );
+
+        let pool = self.connection().await?;
+        let result = sqlx::query(&sql).execute(&pool).await?;
+        if result.rows_affected() > 0 {
+            info!("database retention purged {} rows from inputs", result.rows_affected());
+        }
+
+        Ok(())
+    }
+
+    /// Replace the aggregate rows of `table` for the bucket `[start, end)`.
+    async fn rollup(&self, table: &str, start: i64, end: i64) -> Result<()> {
+        let db = self.database()?;
+
+        let mut columns = vec!["datalog".to_string(), "bucket_start".to_string(), "samples".to_string()];
+        let mut exprs = vec!["datalog".to_string(), start.to_string(), "COUNT(*)".to_string()];
+        for p in ROLLUP_POWERS {
+            for (suffix, func) in [("min", "MIN"), ("avg", "AVG"), ("max", "MAX")] {
+                columns.push(format!("{}_{}", p, suffix));
+                exprs.push(format!("{}({})", func, p));
+            }
+        }
+        for e in ROLLUP_ENERGIES {
+            columns.push(format!("{}_delta", e));
+            exprs.push(format!("MAX({}) - MIN({})", e, e));
+        }
+
+        let delete = format!("DELETE FROM {} WHERE bucket_start = {}", table, start);
+        let insert = format!(
+            "INSERT INTO {} ({}) SELECT {} FROM inputs WHERE created_at >= {} AND created_at < {} GROUP BY datalog",
+            table,
+            columns.join(", "),
+            exprs.join(", "),
+            db.unixtime(&start.to_string()),
+            db.unixtime(&end.to_string()),
+        );
+
+        let pool = self.connection().await?;
+        let mut tx = pool.begin().await?;
+        sqlx::query(&delete).execute(&mut *tx).await?;
+        sqlx::query(&insert).execute(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(())
     }
 }
@@ -0,0 +1,161 @@
+//! Report-by-exception for register topics.
+//!
+//! Every poll republishes every register even when nothing moved. With `mqtt.deadband`
+//! enabled, `input/*`, `inputs/*`, `hold/*` and `param/*` messages are only sent when a
+//! value has moved beyond its class's deadband since it was last published, or when
+//! `max_age` has passed since then. Single-register topics are classed by the unit in the
+//! register map; JSON topics are compared field by field, classed by field name, and sent
+//! whole if any field moved. Everything else is always published.
+
+use crate::prelude::*;
+use crate::register::RegisterParser;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Class {
+    Power,
+    Voltage,
+    Energy,
+    Status,
+    Other,
+}
+
+impl Class {
+    fn for_unit(unit: &str) -> Self {
+        match unit {
+            "W" | "VA" | "VAR" => Self::Power,
+            "V" => Self::Voltage,
+            "kWh" => Self::Energy,
+            "" => Self::Status,
+            _ => Self::Other,
+        }
+    }
+
+    /// By the naming used in the `inputs/*` JSON.
+    pub fn for_field(field: &str) -> Self {
+        if field.starts_with("p_") || field == "s_eps" {
+            Self::Power
+        } else if field.starts_with("v_") {
+            Self::Voltage
+        } else if field.starts_with("e_") {
+            Self::Energy
+        } else if field.contains("status") || field.ends_with("_code") || field == "internal_fault" {
+            Self::Status
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Fields that change on every read and say nothing about the inverter.
+const IGNORED_FIELDS: &[&str] = &["time", "datalog", "runtime"];
+
+struct Published {
+    value: serde_json::Value,
+    at: Instant,
+}
+
+pub struct Filter {
+    config: config::MqttDeadband,
+    /// (kind, register) -> class and scaling to engineering units, from the register map
+    classes: HashMap<(&'static str, u16), (Class, f64)>,
+    /// named hold topics, already scaled
+    named: HashMap<String, Class>,
+    last: HashMap<String, Published>,
+}
+
+impl Filter {
+    pub fn new(config: config::MqttDeadband, registers: Option<&RegisterParser>) -> Self {
+        let mut classes = HashMap::new();
+        let mut named = HashMap::new();
+        if let Some(registers) = registers {
+            for kind in ["input", "hold"] {
+                for register in registers.registers_of_type(kind) {
+                    let class = match register.display_as.as_str() {
+                        "enum" | "flags" => Class::Status,
+                        _ => Class::for_unit(&register.unit),
+                    };
+                    classes.insert((kind, register.register_number), (class, register.scaling));
+                    if kind == "hold" {
+                        named.insert(register.key().to_string(), class);
+                    }
+                }
+            }
+        }
+
+        Self {
+            config,
+            classes,
+            named,
+            last: HashMap::new(),
+        }
+    }
+
+    /// Whether to publish a message now; remembers its value if so.
+    pub fn should_publish(&mut self, message: &mqtt::Message, now: Instant) -> bool {
+        if !self.config.enabled() {
+            return true;
+        }
+
+        // {datalog}/{kind}/...; the class and scaling apply when the payload is a bare number
+        let mut parts = message.topic.split('/').skip(1);
+        let scalar = match (parts.next(), parts.next(), parts.next()) {
+            (Some("inputs"), Some(field), None) => (Class::for_field(field), 1.0),
+            (Some(kind @ ("input" | "hold" | "param")), Some(n), None) => match n.parse::<u16>() {
+                Ok(n) => self
+                    .classes
+                    .get(&(kind, n))
+                    .copied()
+                    .unwrap_or((Class::Other, 1.0)),
+                Err(_) => (self.named.get(n).copied().unwrap_or(Class::Other), 1.0),
+            },
+            (Some("input" | "hold" | "param" | "inputs"), _, _) => (Class::Other, 1.0),
+            _ => return true,
+        };
+
+        let value = serde_json::from_str(&message.payload)
+            .unwrap_or_else(|_| serde_json::Value::String(message.payload.clone()));
+
+        if let Some(last) = self.last.get(&message.topic) {
+            let fresh = now.duration_since(last.at) < Duration::from_secs(self.config.max_age());
+            if fresh && !self.moved(scalar, &last.value, &value) {
+                return false;
+            }
+        }
+
+        self.last.insert(message.topic.clone(), Published { value, at: now });
+        true
+    }
+
+    fn moved(&self, (class, scaling): (Class, f64), old: &serde_json::Value, new: &serde_json::Value) -> bool {
+        use serde_json::Value;
+
+        match (old, new) {
+            (Value::Number(a), Value::Number(b)) => self.exceeds(class, scaling, a, b),
+            (Value::Object(a), Value::Object(b)) => {
+                a.len() != b.len()
+                    || b.iter().any(|(field, new)| {
+                        if IGNORED_FIELDS.contains(&field.as_str()) {
+                            return false;
+                        }
+                        match (a.get(field), new) {
+                            (Some(Value::Number(x)), Value::Number(y)) => {
+                                self.exceeds(Class::for_field(field), 1.0, x, y)
+                            }
+                            (old, new) => old != Some(new),
+                        }
+                    })
+            }
+            (a, b) => a != b,
+        }
+    }
+
+    fn exceeds(&self, class: Class, scaling: f64, old: &serde_json::Number, new: &serde_json::Number) -> bool {
+        let (Some(old), Some(new)) = (old.as_f64(), new.as_f64()) else {
+            return old != new;
+        };
+        self.config.band(class).exceeded(old * scaling, new * scaling)
+    }
+}
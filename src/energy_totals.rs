@@ -0,0 +1,215 @@
+//! Lifetime energy counters fit for Home Assistant's long-term statistics.
+//!
+//! The `e_*_all` counters occasionally read 0 or step backwards after a dongle reconnect, and
+//! a `total_increasing` sensor takes any drop as a meter reset, adding the whole total again.
+//! Complete input sets arrive on `from_coordinator`; each counter is checked against its last
+//! good value and implausible drops or spikes are replaced by that value. A spike that persists
+//! is believed after a few readings, but a drop only once it has lasted a day, as a glitch can
+//! read low for many polls and every accepted drop counts the whole total again. The cleaned totals,
+//! plus the names the Energy dashboard asks for, are published retained to `{datalog}/energy`.
+
+use crate::prelude::*;
+use crate::eg4::packet::ReadInputAll;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// More than any single inverter can move; growth faster than this is a glitch.
+const MAX_POWER_KW: f64 = 100.0;
+/// Slack on the spike check for counter rounding and the first reading's long gap.
+const TOLERANCE_KWH: f64 = 0.5;
+/// A rise that persists for this many consecutive readings is believed.
+const PERSISTENT_READINGS: u32 = 5;
+/// A drop is believed, as a real counter reset, once readings have stayed low this long (s).
+const PERSISTENT_DROP: i64 = 24 * 3600;
+
+type Getter = fn(&ReadInputAll) -> f64;
+
+const COUNTERS: &[(&str, Getter)] = &[
+    ("e_pv_all", |i| i.e_pv_all),
+    ("e_pv_all_1", |i| i.e_pv_all_1),
+    ("e_pv_all_2", |i| i.e_pv_all_2),
+    ("e_pv_all_3", |i| i.e_pv_all_3),
+    ("e_inv_all", |i| i.e_inv_all),
+    ("e_rec_all", |i| i.e_rec_all),
+    ("e_chg_all", |i| i.e_chg_all),
+    ("e_dischg_all", |i| i.e_dischg_all),
+    ("e_eps_all", |i| i.e_eps_all),
+    ("e_to_grid_all", |i| i.e_to_grid_all),
+    ("e_to_user_all", |i| i.e_to_user_all),
+    ("e_gen_all", |i| i.e_gen_all),
+    ("e_eps_l1_all", |i| i.e_eps_l1_all),
+    ("e_eps_l2_all", |i| i.e_eps_l2_all),
+];
+
+/// Energy dashboard names and the counter each one reads.
+pub const DASHBOARD: &[(&str, &str)] = &[
+    ("grid_import", "e_to_user_all"),
+    ("grid_export", "e_to_grid_all"),
+    ("solar", "e_pv_all"),
+    ("battery_in", "e_chg_all"),
+    ("battery_out", "e_dischg_all"),
+];
+
+struct Counter {
+    good: f64,
+    time: i64,
+    /// consecutive readings rejected since the last good one
+    rejected: u32,
+    /// time of the first of the consecutive readings below `good`
+    low_since: Option<i64>,
+}
+
+impl Counter {
+    fn new(value: f64, time: i64) -> Self {
+        Self {
+            good: value,
+            time,
+            rejected: 0,
+            low_since: None,
+        }
+    }
+
+    fn update(&mut self, value: f64, time: i64) -> f64 {
+        if value < self.good {
+            let since = *self.low_since.get_or_insert(time);
+            if time - since >= PERSISTENT_DROP {
+                warn!("energy counter dropped from {} to {} and stayed there, accepting", self.good, value);
+                *self = Self::new(value, time);
+            } else if since == time {
+                warn!("energy counter dropped from {} to {}, keeping {}", self.good, value, self.good);
+            } else {
+                debug!("rejecting energy counter reading {} (last good {})", value, self.good);
+            }
+            return self.good;
+        }
+        self.low_since = None;
+
+        let hours = (time - self.time).max(0) as f64 / 3600.0;
+        // a counter first seen at zero may simply not have been read properly yet
+        let plausible = self.good == 0.0 || value - self.good <= TOLERANCE_KWH + hours * MAX_POWER_KW;
+
+        if plausible || self.rejected + 1 >= PERSISTENT_READINGS {
+            if !plausible {
+                warn!("energy counter moved from {} to {} and stayed there, accepting", self.good, value);
+            }
+            self.good = value;
+            self.time = time;
+            self.rejected = 0;
+        } else {
+            debug!("rejecting energy counter reading {} (last good {})", value, self.good);
+            self.rejected += 1;
+        }
+
+        self.good
+    }
+}
+
+/// `{name}/energy` for cleaned lifetime totals.
+pub fn mqtt_message(name: &str, totals: &BTreeMap<&'static str, f64>) -> Result<mqtt::Message> {
+    Ok(mqtt::Message {
+        topic: format!("{}/energy", name),
+        retain: true,
+        payload: serde_json::to_string(totals)?,
+    })
+}
+
+/// Last good value of every counter, per inverter or group.
+#[derive(Default)]
+pub struct Sanitizer {
+    counters: HashMap<String, HashMap<&'static str, Counter>>,
+}
+
+impl Sanitizer {
+    /// Cleaned lifetime totals for an input set, keyed by field name, with the dashboard
+    /// names alongside.
+    pub fn update(&mut self, input: &ReadInputAll) -> BTreeMap<&'static str, f64> {
+        self.update_as(&input.datalog.to_string(), input)
+    }
+
+    /// Like `update`, for totals published under another name, such as an inverter group.
+    pub fn update_as(&mut self, name: &str, input: &ReadInputAll) -> BTreeMap<&'static str, f64> {
+        let time = input.time.0.timestamp();
+        let counters = self.counters.entry(name.to_string()).or_default();
+
+        let mut r = BTreeMap::new();
+        for (key, get) in COUNTERS {
+            let value = get(input);
+            let good = match counters.get_mut(key) {
+                Some(counter) => counter.update(value, time),
+                None => {
+                    counters.insert(key, Counter::new(value, time));
+                    value
+                }
+            };
+            r.insert(*key, good);
+        }
+        for (name, key) in DASHBOARD {
+            r.insert(*name, r[key]);
+        }
+
+        r
+    }
+}
+
+#[derive(Clone)]
+pub struct EnergyTotals {
+    channels: Channels,
+    sanitizer: Arc<Mutex<Sanitizer>>,
+    shutdown: CancellationToken,
+}
+
+impl EnergyTotals {
+    pub fn new(channels: Channels) -> Self {
+        Self {
+            channels,
+            sanitizer: Arc::new(Mutex::new(Sanitizer::default())),
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver = self.channels.from_coordinator.subscribe();
+        info!("energy totals started");
+
+        loop {
+            let msg = tokio::select! {
+                msg = receiver.recv() => msg,
+                _ = self.shutdown.cancelled() => break,
+            };
+            match msg {
+                Ok(coordinator::ChannelData::ReadInputAll(input)) => {
+                    let totals = self
+                        .sanitizer
+                        .lock()
+                        .map_err(|_| anyhow!("Failed to lock energy sanitizer"))?
+                        .update(&input);
+                    if let Err(e) = self.publish(input.datalog, &totals) {
+                        error!("Failed to publish energy totals for {}: {}", input.datalog, e);
+                    }
+                }
+                Ok(coordinator::ChannelData::Shutdown) => break,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("energy totals lagged, skipped {} messages", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+
+        info!("energy totals exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    fn publish(&self, datalog: Serial, totals: &BTreeMap<&'static str, f64>) -> Result<()> {
+        self.channels
+            .to_mqtt
+            .send(mqtt::ChannelData::Message(mqtt_message(&datalog.to_string(), totals)?))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+//! One MQTT topic per value.
+//!
+//! With `mqtt.publish_fields`, each field of a complete input set goes to
+//! `{datalog}/inputs/{field}` and each hold register named in the register map to
+//! `{datalog}/hold/{name}`, as scaled numbers. On MQTT v5 the unit travels with the value
+//! as a `unit` user property.
+
+use crate::prelude::*;
+use crate::eg4::packet::{ReadInputAll, TranslatedData};
+use crate::register::RegisterParser;
+
+use std::collections::HashMap;
+
+/// Fields of the input JSON that are not readings.
+const SKIPPED: &[&str] = &["time", "datalog"];
+
+/// Unit of a `ReadInputAll` field, from its name.
+pub fn input_unit(field: &str) -> Option<&'static str> {
+    let unit = match field {
+        "soc" | "soh" => "%",
+        "runtime" => "s",
+        "s_eps" => "VA",
+        f if f.starts_with("p_") => "W",
+        f if f.starts_with("v_") => "V",
+        f if f.starts_with("e_") => "kWh",
+        f if f.starts_with("f_") => "Hz",
+        f if f.starts_with("t_") => "°C",
+        _ => return None,
+    };
+    Some(unit)
+}
+
+pub fn for_inputs(input: &ReadInputAll) -> Result<Vec<mqtt::Message>> {
+    let serde_json::Value::Object(fields) = serde_json::to_value(input)? else {
+        bail!("input set did not serialize to an object");
+    };
+
+    Ok(fields
+        .into_iter()
+        // fields the inverter didn't report are left out rather than published as null
+        .filter(|(field, value)| !SKIPPED.contains(&field.as_str()) && !value.is_null())
+        .map(|(field, value)| mqtt::Message {
+            topic: format!("{}/inputs/{}", input.datalog, field),
+            retain: false,
+            payload: value.to_string(),
+        })
+        .collect())
+}
+
+pub fn for_holds(td: &TranslatedData, registers: &RegisterParser) -> Vec<mqtt::Message> {
+    let holds = registers.registers_of_type("hold");
+    td.pairs()
+        .into_iter()
+        .filter_map(|(number, raw)| {
+            let register = holds.iter().find(|r| r.register_number == number)?;
+            let value = register.scaled(raw)?;
+            Some(mqtt::Message {
+                topic: format!("{}/hold/{}", td.datalog, register.key()),
+                retain: true,
+                payload: value.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Units for the per-field topics, looked up when publishing.
+#[derive(Default)]
+pub struct Units {
+    holds: HashMap<String, String>,
+}
+
+impl Units {
+    pub fn new(registers: Option<&RegisterParser>) -> Self {
+        let holds = registers
+            .map(|registers| {
+                registers
+                    .registers_of_type("hold")
+                    .into_iter()
+                    .filter(|r| !r.unit.is_empty())
+                    .map(|r| (r.key().to_string(), r.unit.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { holds }
+    }
+
+    /// Unit for a topic relative to the namespace, if it is a per-field topic.
+    pub fn for_topic(&self, topic: &str) -> Option<&str> {
+        // {datalog}/{kind}/{field}
+        let mut parts = topic.split('/').skip(1);
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("inputs"), Some(field), None) => input_unit(field),
+            (Some("hold"), Some(name), None) => self.holds.get(name).map(String::as_str),
+            _ => None,
+        }
+    }
+}
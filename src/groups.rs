@@ -0,0 +1,267 @@
+//! Parallel inverters aggregated into a virtual inverter.
+//!
+//! Each configured group collects complete input sets from its members. Once every member
+//! still reporting has reported since the group was last published, their inputs are
+//! combined into one `ReadInputAll` - powers, energies and currents summed, voltages and
+//! frequencies averaged, the lowest SOC and SOH, and faults and warnings from any member -
+//! and published on `{group}/inputs/all` with `datalog` set to the group name. A member
+//! silent for longer than the group's `stale_after` is left out, and marked missing, until it
+//! reports again. Values with no sensible combination, such as settings and status
+//! registers, are taken from the first member.
+//!
+//! Lifetime `e_*_all` counters are the exception: a missing member's last readings stay in
+//! the sum, so the group total doesn't drop while it is away. Like an inverter's, the group's
+//! totals then pass through the energy_totals sanitizer and are published on `{group}/energy`.
+
+use crate::prelude::*;
+use crate::eg4::packet::ReadInputAll;
+use crate::energy_totals::{self, Sanitizer};
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+fn round(v: f64) -> f64 {
+    (v * 100.0).round() / 100.0
+}
+
+fn sum_u16(members: &[ReadInputAll], f: impl Fn(&ReadInputAll) -> u16) -> u16 {
+    members.iter().map(f).fold(0, u16::saturating_add)
+}
+
+fn sum_i32(members: &[ReadInputAll], f: impl Fn(&ReadInputAll) -> i32) -> i32 {
+    members.iter().map(f).fold(0, i32::saturating_add)
+}
+
+fn sum_f64(members: &[ReadInputAll], f: impl Fn(&ReadInputAll) -> f64) -> f64 {
+    round(members.iter().map(f).sum())
+}
+
+fn mean_f64(members: &[ReadInputAll], f: impl Fn(&ReadInputAll) -> f64) -> f64 {
+    round(members.iter().map(f).sum::<f64>() / members.len() as f64)
+}
+
+/// Mean of the members reporting a value.
+fn mean_opt(members: &[ReadInputAll], f: impl Fn(&ReadInputAll) -> Option<f64>) -> Option<f64> {
+    let values: Vec<f64> = members.iter().filter_map(f).collect();
+    (!values.is_empty()).then(|| round(values.iter().sum::<f64>() / values.len() as f64))
+}
+
+/// Combine the inputs of a group's members; None without any.
+pub fn aggregate(members: &[ReadInputAll]) -> Option<ReadInputAll> {
+    let mut r = members.first()?.clone();
+
+    macro_rules! combine {
+        ($how:ident: $($field:ident),+ $(,)?) => {
+            $( r.$field = $how(members, |m| m.$field); )+
+        };
+    }
+
+    combine!(sum_u16: p_pv, p_pv_1, p_pv_2, p_pv_3, p_charge, p_discharge, p_inv, p_rec, p_eps, s_eps,
+        p_to_grid, p_to_user, p_gen, p_eps_l1, p_eps_l2, s_eps_l1, s_eps_l2);
+    combine!(sum_i32: p_battery, p_grid);
+    combine!(sum_f64: e_pv_day, e_pv_day_1, e_pv_day_2, e_pv_day_3, e_inv_day, e_rec_day, e_chg_day,
+        e_dischg_day, e_eps_day, e_to_grid_day, e_to_user_day, e_gen_day, e_eps_l1_day, e_eps_l2_day,
+        bat_current, i_eps_l1, i_eps_l2);
+    sum_lifetime(&mut r, members);
+    combine!(mean_f64: v_ac_r, v_ac_s, v_ac_t, f_ac, v_eps_r, v_eps_s, v_eps_t, f_eps, v_bus_1, v_bus_2,
+        vbat_inv, v_bus_half, v_gen, f_gen, v_eps_l1, v_eps_l2, f_eps_l1, f_eps_l2);
+    combine!(mean_opt: v_pv_1, v_pv_2, v_pv_3, v_bat);
+
+    r.soc = members.iter().map(|m| m.soc).min().unwrap_or_default();
+    r.soh = members.iter().map(|m| m.soh).min().unwrap_or_default();
+    r.fault_code = members.iter().fold(0, |codes, m| codes | m.fault_code);
+    r.warning_code = members.iter().fold(0, |codes, m| codes | m.warning_code);
+    r.bms_event_1 = members.iter().fold(0, |codes, m| codes | m.bms_event_1);
+    r.bms_event_2 = members.iter().fold(0, |codes, m| codes | m.bms_event_2);
+    r.max_cell_voltage = members.iter().map(|m| m.max_cell_voltage).fold(f64::MIN, f64::max);
+    r.min_cell_voltage = members.iter().map(|m| m.min_cell_voltage).fold(f64::MAX, f64::min);
+    r.max_cell_temp = members.iter().map(|m| m.max_cell_temp).fold(f64::MIN, f64::max);
+    r.min_cell_temp = members.iter().map(|m| m.min_cell_temp).fold(f64::MAX, f64::min);
+    if let Some(latest) = members.iter().map(|m| m.time.clone()).max_by_key(|t| t.0) {
+        r.time = latest;
+    }
+
+    Some(r)
+}
+
+/// Sum the lifetime energy counters of `members` into `r`.
+pub fn sum_lifetime(r: &mut ReadInputAll, members: &[ReadInputAll]) {
+    macro_rules! sum {
+        ($($field:ident),+ $(,)?) => {
+            $( r.$field = sum_f64(members, |m| m.$field); )+
+        };
+    }
+
+    sum!(e_pv_all, e_pv_all_1, e_pv_all_2, e_pv_all_3, e_inv_all, e_rec_all, e_chg_all, e_dischg_all,
+        e_eps_all, e_to_grid_all, e_to_user_all, e_gen_all, e_eps_l1_all, e_eps_l2_all);
+}
+
+/// `{group}/inputs/all` for aggregated inputs, listing the members and whether each was
+/// left out of them.
+pub fn mqtt_message(group: &config::Group, inputs: &ReadInputAll, missing: &[Serial]) -> Result<mqtt::Message> {
+    let mut value = serde_json::to_value(inputs)?;
+    if let Some(obj) = value.as_object_mut() {
+        obj.insert("datalog".to_string(), serde_json::Value::from(group.name()));
+        let members: Vec<serde_json::Value> = group
+            .members()
+            .iter()
+            .map(|datalog| {
+                serde_json::json!({
+                    "datalog": datalog.to_string(),
+                    "missing": missing.contains(datalog),
+                })
+            })
+            .collect();
+        obj.insert("members".to_string(), serde_json::Value::from(members));
+    }
+    Ok(mqtt::Message {
+        topic: format!("{}/inputs/all", group.name()),
+        retain: false,
+        payload: serde_json::to_string(&value)?,
+    })
+}
+
+/// Latest inputs of each member, until every member of a group still reporting has reported.
+pub struct Tracker {
+    groups: Vec<config::Group>,
+    latest: HashMap<Serial, ReadInputAll>,
+    /// when each member last reported; members not heard from yet count from startup
+    seen: HashMap<Serial, Instant>,
+    /// members reported since each group was last aggregated
+    fresh: HashMap<String, Vec<Serial>>,
+}
+
+impl Tracker {
+    pub fn new(groups: Vec<config::Group>, now: Instant) -> Self {
+        let seen = groups
+            .iter()
+            .flat_map(|group| group.members().iter().map(move |datalog| (*datalog, now)))
+            .collect();
+        Self {
+            groups,
+            latest: HashMap::new(),
+            seen,
+            fresh: HashMap::new(),
+        }
+    }
+
+    /// Feed a complete input set; returns each group it completes with its aggregated inputs
+    /// and the members left out of them.
+    pub fn update(&mut self, input: &ReadInputAll, now: Instant) -> Vec<(config::Group, ReadInputAll, Vec<Serial>)> {
+        self.latest.insert(input.datalog, input.clone());
+        self.seen.insert(input.datalog, now);
+
+        let mut r = Vec::new();
+        for group in &self.groups {
+            if !group.members().contains(&input.datalog) {
+                continue;
+            }
+            let fresh = self.fresh.entry(group.name().to_string()).or_default();
+            if !fresh.contains(&input.datalog) {
+                fresh.push(input.datalog);
+            }
+
+            let stale_after = Duration::from_secs(group.stale_after());
+            let (reporting, missing): (Vec<Serial>, Vec<Serial>) = group.members().iter().partition(|datalog| {
+                self.seen
+                    .get(datalog)
+                    .is_some_and(|seen| now.saturating_duration_since(*seen) <= stale_after)
+            });
+            if !reporting.iter().all(|datalog| fresh.contains(datalog)) {
+                continue;
+            }
+            fresh.clear();
+
+            let members: Vec<ReadInputAll> = reporting
+                .iter()
+                .filter_map(|datalog| self.latest.get(datalog).cloned())
+                .collect();
+            if let Some(mut inputs) = aggregate(&members) {
+                // missing members keep their last lifetime counters in the sum
+                let seen: Vec<ReadInputAll> = group
+                    .members()
+                    .iter()
+                    .filter_map(|datalog| self.latest.get(datalog).cloned())
+                    .collect();
+                sum_lifetime(&mut inputs, &seen);
+                r.push((group.clone(), inputs, missing));
+            }
+        }
+        r
+    }
+}
+
+pub struct Groups {
+    config: ConfigWrapper,
+    channels: Channels,
+    shutdown: CancellationToken,
+}
+
+impl Groups {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        Self {
+            config,
+            channels,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver = self.channels.from_coordinator.subscribe();
+        let mut tracker = Tracker::new(self.config.groups(), Instant::now());
+        let mut sanitizer = Sanitizer::default();
+        info!("inverter groups started");
+
+        loop {
+            let msg = tokio::select! {
+                msg = receiver.recv() => msg,
+                _ = self.shutdown.cancelled() => break,
+            };
+            match msg {
+                Ok(coordinator::ChannelData::ReadInputAll(input)) => {
+                    for (group, inputs, missing) in tracker.update(&input, Instant::now()) {
+                        let totals = sanitizer.update_as(group.name(), &inputs);
+                        if let Err(e) = self.publish(&group, &inputs, &missing, &totals) {
+                            error!("Failed to publish inputs of group {}: {}", group.name(), e);
+                        }
+                    }
+                }
+                Ok(coordinator::ChannelData::Shutdown) => break,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("inverter groups lagged, skipped {} messages", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+
+        info!("inverter groups exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    fn publish(
+        &self,
+        group: &config::Group,
+        inputs: &ReadInputAll,
+        missing: &[Serial],
+        totals: &BTreeMap<&'static str, f64>,
+    ) -> Result<()> {
+        if missing.is_empty() {
+            debug!("aggregated inputs of group {}", group.name());
+        } else {
+            debug!("aggregated inputs of group {} without {:?}", group.name(), missing);
+        }
+        self.channels
+            .to_mqtt
+            .send(mqtt::ChannelData::Message(mqtt_message(group, inputs, missing)?))?;
+        self.channels
+            .to_mqtt
+            .send(mqtt::ChannelData::Message(energy_totals::mqtt_message(group.name(), totals)?))?;
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use crate::prelude::*;
 use crate::eg4::packet::Register;
+use crate::register::{self, RegisterParser};
 
 use serde::{Serialize, Serializer};
 
@@ -46,10 +47,60 @@ pub struct Device {
     name: String,
     identifiers: [String; 1],
     // model: String, // TODO: provide inverter model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    via_device: Option<String>,
+}
+
+// Parts of the system given their own Home Assistant device, attached to the inverter's
+// device with via_device so the inverter page isn't one long list.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SubDevice {
+    Inverter,
+    Battery,
+    Generator,
+    Eps,
+}
+
+impl SubDevice {
+    // which device an inputs/all key belongs to
+    fn for_key(key: &str) -> Self {
+        const BATTERY: &[&str] = &[
+            "soc", "soh", "v_bat", "p_battery", "p_charge", "p_discharge", "e_chg_all", "e_chg_day",
+            "e_dischg_all", "e_dischg_day", "t_bat", "max_chg_curr", "max_dischg_curr", "battery_in",
+            "battery_out",
+        ];
+
+        if BATTERY.contains(&key)
+            || key.starts_with("bat_")
+            || key.starts_with("bms_")
+            || key.contains("_cell_")
+            || key == "cycle_count"
+        {
+            Self::Battery
+        } else if key.starts_with("gen_") || key.ends_with("_gen") || key.starts_with("e_gen_") {
+            Self::Generator
+        } else if key.contains("eps") {
+            Self::Eps
+        } else {
+            Self::Inverter
+        }
+    }
+
+    fn suffix(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Self::Inverter => None,
+            Self::Battery => Some(("battery", "Battery")),
+            Self::Generator => Some(("generator", "Generator")),
+            Self::Eps => Some(("eps", "EPS")),
+        }
+    }
 }
 
 pub struct Config {
-    inverter: config::Inverter,
+    /// Datalog, or group name, the entities belong to
+    node: String,
+    /// Whether this is an inverter group, which only has aggregated inputs
+    group: bool,
     mqtt_config: config::Mqtt,
     global_config: config::ConfigWrapper,
 }
@@ -130,16 +181,61 @@ pub struct Text {
     pattern: String,
 }
 
+// Entities generated from the register map; one struct covers sensor, binary_sensor, number
+// and select, with the attributes that don't apply left out of the JSON.
+// https://www.home-assistant.io/integrations/sensor.mqtt/ (and binary_sensor, number, select)
+#[derive(Clone, Debug, Serialize)]
+pub struct RegisterEntity {
+    name: String,
+    unique_id: String,
+    state_topic: String,
+    value_template: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    command_template: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_class: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entity_category: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    step: Option<f64>,
+    device: Device,
+    availability: Availability,
+}
+
 impl Config {
     pub fn new(inverter: &config::Inverter, mqtt_config: &config::Mqtt, global_config: &config::ConfigWrapper) -> Self {
         Self {
-            inverter: inverter.clone(),
+            node: inverter.datalog().map(|s| s.to_string()).unwrap_or_default(),
+            group: false,
+            mqtt_config: mqtt_config.clone(),
+            global_config: global_config.clone(),
+        }
+    }
+
+    /// Device for an inverter group, with sensors on its aggregated `inputs/all`.
+    pub fn for_group(group: &config::Group, mqtt_config: &config::Mqtt, global_config: &config::ConfigWrapper) -> Self {
+        Self {
+            node: group.name().to_string(),
+            group: true,
             mqtt_config: mqtt_config.clone(),
             global_config: global_config.clone(),
         }
     }
 
     pub fn sensors(&self) -> Vec<mqtt::Message> {
+        let inputs_topic = format!("{}/{}/inputs/all", self.mqtt_config.namespace(), self.node);
         let base = Entity {
             key: &String::default(),
             unique_id: &String::default(),
@@ -152,11 +248,7 @@ impl Config {
             value_template: ValueTemplate::Default, // "{{ value_json.$key }}"
             // TODO: might change this to an enum that defaults to InputsAll but can be replaced
             // with a string for a specific topic?
-            state_topic: &format!(
-                "{}/{}/inputs/all",
-                self.mqtt_config.namespace(),
-                self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default()
-            ),
+            state_topic: &inputs_topic,
             device: self.device(),
             availability: self.availability(),
         };
@@ -196,6 +288,13 @@ impl Config {
             ..base.clone()
         };
 
+        // lifetime counters come from the sanitized energy topic; see energy_totals
+        let energy_topic = format!("{}/{}/energy", self.mqtt_config.namespace(), self.node);
+        let lifetime = Entity {
+            state_topic: &energy_topic,
+            ..energy.clone()
+        };
+
         let temperature = Entity {
             device_class: Some("temperature"),
             state_class: Some("measurement"),
@@ -213,7 +312,7 @@ impl Config {
                 state_topic: &format!(
                     "{}/{}/input/0/parsed",
                     self.mqtt_config.namespace(),
-                    self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default()
+                    self.node
                 ),
                 value_template: ValueTemplate::None,
                 ..base.clone()
@@ -233,7 +332,7 @@ impl Config {
                 state_topic: &format!(
                     "{}/{}/input/fault_code/parsed",
                     self.mqtt_config.namespace(),
-                    self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default()
+                    self.node
                 ),
                 value_template: ValueTemplate::None,
                 icon: Some("mdi:alert"),
@@ -246,7 +345,7 @@ impl Config {
                 state_topic: &format!(
                     "{}/{}/input/warning_code/parsed",
                     self.mqtt_config.namespace(),
-                    self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default()
+                    self.node
                 ),
                 value_template: ValueTemplate::None,
                 icon: Some("mdi:alert-outline"),
@@ -259,7 +358,7 @@ impl Config {
                 state_topic: &format!(
                     "{}/{}/inputs/3/bat_status_9_decoded",
                     self.mqtt_config.namespace(),
-                    self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default()
+                    self.node
                 ),
                 value_template: ValueTemplate::None,
                 icon: Some("mdi:battery-status-variant"),
@@ -272,7 +371,7 @@ impl Config {
                 state_topic: &format!(
                     "{}/{}/inputs/3/bat_status_inv_decoded",
                     self.mqtt_config.namespace(),
-                    self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default()
+                    self.node
                 ),
                 value_template: ValueTemplate::None,
                 icon: Some("mdi:battery-sync"),
@@ -442,22 +541,22 @@ impl Config {
             Entity {
                 key: "e_pv_all",
                 name: "PV Generation (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_pv_all_1",
                 name: "PV Generation (All time) (String 1)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_pv_all_2",
                 name: "PV Generation (All time) (String 2)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_pv_all_3",
                 name: "PV Generation (All time) (String 3)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_pv_day",
@@ -482,7 +581,7 @@ impl Config {
             Entity {
                 key: "e_chg_all",
                 name: "Battery Charge (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_chg_day",
@@ -492,7 +591,7 @@ impl Config {
             Entity {
                 key: "e_dischg_all",
                 name: "Battery Discharge (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_dischg_day",
@@ -502,7 +601,7 @@ impl Config {
             Entity {
                 key: "e_to_user_all",
                 name: "Energy from Grid (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_to_user_day",
@@ -512,7 +611,7 @@ impl Config {
             Entity {
                 key: "e_to_grid_all",
                 name: "Energy to Grid (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_to_grid_day",
@@ -522,7 +621,7 @@ impl Config {
             Entity {
                 key: "e_eps_all",
                 name: "Energy from EPS (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_eps_day",
@@ -532,7 +631,7 @@ impl Config {
             Entity {
                 key: "e_rec_all",
                 name: "Energy of AC Charging (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_rec_day",
@@ -542,7 +641,7 @@ impl Config {
             Entity {
                 key: "e_inv_all",
                 name: "Energy of Inverter (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_inv_day",
@@ -552,7 +651,7 @@ impl Config {
             Entity {
                 key: "e_gen_all",
                 name: "Energy of Generator (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_gen_day",
@@ -562,7 +661,7 @@ impl Config {
             Entity {
                 key: "e_eps_l1_all",
                 name: "Energy of EPS L1 (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_eps_l1_day",
@@ -572,13 +671,38 @@ impl Config {
             Entity {
                 key: "e_eps_l2_all",
                 name: "Energy of EPS L2 (All time)",
-                ..energy.clone()
+                ..lifetime.clone()
             },
             Entity {
                 key: "e_eps_l2_day",
                 name: "Energy of EPS L2  (Today)",
                 ..energy.clone()
             },
+            Entity {
+                key: "grid_import",
+                name: "Grid Import",
+                ..lifetime.clone()
+            },
+            Entity {
+                key: "grid_export",
+                name: "Grid Export",
+                ..lifetime.clone()
+            },
+            Entity {
+                key: "solar",
+                name: "Solar Production",
+                ..lifetime.clone()
+            },
+            Entity {
+                key: "battery_in",
+                name: "Energy into Battery",
+                ..lifetime.clone()
+            },
+            Entity {
+                key: "battery_out",
+                name: "Energy out of Battery",
+                ..lifetime.clone()
+            },
             Entity {
                 key: "t_inner",
                 name: "Inverter Temperature",
@@ -629,6 +753,98 @@ impl Config {
                 name: "Max Cell Temperature (BMS)",
                 ..temperature.clone()
             },
+            Entity {
+                key: "soh",
+                name: "State of Health",
+                entity_category: Some("diagnostic"),
+                state_class: Some("measurement"),
+                unit_of_measurement: Some("%"),
+                icon: Some("mdi:battery-heart-variant"),
+                ..base.clone()
+            },
+            Entity {
+                key: "bat_current",
+                name: "Battery Current",
+                ..current.clone()
+            },
+            Entity {
+                key: "bat_capacity",
+                name: "Battery Capacity",
+                entity_category: Some("diagnostic"),
+                unit_of_measurement: Some("Ah"),
+                icon: Some("mdi:battery"),
+                ..base.clone()
+            },
+            Entity {
+                key: "bat_count",
+                name: "Battery Count",
+                entity_category: Some("diagnostic"),
+                icon: Some("mdi:battery-plus-variant"),
+                ..base.clone()
+            },
+            Entity {
+                key: "bat_cell_count",
+                name: "Cells in Series",
+                entity_category: Some("diagnostic"),
+                ..base.clone()
+            },
+            Entity {
+                key: "bat_parallel_count",
+                name: "Cells in Parallel",
+                entity_category: Some("diagnostic"),
+                ..base.clone()
+            },
+            Entity {
+                key: "cycle_count",
+                name: "Cycle Count",
+                entity_category: Some("diagnostic"),
+                state_class: Some("total_increasing"),
+                icon: Some("mdi:battery-sync"),
+                ..base.clone()
+            },
+            Entity {
+                key: "bms_event_1",
+                name: "BMS Fault Code",
+                entity_category: Some("diagnostic"),
+                icon: Some("mdi:alert"),
+                ..base.clone()
+            },
+            Entity {
+                key: "bms_event_2",
+                name: "BMS Warning Code",
+                entity_category: Some("diagnostic"),
+                icon: Some("mdi:alert-outline"),
+                ..base.clone()
+            },
+            Entity {
+                key: "gen_current",
+                name: "Generator Current",
+                ..current.clone()
+            },
+            Entity {
+                key: "gen_status",
+                name: "Generator Status",
+                entity_category: Some("diagnostic"),
+                icon: Some("mdi:engine"),
+                ..base.clone()
+            },
+            Entity {
+                key: "gen_connect_status",
+                name: "Generator Connected",
+                entity_category: Some("diagnostic"),
+                icon: Some("mdi:power-plug"),
+                ..base.clone()
+            },
+            Entity {
+                key: "i_eps_l1",
+                name: "EPS Current L1",
+                ..current.clone()
+            },
+            Entity {
+                key: "i_eps_l2",
+                name: "EPS Current L2",
+                ..current.clone()
+            },
             Entity {
                 key: "runtime",
                 name: "Total Runtime",
@@ -641,10 +857,16 @@ impl Config {
         ];
 
         sensors
+            .into_iter()
+            // a group publishes nothing but inputs/all and energy
+            .filter(|sensor| {
+                !self.group || *sensor.state_topic == inputs_topic || *sensor.state_topic == energy_topic
+            })
             .map(|sensor| {
                 // fill in unique_id and value_template (if default) which are derived from key
                 let mut sensor = Entity {
                     unique_id: &self.unique_id(sensor.key),
+                    device: self.sub_device(SubDevice::for_key(sensor.key)),
                     ..sensor
                 };
                 if sensor.value_template.is_default() {
@@ -657,13 +879,17 @@ impl Config {
                     payload: serde_json::to_string(&sensor).unwrap(),
                 }
             })
-            .to_vec()
+            .collect()
     }
 
     pub fn all(&self) -> Result<Vec<mqtt::Message>> {
         if !self.global_config.homeassistant_enabled() {
             return Ok(Vec::new());
         }
+        // group commands fan out to the members, but there is no group hold state to show
+        if self.group {
+            return Ok(self.sensors());
+        }
 
         let mut r = vec![
             self.switch("ac_charge", "AC Charge")?,
@@ -704,16 +930,222 @@ impl Config {
         ];
 
         r.append(&mut self.sensors());
+        r.append(&mut self.registers()?);
+
+        Ok(r)
+    }
+
+    /// Discovery for every register in the configured register map. Writable holds become
+    /// `number` or `select` entities, bits become `binary_sensor`s and everything else is a
+    /// `sensor`. Input registers only have topics with `publish_individual_input`.
+    pub fn registers(&self) -> Result<Vec<mqtt::Message>> {
+        let Some(file) = self.global_config.register_file() else {
+            return Ok(Vec::new());
+        };
+        let parser = match RegisterParser::new(&file) {
+            Ok(parser) => parser,
+            Err(e) => {
+                warn!("not generating Home Assistant entities from {}: {}", file, e);
+                return Ok(Vec::new());
+            }
+        };
+
+        let mut r = Vec::new();
+        for register in parser.registers_of_type("hold") {
+            r.append(&mut self.register_entities("hold", register)?);
+        }
+        if self.mqtt_config.publish_individual_input() {
+            for register in parser.registers_of_type("input") {
+                r.append(&mut self.register_entities("input", register)?);
+            }
+        }
 
         Ok(r)
     }
 
+    fn register_entities(&self, kind: &str, register: &register::Register) -> Result<Vec<mqtt::Message>> {
+        // 32-bit values span two registers and have no single topic to read them from
+        if register.data_type == "uint32" || register.data_type == "int32" {
+            debug!("skipping Home Assistant entity for 32-bit {} register {}", kind, register.register_number);
+            return Ok(Vec::new());
+        }
+
+        let datalog = self.node.clone();
+        let writable = kind == "hold" && !register.read_only;
+        let key = format!("{}_{}", kind, register.key());
+
+        // Jinja preamble leaving the register value, sign-corrected, in `v`
+        let mut raw = "{% set v = value | int %}".to_string();
+        if register.data_type == "int16" {
+            raw.push_str("{% if v > 32767 %}{% set v = v - 65536 %}{% endif %}");
+        }
+
+        let base = RegisterEntity {
+            name: register.name.clone(),
+            unique_id: self.unique_id(&key),
+            state_topic: format!(
+                "{}/{}/{}/{}",
+                self.mqtt_config.namespace(),
+                datalog,
+                kind,
+                register.register_number
+            ),
+            value_template: String::new(),
+            command_topic: writable.then(|| {
+                format!(
+                    "{}/cmd/{}/set/hold/{}",
+                    self.mqtt_config.namespace(),
+                    datalog,
+                    register.register_number
+                )
+            }),
+            command_template: None,
+            unit_of_measurement: None,
+            device_class: None,
+            state_class: None,
+            entity_category: match (kind, writable) {
+                ("hold", true) => Some("config"),
+                ("hold", false) => Some("diagnostic"),
+                _ => None,
+            },
+            options: None,
+            min: None,
+            max: None,
+            step: None,
+            device: self.device(),
+            availability: self.availability(),
+        };
+
+        let mut entities = Vec::new();
+        match register.display_as.as_str() {
+            "enum" => {
+                let states: Vec<String> = register
+                    .enum_values
+                    .iter()
+                    .map(|e| format!("{}: '{}'", e.value, e.name))
+                    .collect();
+                let values: Vec<String> = register
+                    .enum_values
+                    .iter()
+                    .map(|e| format!("'{}': {}", e.name, e.value))
+                    .collect();
+                let entity = RegisterEntity {
+                    value_template: format!("{}{{{{ {{{}}}.get(v, v) }}}}", raw, states.join(", ")),
+                    command_template: writable.then(|| format!("{{{{ {{{}}}[value] }}}}", values.join(", "))),
+                    device_class: (!writable).then_some("enum"),
+                    options: Some(register.enum_values.iter().map(|e| e.name.clone()).collect()),
+                    ..base
+                };
+                entities.push((if writable { "select" } else { "sensor" }, key, entity));
+            }
+            "flags" => {
+                for flag in &register.flags {
+                    let label = if flag.description.is_empty() { &flag.name } else { &flag.description };
+                    let flag_key = format!("{}_{}", key, flag.name);
+                    let entity = RegisterEntity {
+                        name: format!("{} {}", register.name, label),
+                        unique_id: self.unique_id(&flag_key),
+                        value_template: format!(
+                            "{}{{{{ 'ON' if v | bitwise_and({}) else 'OFF' }}}}",
+                            raw,
+                            1u32 << flag.bit
+                        ),
+                        command_topic: None,
+                        entity_category: base.entity_category.map(|_| "diagnostic"),
+                        ..base.clone()
+                    };
+                    entities.push(("binary_sensor", flag_key, entity));
+                }
+            }
+            "fields" => {
+                for field in &register.fields {
+                    let field_key = format!("{}_{}", key, field.name);
+                    let extract = if field.byte == 0 { "v % 256" } else { "v // 256" };
+                    let label = if field.description.is_empty() { &field.name } else { &field.description };
+                    let entity = RegisterEntity {
+                        name: label.clone(),
+                        unique_id: self.unique_id(&field_key),
+                        value_template: format!("{}{{{{ {} }}}}", raw, extract),
+                        command_topic: None,
+                        unit_of_measurement: (!field.unit.is_empty()).then(|| field.unit.clone()),
+                        device_class: device_class(&field.unit),
+                        state_class: Some("measurement"),
+                        entity_category: base.entity_category.map(|_| "diagnostic"),
+                        ..base.clone()
+                    };
+                    entities.push(("sensor", field_key, entity));
+                }
+            }
+            _ => {
+                let scale = register.scaling;
+                let decimals = decimals(scale);
+                let value_template = if scale == 1.0 {
+                    format!("{}{{{{ v }}}}", raw)
+                } else if decimals == 0 {
+                    format!("{}{{{{ v * {} }}}}", raw, scale as i64)
+                } else {
+                    format!("{}{{{{ (v * {}) | round({}) }}}}", raw, scale, decimals)
+                };
+                let unit = (!register.unit.is_empty()).then(|| register.unit.clone());
+                let device_class = device_class(&register.unit);
+
+                let entity = if writable {
+                    let (low, high) = if register.data_type == "int16" {
+                        (-32768.0, 32767.0)
+                    } else {
+                        (0.0, 65535.0)
+                    };
+                    RegisterEntity {
+                        value_template,
+                        command_template: Some(if scale == 1.0 {
+                            "{{ value | int }}".to_string()
+                        } else if decimals == 0 {
+                            format!("{{{{ (value / {}) | int }}}}", scale as i64)
+                        } else {
+                            format!("{{{{ (value / {}) | round | int }}}}", scale)
+                        }),
+                        unit_of_measurement: unit,
+                        device_class,
+                        min: Some(register.min.unwrap_or(low * scale)),
+                        max: Some(register.max.unwrap_or(high * scale)),
+                        step: Some(scale),
+                        ..base
+                    }
+                } else {
+                    RegisterEntity {
+                        value_template,
+                        unit_of_measurement: unit,
+                        device_class,
+                        state_class: Some(if device_class == Some("energy") {
+                            "total_increasing"
+                        } else {
+                            "measurement"
+                        }),
+                        ..base
+                    }
+                };
+                entities.push((if writable { "number" } else { "sensor" }, key, entity));
+            }
+        }
+
+        entities
+            .into_iter()
+            .map(|(component, key, entity)| {
+                Ok(mqtt::Message {
+                    topic: self.ha_discovery_topic(component, &key),
+                    retain: true,
+                    payload: serde_json::to_string(&entity)?,
+                })
+            })
+            .collect()
+    }
+
     fn ha_discovery_topic(&self, kind: &str, name: &str) -> String {
         format!(
             "{}/{}/lxp_{}/{}/config",
             self.mqtt_config.homeassistant().prefix(),
             kind,
-            self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default(),
+            self.node,
             // The forward slash is used in some names (e.g. ac_charge/1) but
             // has semantic meaning in MQTT, so must be changed
             name.replace('/', "_"),
@@ -726,15 +1158,15 @@ impl Config {
             state_topic: format!(
                 "{}/{}/hold/21/bits",
                 self.mqtt_config.namespace(),
-                self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default()
+                self.node
             ),
             command_topic: format!(
                 "{}/cmd/{}/set/{}",
                 self.mqtt_config.namespace(),
-                self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default(),
+                self.node,
                 name
             ),
-            unique_id: format!("lxp_{}_{}", self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default(), name),
+            unique_id: format!("lxp_{}_{}", self.node, name),
             name: label.to_string(),
             device: self.device(),
             availability: self.availability(),
@@ -753,17 +1185,17 @@ impl Config {
             state_topic: format!(
                 "{}/{}/hold/{}",
                 self.mqtt_config.namespace(),
-                self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default(),
+                self.node,
                 register as u16,
             ),
             command_topic: format!(
                 "{}/cmd/{}/set/hold/{}",
                 self.mqtt_config.namespace(),
-                self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default(),
+                self.node,
                 register as u16,
             ),
             value_template: "{{ float(value) }}".to_string(),
-            unique_id: format!("lxp_{}_number_{:?}", self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default(), register),
+            unique_id: format!("lxp_{}_number_{:?}", self.node, register),
             device: self.device(),
             availability: self.availability(),
             min: 0.0,
@@ -786,18 +1218,18 @@ impl Config {
             state_topic: format!(
                 "{}/{}/{}",
                 self.mqtt_config.namespace(),
-                self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default(),
+                self.node,
                 name,
             ),
             command_topic: format!(
                 "{}/cmd/{}/set/{}",
                 self.mqtt_config.namespace(),
-                self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default(),
+                self.node,
                 name,
             ),
             command_template: r#"{% set parts = value.split("-") %}{"start":"{{ parts[0] }}", "end":"{{ parts[1] }}"}"#.to_string(),
             value_template: r#"{{ value_json["start"] }}-{{ value_json["end"] }}"#.to_string(),
-            unique_id: format!("lxp_{}_text_{}", self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default(), name),
+            unique_id: format!("lxp_{}_text_{}", self.node, name),
             device: self.device(),
             availability: self.availability(),
             pattern: r"([01]?[0-9]|2[0-3]):[0-5][0-9]-([01]?[0-9]|2[0-3]):[0-5][0-9]".to_string(),
@@ -811,14 +1243,28 @@ impl Config {
     }
 
     fn unique_id(&self, name: &str) -> String {
-        format!("lxp_{}_{}", self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default(), name)
+        format!("lxp_{}_{}", self.node, name)
     }
 
     fn device(&self) -> Device {
         Device {
-            identifiers: [format!("lxp_{}", self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default())],
+            identifiers: [format!("lxp_{}", self.node)],
             manufacturer: "LuxPower".to_owned(),
-            name: format!("lxp_{}", self.inverter.datalog().map(|s| s.to_string()).unwrap_or_default()),
+            name: format!("lxp_{}", self.node),
+            via_device: None,
+        }
+    }
+
+    fn sub_device(&self, sub: SubDevice) -> Device {
+        let inverter = self.device();
+        match sub.suffix() {
+            None => inverter,
+            Some((id, label)) => Device {
+                identifiers: [format!("{}_{}", inverter.identifiers[0], id)],
+                manufacturer: inverter.manufacturer.clone(),
+                name: format!("{} {}", inverter.name, label),
+                via_device: Some(inverter.identifiers[0].clone()),
+            },
         }
     }
 
@@ -828,3 +1274,73 @@ impl Config {
         }
     }
 }
+
+/// Home Assistant device class for a register unit, where there is an obvious one.
+fn device_class(unit: &str) -> Option<&'static str> {
+    match unit {
+        "W" | "kW" => Some("power"),
+        "V" => Some("voltage"),
+        "A" => Some("current"),
+        "Hz" => Some("frequency"),
+        "Wh" | "kWh" => Some("energy"),
+        "°C" | "C" => Some("temperature"),
+        "VA" => Some("apparent_power"),
+        _ => None,
+    }
+}
+
+/// Decimal places implied by a register scale (0.1 -> 1, 0.01 -> 2, 1 -> 0).
+fn decimals(scale: f64) -> usize {
+    let s = scale.to_string();
+    s.split_once('.').map(|(_, frac)| frac.len()).unwrap_or(0)
+}
+
+/// Datalog of a discovery config topic we publish (`{prefix}/{component}/lxp_{datalog}/{name}/config`),
+/// or None for any other topic.
+pub fn discovery_datalog<'a>(prefix: &str, topic: &'a str) -> Option<&'a str> {
+    let rest = topic.strip_prefix(prefix)?.strip_prefix('/')?;
+    match rest.split('/').collect::<Vec<_>>()[..] {
+        [_component, node, _name, "config"] => node.strip_prefix("lxp_"),
+        _ => None,
+    }
+}
+
+/// Datalogs and group names a bridge has published discovery for. Each run records its own
+/// retained on `{namespace}/discovery`; with `remove_stale` the next run reads that record back
+/// and only clears configs listed in it, so another bridge on the same broker keeps its own.
+#[derive(Debug, Default)]
+pub struct Published {
+    nodes: std::collections::HashSet<String>,
+}
+
+impl Published {
+    pub fn topic(namespace: &str) -> String {
+        format!("{}/discovery", namespace)
+    }
+
+    /// The retained record of `nodes`, with its full topic.
+    pub fn message(namespace: &str, nodes: &[String]) -> Result<mqtt::Message> {
+        Ok(mqtt::Message {
+            topic: Self::topic(namespace),
+            retain: true,
+            payload: serde_json::to_string(nodes)?,
+        })
+    }
+
+    /// Add the nodes of a record read back from the broker.
+    pub fn read(&mut self, payload: &[u8]) {
+        if payload.is_empty() {
+            return;
+        }
+        match serde_json::from_slice::<Vec<String>>(payload) {
+            Ok(nodes) => self.nodes.extend(nodes),
+            Err(e) => warn!("ignoring unreadable Home Assistant discovery record: {}", e),
+        }
+    }
+
+    /// Whether `topic` is a discovery config this bridge published for a node it no longer runs.
+    pub fn is_stale(&self, prefix: &str, topic: &str, running: &[String]) -> bool {
+        discovery_datalog(prefix, topic)
+            .is_some_and(|node| self.nodes.contains(node) && !running.iter().any(|r| r == node))
+    }
+}
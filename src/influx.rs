@@ -5,46 +5,134 @@ use std::sync::{Arc, Mutex};
 use crate::coordinator::PacketStats;
 
 use chrono::TimeZone;
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use influxdb_line_protocol::LineProtocolBuilder;
+use std::io::Write;
 use url::Url;
 
 static MEASUREMENT: &str = "eg4_inverter";
 
-/// Minimal InfluxDB 1.x HTTP writer: POST line protocol to `/write` with `precision=s`
-/// so timestamps match the JSON `time` field (Unix seconds).
+/// Where and how line protocol gets written.
+#[derive(Clone)]
+enum WriteApi {
+    /// InfluxDB 1.x: `/write?db=` with optional basic auth
+    V1 {
+        database: String,
+        credentials: Option<(String, String)>,
+    },
+    /// InfluxDB 2.x, and 3.x via its v2 compatibility endpoint: `/api/v2/write`
+    /// with `Authorization: Token`
+    V2 {
+        org: Option<String>,
+        bucket: String,
+        token: Option<String>,
+    },
+}
+
+/// Minimal InfluxDB HTTP writer. Timestamps are generated in Unix seconds (matching the
+/// JSON `time` field) and scaled to the configured precision before being written.
 #[derive(Clone)]
 struct InfluxWriteClient {
     http: reqwest::Client,
     base_url: Url,
-    credentials: Option<(String, String)>,
+    api: WriteApi,
+    precision: String,
+    gzip: bool,
 }
 
 impl InfluxWriteClient {
-    fn new(base_url: Url, credentials: Option<(&str, &str)>) -> Result<Self> {
+    fn new(config: &config::Influx) -> Result<Self> {
+        let base_url = Url::parse(config.url())?;
+
+        let api = match config.api_version() {
+            1 => WriteApi::V1 {
+                database: config.database().to_string(),
+                credentials: match (config.username().as_ref(), config.password().as_ref()) {
+                    (Some(u), Some(p)) => Some((u.clone(), p.clone())),
+                    _ => None,
+                },
+            },
+            _ => WriteApi::V2 {
+                org: config.org().clone(),
+                bucket: config.bucket().to_string(),
+                token: config.token().clone(),
+            },
+        };
+
         Ok(Self {
             http: reqwest::Client::new(),
             base_url,
-            credentials: credentials.map(|(u, p)| (u.to_string(), p.to_string())),
+            api,
+            precision: config.precision().to_string(),
+            gzip: config.gzip(),
         })
     }
 
-    async fn send_line_protocol(&self, database: &str, body: Vec<u8>) -> Result<()> {
-        let mut url = self
-            .base_url
-            .join("write")
-            .map_err(|e| anyhow!("InfluxDB write URL: {}", e))?;
-        url.query_pairs_mut()
-            .append_pair("db", database)
-            .append_pair("precision", "s");
-
-        let mut req = self
-            .http
-            .post(url)
-            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
-            .body(body);
-
-        if let Some((ref u, ref p)) = self.credentials {
-            req = req.basic_auth(u, Some(p));
+    /// Convert a Unix timestamp in seconds to the configured write precision.
+    fn timestamp(&self, secs: i64) -> i64 {
+        match self.precision.as_str() {
+            "ms" => secs * 1_000,
+            "us" => secs * 1_000_000,
+            "ns" => secs * 1_000_000_000,
+            _ => secs,
+        }
+    }
+
+    async fn send_line_protocol(&self, body: Vec<u8>) -> Result<()> {
+        let mut req = match &self.api {
+            WriteApi::V1 { database, credentials } => {
+                let mut url = self
+                    .base_url
+                    .join("write")
+                    .map_err(|e| anyhow!("InfluxDB write URL: {}", e))?;
+                url.query_pairs_mut()
+                    .append_pair("db", database)
+                    .append_pair("precision", &self.precision);
+
+                let mut req = self
+                    .http
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/octet-stream");
+                if let Some((ref u, ref p)) = credentials {
+                    req = req.basic_auth(u, Some(p));
+                }
+                req
+            }
+            WriteApi::V2 { org, bucket, token } => {
+                let mut url = self
+                    .base_url
+                    .join("api/v2/write")
+                    .map_err(|e| anyhow!("InfluxDB write URL: {}", e))?;
+                {
+                    let mut query = url.query_pairs_mut();
+                    if let Some(org) = org {
+                        query.append_pair("org", org);
+                    }
+                    query
+                        .append_pair("bucket", bucket)
+                        .append_pair("precision", &self.precision);
+                }
+
+                let mut req = self
+                    .http
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, "text/plain; charset=utf-8");
+                if let Some(token) = token {
+                    req = req.header(reqwest::header::AUTHORIZATION, format!("Token {}", token));
+                }
+                req
+            }
+        };
+
+        if self.gzip {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&body)?;
+            req = req
+                .header(reqwest::header::CONTENT_ENCODING, "gzip")
+                .body(encoder.finish()?);
+        } else {
+            req = req.body(body);
         }
 
         let resp = req.send().await?;
@@ -61,6 +149,9 @@ impl InfluxWriteClient {
 pub enum ChannelData {
     InputData(serde_json::Value),
     HoldData(serde_json::Value),
+    /// A single point for the named measurement: `datalog` and `time` are required and every
+    /// other numeric member becomes a field.
+    Measurement(String, serde_json::Value),
     Shutdown,
 }
 
@@ -94,20 +185,11 @@ impl Influx {
 
         info!("initializing influx at {}", self.config.influx().url());
 
-        let client = {
-            let config = self.config.influx();
-            let url = Url::parse(config.url())?;
-            let credentials = match (config.username().as_ref(), config.password().as_ref()) {
-                (Some(u), Some(p)) => Some((u.as_str(), p.as_str())),
-                _ => None,
-            };
-
-            InfluxWriteClient::new(url, credentials)?
-        };
+        let client = InfluxWriteClient::new(&self.config.influx())?;
 
         // Test the connection by writing a test point
         info!("Testing InfluxDB connection...");
-        let ts = chrono::Utc::now().timestamp();
+        let ts = client.timestamp(chrono::Utc::now().timestamp());
         let test_body = LineProtocolBuilder::new()
             .measurement("connection_test")
             .tag("test", "true")
@@ -116,7 +198,7 @@ impl Influx {
             .close_line()
             .build();
 
-        match client.send_line_protocol(&self.database(), test_body).await {
+        match client.send_line_protocol(test_body).await {
             Ok(_) => {
                 info!("Successfully connected to InfluxDB");
             }
@@ -149,147 +231,229 @@ impl Influx {
         let mut receiver = self.channels.to_influx.subscribe();
         info!("InfluxDB sender started");
 
-        loop {
-            match receiver.recv().await {
-                Ok(Shutdown) => {
-                    info!("InfluxDB sender received shutdown signal");
-                    break;
-                }
-                Ok(InputData(data)) | Ok(HoldData(data)) => {
-                    info!("Received data for InfluxDB: {:?}", data);
-                    let mut lp = LineProtocolBuilder::new();
-
-                    // Extract common fields
-                    let serial = data
-                        .get("serial")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow!("Missing serial in data"))?;
-                    let datalog = data
-                        .get("datalog")
-                        .and_then(|v| v.as_str())
-                        .ok_or_else(|| anyhow!("Missing datalog in data"))?;
-                    let timestamp = data
-                        .get("time")
-                        .and_then(|v| v.as_i64())
-                        .ok_or_else(|| anyhow!("Missing time in data"))?;
-
-                    info!(
-                        "Processing data for serial={}, datalog={}, timestamp={}",
-                        serial, datalog, timestamp
-                    );
+        let batch_size = self.config.influx().batch_size();
+        let mut flush_timer = tokio::time::interval(std::time::Duration::from_secs(
+            self.config.influx().flush_interval().max(1),
+        ));
+        flush_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
 
-                    // Get raw register data
-                    let raw_data = data
-                        .get("raw_data")
-                        .and_then(|v| v.as_object())
-                        .ok_or_else(|| anyhow!("Missing raw_data in data"))?;
-
-                    // Convert raw_data to HashMap<String, String>
-                    let mut register_data = HashMap::new();
-                    for (key, value) in raw_data {
-                        if let Some(hex_value) = value.as_str() {
-                            register_data.insert(key.clone(), hex_value.to_string());
-                        }
-                    }
+        // Line protocol waiting to be written, and how many points it holds
+        let mut batch: Vec<u8> = Vec::new();
+        let mut batch_points = 0;
 
-                    info!("Converted raw data to register data: {:?}", register_data);
-
-                    // Decode register values if we have a register parser
-                    let decoded_values = if let Some(parser) = &self.register_parser {
-                        parser.decode_registers(&register_data, self.config.show_unknown(), datalog)
-                    } else {
-                        // If no register parser, just use raw values
-                        register_data
-                            .iter()
-                            .map(|(k, v)| (k.clone(), u16::from_str_radix(v, 16).unwrap_or(0) as f64))
-                            .collect()
+        loop {
+            tokio::select! {
+                msg = receiver.recv() => {
+                    let (points, point_count) = match msg {
+                        Ok(Shutdown) => {
+                            info!("InfluxDB sender received shutdown signal");
+                            break;
+                        }
+                        Ok(InputData(data)) | Ok(HoldData(data)) => {
+                            info!("Received data for InfluxDB: {:?}", data);
+                            self.points_for(&client, &data)?
+                        }
+                        Ok(Measurement(name, data)) => {
+                            info!("Received {} measurement for InfluxDB: {:?}", name, data);
+                            self.measurement_points(&client, &name, &data)?
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            info!("InfluxDB channel closed, shutting down sender task");
+                            break;
+                        }
+                        Err(e) => {
+                            error!("Error receiving from InfluxDB channel: {}", e);
+                            continue;
+                        }
                     };
-
-                    info!("Decoded values: {:?}", decoded_values);
-
-                    if chrono::Utc.timestamp_opt(timestamp, 0).single().is_none() {
-                        return Err(anyhow!("Invalid timestamp: {}", timestamp));
-                    }
-
-                    let point_count = decoded_values.len();
-
-                    // Create points for each decoded value
-                    for (name, value) in decoded_values {
-                        // Add the field value
-                        lp = lp
-                            .measurement(MEASUREMENT)
-                            .tag("serial", serial)
-                            .tag("datalog", datalog)
-                            .field(name.as_str(), value)
-                            .timestamp(timestamp)
-                            .close_line();
-                        trace!(
-                            "Preparing InfluxDB point: measurement={}, serial={}, datalog={}, field={}, value={}, timestamp={}",
-                            MEASUREMENT, serial, datalog, name, value, timestamp
-                        );
-                    }
-
-                    let points = lp.build();
                     if points.is_empty() {
                         info!("No InfluxDB points to send");
                         continue;
                     }
 
-                    info!(
-                        "Prepared {} points for InfluxDB ({} bytes line protocol)",
-                        point_count,
-                        points.len()
-                    );
+                    batch.extend_from_slice(&points);
+                    batch_points += point_count;
 
-                    let mut retry_count = 0;
-                    while retry_count < 3 {
-                        match client.send_line_protocol(&self.database(), points.clone()).await {
-                            Ok(_) => {
-                                info!(
-                                    "Successfully sent {} points to InfluxDB for datalog={}, serial={}",
-                                    point_count, datalog, serial
-                                );
-                                // Increment stats after successful write
-                                if let Ok(mut stats) = self.shared_stats.lock() {
-                                    stats.influx_writes += 1;
-                                    debug!("Incremented InfluxDB writes counter to {}", stats.influx_writes);
-                                }
-                                break;
-                            }
-                            Err(err) => {
-                                error!(
-                                    "InfluxDB push failed: {:?} - retrying in 10s (attempt {}/3)",
-                                    err,
-                                    retry_count + 1
-                                );
-                                if let Ok(mut stats) = self.shared_stats.lock() {
-                                    stats.influx_errors += 1;
-                                }
-                                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-                                retry_count += 1;
-                            }
-                        }
-                    }
-                    if retry_count == 3 {
-                        error!("Failed to send data to InfluxDB after 3 attempts");
+                    if batch_points >= batch_size {
+                        self.flush(&client, std::mem::take(&mut batch), batch_points).await;
+                        batch_points = 0;
                     }
                 }
-                Err(e) => {
-                    if let broadcast::error::RecvError::Closed = e {
-                        info!("InfluxDB channel closed, shutting down sender task");
-                        break;
-                    } else {
-                        error!("Error receiving from InfluxDB channel: {}", e);
+                _ = flush_timer.tick() => {
+                    if batch_points > 0 {
+                        self.flush(&client, std::mem::take(&mut batch), batch_points).await;
+                        batch_points = 0;
                     }
                 }
             }
         }
 
+        if batch_points > 0 {
+            self.flush(&client, batch, batch_points).await;
+        }
+
         info!("InfluxDB sender loop exiting");
         Ok(())
     }
 
-    fn database(&self) -> String {
-        self.config.influx().database().to_string()
+    /// Build line protocol for one input/hold message; returns the body and its point count.
+    fn points_for(&self, client: &InfluxWriteClient, data: &serde_json::Value) -> Result<(Vec<u8>, usize)> {
+        let mut lp = LineProtocolBuilder::new();
+        let extra_tags = self.config.influx().tags().clone();
+
+        // Extract common fields
+        let serial = data
+            .get("serial")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Missing serial in data"))?;
+        let datalog = data
+            .get("datalog")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Missing datalog in data"))?;
+        let timestamp = data
+            .get("time")
+            .and_then(|v| v.as_i64())
+            .ok_or_else(|| anyhow!("Missing time in data"))?;
+
+        info!(
+            "Processing data for serial={}, datalog={}, timestamp={}",
+            serial, datalog, timestamp
+        );
+
+        // Get raw register data
+        let raw_data = data
+            .get("raw_data")
+            .and_then(|v| v.as_object())
+            .ok_or_else(|| anyhow!("Missing raw_data in data"))?;
+
+        // Convert raw_data to HashMap<String, String>
+        let mut register_data = HashMap::new();
+        for (key, value) in raw_data {
+            if let Some(hex_value) = value.as_str() {
+                register_data.insert(key.clone(), hex_value.to_string());
+            }
+        }
+
+        info!("Converted raw data to register data: {:?}", register_data);
+
+        // Decode register values if we have a register parser
+        let decoded_values = if let Some(parser) = &self.register_parser {
+            parser.decode_registers(&register_data, self.config.show_unknown(), datalog)
+        } else {
+            // If no register parser, just use raw values
+            register_data
+                .iter()
+                .map(|(k, v)| (k.clone(), u16::from_str_radix(v, 16).unwrap_or(0) as f64))
+                .collect()
+        };
+
+        info!("Decoded values: {:?}", decoded_values);
+
+        if chrono::Utc.timestamp_opt(timestamp, 0).single().is_none() {
+            return Err(anyhow!("Invalid timestamp: {}", timestamp));
+        }
+
+        let point_count = decoded_values.len();
+
+        // Create points for each decoded value
+        for (name, value) in decoded_values {
+            let mut line = lp
+                .measurement(MEASUREMENT)
+                .tag("serial", serial)
+                .tag("datalog", datalog);
+            for (key, tag) in &extra_tags {
+                line = line.tag(key, tag);
+            }
+            // Add the field value
+            lp = line
+                .field(name.as_str(), value)
+                .timestamp(client.timestamp(timestamp))
+                .close_line();
+            trace!(
+                "Preparing InfluxDB point: measurement={}, serial={}, datalog={}, field={}, value={}, timestamp={}",
+                MEASUREMENT, serial, datalog, name, value, timestamp
+            );
+        }
+
+        Ok((lp.build(), point_count))
+    }
+
+    /// Build one line for a `Measurement` message; returns the body and its point count.
+    fn measurement_points(
+        &self,
+        client: &InfluxWriteClient,
+        measurement: &str,
+        data: &serde_json::Value,
+    ) -> Result<(Vec<u8>, usize)> {
+        let datalog = data
+            .get("datalog")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Missing datalog in data"))?;
+        let timestamp = data
+            .get("time")
+            .and_then(|v| v.as_i64())
+            .ok_or_else(|| anyhow!("Missing time in data"))?;
+
+        let mut fields = data
+            .as_object()
+            .ok_or_else(|| anyhow!("Measurement data is not an object"))?
+            .iter()
+            .filter(|(name, _)| name.as_str() != "time")
+            .filter_map(|(name, value)| value.as_f64().map(|v| (name.as_str(), v)));
+
+        let Some((first_name, first_value)) = fields.next() else {
+            return Ok((Vec::new(), 0));
+        };
+
+        let mut line = LineProtocolBuilder::new()
+            .measurement(measurement)
+            .tag("datalog", datalog);
+        for (key, tag) in self.config.influx().tags() {
+            line = line.tag(key, tag);
+        }
+        let mut line = line.field(first_name, first_value);
+        for (name, value) in fields {
+            line = line.field(name, value);
+        }
+
+        Ok((line.timestamp(client.timestamp(timestamp)).close_line().build(), 1))
+    }
+
+    async fn flush(&self, client: &InfluxWriteClient, points: Vec<u8>, point_count: usize) {
+        info!(
+            "Prepared {} points for InfluxDB ({} bytes line protocol)",
+            point_count,
+            points.len()
+        );
+
+        let mut retry_count = 0;
+        while retry_count < 3 {
+            match client.send_line_protocol(points.clone()).await {
+                Ok(_) => {
+                    info!("Successfully sent {} points to InfluxDB", point_count);
+                    // Increment stats after successful write
+                    if let Ok(mut stats) = self.shared_stats.lock() {
+                        stats.influx_writes += 1;
+                        debug!("Incremented InfluxDB writes counter to {}", stats.influx_writes);
+                    }
+                    break;
+                }
+                Err(err) => {
+                    error!(
+                        "InfluxDB push failed: {:?} - retrying in 10s (attempt {}/3)",
+                        err,
+                        retry_count + 1
+                    );
+                    if let Ok(mut stats) = self.shared_stats.lock() {
+                        stats.influx_errors += 1;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                    retry_count += 1;
+                }
+            }
+        }
+        if retry_count == 3 {
+            error!("Failed to send data to InfluxDB after 3 attempts");
+        }
     }
 }
@@ -0,0 +1,296 @@
+//! JSON documents on `cmd/{datalog}/json`.
+//!
+//! The path-shaped command topics each take one plain-text argument. A JSON document can
+//! carry any `Command` with typed arguments, read several registers at once with `count`,
+//! name registers from the register map instead of numbering them, and batch commands in
+//! an array. Documents are checked field by field against [`schema`], which the bridge
+//! publishes retained on `{namespace}/schema/command`.
+
+use crate::prelude::*;
+use crate::register::RegisterParser;
+
+use serde::Deserialize;
+
+/// A register by number, or by name in the register map.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum RegisterRef {
+    Number(u16),
+    Name(String),
+}
+
+impl RegisterRef {
+    fn resolve(&self, kind: &str, registers: Option<&RegisterParser>) -> Result<u16> {
+        match self {
+            Self::Number(n) => Ok(*n),
+            Self::Name(name) => {
+                let registers =
+                    registers.ok_or_else(|| anyhow!("no register map loaded to look up {}", name))?;
+                registers
+                    .find(kind, name)
+                    .map(|r| r.register_number)
+                    .ok_or_else(|| anyhow!("unknown {} register {}", kind, name))
+            }
+        }
+    }
+}
+
+fn one() -> u16 {
+    1
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum JsonCommand {
+    ReadInputs { block: u16 },
+    ReadInput {
+        register: RegisterRef,
+        #[serde(default = "one")]
+        count: u16,
+    },
+    ReadHold {
+        register: RegisterRef,
+        #[serde(default = "one")]
+        count: u16,
+    },
+    ReadParam { register: u16 },
+    ReadAcCharge { slot: u16 },
+    ReadAcFirst { slot: u16 },
+    ReadChargePriority { slot: u16 },
+    ReadForcedDischarge { slot: u16 },
+    SetHold { register: RegisterRef, value: u16 },
+    SetParam { register: u16, value: u16 },
+    SetAcCharge { slot: u16, start: String, end: String },
+    SetAcFirst { slot: u16, start: String, end: String },
+    SetChargePriority { slot: u16, start: String, end: String },
+    SetForcedDischarge { slot: u16, start: String, end: String },
+    AcCharge { enable: bool },
+    ChargePriority { enable: bool },
+    ForcedDischarge { enable: bool },
+    ChargeRatePct { value: u16 },
+    DischargeRatePct { value: u16 },
+    AcChargeRatePct { value: u16 },
+    AcChargeSocLimitPct { value: u16 },
+    DischargeCutoffSocLimitPct { value: u16 },
+    ConfirmHold { register: RegisterRef, token: String },
+}
+
+impl JsonCommand {
+    pub fn into_command(
+        self,
+        inverter: config::Inverter,
+        registers: Option<&RegisterParser>,
+    ) -> Result<Command> {
+        use JsonCommand::*;
+
+        let r = match self {
+            ReadInputs { block } => {
+                if !(1..=6).contains(&block) {
+                    bail!("block must be 1-6, got {}", block);
+                }
+                Command::ReadInputs(inverter, block)
+            }
+            ReadInput { register, count } => {
+                Command::ReadInput(inverter, register.resolve("input", registers)?, count_ok(count)?)
+            }
+            ReadHold { register, count } => {
+                Command::ReadHold(inverter, register.resolve("hold", registers)?, count_ok(count)?)
+            }
+            ReadParam { register } => Command::ReadParam(inverter, register),
+            ReadAcCharge { slot } => Command::ReadAcChargeTime(inverter, slot_ok(slot)?),
+            ReadAcFirst { slot } => Command::ReadAcFirstTime(inverter, slot_ok(slot)?),
+            ReadChargePriority { slot } => Command::ReadChargePriorityTime(inverter, slot_ok(slot)?),
+            ReadForcedDischarge { slot } => Command::ReadForcedDischargeTime(inverter, slot_ok(slot)?),
+            SetHold { register, value } => {
+                Command::SetHold(inverter, register.resolve("hold", registers)?, value)
+            }
+            SetParam { register, value } => Command::WriteParam(inverter, register, value),
+            SetAcCharge { slot, start, end } => {
+                Command::SetAcChargeTime(inverter, slot_ok(slot)?, times(&start, &end)?)
+            }
+            SetAcFirst { slot, start, end } => {
+                Command::SetAcFirstTime(inverter, slot_ok(slot)?, times(&start, &end)?)
+            }
+            SetChargePriority { slot, start, end } => {
+                Command::SetChargePriorityTime(inverter, slot_ok(slot)?, times(&start, &end)?)
+            }
+            SetForcedDischarge { slot, start, end } => {
+                Command::SetForcedDischargeTime(inverter, slot_ok(slot)?, times(&start, &end)?)
+            }
+            AcCharge { enable } => Command::AcCharge(inverter, enable),
+            ChargePriority { enable } => Command::ChargePriority(inverter, enable),
+            ForcedDischarge { enable } => Command::ForcedDischarge(inverter, enable),
+            ChargeRatePct { value } => Command::ChargeRate(inverter, percent(value)?),
+            DischargeRatePct { value } => Command::DischargeRate(inverter, percent(value)?),
+            AcChargeRatePct { value } => Command::AcChargeRate(inverter, percent(value)?),
+            AcChargeSocLimitPct { value } => Command::AcChargeSocLimit(inverter, percent(value)?),
+            DischargeCutoffSocLimitPct { value } => {
+                Command::DischargeCutoffSocLimit(inverter, percent(value)?)
+            }
+            ConfirmHold { register, token } => {
+                Command::ConfirmHold(inverter, register.resolve("hold", registers)?, token)
+            }
+        };
+
+        Ok(r)
+    }
+}
+
+/// Parse a document: one command object, or an array of them run in order.
+pub fn parse(payload: &str) -> Result<Vec<JsonCommand>> {
+    let value: serde_json::Value =
+        serde_json::from_str(payload).map_err(|e| anyhow!("invalid JSON command: {}", e))?;
+
+    match value {
+        serde_json::Value::Array(items) => {
+            if items.is_empty() {
+                bail!("empty command list");
+            }
+            items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    serde_json::from_value(item).map_err(|e| anyhow!("command {}: {}", i, e))
+                })
+                .collect()
+        }
+        value => Ok(vec![serde_json::from_value(value)?]),
+    }
+}
+
+fn count_ok(count: u16) -> Result<u16> {
+    if !(1..=MAX_COUNT).contains(&count) {
+        bail!("count must be 1-{}, got {}", MAX_COUNT, count);
+    }
+    Ok(count)
+}
+
+fn slot_ok(slot: u16) -> Result<u16> {
+    if !(1..=3).contains(&slot) {
+        bail!("slot must be 1-3, got {}", slot);
+    }
+    Ok(slot)
+}
+
+fn percent(value: u16) -> Result<u16> {
+    if value > 100 {
+        bail!("percentage must be 0-100, got {}", value);
+    }
+    Ok(value)
+}
+
+// "20:00", "21:30" -> [20, 0, 21, 30]
+fn times(start: &str, end: &str) -> Result<[u8; 4]> {
+    let hm = |t: &str| -> Result<(u8, u8)> {
+        let (h, m) = t
+            .split_once(':')
+            .ok_or_else(|| anyhow!("badly formatted time {}, use HH:MM", t))?;
+        let (h, m): (u8, u8) = (h.parse()?, m.parse()?);
+        if h > 23 || m > 59 {
+            bail!("time {} out of range", t);
+        }
+        Ok((h, m))
+    };
+    let (start, end) = (hm(start)?, hm(end)?);
+    Ok([start.0, start.1, end.0, end.1])
+}
+
+/// Most registers one read will ask the inverter for.
+const MAX_COUNT: u16 = 40;
+
+enum Arg {
+    Register,
+    Number,
+    Count,
+    Block,
+    Slot,
+    Time,
+    Value,
+    Percent,
+    Enable,
+    Token,
+}
+
+/// Every command and its arguments, in the order `schema()` lists them. `count` is optional.
+const COMMANDS: &[(&str, &[(&str, Arg)])] = &[
+    ("read_inputs", &[("block", Arg::Block)]),
+    ("read_input", &[("register", Arg::Register), ("count", Arg::Count)]),
+    ("read_hold", &[("register", Arg::Register), ("count", Arg::Count)]),
+    ("read_param", &[("register", Arg::Number)]),
+    ("read_ac_charge", &[("slot", Arg::Slot)]),
+    ("read_ac_first", &[("slot", Arg::Slot)]),
+    ("read_charge_priority", &[("slot", Arg::Slot)]),
+    ("read_forced_discharge", &[("slot", Arg::Slot)]),
+    ("set_hold", &[("register", Arg::Register), ("value", Arg::Value)]),
+    ("set_param", &[("register", Arg::Number), ("value", Arg::Value)]),
+    ("set_ac_charge", &[("slot", Arg::Slot), ("start", Arg::Time), ("end", Arg::Time)]),
+    ("set_ac_first", &[("slot", Arg::Slot), ("start", Arg::Time), ("end", Arg::Time)]),
+    ("set_charge_priority", &[("slot", Arg::Slot), ("start", Arg::Time), ("end", Arg::Time)]),
+    ("set_forced_discharge", &[("slot", Arg::Slot), ("start", Arg::Time), ("end", Arg::Time)]),
+    ("ac_charge", &[("enable", Arg::Enable)]),
+    ("charge_priority", &[("enable", Arg::Enable)]),
+    ("forced_discharge", &[("enable", Arg::Enable)]),
+    ("charge_rate_pct", &[("value", Arg::Percent)]),
+    ("discharge_rate_pct", &[("value", Arg::Percent)]),
+    ("ac_charge_rate_pct", &[("value", Arg::Percent)]),
+    ("ac_charge_soc_limit_pct", &[("value", Arg::Percent)]),
+    ("discharge_cutoff_soc_limit_pct", &[("value", Arg::Percent)]),
+    ("confirm_hold", &[("register", Arg::Register), ("token", Arg::Token)]),
+];
+
+/// JSON Schema (draft 2020-12) for documents on `cmd/{datalog}/json`.
+pub fn schema() -> serde_json::Value {
+    use serde_json::json;
+
+    let commands: Vec<serde_json::Value> = COMMANDS
+        .iter()
+        .map(|(name, args)| {
+            let mut properties = serde_json::Map::new();
+            properties.insert("command".to_string(), json!({ "const": name }));
+            let mut required = vec!["command"];
+            for (arg, kind) in args.iter() {
+                let schema = match kind {
+                    Arg::Register => json!({ "$ref": "#/$defs/register" }),
+                    Arg::Number | Arg::Value => json!({ "type": "integer", "minimum": 0, "maximum": 65535 }),
+                    Arg::Count => json!({ "type": "integer", "minimum": 1, "maximum": MAX_COUNT, "default": 1 }),
+                    Arg::Block => json!({ "type": "integer", "minimum": 1, "maximum": 6 }),
+                    Arg::Slot => json!({ "type": "integer", "minimum": 1, "maximum": 3 }),
+                    Arg::Time => json!({ "$ref": "#/$defs/time" }),
+                    Arg::Percent => json!({ "type": "integer", "minimum": 0, "maximum": 100 }),
+                    Arg::Enable => json!({ "type": "boolean" }),
+                    Arg::Token => json!({ "type": "string", "minLength": 1 }),
+                };
+                properties.insert(arg.to_string(), schema);
+                if !matches!(kind, Arg::Count) {
+                    required.push(arg);
+                }
+            }
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+                "additionalProperties": false,
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "eg4-bridge command",
+        "oneOf": [
+            { "$ref": "#/$defs/command" },
+            { "type": "array", "items": { "$ref": "#/$defs/command" }, "minItems": 1 },
+        ],
+        "$defs": {
+            "register": {
+                "description": "register number, or name from the register map",
+                "oneOf": [
+                    { "type": "integer", "minimum": 0, "maximum": 65535 },
+                    { "type": "string", "minLength": 1 },
+                ],
+            },
+            "time": { "type": "string", "pattern": "^([01]?[0-9]|2[0-3]):[0-5][0-9]$" },
+            "command": { "oneOf": commands },
+        },
+    })
+}
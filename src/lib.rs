@@ -1,19 +1,34 @@
 // Module declarations for the application's core components
+pub mod alerts;        // Fault and warning notifications
+pub mod audit;         // Audit log of inverter writes
+pub mod battery_health; // Cell balance, SOH trend, efficiency and capacity
 pub mod channels;      // Inter-component communication channels
+pub mod charge_planner; // Forecast-driven overnight AC charge planning
 pub mod command;       // Command processing and handling
 pub mod config;        // Configuration management
+pub mod controllers;   // Closed-loop controllers for inverter settings
 pub mod coordinator;   // Main application coordinator
+pub mod daily_summary; // End-of-day energy totals
 pub mod database;      // Database operations and storage
 pub mod datalog_writer; // Data logging functionality
+pub mod deadband;      // Report-by-exception for register topics
+pub mod energy_totals; // Sanitized lifetime energy counters
+pub mod fields;        // Per-field MQTT topics
+pub mod groups;        // Parallel inverters aggregated into a virtual inverter
 pub mod home_assistant; // Home Assistant integration
 pub mod influx;        // InfluxDB integration
+pub mod json_command;  // JSON command documents
 pub mod mqtt;          // MQTT client and messaging
 pub mod options;       // Command line options parsing
+pub mod outbox;        // MQTT store-and-forward outbox
 pub mod prelude;       // Common imports and types
 pub mod register_cache; // Register value caching
+pub mod rules;         // Local automations on live input data
 pub mod scheduler;     // Task scheduling
+pub mod tariff;        // Tariff cost and savings accounting
 pub mod unixtime;      // Unix timestamp handling
 pub mod utils;         // Utility functions
+pub mod write_policy;  // Which hold registers may be written, and how
 pub mod eg4;           // EG4 inverter protocol implementation
 pub mod error;         // Error handling and types
 pub mod register;      // Register definitions and parsing
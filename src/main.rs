@@ -53,10 +53,10 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     info!("Starting eg4-bridge {}", CARGO_PKG_VERSION);
 
     // Create a channel for shutdown signaling
-    let (_shutdown_tx, shutdown_rx) = broadcast::channel(1);
+    let (shutdown_tx, shutdown_rx) = broadcast::channel(1);
 
     // Run the application
-    let _app_handle = tokio::spawn(async move {
+    let app_handle = tokio::spawn(async move {
         if let Err(e) = Coordinator::app(shutdown_rx, config.clone()).await {
             error!("Application error: {}", e);
             std::process::exit(1);
@@ -71,11 +71,9 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         select! {
             _ = tokio::time::sleep(duration) => {
                 info!("Runtime duration reached, terminating");
-                std::process::exit(0);
             }
             _ = tokio::signal::ctrl_c() => {
                 info!("Ctrl+C received, terminating");
-                std::process::exit(0);
             }
         }
     } else {
@@ -84,8 +82,13 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             error!("Failed to listen for Ctrl+C: {}", e);
         }
         info!("Ctrl+C received, terminating");
-        std::process::exit(0);
     }
+
+    // let the application restore anything it changed on the inverters before exiting;
+    // the coordinator bounds how long that takes
+    let _ = shutdown_tx.send(());
+    let _ = app_handle.await;
+    std::process::exit(0);
 }
 
 
@@ -1,12 +1,23 @@
 use crate::prelude::*;
 use crate::eg4::packet::BatteryStatusString;
 use crate::coordinator::PacketStats;
-
-use rumqttc::{AsyncClient, Event, EventLoop, Incoming, LastWill, MqttOptions, Publish, QoS};
+use crate::deadband;
+use crate::fields;
+use crate::json_command;
+use crate::outbox::Outbox;
+use crate::register::RegisterParser;
+
+use rumqttc::{v5, AsyncClient, Event, EventLoop, Incoming, LastWill, MqttOptions, QoS, Transport};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
+/// How long after connecting `remove_stale` listens for retained discovery configs.
+const REMOVE_STALE_WINDOW: std::time::Duration = std::time::Duration::from_secs(10);
+
 // Message {{{
-#[derive(Eq, PartialEq, Debug, Clone)]
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub topic: String,
     pub retain: bool,
@@ -16,6 +27,8 @@ pub struct Message {
 pub enum TargetInverter {
     Serial(Serial),
     All,
+    /// An inverter group from config, by name
+    Group(String),
 }
 
 impl Message {
@@ -297,12 +310,66 @@ impl Message {
             ["set", "discharge_cutoff_soc_limit_pct"] => {
                 DischargeCutoffSocLimit(inverter, self.payload_int()?)
             }
+            ["confirm", "hold", register] => ConfirmHold(inverter, register.parse()?, self.payload.clone()),
+            ["charge_plan", "cancel"] => CancelChargePlan(inverter),
             [..] => bail!("unhandled: {:?}", self),
         };
 
         Ok(r)
     }
 
+    /// Commands for one inverter: every command in a JSON document on `cmd/{datalog}/json`,
+    /// otherwise the single command named by the topic. Register names are looked up in
+    /// `registers`.
+    pub fn to_commands(
+        &self,
+        inverter: config::Inverter,
+        registers: Option<&RegisterParser>,
+    ) -> Result<Vec<Command>> {
+        if !self.is_json_command() {
+            return Ok(vec![self.to_command(inverter)?]);
+        }
+
+        json_command::parse(&self.payload)?
+            .into_iter()
+            .map(|c| c.into_command(inverter.clone(), registers))
+            .collect()
+    }
+
+    pub fn is_json_command(&self) -> bool {
+        matches!(self.split_cmd_topic(), Ok((_, parts)) if parts[..] == ["json"])
+    }
+
+    /// Unwrap a `{"id": ..., "payload": ...}` command envelope, leaving the inner payload in
+    /// place. Returns the id to echo in the result, or None for a bare payload.
+    pub fn take_envelope_id(&mut self) -> Option<serde_json::Value> {
+        let serde_json::Value::Object(mut obj) = serde_json::from_str(&self.payload).ok()? else {
+            return None;
+        };
+        if obj.len() != 2 || !obj.contains_key("id") || !obj.contains_key("payload") {
+            return None;
+        }
+
+        let id = obj.remove("id")?;
+        self.payload = match obj.remove("payload")? {
+            serde_json::Value::String(s) => s,
+            other => other.to_string(),
+        };
+        Some(id)
+    }
+
+    /// Publish settings for this message's class, from a topic relative to the namespace.
+    pub fn publish_policy<'a>(&self, policy: &'a config::MqttPublishPolicy) -> &'a config::MqttPublish {
+        // {datalog}/{kind}/...
+        match self.topic.split('/').nth(1) {
+            Some("input" | "inputs") => &policy.inputs,
+            Some("hold" | "param" | "ac_charge" | "ac_first" | "charge_priority" | "forced_discharge") => {
+                &policy.holds
+            }
+            _ => &policy.other,
+        }
+    }
+
     // given a cmd Message, return the datalog it is intended for.
     //
     // eg cmd/AB12345678/set/ac_charge => (AB12345678, ['set', 'ac_charge'])
@@ -321,6 +388,9 @@ impl Message {
 
         if datalog == "all" {
             Ok((TargetInverter::All, rest))
+        } else if datalog.len() != 10 {
+            // datalogs are always 10 characters, group names never are
+            Ok((TargetInverter::Group(datalog.to_string()), rest))
         } else {
             let serial = Serial::from_str(datalog)?;
             Ok((TargetInverter::Serial(serial), rest))
@@ -370,12 +440,217 @@ impl Message {
     }
 } // }}}
 
+// CommandResult {{{
+/// Where a command's result should also be sent, from MQTT v5 request properties.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct ReplyTo {
+    /// Full topic, not under the namespace
+    pub response_topic: Option<String>,
+    pub correlation_data: Option<Vec<u8>>,
+}
+
+/// JSON published to the response topic, and to `result/...` with `mqtt.json_results`, for
+/// every command.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct CommandResult {
+    /// `ok` or `error`
+    pub status: &'static str,
+    pub error: Option<String>,
+    /// Register values returned by a read, keyed by register number
+    pub values: BTreeMap<u16, u16>,
+    /// Echoed from a `{id, payload}` envelope
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+}
+
+impl CommandResult {
+    pub fn new(id: Option<serde_json::Value>, result: &Result<Vec<(u16, u16)>>) -> Self {
+        match result {
+            Ok(values) => Self {
+                status: "ok",
+                error: None,
+                values: values.iter().copied().collect(),
+                id,
+            },
+            Err(e) => Self {
+                status: "error",
+                error: Some(e.to_string()),
+                values: BTreeMap::new(),
+                id,
+            },
+        }
+    }
+} // }}}
+
+// StateCache {{{
+/// Last message published to each retained topic, kept so state can be re-sent when Home
+/// Assistant restarts and may have lost what it had.
+#[derive(Default, Debug)]
+pub struct StateCache {
+    messages: HashMap<String, Message>,
+}
+
+impl StateCache {
+    pub fn record(&mut self, message: &Message) {
+        if message.retain {
+            self.messages.insert(message.topic.clone(), message.clone());
+        }
+    }
+
+    /// Cached messages, ordered by topic.
+    pub fn messages(&self) -> Vec<Message> {
+        let mut r: Vec<Message> = self.messages.values().cloned().collect();
+        r.sort_by(|a, b| a.topic.cmp(&b.topic));
+        r
+    }
+} // }}}
+
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum ChannelData {
     Message(Message),
+    /// A command carrying MQTT v5 response properties (from_mqtt), or the result being sent
+    /// back with them (to_mqtt, with a full topic)
+    Request(Message, ReplyTo),
+    Reply(Message, ReplyTo),
     Shutdown,
 }
 
+// Client {{{
+/// The broker connection over MQTT 3.1.1 or 5; only v5 carries the response topic and
+/// correlation data for command results.
+#[derive(Clone)]
+enum Client {
+    V4(AsyncClient),
+    V5(v5::AsyncClient),
+}
+
+impl Client {
+    async fn publish(&self, topic: &str, qos: QoS, retain: bool, payload: impl Into<Vec<u8>>) -> Result<()> {
+        let payload = payload.into();
+        match self {
+            Self::V4(c) => c.publish(topic, qos, retain, payload).await?,
+            Self::V5(c) => c.publish(topic, qos_v5(qos), retain, payload).await?,
+        }
+        Ok(())
+    }
+
+    /// As `publish`, with the value's unit as a user property on v5.
+    async fn publish_with_unit(
+        &self,
+        topic: &str,
+        qos: QoS,
+        retain: bool,
+        payload: impl Into<Vec<u8>>,
+        unit: Option<&str>,
+    ) -> Result<()> {
+        match (self, unit) {
+            (Self::V5(c), Some(unit)) => {
+                let properties = v5::mqttbytes::v5::PublishProperties {
+                    user_properties: vec![("unit".to_string(), unit.to_string())],
+                    ..Default::default()
+                };
+                c.publish_with_properties(topic, qos_v5(qos), retain, payload.into(), properties)
+                    .await?;
+                Ok(())
+            }
+            _ => self.publish(topic, qos, retain, payload).await,
+        }
+    }
+
+    async fn reply(&self, topic: &str, qos: QoS, payload: Vec<u8>, reply_to: &ReplyTo) -> Result<()> {
+        match self {
+            Self::V4(c) => c.publish(topic, qos, false, payload).await?,
+            Self::V5(c) => {
+                let properties = v5::mqttbytes::v5::PublishProperties {
+                    correlation_data: reply_to.correlation_data.clone().map(Into::into),
+                    ..Default::default()
+                };
+                c.publish_with_properties(topic, qos_v5(qos), false, payload, properties)
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self, topic: impl Into<String>, qos: QoS) -> Result<()> {
+        match self {
+            Self::V4(c) => c.subscribe(topic, qos).await?,
+            Self::V5(c) => c.subscribe(topic, qos_v5(qos)).await?,
+        }
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, topic: impl Into<String>) -> Result<()> {
+        match self {
+            Self::V4(c) => c.unsubscribe(topic).await?,
+            Self::V5(c) => c.unsubscribe(topic).await?,
+        }
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<()> {
+        match self {
+            Self::V4(c) => c.disconnect().await?,
+            Self::V5(c) => c.disconnect().await?,
+        }
+        Ok(())
+    }
+}
+
+enum Events {
+    V4(Box<EventLoop>),
+    V5(Box<v5::EventLoop>),
+}
+
+/// An incoming publish from either protocol.
+struct Received {
+    topic: String,
+    retain: bool,
+    payload: Vec<u8>,
+    reply_to: ReplyTo,
+}
+
+enum Polled {
+    Publish(Received),
+    /// The broker accepted our connection, first time or after an outage
+    Connected,
+    /// acks, keepalives and the like
+    Other,
+}
+
+impl Events {
+    async fn poll(&mut self) -> Result<Polled> {
+        match self {
+            Self::V4(e) => match e.poll().await? {
+                Event::Incoming(Incoming::Publish(p)) => Ok(Polled::Publish(Received {
+                    topic: p.topic,
+                    retain: p.retain,
+                    payload: p.payload.to_vec(),
+                    reply_to: ReplyTo::default(),
+                })),
+                Event::Incoming(Incoming::ConnAck(_)) => Ok(Polled::Connected),
+                _ => Ok(Polled::Other),
+            },
+            Self::V5(e) => match e.poll().await? {
+                v5::Event::Incoming(v5::Incoming::Publish(p)) => {
+                    let properties = p.properties.unwrap_or_default();
+                    Ok(Polled::Publish(Received {
+                        topic: String::from_utf8_lossy(&p.topic).into_owned(),
+                        retain: p.retain,
+                        payload: p.payload.to_vec(),
+                        reply_to: ReplyTo {
+                            response_topic: properties.response_topic,
+                            correlation_data: properties.correlation_data.map(|b| b.to_vec()),
+                        },
+                    }))
+                }
+                v5::Event::Incoming(v5::Incoming::ConnAck(_)) => Ok(Polled::Connected),
+                _ => Ok(Polled::Other),
+            },
+        }
+    }
+} // }}}
+
 pub type Sender = broadcast::Sender<ChannelData>;
 
 #[derive(Clone)]
@@ -384,15 +659,43 @@ pub struct Mqtt {
     shutdown: bool,
     channels: Channels,
     shared_stats: Arc<Mutex<PacketStats>>,
+    state: Arc<Mutex<StateCache>>,
+    outbox: Arc<Mutex<Outbox>>,
+    deadband: Arc<Mutex<deadband::Filter>>,
+    units: Arc<fields::Units>,
+    /// Set between a ConnAck and the next connection error
+    connected: Arc<AtomicBool>,
+    reconnected: Arc<tokio::sync::Notify>,
+    /// Discovery recorded by earlier runs, read back for `remove_stale`
+    published: Arc<Mutex<home_assistant::Published>>,
 }
 
 impl Mqtt {
     pub fn new(config: ConfigWrapper, channels: Channels, shared_stats: Arc<Mutex<PacketStats>>) -> Self {
+        let outbox = Outbox::new(config.mqtt().outbox());
+        let mqtt = config.mqtt();
+        // register units class deadband topics and label per-field ones
+        let registers = (mqtt.deadband().enabled() || mqtt.publish_fields()).then(|| {
+            let file = config.register_file().unwrap_or_else(|| "doc/eg4_registers.json".to_string());
+            RegisterParser::new(&file)
+                .map_err(|e| warn!("register units unavailable from {}: {}", file, e))
+                .ok()
+        });
+        let registers = registers.flatten();
+        let deadband = deadband::Filter::new(mqtt.deadband().clone(), registers.as_ref());
+        let units = fields::Units::new(registers.as_ref());
         Self {
             config,
             channels,
             shutdown: false,
             shared_stats,
+            state: Arc::new(Mutex::new(StateCache::default())),
+            outbox: Arc::new(Mutex::new(outbox)),
+            deadband: Arc::new(Mutex::new(deadband)),
+            units: Arc::new(units),
+            connected: Arc::new(AtomicBool::new(false)),
+            reconnected: Arc::new(tokio::sync::Notify::new()),
+            published: Arc::new(Mutex::new(home_assistant::Published::default())),
         }
     }
 
@@ -404,32 +707,20 @@ impl Mqtt {
             return Ok(());
         }
 
-        let mut options = MqttOptions::new("lxp-bridge", c.mqtt().host(), c.mqtt().port());
-
-        let will = LastWill {
-            topic: self.lwt_topic(),
-            message: bytes::Bytes::from("offline"),
-            qos: QoS::AtLeastOnce,
-            retain: true,
-        };
-        options.set_last_will(will);
-
-        options.set_keep_alive(std::time::Duration::from_secs(60));
-        if let (Some(u), Some(p)) = (c.mqtt().username(), c.mqtt().password()) {
-            options.set_credentials(u, p);
-        }
+        let (client, eventloop) = self.connect()?;
 
         info!(
-            "initializing mqtt at {}:{}",
+            "initializing mqtt at {}:{} as {}{}{}",
             c.mqtt().host(),
-            c.mqtt().port()
+            c.mqtt().connect_port(),
+            c.mqtt().client_id(),
+            if c.mqtt().tls().is_some() { " (tls)" } else { "" },
+            if c.mqtt().v5() { " (v5)" } else { "" }
         );
 
-        let (client, eventloop) = AsyncClient::new(options, 10);
-
         futures::try_join!(
             self.setup(client.clone()),
-            self.receiver(eventloop),
+            self.receiver(eventloop, client.clone()),
             self.sender(client)
         )?;
 
@@ -447,9 +738,71 @@ impl Mqtt {
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
     }
 
-    async fn setup(&self, client: AsyncClient) -> Result<()> {
+    fn connect(&self) -> Result<(Client, Events)> {
+        let mqtt = self.config.mqtt();
+        let keep_alive = std::time::Duration::from_secs(mqtt.keepalive());
+        let transport = mqtt.tls().map(Self::tls_transport).transpose()?;
+
+        if mqtt.v5() {
+            let mut options =
+                v5::MqttOptions::new(mqtt.client_id(), mqtt.host(), mqtt.connect_port());
+            options.set_last_will(v5::mqttbytes::v5::LastWill::new(
+                self.lwt_topic(),
+                "offline",
+                v5::mqttbytes::QoS::AtLeastOnce,
+                true,
+                None,
+            ));
+            options.set_keep_alive(keep_alive);
+            options.set_clean_start(mqtt.clean_session());
+            if let (Some(u), Some(p)) = (mqtt.username(), mqtt.password()) {
+                options.set_credentials(u, p);
+            }
+            if let Some(transport) = transport {
+                options.set_transport(transport);
+            }
+            let (client, eventloop) = v5::AsyncClient::new(options, 10);
+            return Ok((Client::V5(client), Events::V5(Box::new(eventloop))));
+        }
+
+        let mut options = MqttOptions::new(mqtt.client_id(), mqtt.host(), mqtt.connect_port());
+        options.set_last_will(LastWill {
+            topic: self.lwt_topic(),
+            message: bytes::Bytes::from("offline"),
+            qos: QoS::AtLeastOnce,
+            retain: true,
+        });
+        options.set_keep_alive(keep_alive);
+        options.set_clean_session(mqtt.clean_session());
+        if let (Some(u), Some(p)) = (mqtt.username(), mqtt.password()) {
+            options.set_credentials(u, p);
+        }
+        if let Some(transport) = transport {
+            options.set_transport(transport);
+        }
+        let (client, eventloop) = AsyncClient::new(options, 10);
+        Ok((Client::V4(client), Events::V4(Box::new(eventloop))))
+    }
+
+    fn tls_transport(tls: &config::MqttTls) -> Result<Transport> {
+        let Some(ca_file) = tls.ca_file() else {
+            return Ok(Transport::tls_with_default_config());
+        };
+
+        let read = |file: &str| {
+            std::fs::read(file).map_err(|e| anyhow!("mqtt.rs:failed to read {}: {}", file, e))
+        };
+        let client_auth = match (tls.cert_file(), tls.key_file()) {
+            (Some(cert), Some(key)) => Some((read(cert)?, read(key)?)),
+            _ => None,
+        };
+
+        Ok(Transport::tls(read(ca_file)?, client_auth, None))
+    }
+
+    async fn setup(&self, client: Client) -> Result<()> {
         client
-            .publish(self.lwt_topic(), QoS::AtLeastOnce, true, "online")
+            .publish(&self.lwt_topic(), QoS::AtLeastOnce, true, "online")
             .await?;
 
         client
@@ -470,22 +823,88 @@ impl Mqtt {
                     QoS::AtMostOnce,
                 )
                 .await?;
+        }
 
-            if self.config.homeassistant_enabled() && self.config.mqtt().homeassistant().enabled() {
-                let ha = home_assistant::Config::new(&inverter, &self.config.mqtt(), &self.config);
-                for msg in ha.all()?.into_iter() {
-                    let _ = client
-                        .publish(&msg.topic, QoS::AtLeastOnce, msg.retain, msg.payload)
-                        .await;
-                }
+        for group in self.config.groups() {
+            client
+                .subscribe(
+                    format!("{}/cmd/{}/#", self.config.mqtt().namespace(), group.name()),
+                    QoS::AtMostOnce,
+                )
+                .await?;
+        }
+
+        let policy = self.config.mqtt().publish().other.clone();
+        client
+            .publish(
+                &format!("{}/schema/command", self.config.mqtt().namespace()),
+                qos(policy.qos()),
+                policy.retain(true),
+                json_command::schema().to_string(),
+            )
+            .await?;
+
+        if self.homeassistant_enabled() {
+            let ha = self.config.mqtt().homeassistant().clone();
+            client.subscribe(ha.status_topic(), QoS::AtMostOnce).await?;
+            let record = home_assistant::Published::topic(self.config.mqtt().namespace());
+            let configs = format!("{}/+/+/+/config", ha.prefix());
+            if ha.remove_stale() {
+                // the broker replies with our previous record and every retained discovery
+                // config; ones we published for datalogs we no longer run are cleared in
+                // handle_homeassistant until the subscriptions are dropped again
+                client.subscribe(record.clone(), QoS::AtMostOnce).await?;
+                client.subscribe(configs.clone(), QoS::AtMostOnce).await?;
+                let client = client.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(REMOVE_STALE_WINDOW).await;
+                    for topic in [record, configs] {
+                        if let Err(e) = client.unsubscribe(topic.clone()).await {
+                            warn!("Failed to unsubscribe from {}: {}", topic, e);
+                        }
+                    }
+                });
             }
+
+            let policy = self.config.mqtt().publish().discovery.clone();
+            for msg in self.discovery()?.into_iter() {
+                let _ = client
+                    .publish(&msg.topic, qos(policy.qos()), policy.retain(msg.retain), msg.payload)
+                    .await;
+            }
+            // always retained, for the next run to read back
+            let record = home_assistant::Published::message(
+                self.config.mqtt().namespace(),
+                &self.discovery_nodes(),
+            )?;
+            client
+                .publish(&record.topic, QoS::AtLeastOnce, record.retain, record.payload)
+                .await?;
         }
 
         Ok(())
     }
 
+    fn homeassistant_enabled(&self) -> bool {
+        self.config.homeassistant_enabled() && self.config.mqtt().homeassistant().enabled()
+    }
+
+    // discovery configs for all enabled inverters, with full topics
+    fn discovery(&self) -> Result<Vec<Message>> {
+        let mut r = Vec::new();
+        for inverter in self.config.enabled_inverters() {
+            let ha = home_assistant::Config::new(&inverter, &self.config.mqtt(), &self.config);
+            r.extend(ha.all()?);
+        }
+        for group in self.config.groups() {
+            let ha = home_assistant::Config::for_group(&group, &self.config.mqtt(), &self.config);
+            r.extend(ha.all()?);
+        }
+        Ok(r)
+    }
+
     // mqtt -> coordinator
-    async fn receiver(&self, mut eventloop: EventLoop) -> Result<()> {
+    async fn receiver(&self, mut eventloop: Events, client: Client) -> Result<()> {
         loop {
             if self.shutdown {
                 info!("MQTT receiver shutting down");
@@ -496,17 +915,27 @@ impl Mqtt {
                 tokio::time::timeout(std::time::Duration::from_secs(1), eventloop.poll()).await
             {
                 match event {
-                    Ok(Event::Incoming(Incoming::Publish(publish))) => {
-                        self.handle_message(publish)?;
+                    Ok(Polled::Publish(publish)) => {
+                        if self.is_homeassistant_topic(&publish.topic) {
+                            self.handle_homeassistant(&client, publish);
+                        } else {
+                            self.handle_message(publish)?;
+                        }
+                    }
+                    Ok(Polled::Connected) => {
+                        info!("connected to MQTT broker");
+                        self.connected.store(true, Ordering::SeqCst);
+                        self.reconnected.notify_one();
                     }
                     Err(e) => {
+                        self.connected.store(false, Ordering::SeqCst);
                         if !self.shutdown {
                             error!("{}", e);
                             info!("reconnecting in 5s");
                             tokio::time::sleep(std::time::Duration::from_secs(5)).await;
                         }
                     }
-                    _ => {} // keepalives etc
+                    Ok(Polled::Other) => {} // keepalives etc
                 }
             }
         }
@@ -515,7 +944,105 @@ impl Mqtt {
         Ok(())
     }
 
-    fn handle_message(&self, publish: Publish) -> Result<()> {
+    fn is_homeassistant_topic(&self, topic: &str) -> bool {
+        if !self.homeassistant_enabled() {
+            return false;
+        }
+        let mqtt = self.config.mqtt();
+        let ha = mqtt.homeassistant();
+        topic == ha.status_topic()
+            || topic == home_assistant::Published::topic(mqtt.namespace())
+            || home_assistant::discovery_datalog(ha.prefix(), topic).is_some()
+    }
+
+    fn handle_homeassistant(&self, client: &Client, publish: Received) {
+        let ha = self.config.mqtt().homeassistant().clone();
+
+        if publish.topic == ha.status_topic() {
+            if publish.payload == b"online" {
+                info!("Home Assistant is online, republishing discovery and state");
+                match self.republish_messages() {
+                    Ok(messages) => Self::publish_in_background(client.clone(), messages),
+                    Err(e) => error!("Failed to build Home Assistant discovery: {}", e),
+                }
+            }
+            return;
+        }
+
+        let Ok(mut published) = self.published.lock() else {
+            return;
+        };
+        if publish.topic == home_assistant::Published::topic(self.config.mqtt().namespace()) {
+            published.read(&publish.payload);
+            return;
+        }
+        // an empty payload is a deletion, possibly our own
+        if publish.payload.is_empty() || !published.is_stale(ha.prefix(), &publish.topic, &self.discovery_nodes()) {
+            return;
+        }
+        info!("removing stale Home Assistant discovery: {}", publish.topic);
+        let deletion = Message {
+            topic: publish.topic,
+            retain: true,
+            payload: String::new(),
+        };
+        Self::publish_in_background(client.clone(), vec![(deletion, QoS::AtLeastOnce)]);
+    }
+
+    // datalogs of enabled inverters, and inverter groups with discovery under the group name
+    fn discovery_nodes(&self) -> Vec<String> {
+        self.config
+            .enabled_inverters()
+            .iter()
+            .filter_map(|i| i.datalog().map(|s| s.to_string()))
+            .chain(self.config.groups().iter().map(|g| g.name().to_string()))
+            .collect()
+    }
+
+    // discovery configs followed by the latest retained state, all with full topics
+    fn republish_messages(&self) -> Result<Vec<(Message, QoS)>> {
+        let mqtt = self.config.mqtt();
+        let policy = mqtt.publish();
+
+        let mut r: Vec<(Message, QoS)> = self
+            .discovery()?
+            .into_iter()
+            .map(|m| {
+                let retain = policy.discovery.retain(m.retain);
+                (Message { retain, ..m }, qos(policy.discovery.qos()))
+            })
+            .collect();
+
+        let state = self
+            .state
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock mqtt state cache"))?
+            .messages();
+        r.extend(state.into_iter().map(|m| {
+            let qos = qos(m.publish_policy(policy).qos());
+            let topic = format!("{}/{}", mqtt.namespace(), m.topic);
+            (Message { topic, ..m }, qos)
+        }));
+        Ok(r)
+    }
+
+    // Publishing from the receiver task would stall the event loop once the client's request
+    // queue fills, so hand the messages to a task of their own.
+    fn publish_in_background(client: Client, messages: Vec<(Message, QoS)>) {
+        tokio::spawn(async move {
+            for (msg, qos) in messages {
+                if let Err(e) = client
+                    .publish(&msg.topic, qos, msg.retain, msg.payload)
+                    .await
+                {
+                    warn!("Failed to publish {}: {}", msg.topic, e);
+                    break;
+                }
+            }
+        });
+    }
+
+    fn handle_message(&self, publish: Received) -> Result<()> {
         // remove the namespace, including the first /
         // doing it this way means we don't break if namespace happens to contain a /
         let topic = publish.topic[self.config.mqtt().namespace().len() + 1..].to_owned();
@@ -523,15 +1050,15 @@ impl Mqtt {
         let message = Message {
             topic,
             retain: publish.retain,
-            payload: String::from_utf8(publish.payload.to_vec())?,
+            payload: String::from_utf8(publish.payload)?,
         };
         debug!("RX: {:?}", message);
-        if self
-            .channels
-            .from_mqtt
-            .send(ChannelData::Message(message))
-            .is_err()
-        {
+        let data = if publish.reply_to.response_topic.is_some() {
+            ChannelData::Request(message, publish.reply_to)
+        } else {
+            ChannelData::Message(message)
+        };
+        if self.channels.from_mqtt.send(data).is_err() {
             bail!("send(from_mqtt) failed - channel closed?");
         }
 
@@ -539,46 +1066,41 @@ impl Mqtt {
     }
 
     // coordinator -> mqtt
-    async fn sender(&self, client: AsyncClient) -> Result<()> {
+    async fn sender(&self, client: Client) -> Result<()> {
         use ChannelData::*;
 
         let mut receiver = self.channels.to_mqtt.subscribe();
 
         loop {
-            match receiver.recv().await? {
+            let data = tokio::select! {
+                data = receiver.recv() => data?,
+                _ = self.reconnected.notified() => {
+                    self.drain_outbox(&client).await;
+                    continue;
+                }
+            };
+
+            match data {
                 Shutdown => {
                     info!("MQTT sender received shutdown signal");
                     // Flush any remaining messages before exiting
                     let _ = client.disconnect().await;
                     break;
                 }
-                Message(message) => {
-                    let topic = format!("{}/{}", self.config.mqtt().namespace(), message.topic);
-                    info!("publishing: {} = {}", topic, message.payload);
-                    let payload = message.payload.as_bytes().to_vec();
-                    let mut retry_count = 0;
-                    loop {
-                        match client.publish(&topic, QoS::AtLeastOnce, message.retain, payload.as_slice()).await {
-                            Ok(_) => {
-                                info!("Successfully published message to topic: {}", topic);
-                                // Increment stats after successful publish
-                                if let Ok(mut stats) = self.shared_stats.lock() {
-                                    stats.mqtt_messages_sent += 1;
-                                    debug!("Incremented MQTT messages sent counter to {}", stats.mqtt_messages_sent);
-                                }
-                                break;
-                            }
-                            Err(err) => {
-                                error!("MQTT publish failed: {:?} - retrying in 10s (attempt {}/3)", err, retry_count + 1);
-                                if let Ok(mut stats) = self.shared_stats.lock() {
-                                    stats.mqtt_errors += 1;
-                                }
-                                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
-                                retry_count += 1;
-                            }
+                Reply(message, reply_to) => {
+                    // response topics are chosen by the requester, so no namespace
+                    info!("replying: {} = {}", message.topic, message.payload);
+                    let qos = qos(self.config.mqtt().publish().other.qos());
+                    let payload = message.payload.into_bytes();
+                    if let Err(err) = client.reply(&message.topic, qos, payload, &reply_to).await {
+                        error!("MQTT reply to {} failed: {:?}", message.topic, err);
+                        if let Ok(mut stats) = self.shared_stats.lock() {
+                            stats.mqtt_errors += 1;
                         }
                     }
                 }
+                Request(..) => {} // only arrives on from_mqtt
+                Message(message) => self.send(&client, message).await,
             }
         }
 
@@ -586,7 +1108,130 @@ impl Mqtt {
         Ok(())
     }
 
+    /// Publish a message, or queue it in the outbox while the broker is unreachable or
+    /// older messages are still waiting to go out.
+    async fn send(&self, client: &Client, message: Message) {
+        let now = std::time::Instant::now();
+        if let Ok(mut deadband) = self.deadband.lock() {
+            if !deadband.should_publish(&message, now) {
+                trace!("unchanged, not publishing {}", message.topic);
+                return;
+            }
+        }
+
+        let outbox_enabled = self.config.mqtt().outbox().enabled();
+        let connected = self.connected.load(Ordering::SeqCst);
+
+        if outbox_enabled && (!connected || !self.outbox_is_empty()) {
+            self.queue(message).await;
+            if connected {
+                self.drain_outbox(client).await;
+            }
+            return;
+        }
+
+        if let Err(err) = self.publish(client, &message).await {
+            error!("MQTT publish to {} failed: {:?}", message.topic, err);
+            if let Ok(mut stats) = self.shared_stats.lock() {
+                stats.mqtt_errors += 1;
+            }
+            if outbox_enabled {
+                self.queue(message).await;
+            }
+        }
+    }
+
+    async fn publish(&self, client: &Client, message: &Message) -> Result<()> {
+        let mqtt = self.config.mqtt();
+        let policy = message.publish_policy(mqtt.publish());
+        let retain = policy.retain(message.retain);
+        let topic = format!("{}/{}", mqtt.namespace(), message.topic);
+        info!("publishing: {} = {}", topic, message.payload);
+        let unit = self.units.for_topic(&message.topic);
+        client
+            .publish_with_unit(&topic, qos(policy.qos()), retain, message.payload.as_bytes(), unit)
+            .await?;
+
+        if retain {
+            if let Ok(mut state) = self.state.lock() {
+                state.record(&Message { retain, ..message.clone() });
+            }
+        }
+        if let Ok(mut stats) = self.shared_stats.lock() {
+            stats.mqtt_messages_sent += 1;
+        }
+        Ok(())
+    }
+
+    async fn queue(&self, message: Message) {
+        debug!("queueing MQTT message for {} until the broker is back", message.topic);
+        self.with_outbox(move |outbox| outbox.push(message)).await;
+    }
+
+    /// Change the outbox on a blocking thread, since it may write its file, and update the
+    /// outbox statistics.
+    async fn with_outbox<T: Send + 'static>(&self, f: impl FnOnce(&mut Outbox) -> T + Send + 'static) -> Option<T> {
+        let outbox = self.outbox.clone();
+        let (r, size, dropped) = tokio::task::spawn_blocking(move || {
+            let mut outbox = outbox.lock().ok()?;
+            let r = f(&mut outbox);
+            Some((r, outbox.len(), outbox.dropped()))
+        })
+        .await
+        .ok()
+        .flatten()?;
+
+        if let Ok(mut stats) = self.shared_stats.lock() {
+            stats.mqtt_outbox_size = size as u64;
+            stats.mqtt_outbox_dropped = dropped;
+        }
+        Some(r)
+    }
+
+    fn outbox_is_empty(&self) -> bool {
+        self.outbox.lock().map(|o| o.is_empty()).unwrap_or(true)
+    }
+
+    /// Replay queued messages in order until the outbox is empty or the connection drops.
+    async fn drain_outbox(&self, client: &Client) {
+        let mut sent = 0;
+        while self.connected.load(Ordering::SeqCst) {
+            let Some(message) = self.outbox.lock().ok().and_then(|o| o.front().cloned()) else {
+                break;
+            };
+            if let Err(err) = self.publish(client, &message).await {
+                error!("MQTT replay of {} failed: {:?}", message.topic, err);
+                break;
+            }
+            self.with_outbox(|outbox| {
+                outbox.pop();
+            })
+            .await;
+            sent += 1;
+        }
+        if sent > 0 {
+            info!("replayed {} queued MQTT messages", sent);
+        }
+    }
+
     fn lwt_topic(&self) -> String {
         format!("{}/LWT", self.config.mqtt().namespace())
     }
 }
+
+// config validation limits qos to 0-2
+fn qos(level: u8) -> QoS {
+    match level {
+        0 => QoS::AtMostOnce,
+        2 => QoS::ExactlyOnce,
+        _ => QoS::AtLeastOnce,
+    }
+}
+
+fn qos_v5(qos: QoS) -> v5::mqttbytes::QoS {
+    match qos {
+        QoS::AtMostOnce => v5::mqttbytes::QoS::AtMostOnce,
+        QoS::AtLeastOnce => v5::mqttbytes::QoS::AtLeastOnce,
+        QoS::ExactlyOnce => v5::mqttbytes::QoS::ExactlyOnce,
+    }
+}
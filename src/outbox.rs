@@ -0,0 +1,207 @@
+//! Store-and-forward queue for MQTT messages published while the broker is unreachable.
+//!
+//! Messages are kept in arrival order and replayed once the connection is back. The queue is
+//! bounded: past `max_messages` the oldest message is dropped and counted. With the `latest`
+//! policy, or for retained messages under either policy, a newer message replaces any queued
+//! one for the same topic since the broker would only keep the last anyway.
+//!
+//! With a `file` set, every queued message is appended to it as a JSON line, so a restart
+//! mid-outage resumes the backlog. As messages are replayed, the number of lines before the
+//! next one to send is saved to `{file}.offset`, and a restart skips them; only a message
+//! published just before a crash can go out twice. The file is rewritten from the queue once
+//! it holds mostly stale lines.
+//!
+//! The file is written synchronously; callers on an async runtime should use the outbox from
+//! a blocking task.
+
+use crate::prelude::*;
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+
+/// Stale lines tolerated in the file before it is rewritten.
+const COMPACT_SLACK: usize = 64;
+
+pub struct Outbox {
+    /// queued messages, each with its line in the file
+    queue: VecDeque<(usize, mqtt::Message)>,
+    max_messages: usize,
+    latest_only: bool,
+    file: Option<String>,
+    /// lines currently in the file, including ones already sent or replaced
+    file_lines: usize,
+    /// lines at the start of the file that are sent or replaced, as saved in the offset file
+    offset: usize,
+    dropped: u64,
+}
+
+impl Outbox {
+    /// An empty outbox, or the backlog left in `config.file` by a previous run.
+    pub fn new(config: &config::MqttOutbox) -> Self {
+        let mut outbox = Self {
+            queue: VecDeque::new(),
+            max_messages: config.max_messages(),
+            latest_only: config.latest_only(),
+            file: config.file().map(String::from),
+            file_lines: 0,
+            offset: 0,
+            dropped: 0,
+        };
+
+        if let Some(file) = outbox.file.clone() {
+            match Self::load(&file) {
+                Ok(messages) => {
+                    outbox.file_lines = messages.len();
+                    outbox.offset = Self::load_offset(&file).min(messages.len());
+                    for (line, message) in messages.into_iter().enumerate().skip(outbox.offset) {
+                        outbox.enqueue(line, message);
+                    }
+                    if !outbox.queue.is_empty() {
+                        info!("resuming {} queued MQTT messages from {}", outbox.queue.len(), file);
+                    }
+                }
+                Err(e) => warn!("not resuming MQTT outbox from {}: {}", file, e),
+            }
+        }
+
+        outbox
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Messages dropped to keep within `max_messages`.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Queue a message behind everything already waiting.
+    pub fn push(&mut self, message: mqtt::Message) {
+        let line = self.file_lines;
+        if let Err(e) = self.append(&message) {
+            warn!("failed to persist queued MQTT message: {}", e);
+        }
+        self.enqueue(line, message);
+        self.compact();
+    }
+
+    /// The next message to replay; remove it with `pop` once published.
+    pub fn front(&self) -> Option<&mqtt::Message> {
+        self.queue.front().map(|(_, message)| message)
+    }
+
+    pub fn pop(&mut self) -> Option<mqtt::Message> {
+        let (_, message) = self.queue.pop_front()?;
+        self.save_offset();
+        self.compact();
+        Some(message)
+    }
+
+    fn enqueue(&mut self, line: usize, message: mqtt::Message) {
+        if self.latest_only || message.retain {
+            self.queue.retain(|(_, m)| m.topic != message.topic);
+        }
+        if self.queue.len() >= self.max_messages {
+            self.queue.pop_front();
+            self.dropped += 1;
+        }
+        self.queue.push_back((line, message));
+    }
+
+    fn offset_file(file: &str) -> String {
+        format!("{}.offset", file)
+    }
+
+    /// Lines already replayed from `file`; none when there is no offset file or it is unreadable.
+    fn load_offset(file: &str) -> usize {
+        std::fs::read_to_string(Self::offset_file(file))
+            .ok()
+            .and_then(|offset| offset.trim().parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Record that every line before the next message to replay is done with.
+    fn save_offset(&mut self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let offset = self.queue.front().map_or(self.file_lines, |(line, _)| *line);
+        if offset == self.offset {
+            return;
+        }
+        match std::fs::write(Self::offset_file(file), offset.to_string()) {
+            Ok(()) => self.offset = offset,
+            Err(e) => warn!("failed to save MQTT outbox offset for {}: {}", file, e),
+        }
+    }
+
+    fn load(file: &str) -> Result<Vec<mqtt::Message>> {
+        let f = match std::fs::File::open(file) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut r = Vec::new();
+        for line in BufReader::new(f).lines() {
+            let line = line?;
+            // a torn final line from a crash mid-write is skipped
+            match serde_json::from_str(&line) {
+                Ok(message) => r.push(message),
+                Err(e) => warn!("skipping unreadable MQTT outbox line: {}", e),
+            }
+        }
+        Ok(r)
+    }
+
+    fn append(&mut self, message: &mqtt::Message) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let mut f = std::fs::OpenOptions::new().create(true).append(true).open(file)?;
+        writeln!(f, "{}", serde_json::to_string(message)?)?;
+        self.file_lines += 1;
+        Ok(())
+    }
+
+    /// Rewrite the file from the queue once stale lines outnumber live ones.
+    fn compact(&mut self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mostly_live = self.file_lines < 2 * self.queue.len() + COMPACT_SLACK;
+        if self.file_lines == 0 || (mostly_live && !self.queue.is_empty()) {
+            return;
+        }
+
+        let result = (|| -> Result<()> {
+            let tmp = format!("{}.tmp", file);
+            let mut f = std::fs::File::create(&tmp)?;
+            for (_, message) in &self.queue {
+                writeln!(f, "{}", serde_json::to_string(message)?)?;
+            }
+            f.sync_all()?;
+            // reset the offset first: a crash before the rename replays the old file in full,
+            // which only repeats messages, rather than skipping unsent ones in the new file
+            std::fs::write(Self::offset_file(file), "0")?;
+            std::fs::rename(&tmp, file)?;
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => {
+                for (line, (n, _)) in self.queue.iter_mut().enumerate() {
+                    *n = line;
+                }
+                self.file_lines = self.queue.len();
+                self.offset = 0;
+            }
+            Err(e) => warn!("failed to rewrite MQTT outbox {}: {}", file, e),
+        }
+    }
+}
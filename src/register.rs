@@ -11,7 +11,7 @@ pub struct Register {
     pub data_type: String,
     #[serde(default)]
     pub access: String,
-    #[serde(default = "default_scaling")]
+    #[serde(default = "default_scaling", alias = "unit_scale")]
     pub scaling: f64,
     #[serde(default)]
     pub unit: String,
@@ -19,6 +19,47 @@ pub struct Register {
     pub shortname: String,
     #[serde(default)]
     pub read_only: bool,
+    /// How the raw value should be presented: "enum", "flags", "fields" or empty for a number
+    #[serde(default)]
+    pub display_as: String,
+    #[serde(default)]
+    pub enum_values: Vec<EnumValue>,
+    #[serde(default)]
+    pub flags: Vec<Flag>,
+    #[serde(default)]
+    pub fields: Vec<Field>,
+    /// Optional limits, in scaled units, for writable registers
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Smallest change, in scaled units, a writable register accepts
+    pub step: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EnumValue {
+    pub value: u16,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Flag {
+    pub bit: u8,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+/// A value packed into one byte of the register
+#[derive(Debug, Clone, Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub unit: String,
+    pub byte: u8,
 }
 
 fn default_scaling() -> f64 {
@@ -39,6 +80,7 @@ pub struct RegisterMap {
 #[derive(Clone)]
 pub struct RegisterParser {
     registers: HashMap<u16, Register>,
+    register_types: Vec<RegisterType>,
 }
 
 impl RegisterParser {
@@ -159,13 +201,32 @@ impl RegisterParser {
             bail!("{}", error_msg);
         }
 
-        Ok(Self { registers })
+        Ok(Self {
+            registers,
+            register_types: register_map.registers,
+        })
     }
 
     pub fn get_register(&self, register_number: u16) -> Option<&Register> {
         self.registers.get(&register_number)
     }
 
+    /// Every register of one type ("hold" or "input") in file order.
+    pub fn registers_of_type(&self, register_type: &str) -> Vec<&Register> {
+        self.register_types
+            .iter()
+            .filter(|t| t.register_type == register_type)
+            .flat_map(|t| t.register_map.iter())
+            .collect()
+    }
+
+    /// A register of one type by its topic key or full name.
+    pub fn find(&self, register_type: &str, name: &str) -> Option<&Register> {
+        self.registers_of_type(register_type)
+            .into_iter()
+            .find(|r| r.key() == name || r.name == name)
+    }
+
     pub fn decode_registers(&self, raw_data: &HashMap<String, String>, show_unknown: bool, register_type: &str) -> HashMap<String, f64> {
         let mut decoded = HashMap::new();
         
@@ -192,6 +253,26 @@ impl RegisterParser {
 }
 
 impl Register {
+    /// Name used in topics and unique ids.
+    pub fn key(&self) -> &str {
+        if self.shortname.is_empty() {
+            &self.name
+        } else {
+            &self.shortname
+        }
+    }
+
+    /// Value of a raw 16-bit register in its unit; None for 32-bit registers, which span two.
+    pub fn scaled(&self, raw: u16) -> Option<f64> {
+        let value = match self.data_type.as_str() {
+            "int16" => raw as i16 as f64,
+            "uint32" | "int32" => return None,
+            _ => raw as f64,
+        };
+        // scalings go down to 0.001; round away the float noise they add
+        Some((value * self.scaling * 1000.0).round() / 1000.0)
+    }
+
     pub fn decode_value(&self, hex_value: &str) -> f64 {
         let value = u16::from_str_radix(hex_value, 16)
             .unwrap_or(0) as f64;
@@ -1,24 +1,23 @@
 use crate::prelude::*;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
-// this just needs to be bigger than the max register we'll see
-const REGISTER_COUNT: usize = 512;
-
+// values are kept per inverter, keyed by datalog and register
 #[derive(Clone, Debug)]
 pub enum ChannelData {
-    ReadRegister(u16, Arc<Mutex<Option<oneshot::Sender<u16>>>>),
-    RegisterData(u16, u16),
+    ReadRegister(Serial, u16, Arc<Mutex<Option<oneshot::Sender<Option<u16>>>>>),
+    RegisterData(Serial, u16, u16),
     Shutdown,
 }
 
 pub struct RegisterCache {
     channels: Channels,
-    register_data: Arc<Mutex<[u16; REGISTER_COUNT]>>,
+    register_data: Arc<Mutex<HashMap<(Serial, u16), u16>>>,
 }
 
 impl RegisterCache {
     pub fn new(channels: Channels) -> Self {
-        let register_data = Arc::new(Mutex::new([0; REGISTER_COUNT]));
+        let register_data = Arc::new(Mutex::new(HashMap::new()));
 
         Self {
             channels,
@@ -34,16 +33,28 @@ impl RegisterCache {
 
     // external helper method to simplify access to the cache, use like so:
     //
-    //   RegisterCache::get(&self.channels, 1);
+    //   RegisterCache::get(&self.channels, datalog, 1);
     //
-    pub async fn get(channels: &Channels, register: u16) -> u16 {
+    pub async fn get(channels: &Channels, datalog: Serial, register: u16) -> u16 {
         let (tx, rx) = oneshot::channel();
         let tx = Arc::new(Mutex::new(Some(tx)));
-        let channel_data = ChannelData::ReadRegister(register, tx);
-        debug!("Reading register {} from cache", register);
+        let channel_data = ChannelData::ReadRegister(datalog, register, tx);
+        debug!("Reading register {} of {} from cache", register, datalog);
         let _ = channels.read_register_cache.send(channel_data);
         rx.await
             .expect("unexpected error reading from register cache")
+            .unwrap_or_default()
+    }
+
+    // like get, but None for registers not seen yet, or when the cache is not running
+    pub async fn cached(channels: &Channels, datalog: Serial, register: u16) -> Option<u16> {
+        let (tx, rx) = oneshot::channel();
+        let tx = Arc::new(Mutex::new(Some(tx)));
+        channels
+            .read_register_cache
+            .send(ChannelData::ReadRegister(datalog, register, tx))
+            .ok()?;
+        rx.await.ok().flatten()
     }
 
     async fn cache_getter(&self) -> Result<()> {
@@ -53,9 +64,9 @@ impl RegisterCache {
 
         while let Ok(data) = receiver.recv().await {
             match data {
-                ChannelData::ReadRegister(register, tx) => {
-                    let value = self.register_data.lock().unwrap()[register as usize];
-                    debug!("Cache hit for register {}: value = {}", register, value);
+                ChannelData::ReadRegister(datalog, register, tx) => {
+                    let value = self.register_data.lock().unwrap().get(&(datalog, register)).copied();
+                    debug!("Cache lookup for register {} of {}: value = {:?}", register, datalog, value);
                     if let Ok(mut tx) = tx.lock() {
                         if let Some(tx) = tx.take() {
                             let _ = tx.send(value);
@@ -77,9 +88,9 @@ impl RegisterCache {
 
         while let Ok(data) = receiver.recv().await {
             match data {
-                ChannelData::RegisterData(register, value) => {
-                    debug!("Caching register {} with value {}", register, value);
-                    self.register_data.lock().unwrap()[register as usize] = value;
+                ChannelData::RegisterData(datalog, register, value) => {
+                    debug!("Caching register {} of {} with value {}", register, datalog, value);
+                    self.register_data.lock().unwrap().insert((datalog, register), value);
                 }
                 ChannelData::Shutdown => break,
                 _ => (),
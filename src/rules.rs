@@ -0,0 +1,340 @@
+//! Local automations on live inverter data.
+//!
+//! Every rule is checked against each complete input set. A rule fires when all of its
+//! conditions hold inside its day and time window, running its actions, and stays active
+//! until they stop holding, when it releases and runs its release actions. While active,
+//! each threshold is relaxed by the rule's `hysteresis` so a value hovering at the threshold
+//! does not flap, and a rule that released will not fire again until `cooldown` seconds
+//! after it last fired.
+//!
+//! Each evaluation is published retained on `{datalog}/rules/{name}/state`, and each firing
+//! or release on `{datalog}/rules/{name}/actions`. In dry-run mode rules are evaluated and
+//! published as usual but their commands are only logged.
+
+use crate::prelude::*;
+use crate::command::{Origin, Source};
+use crate::eg4::packet::ReadInputAll;
+use crate::json_command::JsonCommand;
+use crate::register::RegisterParser;
+
+use chrono::{Datelike, Timelike};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+/// Minutes past midnight for an `HH:MM` time.
+pub fn minute_of_day(time: &str) -> Option<u32> {
+    let (h, m) = time.split_once(':')?;
+    let (h, m): (u32, u32) = (h.parse().ok()?, m.parse().ok()?);
+    (h < 24 && m < 60).then_some(h * 60 + m)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// inactive, conditions not met
+    Idle,
+    /// conditions newly met; actions run
+    Fired,
+    /// active, conditions still met
+    Held,
+    /// conditions met, but the rule fired too recently
+    CoolingDown,
+    /// conditions stopped holding; release actions run
+    Released,
+}
+
+/// One rule checked against one input set.
+#[derive(Clone, Debug, Serialize)]
+pub struct Evaluation {
+    pub rule: String,
+    pub datalog: Serial,
+    pub time: UnixTime,
+    pub outcome: Outcome,
+    pub active: bool,
+    pub in_window: bool,
+    /// the field of each condition; null when the input set lacks it
+    pub values: BTreeMap<String, Option<f64>>,
+    pub dry_run: bool,
+    /// commands to run for this outcome
+    #[serde(skip)]
+    pub actions: Vec<JsonCommand>,
+}
+
+#[derive(Default)]
+struct State {
+    active: bool,
+    last_fired: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub struct Engine {
+    rules: config::Rules,
+    /// (rule name, datalog) -> state
+    state: HashMap<(String, Serial), State>,
+}
+
+impl Engine {
+    pub fn new(rules: config::Rules) -> Self {
+        Self {
+            rules,
+            state: HashMap::new(),
+        }
+    }
+
+    /// Check every enabled rule that applies to the input set's inverter.
+    pub fn evaluate(&mut self, input: &ReadInputAll) -> Vec<Evaluation> {
+        let fields = serde_json::to_value(input).unwrap_or_default();
+        let local = input.time.0.with_timezone(&chrono::Local);
+        let minute = local.hour() * 60 + local.minute();
+
+        let mut r = Vec::new();
+        for rule in self.rules.rules.iter().filter(|r| r.enabled) {
+            if rule.datalog.is_some_and(|d| d != input.datalog) {
+                continue;
+            }
+
+            let state = self.state.entry((rule.name.clone(), input.datalog)).or_default();
+            let in_window = in_window(
+                &rule.days,
+                rule.start_time.as_deref(),
+                rule.end_time.as_deref(),
+                local.weekday(),
+                minute,
+            );
+            let slack = if state.active { rule.hysteresis } else { 0.0 };
+            let met = in_window && conditions_hold(rule, &fields, slack);
+
+            let outcome = match (state.active, met) {
+                (true, true) => Outcome::Held,
+                (true, false) => {
+                    state.active = false;
+                    Outcome::Released
+                }
+                (false, false) => Outcome::Idle,
+                (false, true) => {
+                    let cooling = state.last_fired.is_some_and(|t| {
+                        (input.time.0 - t).num_seconds() < rule.cooldown as i64
+                    });
+                    if cooling {
+                        Outcome::CoolingDown
+                    } else {
+                        state.active = true;
+                        state.last_fired = Some(input.time.0);
+                        Outcome::Fired
+                    }
+                }
+            };
+
+            let actions = match outcome {
+                Outcome::Fired => rule.actions.clone(),
+                Outcome::Released => rule.release_actions.clone(),
+                _ => Vec::new(),
+            };
+
+            r.push(Evaluation {
+                rule: rule.name.clone(),
+                datalog: input.datalog,
+                time: input.time.clone(),
+                outcome,
+                active: state.active,
+                in_window,
+                values: rule
+                    .conditions
+                    .iter()
+                    .map(|c| (c.field.clone(), field(&fields, &c.field)))
+                    .collect(),
+                dry_run: self.rules.dry_run || rule.dry_run,
+                actions,
+            });
+        }
+        r
+    }
+}
+
+fn field(fields: &serde_json::Value, name: &str) -> Option<f64> {
+    fields.get(name)?.as_f64()
+}
+
+/// Whether a local weekday and minute of the day fall on one of `days` (any day if empty)
+/// and within `start`..`end` (`HH:MM`, all day if unset).
+pub fn in_window(
+    days: &[String],
+    start: Option<&str>,
+    end: Option<&str>,
+    weekday: chrono::Weekday,
+    minute: u32,
+) -> bool {
+    let day_ok = days.is_empty()
+        || days
+            .iter()
+            .any(|d| d.parse::<chrono::Weekday>().is_ok_and(|d| d == weekday));
+
+    let start = start.and_then(minute_of_day);
+    let end = end.and_then(minute_of_day);
+    let time_ok = match (start, end) {
+        (Some(start), Some(end)) if start <= end => (start..end).contains(&minute),
+        // window wraps past midnight
+        (Some(start), Some(end)) => minute >= start || minute < end,
+        _ => true,
+    };
+
+    day_ok && time_ok
+}
+
+/// Whether every condition holds, with thresholds relaxed by `slack`.
+fn conditions_hold(rule: &config::Rule, fields: &serde_json::Value, slack: f64) -> bool {
+    rule.conditions.iter().all(|c| match field(fields, &c.field) {
+        Some(v) => {
+            c.above.is_none_or(|above| v > above - slack)
+                && c.below.is_none_or(|below| v < below + slack)
+        }
+        None => false,
+    })
+}
+
+#[derive(Serialize)]
+struct Actions<'a> {
+    time: &'a UnixTime,
+    outcome: Outcome,
+    dry_run: bool,
+    commands: Vec<String>,
+    errors: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Rules {
+    config: ConfigWrapper,
+    channels: Channels,
+    engine: Arc<Mutex<Engine>>,
+    registers: Option<Arc<RegisterParser>>,
+    shutdown: CancellationToken,
+}
+
+impl Rules {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        let engine = Engine::new(config.rules());
+        // for actions that name their register
+        let file = config.register_file().unwrap_or_else(|| "doc/eg4_registers.json".to_string());
+        let registers = RegisterParser::new(&file)
+            .map_err(|e| warn!("rules cannot use register names, cannot load {}: {}", file, e))
+            .ok()
+            .map(Arc::new);
+
+        Self {
+            config,
+            channels,
+            engine: Arc::new(Mutex::new(engine)),
+            registers,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver = self.channels.from_coordinator.subscribe();
+        info!("rules started");
+
+        loop {
+            let msg = tokio::select! {
+                msg = receiver.recv() => msg,
+                _ = self.shutdown.cancelled() => break,
+            };
+            match msg {
+                Ok(coordinator::ChannelData::ReadInputAll(input)) => {
+                    let evaluations = self
+                        .engine
+                        .lock()
+                        .map_err(|_| anyhow!("Failed to lock rules engine"))?
+                        .evaluate(&input);
+                    for evaluation in evaluations {
+                        if let Err(e) = self.handle(&evaluation) {
+                            error!("Failed to handle rule {} on {}: {}", evaluation.rule, evaluation.datalog, e);
+                        }
+                    }
+                }
+                Ok(coordinator::ChannelData::Shutdown) => break,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("rules lagged, skipped {} messages", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+
+        info!("rules exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    fn handle(&self, evaluation: &Evaluation) -> Result<()> {
+        debug!(
+            "rule {} on {}: {:?} values={:?}",
+            evaluation.rule, evaluation.datalog, evaluation.outcome, evaluation.values
+        );
+        self.publish("state", evaluation, true, serde_json::to_string(evaluation)?)?;
+
+        if !matches!(evaluation.outcome, Outcome::Fired | Outcome::Released) {
+            return Ok(());
+        }
+        info!(
+            "rule {} {} on {}{}",
+            evaluation.rule,
+            if evaluation.outcome == Outcome::Fired { "fired" } else { "released" },
+            evaluation.datalog,
+            if evaluation.dry_run { " (dry run)" } else { "" }
+        );
+
+        let inverter = self
+            .config
+            .enabled_inverter_with_datalog(evaluation.datalog)
+            .ok_or_else(|| anyhow!("no enabled inverter with datalog {}", evaluation.datalog))?;
+
+        let mut commands = Vec::new();
+        let mut errors = Vec::new();
+        for action in &evaluation.actions {
+            match action.clone().into_command(inverter.clone(), self.registers.as_deref()) {
+                Ok(command) => {
+                    commands.push(command.describe());
+                    if evaluation.dry_run {
+                        info!("rule {} dry run, not sending {}", evaluation.rule, command.describe());
+                    } else {
+                        self.channels
+                            .to_coordinator
+                            .send(coordinator::ChannelData::Command(
+                                command,
+                                Source::new(Origin::Rules, evaluation.rule.clone()),
+                            ))?;
+                    }
+                }
+                Err(e) => {
+                    warn!("rule {} action {:?} is invalid: {}", evaluation.rule, action, e);
+                    errors.push(e.to_string());
+                }
+            }
+        }
+
+        let actions = Actions {
+            time: &evaluation.time,
+            outcome: evaluation.outcome,
+            dry_run: evaluation.dry_run,
+            commands,
+            errors,
+        };
+        self.publish("actions", evaluation, false, serde_json::to_string(&actions)?)
+    }
+
+    fn publish(&self, kind: &str, evaluation: &Evaluation, retain: bool, payload: String) -> Result<()> {
+        if !self.config.mqtt().enabled() {
+            return Ok(());
+        }
+        self.channels.to_mqtt.send(mqtt::ChannelData::Message(mqtt::Message {
+            topic: format!("{}/rules/{}/{}", evaluation.datalog, evaluation.rule, kind),
+            retain,
+            payload,
+        }))?;
+        Ok(())
+    }
+}
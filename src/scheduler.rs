@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use crate::command::{Origin, Source};
 use std::time::Duration;
 
 #[derive(Clone)]
@@ -55,13 +56,12 @@ impl Scheduler {
             tokio::select! {
                 _ = timesync_interval.tick() => {
                     for inverter in self.config.enabled_inverters() {
-                        if let Err(e) = crate::coordinator::commands::timesync::TimeSync::new(
+                        let timesync = crate::coordinator::commands::timesync::TimeSync::new(
                             self.channels.clone(),
                             inverter.clone(),
-                        )
-                        .run()
-                        .await
-                        {
+                        );
+                        let source = Source::new(Origin::Scheduler, "timesync");
+                        if let Err(e) = crate::audit::scope(source, timesync.run()).await {
                             error!("Failed to sync time for inverter {}: {}", inverter.serial().unwrap_or_default(), e);
                         }
                     }
@@ -0,0 +1,412 @@
+//! Tariff-aware cost and savings accounting.
+//!
+//! Each complete input set advances the `e_to_user_day` / `e_to_grid_day` counters and the
+//! day's consumption; the increase since the previous set is priced at the time-of-use rate
+//! in force when it arrived. Running totals are kept per local day and month: import cost,
+//! export revenue, fixed charges, and the cost the same consumption would have had with no
+//! solar or battery. With a database the day and month totals carry on from the stored rows
+//! after a restart, otherwise they start from zero.
+//!
+//! A counter lower than in the previous set is taken as the inverter's midnight reset only when
+//! the local date has changed or the drop is still there in the next set; a single glitched set
+//! is ignored.
+
+use crate::prelude::*;
+use crate::daily_summary::consumption;
+use crate::database::Database;
+use crate::eg4::packet::ReadInputAll;
+
+use chrono::{Datelike, Timelike};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Month => "month",
+        }
+    }
+
+    /// `period_start` of the day or month an input set belongs to.
+    pub fn start_of(&self, input: &ReadInputAll) -> String {
+        let local = input.time.0.with_timezone(&chrono::Local);
+        match self {
+            Self::Day => local.format("%Y-%m-%d").to_string(),
+            Self::Month => local.format("%Y-%m").to_string(),
+        }
+    }
+
+    fn measurement(&self) -> &'static str {
+        match self {
+            Self::Day => "eg4_tariff_daily",
+            Self::Month => "eg4_tariff_monthly",
+        }
+    }
+}
+
+/// Running totals for one inverter over a day or month. Energies are in kWh, money in the
+/// tariff's currency.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Costs {
+    pub datalog: Serial,
+    pub period: Period,
+    /// `YYYY-MM-DD` for a day, `YYYY-MM` for a month (local time)
+    pub period_start: String,
+    pub import_kwh: f64,
+    pub export_kwh: f64,
+    pub consumption_kwh: f64,
+    pub import_cost: f64,
+    pub export_revenue: f64,
+    /// consumption priced at the import rate
+    pub consumption_cost: f64,
+    pub fixed_charges: f64,
+    /// import_cost + fixed_charges - export_revenue
+    pub net_cost: f64,
+    /// consumption priced at the import rate, plus fixed charges
+    pub baseline_cost: f64,
+    /// baseline_cost - net_cost
+    pub savings: f64,
+}
+
+impl Costs {
+    fn new(datalog: Serial, period: Period, period_start: String, fixed_charges: f64) -> Self {
+        let mut costs = Self {
+            datalog,
+            period,
+            period_start,
+            import_kwh: 0.0,
+            export_kwh: 0.0,
+            consumption_kwh: 0.0,
+            import_cost: 0.0,
+            export_revenue: 0.0,
+            consumption_cost: 0.0,
+            fixed_charges,
+            net_cost: 0.0,
+            baseline_cost: 0.0,
+            savings: 0.0,
+        };
+        costs.settle();
+        costs
+    }
+
+    fn add(&mut self, delta: &Counters, import_price: f64, export_price: f64) {
+        self.import_kwh += delta.import;
+        self.export_kwh += delta.export;
+        self.consumption_kwh += delta.consumption;
+        self.import_cost += delta.import * import_price;
+        self.export_revenue += delta.export * export_price;
+        self.consumption_cost += delta.consumption * import_price;
+        self.settle();
+    }
+
+    /// Recompute the derived totals.
+    fn settle(&mut self) {
+        self.net_cost = self.import_cost + self.fixed_charges - self.export_revenue;
+        self.baseline_cost = self.consumption_cost + self.fixed_charges;
+        self.savings = self.baseline_cost - self.net_cost;
+    }
+
+    /// Copy with values rounded for publishing.
+    pub fn rounded(&self) -> Self {
+        let r = |v: f64| (v * 1000.0).round() / 1000.0;
+        Self {
+            import_kwh: r(self.import_kwh),
+            export_kwh: r(self.export_kwh),
+            consumption_kwh: r(self.consumption_kwh),
+            import_cost: r(self.import_cost),
+            export_revenue: r(self.export_revenue),
+            consumption_cost: r(self.consumption_cost),
+            fixed_charges: r(self.fixed_charges),
+            net_cost: r(self.net_cost),
+            baseline_cost: r(self.baseline_cost),
+            savings: r(self.savings),
+            ..self.clone()
+        }
+    }
+}
+
+/// Prices in force now and what the current power flow costs per hour.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Now {
+    pub datalog: Serial,
+    pub import_price: f64,
+    pub export_price: f64,
+    /// From `p_to_user` / `p_to_grid`; negative while exporting earns more than importing costs
+    pub cost_per_hour: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Counters {
+    import: f64,
+    export: f64,
+    consumption: f64,
+}
+
+impl Counters {
+    fn from_inputs(input: &ReadInputAll) -> Self {
+        Self {
+            import: input.e_to_user_day,
+            export: input.e_to_grid_day,
+            consumption: consumption(input),
+        }
+    }
+
+    fn dropped_from(&self, previous: &Self) -> bool {
+        self.import < previous.import
+            || self.export < previous.export
+            || self.consumption < previous.consumption
+    }
+
+    /// Energy since `previous`; a counter lower than before restarted at the inverter's midnight.
+    fn since(&self, previous: &Self) -> Self {
+        let delta = |now: f64, before: f64| if now >= before { now - before } else { now };
+        Self {
+            import: delta(self.import, previous.import),
+            export: delta(self.export, previous.export),
+            consumption: delta(self.consumption, previous.consumption),
+        }
+    }
+}
+
+struct Ledger {
+    last: Counters,
+    /// the last set dropped on the same local date, awaiting a second one
+    dropped: bool,
+    day: Costs,
+    month: Costs,
+}
+
+/// Per-inverter ledgers for a tariff.
+pub struct Accounts {
+    tariff: config::Tariff,
+    ledgers: HashMap<Serial, Ledger>,
+    /// stored totals to carry on from when an inverter's ledger is opened
+    stored: HashMap<(Serial, Period), Costs>,
+}
+
+impl Accounts {
+    pub fn new(tariff: config::Tariff) -> Self {
+        Self {
+            tariff,
+            ledgers: HashMap::new(),
+            stored: HashMap::new(),
+        }
+    }
+
+    /// Carry on from stored totals; used when the inverter's first input set arrives and ignored
+    /// unless `costs` is for that set's day or month.
+    pub fn seed(&mut self, costs: Costs) {
+        self.stored.insert((costs.datalog, costs.period), costs);
+    }
+
+    /// (import, export) price per kWh at a local weekday and hour; zero when no rate matches.
+    pub fn prices_at(&self, weekday: chrono::Weekday, hour: u32) -> (f64, f64) {
+        self.tariff
+            .rates()
+            .iter()
+            .find(|r| r.applies(weekday, hour))
+            .map(|r| (r.import, r.export))
+            .unwrap_or_default()
+    }
+
+    /// Account for one input set; returns the updated day and month totals and the current prices.
+    pub fn update(&mut self, input: &ReadInputAll) -> (Costs, Costs, Now) {
+        let local = input.time.0.with_timezone(&chrono::Local);
+        let (import_price, export_price) = self.prices_at(local.weekday(), local.hour());
+        let day = Period::Day.start_of(input);
+        let month = Period::Month.start_of(input);
+        let counters = Counters::from_inputs(input);
+        let (fixed_daily, fixed_monthly) = (self.tariff.fixed_daily(), self.tariff.fixed_monthly());
+
+        let datalog = input.datalog;
+        if !self.ledgers.contains_key(&datalog) {
+            let mut stored = |period: Period, start: &str| {
+                self.stored
+                    .remove(&(datalog, period))
+                    .filter(|costs| costs.period_start == start)
+            };
+            let stored_day = stored(Period::Day, &day);
+            let stored_month = stored(Period::Month, &month);
+            // a day not stored yet adds its fixed charge to the month
+            let today = if stored_day.is_some() && stored_month.is_some() { 0.0 } else { fixed_daily };
+            let mut ledger = Ledger {
+                last: counters,
+                dropped: false,
+                day: stored_day.unwrap_or_else(|| Costs::new(datalog, Period::Day, day.clone(), fixed_daily)),
+                month: stored_month
+                    .unwrap_or_else(|| Costs::new(datalog, Period::Month, month.clone(), fixed_monthly)),
+            };
+            ledger.month.fixed_charges += today;
+            ledger.month.settle();
+            self.ledgers.insert(datalog, ledger);
+        }
+        let ledger = self.ledgers.get_mut(&datalog).expect("ledger opened above");
+        let same_day = ledger.day.period_start == day;
+
+        if ledger.month.period_start != month {
+            ledger.month = Costs::new(datalog, Period::Month, month, fixed_monthly);
+        }
+        if ledger.day.period_start != day {
+            ledger.day = Costs::new(datalog, Period::Day, day, fixed_daily);
+            ledger.month.fixed_charges += fixed_daily;
+            ledger.month.settle();
+        }
+
+        if counters.dropped_from(&ledger.last) && same_day && !ledger.dropped {
+            // keep the counters from before the drop until it is confirmed
+            ledger.dropped = true;
+        } else {
+            let delta = counters.since(&ledger.last);
+            ledger.dropped = false;
+            ledger.last = counters;
+            ledger.day.add(&delta, import_price, export_price);
+            ledger.month.add(&delta, import_price, export_price);
+        }
+
+        let now = Now {
+            datalog,
+            import_price,
+            export_price,
+            cost_per_hour: (f64::from(input.p_to_user) * import_price
+                - f64::from(input.p_to_grid) * export_price)
+                / 1000.0,
+        };
+
+        (ledger.day.rounded(), ledger.month.rounded(), now)
+    }
+}
+
+#[derive(Clone)]
+pub struct Tariff {
+    config: ConfigWrapper,
+    channels: Channels,
+    accounts: Arc<Mutex<Accounts>>,
+    /// First database, where the day and month totals are stored
+    database: Option<Arc<Database>>,
+    shutdown: CancellationToken,
+}
+
+impl Tariff {
+    pub fn new(config: ConfigWrapper, channels: Channels, database: Option<Arc<Database>>) -> Self {
+        let accounts = Accounts::new(config.tariff());
+        Self {
+            config,
+            channels,
+            accounts: Arc::new(Mutex::new(accounts)),
+            database,
+            shutdown: CancellationToken::new(),
+        }
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        let mut receiver = self.channels.from_coordinator.subscribe();
+        let mut seeded = HashSet::new();
+        info!("tariff accounting started");
+
+        loop {
+            let msg = tokio::select! {
+                msg = receiver.recv() => msg,
+                _ = self.shutdown.cancelled() => break,
+            };
+            match msg {
+                Ok(coordinator::ChannelData::ReadInputAll(input)) => {
+                    if seeded.insert(input.datalog) {
+                        self.seed(&input).await?;
+                    }
+                    let (day, month, now) = self
+                        .accounts
+                        .lock()
+                        .map_err(|_| anyhow!("Failed to lock tariff accounts"))?
+                        .update(&input);
+                    if let Err(e) = self.publish(&day, &month, &now, input.time.0.timestamp()) {
+                        error!("Failed to publish tariff costs for {}: {}", input.datalog, e);
+                    }
+                }
+                Ok(coordinator::ChannelData::Shutdown) => break,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    warn!("tariff accounting lagged, skipped {} messages", n);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+
+        info!("tariff accounting exiting");
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.shutdown.cancel();
+    }
+
+    /// Carry the day and month totals across restarts from the stored rows.
+    async fn seed(&self, input: &ReadInputAll) -> Result<()> {
+        let Some(database) = &self.database else {
+            return Ok(());
+        };
+        for period in [Period::Day, Period::Month] {
+            match database.tariff_costs(input.datalog, period, &period.start_of(input)).await {
+                Ok(Some(costs)) => self
+                    .accounts
+                    .lock()
+                    .map_err(|_| anyhow!("Failed to lock tariff accounts"))?
+                    .seed(costs),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to read stored tariff costs for {}: {}", input.datalog, e),
+            }
+        }
+        Ok(())
+    }
+
+    fn publish(&self, day: &Costs, month: &Costs, now: &Now, time: i64) -> Result<()> {
+        debug!(
+            "tariff costs for {}: day net={} savings={}, month net={} savings={}",
+            day.datalog, day.net_cost, day.savings, month.net_cost, month.savings
+        );
+
+        if self.config.mqtt().enabled() {
+            for (topic, payload) in [
+                ("day", serde_json::to_string(day)?),
+                ("month", serde_json::to_string(month)?),
+                ("now", serde_json::to_string(now)?),
+            ] {
+                self.channels.to_mqtt.send(mqtt::ChannelData::Message(mqtt::Message {
+                    topic: format!("{}/tariff/{}", day.datalog, topic),
+                    retain: true,
+                    payload,
+                }))?;
+            }
+        }
+
+        for costs in [day, month] {
+            if self.config.have_enabled_database() {
+                self.channels
+                    .to_database
+                    .send(database::ChannelData::TariffCosts(costs.clone()))?;
+            }
+
+            if self.config.influx().enabled() {
+                let mut value = serde_json::to_value(costs)?;
+                if let Some(obj) = value.as_object_mut() {
+                    obj.insert("time".to_string(), serde_json::Value::from(time));
+                }
+                self.channels.to_influx.send(influx::ChannelData::Measurement(
+                    costs.period.measurement().to_string(),
+                    value,
+                ))?;
+            }
+        }
+
+        Ok(())
+    }
+}
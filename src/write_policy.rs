@@ -0,0 +1,294 @@
+//! Write safety policy for hold registers.
+//!
+//! Every hold register write made through `WriteInverter` is checked here before it goes
+//! out, whoever asked for it. A write is refused when the register is denied or not on a
+//! non-empty allow list, when the value is outside the register's `min`/`max` or off its
+//! `step` (from the register map, or `limits` in the config), when a time slot register is
+//! not given a time of day, when the same register on
+//! the same inverter was written less than `min_interval` seconds ago, or when the register
+//! is `dangerous` and has not been confirmed.
+//!
+//! Restores of a setting a controller changed, such as on shutdown or when an inverter's
+//! data goes stale, skip the interval and confirmation so the fail-safe always gets through.
+//!
+//! A dangerous register is confirmed by publishing `confirm_token` to
+//! `cmd/{datalog}/confirm/hold/{register}`; the confirmation covers one write made within
+//! `confirm_timeout` seconds.
+//!
+//! Every refusal is logged, recorded as a `rejected` event in the database and published on
+//! `{datalog}/write_policy/rejected`.
+
+use crate::prelude::*;
+use crate::coordinator::commands::time_register_ops;
+use crate::database::{Event, EventKind};
+use crate::register::RegisterParser;
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits on the values of one register, in the units of the register map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub step: Option<f64>,
+    pub scaling: f64,
+}
+
+impl Bounds {
+    fn check(&self, value: u16) -> Result<()> {
+        let scaled = f64::from(value) * self.scaling;
+        if self.min.is_some_and(|min| scaled < min) || self.max.is_some_and(|max| scaled > max) {
+            bail!(
+                "{} is outside {}..{}",
+                scaled,
+                self.min.map(|v| v.to_string()).unwrap_or_default(),
+                self.max.map(|v| v.to_string()).unwrap_or_default()
+            );
+        }
+        if let Some(step) = self.step {
+            let steps = (scaled - self.min.unwrap_or(0.0)) / step;
+            // scalings go down to 0.001, so allow for float noise
+            if (steps - steps.round()).abs() > 1e-6 {
+                bail!("{} is not a multiple of {}", scaled, step);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Policy {
+    config: config::WritePolicy,
+    bounds: HashMap<u16, Bounds>,
+    last_write: HashMap<(Serial, u16), Instant>,
+    confirmed: HashMap<(Serial, u16), Instant>,
+}
+
+impl Policy {
+    /// Bounds are taken from the hold registers of `registers`, then from `limits`.
+    pub fn new(config: config::WritePolicy, registers: Option<&RegisterParser>) -> Self {
+        let mut bounds = HashMap::new();
+        for register in registers.map(|r| r.registers_of_type("hold")).unwrap_or_default() {
+            if register.min.is_some() || register.max.is_some() || register.step.is_some() {
+                bounds.insert(
+                    register.register_number,
+                    Bounds {
+                        min: register.min,
+                        max: register.max,
+                        step: register.step,
+                        scaling: register.scaling,
+                    },
+                );
+            }
+        }
+        for limit in config.limits() {
+            let scaling = bounds.get(&limit.register).map_or(1.0, |b| b.scaling);
+            bounds.insert(
+                limit.register,
+                Bounds {
+                    min: limit.min,
+                    max: limit.max,
+                    step: limit.step,
+                    scaling,
+                },
+            );
+        }
+
+        Self {
+            config,
+            bounds,
+            last_write: HashMap::new(),
+            confirmed: HashMap::new(),
+        }
+    }
+
+    pub fn bounds(&self, register: u16) -> Option<&Bounds> {
+        self.bounds.get(&register)
+    }
+
+    /// Check a write of `value` to a hold register, or of some bits of it when `value` is
+    /// None. Nothing is taken as written until `record` is called.
+    pub fn check(&self, datalog: Serial, register: u16, value: Option<u16>, now: Instant) -> Result<()> {
+        if !self.config.enabled() {
+            return Ok(());
+        }
+        self.check_restore(register, value)?;
+
+        let key = (datalog, register);
+        let min_interval = Duration::from_secs(self.config.min_interval());
+        if let Some(last) = self.last_write.get(&key) {
+            let elapsed = now.duration_since(*last);
+            if elapsed < min_interval {
+                bail!(
+                    "register {} was written {}s ago, the minimum interval is {}s",
+                    register,
+                    elapsed.as_secs(),
+                    min_interval.as_secs()
+                );
+            }
+        }
+
+        if self.config.dangerous(register) {
+            let timeout = Duration::from_secs(self.config.confirm_timeout());
+            match self.confirmed.get(&key) {
+                Some(at) if now.duration_since(*at) < timeout => {}
+                Some(_) => bail!("confirmation of dangerous register {} has expired", register),
+                None => bail!("register {} is dangerous and needs a confirmation first", register),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check a write restoring a setting, which only has to be allowed and within bounds.
+    pub fn check_restore(&self, register: u16, value: Option<u16>) -> Result<()> {
+        if !self.config.enabled() {
+            return Ok(());
+        }
+        if !self.config.allowed(register) {
+            bail!("register {} may not be written", register);
+        }
+        if let (Some(bounds), Some(value)) = (self.bounds.get(&register), value) {
+            bounds
+                .check(value)
+                .map_err(|e| anyhow!("value {} for register {}: {}", value, register, e))?;
+        }
+        if let (true, Some(value)) = (time_register_ops::Action::is_time_register(register), value) {
+            let [hour, minute] = value.to_le_bytes();
+            if hour > 23 || minute > 59 {
+                bail!("{:02}:{:02} for time register {} is not a time of day", hour, minute, register);
+            }
+        }
+        Ok(())
+    }
+
+    /// Take a write the inverter acknowledged as made, starting its interval and using up
+    /// its confirmation.
+    pub fn record(&mut self, datalog: Serial, register: u16, now: Instant) {
+        if !self.config.enabled() {
+            return;
+        }
+        let key = (datalog, register);
+        self.last_write.insert(key, now);
+        self.confirmed.remove(&key);
+    }
+
+    /// Confirm the next write of a dangerous register.
+    pub fn confirm(&mut self, datalog: Serial, register: u16, token: &str, now: Instant) -> Result<()> {
+        if !self.config.dangerous(register) {
+            bail!("register {} does not need a confirmation", register);
+        }
+        match self.config.confirm_token() {
+            Some(expected) if expected == token => {
+                self.confirmed.insert((datalog, register), now);
+                Ok(())
+            }
+            Some(_) => bail!("wrong confirmation token for register {}", register),
+            None => bail!("no confirm_token is configured, dangerous registers cannot be written"),
+        }
+    }
+}
+
+/// A refused write, as published.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Rejection {
+    pub register: u16,
+    pub value: Option<u16>,
+    pub reason: String,
+}
+
+#[derive(Clone)]
+pub struct WritePolicy {
+    config: ConfigWrapper,
+    channels: Channels,
+    policy: Arc<Mutex<Policy>>,
+}
+
+impl WritePolicy {
+    pub fn new(config: ConfigWrapper, channels: Channels) -> Self {
+        let policy = config.write_policy();
+        // only an enabled policy needs the register map
+        let registers = if policy.enabled() {
+            let file = config.register_file().unwrap_or_else(|| "doc/eg4_registers.json".to_string());
+            RegisterParser::new(&file)
+                .map_err(|e| warn!("write policy has no register map bounds, cannot load {}: {}", file, e))
+                .ok()
+        } else {
+            None
+        };
+        let policy = Policy::new(policy, registers.as_ref());
+
+        Self {
+            config,
+            channels,
+            policy: Arc::new(Mutex::new(policy)),
+        }
+    }
+
+    /// Check a hold register write, auditing it if refused. Writes made on behalf of a
+    /// restoring source skip the interval and confirmation.
+    pub fn check(&self, datalog: Serial, register: u16, value: Option<u16>) -> Result<()> {
+        let restore = crate::audit::current().is_some_and(|source| source.restore);
+        let result = if restore {
+            self.lock()?.check_restore(register, value)
+        } else {
+            self.lock()?.check(datalog, register, value, Instant::now())
+        };
+        result.map_err(|e| self.reject(datalog, register, value, e))
+    }
+
+    /// Record a hold register write the inverter acknowledged.
+    pub fn record(&self, datalog: Serial, register: u16) -> Result<()> {
+        self.lock()?.record(datalog, register, Instant::now());
+        Ok(())
+    }
+
+    /// Confirm the next write of a dangerous register, auditing a refused confirmation.
+    pub fn confirm(&self, datalog: Serial, register: u16, token: &str) -> Result<()> {
+        let result = self.lock()?.confirm(datalog, register, token, Instant::now());
+        match result {
+            Ok(()) => {
+                info!("write policy: register {} on {} confirmed", register, datalog);
+                Ok(())
+            }
+            Err(e) => Err(self.reject(datalog, register, None, e)),
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Policy>> {
+        self.policy
+            .lock()
+            .map_err(|_| anyhow!("Failed to lock write policy"))
+    }
+
+    fn reject(&self, datalog: Serial, register: u16, value: Option<u16>, error: anyhow::Error) -> anyhow::Error {
+        let reason = error.to_string();
+        warn!("write policy: rejected write to register {} on {}: {}", register, datalog, reason);
+
+        if self.config.have_enabled_database() {
+            let _ = self.channels.to_database.send(database::ChannelData::Event(Event::new(
+                datalog,
+                EventKind::Rejected,
+                format!("hold/{}: {}", register, reason),
+            )));
+        }
+        if self.config.mqtt().enabled() {
+            let rejection = Rejection {
+                register,
+                value,
+                reason: reason.clone(),
+            };
+            if let Ok(payload) = serde_json::to_string(&rejection) {
+                let _ = self.channels.to_mqtt.send(mqtt::ChannelData::Message(mqtt::Message {
+                    topic: format!("{}/write_policy/rejected", datalog),
+                    retain: false,
+                    payload,
+                }));
+            }
+        }
+
+        anyhow!("rejected by write policy: {}", reason)
+    }
+}
@@ -196,6 +196,7 @@ impl Factory {
             register_block_size: None,
             delay_ms: None,
             read_only: None,
+            dry_run: None,
             register_read_interval: None,
         }
     }
@@ -450,6 +451,27 @@ impl Factory {
         ri.set_read_input_6(Self::read_input_6());
         ri.to_input_all().expect("factory ReadInputs should merge")
     }
+
+    /// `read_input_all()` stamped at `time`.
+    pub fn read_input_all_at(time: chrono::DateTime<chrono::Utc>) -> eg4::packet::ReadInputAll {
+        let mut ria = Self::read_input_all();
+        ria.time = UnixTime(time);
+        ria
+    }
+
+    /// A local wall-clock time in October 2026; the 19th is a Monday.
+    pub fn local_time(day: u32, hour: u32, min: u32, sec: u32) -> chrono::DateTime<chrono::Utc> {
+        use chrono::TimeZone;
+        chrono::Local
+            .with_ymd_and_hms(2026, 10, day, hour, min, sec)
+            .unwrap()
+            .with_timezone(&chrono::Utc)
+    }
+
+    /// A config section parsed from YAML.
+    pub fn yaml<T: serde::de::DeserializeOwned>(yaml: &str) -> T {
+        serde_yaml::from_str(yaml).unwrap()
+    }
 }
 
 pub fn common_setup() {
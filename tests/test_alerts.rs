@@ -0,0 +1,300 @@
+mod common;
+use common::*;
+
+use eg4_bridge::alerts::{self, Kind, Monitor, Notification, State};
+use eg4_bridge::config::Severity;
+use eg4_bridge::prelude::*;
+
+use mockito::Matcher;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+fn datalog() -> Serial {
+    Serial::from_str("2222222222").unwrap()
+}
+
+fn summary(notifications: &[Notification]) -> Vec<(Kind, State)> {
+    notifications.iter().map(|n| (n.kind, n.state)).collect()
+}
+
+#[test]
+fn alerts_once_per_condition_and_recovers() {
+    common_setup();
+
+    let mut monitor = Monitor::new(Factory::yaml("{enabled: true, stale_after: 0}"), vec![datalog()], Instant::now());
+    let now = Instant::now();
+    let mut input = Factory::read_input_all();
+    input.datalog = datalog();
+    input.fault_code = 0;
+    input.warning_code = 0;
+    input.bms_event_1 = 0;
+    input.bms_event_2 = 0;
+    assert!(monitor.inputs(&input, now).is_empty());
+
+    input.fault_code = 1 << 3;
+    input.bms_event_2 = 0x10;
+    let notifications = monitor.inputs(&input, now);
+    assert_eq!(
+        summary(&notifications),
+        vec![(Kind::Fault, State::Alert), (Kind::BmsWarning, State::Alert)]
+    );
+    assert_eq!(notifications[0].severity, Severity::Critical);
+    assert_eq!(notifications[0].code, Some(8));
+    assert_eq!(notifications[1].severity, Severity::Warning);
+
+    // unchanged conditions are not sent again
+    assert!(monitor.inputs(&input, now).is_empty());
+    assert!(monitor.tick(now + Duration::from_secs(3600)).is_empty());
+
+    // a different fault is a new alert
+    input.fault_code = 1;
+    assert_eq!(summary(&monitor.inputs(&input, now)), vec![(Kind::Fault, State::Alert)]);
+
+    input.fault_code = 0;
+    input.bms_event_2 = 0;
+    let notifications = monitor.inputs(&input, now);
+    assert_eq!(
+        summary(&notifications),
+        vec![(Kind::Fault, State::Recovery), (Kind::BmsWarning, State::Recovery)]
+    );
+    assert_eq!(notifications[0].title(), "[recovered] 2222222222 fault");
+}
+
+#[test]
+fn reports_disconnects_and_stale_data() {
+    common_setup();
+
+    let start = Instant::now();
+    let mut monitor = Monitor::new(Factory::yaml("{enabled: true, stale_after: 60, repeat: 600}"), vec![datalog()], start);
+
+    assert!(monitor.tick(start + Duration::from_secs(30)).is_empty());
+    assert_eq!(
+        summary(&monitor.tick(start + Duration::from_secs(60))),
+        vec![(Kind::Stale, State::Alert)]
+    );
+    assert!(monitor.tick(start + Duration::from_secs(70)).is_empty());
+
+    assert_eq!(
+        summary(&monitor.disconnected(datalog(), start + Duration::from_secs(80))),
+        vec![(Kind::Disconnected, State::Alert)]
+    );
+    assert!(monitor.disconnected(datalog(), start + Duration::from_secs(90)).is_empty());
+
+    // still-active alerts are repeated
+    let mut repeated = summary(&monitor.tick(start + Duration::from_secs(700)));
+    repeated.sort_by_key(|(kind, _)| kind.as_str());
+    assert_eq!(repeated, vec![(Kind::Disconnected, State::Alert), (Kind::Stale, State::Alert)]);
+
+    // data coming back clears both
+    let mut input = Factory::read_input_all();
+    input.datalog = datalog();
+    input.fault_code = 0;
+    input.warning_code = 0;
+    input.bms_event_1 = 0;
+    input.bms_event_2 = 0;
+    assert_eq!(
+        summary(&monitor.inputs(&input, start + Duration::from_secs(710))),
+        vec![(Kind::Stale, State::Recovery), (Kind::Disconnected, State::Recovery)]
+    );
+}
+
+#[test]
+fn routes_by_severity_and_quiet_hours() {
+    common_setup();
+
+    let alerts: config::Alerts = Factory::yaml(
+        r#"
+enabled: true
+quiet_start: "22:00"
+quiet_end: "07:00"
+notifiers:
+  - type: webhook
+    url: http://localhost/all
+  - type: ntfy
+    url: http://localhost/critical
+    min_severity: critical
+"#,
+    );
+    let mut monitor = Monitor::new(alerts.clone(), vec![datalog()], Instant::now());
+    let warning = monitor.disconnected(datalog(), Instant::now()).remove(0);
+    let mut input = Factory::read_input_all();
+    input.datalog = datalog();
+    input.fault_code = 1;
+    let critical = monitor
+        .inputs(&input, Instant::now())
+        .into_iter()
+        .find(|n| n.kind == Kind::Fault)
+        .unwrap();
+
+    let names = |notification: &Notification, minute: u32| -> Vec<&str> {
+        alerts::recipients(&alerts, notification, minute)
+            .into_iter()
+            .map(|n| n.kind.name())
+            .collect()
+    };
+    let noon = 12 * 60;
+    let night = 23 * 60;
+    let morning = 6 * 60 + 59;
+    assert_eq!(names(&warning, noon), vec!["webhook"]);
+    assert_eq!(names(&critical, noon), vec!["webhook", "ntfy"]);
+    assert!(names(&warning, night).is_empty());
+    assert!(names(&warning, morning).is_empty());
+    assert_eq!(names(&critical, night), vec!["webhook", "ntfy"]);
+    assert_eq!(names(&warning, 7 * 60), vec!["webhook"]);
+}
+
+fn fault() -> Notification {
+    let mut monitor = Monitor::new(Factory::yaml("enabled: true"), vec![datalog()], Instant::now());
+    let mut input = Factory::read_input_all();
+    input.datalog = datalog();
+    input.fault_code = 1;
+    monitor
+        .inputs(&input, Instant::now())
+        .into_iter()
+        .find(|n| n.kind == Kind::Fault)
+        .unwrap()
+}
+
+fn notifier(yaml: &str) -> config::Notifier {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+#[tokio::test]
+async fn http_notifiers_post_to_their_endpoints() {
+    common_setup();
+
+    let mut server = mockito::Server::new_async().await;
+    let webhook = server
+        .mock("POST", "/hook")
+        .match_body(Matcher::PartialJson(serde_json::json!({
+            "datalog": "2222222222",
+            "kind": "fault",
+            "severity": "critical",
+            "state": "alert",
+            "code": 1,
+        })))
+        .with_status(200)
+        .create_async()
+        .await;
+    let ntfy = server
+        .mock("POST", "/inverter")
+        .match_header("Title", "[critical] 2222222222 fault")
+        .match_header("Priority", "urgent")
+        .match_header("Authorization", "Bearer tk_secret")
+        .with_status(200)
+        .create_async()
+        .await;
+    let gotify = server
+        .mock("POST", "/message")
+        .match_header("X-Gotify-Key", "app-token")
+        .match_body(Matcher::PartialJson(serde_json::json!({"priority": 8})))
+        .with_status(200)
+        .create_async()
+        .await;
+
+    let http = reqwest::Client::new();
+    let notification = fault();
+    for yaml in [
+        format!("{{type: webhook, url: '{}/hook'}}", server.url()),
+        format!("{{type: ntfy, url: '{}/inverter', token: tk_secret}}", server.url()),
+        format!("{{type: gotify, url: '{}/message', token: app-token}}", server.url()),
+    ] {
+        alerts::send(&http, &notifier(&yaml), &notification).await.unwrap();
+    }
+
+    webhook.assert_async().await;
+    ntfy.assert_async().await;
+    gotify.assert_async().await;
+
+    // failures are reported
+    let missing = notifier(&format!("{{type: webhook, url: '{}/missing'}}", server.url()));
+    assert!(alerts::send(&http, &missing, &notification).await.is_err());
+}
+
+/// A relay that accepts anything, returning the commands and message it was given.
+async fn relay(ehlo: &'static [u8]) -> (u16, tokio::task::JoinHandle<Vec<String>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let relay = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut read = BufReader::new(read);
+        let mut received = Vec::new();
+        let mut in_data = false;
+        write.write_all(b"220 relay ready\r\n").await.unwrap();
+        loop {
+            let mut line = String::new();
+            if read.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end().to_string();
+            let reply: &[u8] = if in_data {
+                in_data = line != ".";
+                if in_data { b"" } else { b"250 queued\r\n" }
+            } else {
+                match line.split(' ').next().unwrap_or_default() {
+                    "EHLO" => ehlo,
+                    "AUTH" => b"235 ok\r\n",
+                    "DATA" => {
+                        in_data = true;
+                        b"354 go ahead\r\n"
+                    }
+                    "QUIT" => b"221 bye\r\n",
+                    _ => b"250 ok\r\n",
+                }
+            };
+            received.push(line);
+            write.write_all(reply).await.unwrap();
+        }
+        received
+    });
+
+    (port, relay)
+}
+
+#[tokio::test]
+async fn smtp_notifier_hands_the_message_to_a_relay() {
+    common_setup();
+
+    let (port, relay) = relay(b"250-relay\r\n250 8BITMIME\r\n").await;
+
+    let smtp = notifier(&format!(
+        "{{type: smtp, host: 127.0.0.1, port: {}, tls: none, from: bridge@example.com, to: [me@example.com]}}",
+        port
+    ));
+    alerts::send(&reqwest::Client::new(), &smtp, &fault()).await.unwrap();
+
+    let received = relay.await.unwrap();
+    assert_eq!(received[0], "EHLO eg4-bridge");
+    assert_eq!(received[1], "MAIL FROM:<bridge@example.com>");
+    assert_eq!(received[2], "RCPT TO:<me@example.com>");
+    assert_eq!(received[3], "DATA");
+    assert!(received.contains(&"Subject: [critical] 2222222222 fault".to_string()));
+    assert!(received.contains(&".".to_string()));
+    assert_eq!(received.last().map(String::as_str), Some("QUIT"));
+}
+
+#[tokio::test]
+async fn smtp_notifier_only_logs_in_over_tls() {
+    common_setup();
+
+    // credentials are never sent in the clear
+    let plain = notifier(
+        "{type: smtp, host: 127.0.0.1, port: 1, tls: none, username: bridge, password: pw, from: bridge@example.com, to: [me@example.com]}",
+    );
+    let err = alerts::send(&reqwest::Client::new(), &plain, &fault()).await.unwrap_err();
+    assert!(err.to_string().contains("without TLS"), "got: {err}");
+
+    // nor to a relay that cannot upgrade the connection
+    let (port, relay) = relay(b"250-relay\r\n250 AUTH PLAIN\r\n").await;
+    let starttls = notifier(&format!(
+        "{{type: smtp, host: 127.0.0.1, port: {}, username: bridge, password: pw, from: bridge@example.com, to: [me@example.com]}}",
+        port
+    ));
+    let err = alerts::send(&reqwest::Client::new(), &starttls, &fault()).await.unwrap_err();
+    assert!(err.to_string().contains("STARTTLS"), "got: {err}");
+
+    let received = relay.await.unwrap();
+    assert_eq!(received, vec!["EHLO eg4-bridge".to_string()]);
+}
@@ -0,0 +1,138 @@
+mod common;
+use common::*;
+
+use eg4_bridge::audit::{self, AuditLog, Tracker};
+use eg4_bridge::command::{Origin, Source};
+use eg4_bridge::prelude::*;
+use eg4::packet::{DeviceFunction, TranslatedData};
+
+use std::time::{Duration, Instant};
+
+fn hold(device_function: DeviceFunction, register: u16, value: u16) -> Packet {
+    Packet::TranslatedData(TranslatedData {
+        datalog: Serial::from_str("2222222222").unwrap(),
+        device_function,
+        inverter: Serial::from_str("5555555555").unwrap(),
+        register,
+        values: value.to_le_bytes().to_vec(),
+    })
+}
+
+#[test]
+fn tracker_tells_own_writes_from_external_ones() {
+    common_setup();
+
+    let mut tracker = Tracker::new();
+    let now = Instant::now();
+
+    // a read seeds the before value of later external writes
+    assert_eq!(tracker.received(&hold(DeviceFunction::ReadHold, 64, 100), now), None);
+
+    // our own write and its reply
+    tracker.sent(&hold(DeviceFunction::WriteSingle, 64, 80), now);
+    assert_eq!(tracker.received(&hold(DeviceFunction::WriteSingle, 64, 80), now), None);
+
+    // a second reply answers nothing we sent
+    let record = tracker
+        .received(&hold(DeviceFunction::WriteSingle, 64, 50), now + Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(record.origin, Some(Origin::External));
+    assert_eq!(record.source, None);
+    assert_eq!(record.frame.register, 64);
+    assert_eq!(record.frame.old_value, Some(80));
+    assert_eq!(record.frame.new_value, Some(50));
+
+    // reads are never audited
+    tracker.sent(&hold(DeviceFunction::ReadHold, 21, 1), now);
+    assert_eq!(tracker.received(&hold(DeviceFunction::ReadHold, 21, 0), now), None);
+}
+
+#[test]
+fn tracker_treats_late_replies_as_external() {
+    common_setup();
+
+    let mut tracker = Tracker::new();
+    let now = Instant::now();
+
+    tracker.sent(&hold(DeviceFunction::WriteSingle, 66, 10), now);
+    let record = tracker.received(&hold(DeviceFunction::WriteSingle, 66, 10), now + Duration::from_secs(60));
+    assert_eq!(record.map(|r| r.origin), Some(Some(Origin::External)));
+}
+
+#[tokio::test]
+async fn records_carry_the_source_of_their_command() {
+    common_setup();
+
+    let channels = Channels::new();
+    let mut receiver = channels.to_audit.subscribe();
+    let packet = hold(DeviceFunction::WriteSingle, 21, 5);
+
+    let source = Source::new(Origin::Rules, "cheap_night");
+    audit::scope(source, async {
+        audit::record(&channels, &packet, Some(4), &Ok(()));
+    })
+    .await;
+    audit::record(&channels, &packet, None, &Err::<(), _>(anyhow!("timeout")));
+
+    let audit::ChannelData::Record(record) = receiver.recv().await.unwrap() else {
+        panic!("expected a record");
+    };
+    assert_eq!(record.origin, Some(Origin::Rules));
+    assert_eq!(record.source.as_deref(), Some("cheap_night"));
+    assert_eq!((record.frame.old_value, record.frame.new_value), (Some(4), Some(5)));
+    assert_eq!(record.result, "ok");
+
+    let audit::ChannelData::Record(record) = receiver.recv().await.unwrap() else {
+        panic!("expected a record");
+    };
+    assert_eq!(record.origin, None);
+    assert_eq!(record.result, "failed: timeout");
+}
+
+#[tokio::test]
+async fn audit_log_appends_json_lines() {
+    common_setup();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let mut config = Factory::example_config();
+    config.audit = serde_yaml::from_str(&format!("{{enabled: true, file: {}}}", path.display())).unwrap();
+    let config = ConfigWrapper::from_config(config);
+
+    let channels = Channels::new();
+    let audit_log = AuditLog::new(config, channels.clone());
+
+    let tf = async {
+        // an external write, seen only by its reply
+        while channels.from_inverter.receiver_count() == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        channels
+            .from_inverter
+            .send(eg4::inverter::ChannelData::Packet(hold(DeviceFunction::WriteSingle, 64, 50)))
+            .unwrap();
+
+        let mut retries = 0;
+        let line = loop {
+            let contents = std::fs::read_to_string(&path).unwrap_or_default();
+            if let Some(line) = contents.lines().next() {
+                break line.to_string();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            retries += 1;
+            if retries > 500 {
+                panic!("nothing appended to the audit file");
+            }
+        };
+        let json: serde_json::Value = serde_json::from_str(&line)?;
+        assert_eq!(json["origin"], "external");
+        assert_eq!(json["register"], 64);
+        assert_eq!(json["new_value"], 50);
+        assert_eq!(json["result"], "ok");
+
+        audit_log.stop();
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(audit_log.start(), tf).unwrap();
+}
@@ -0,0 +1,170 @@
+mod common;
+use common::*;
+
+use eg4_bridge::battery_health::{BatteryHealth, Tracker};
+use eg4_bridge::eg4::packet::ReadInputAll;
+use eg4_bridge::prelude::*;
+
+/// Inputs at noon UTC on 2026-10-`day`.
+fn inputs(day: u32, soc: i8, max_cell: f64, min_cell: f64) -> ReadInputAll {
+    let time = chrono::NaiveDate::from_ymd_opt(2026, 10, day)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap()
+        .and_utc();
+    let mut ria = Factory::read_input_all_at(time);
+    ria.soc = soc;
+    ria.soh = 98;
+    ria.cycle_count = 200;
+    ria.max_cell_voltage = max_cell;
+    ria.min_cell_voltage = min_cell;
+    ria.max_cell_temp = 24.0;
+    ria.min_cell_temp = 21.5;
+    ria.e_chg_all = 1000.0;
+    ria.e_dischg_all = 900.0;
+    ria.e_chg_day = 0.0;
+    ria.e_dischg_day = 0.0;
+    ria
+}
+
+#[test]
+fn cell_spread_alarm_has_hysteresis() {
+    common_setup();
+
+    let mut tracker = Tracker::new(Factory::yaml("{enabled: true, cell_spread_alarm: 0.1, cell_spread_hysteresis: 0.03}"));
+
+    let update = tracker.update(&inputs(1, 50, 3.35, 3.30));
+    let cells = update.cells.unwrap();
+    assert_eq!(cells.cell_spread, 0.05);
+    assert_eq!(cells.cell_temp_spread, 2.5);
+    assert!(!cells.alarm);
+    assert_eq!(update.alarm, None);
+
+    let update = tracker.update(&inputs(1, 50, 3.45, 3.30));
+    assert_eq!(update.alarm, Some(true));
+    assert!(update.cells.unwrap().alarm);
+
+    // below the threshold but within the hysteresis band
+    let update = tracker.update(&inputs(1, 50, 3.38, 3.30));
+    assert_eq!(update.alarm, None);
+    assert!(update.cells.unwrap().alarm);
+
+    let update = tracker.update(&inputs(1, 50, 3.36, 3.30));
+    assert_eq!(update.alarm, Some(false));
+    assert!(!update.cells.unwrap().alarm);
+
+    // no BMS data, no cells
+    assert_eq!(tracker.update(&inputs(1, 50, 0.0, 0.0)).cells, None);
+}
+
+#[test]
+fn estimates_capacity_from_soc_swings() {
+    common_setup();
+
+    let mut tracker = Tracker::new(Factory::yaml("{enabled: true, capacity_soc_delta: 20}"));
+    assert_eq!(tracker.update(&inputs(1, 30, 3.3, 3.3)).capacity, None);
+
+    let mut charging = inputs(1, 45, 3.3, 3.3);
+    charging.e_chg_all = 1002.0;
+    assert_eq!(tracker.update(&charging).capacity, None);
+
+    // 30% -> 55% took 3.5 kWh in and 0.5 kWh out
+    let mut charged = inputs(1, 55, 3.3, 3.3);
+    charged.e_chg_all = 1003.5;
+    charged.e_dischg_all = 900.5;
+    let capacity = tracker.update(&charged).capacity.unwrap();
+    assert_eq!((capacity.soc_from, capacity.soc_to), (30, 55));
+    assert_eq!(capacity.energy, 3.0);
+    assert_eq!(capacity.capacity, 12.0);
+
+    // the next swing starts where that one ended
+    let mut discharged = inputs(1, 15, 3.3, 3.3);
+    discharged.e_chg_all = 1003.5;
+    discharged.e_dischg_all = 905.3;
+    let capacity = tracker.update(&discharged).capacity.unwrap();
+    assert_eq!((capacity.soc_from, capacity.soc_to), (55, 15));
+    assert_eq!(capacity.energy, 4.8);
+    assert_eq!(capacity.capacity, 12.0);
+}
+
+#[test]
+fn reports_the_finished_day_with_trend_and_efficiency() {
+    common_setup();
+
+    let mut tracker = Tracker::new(Factory::yaml("enabled: true"));
+    let mut morning = inputs(1, 20, 3.32, 3.30);
+    // SOH 100% at 100 cycles, from an earlier run
+    tracker.seed(morning.datalog, 100, 100);
+
+    morning.e_chg_day = 1.0;
+    assert_eq!(tracker.update(&morning).health, None);
+
+    let mut full = inputs(1, 60, 3.42, 3.30);
+    full.e_chg_day = 8.0;
+    full.e_dischg_all = 901.0;
+    full.e_chg_all = 1005.0;
+    assert_eq!(tracker.update(&full).health, None);
+
+    let mut evening = inputs(1, 25, 3.33, 3.30);
+    evening.e_chg_day = 8.0;
+    evening.e_dischg_day = 7.2;
+    evening.e_chg_all = 1005.0;
+    evening.e_dischg_all = 905.0;
+    assert_eq!(tracker.update(&evening).health, None);
+
+    let health = tracker.update(&inputs(2, 25, 3.33, 3.30)).health.unwrap();
+    assert_eq!(health.date, evening.time.0.with_timezone(&chrono::Local).date_naive());
+    assert_eq!((health.soh, health.cycle_count), (98, 200));
+    // 2 points over 100 cycles
+    assert_eq!(health.soh_per_100_cycles, Some(2.0));
+    assert_eq!(health.max_cell_spread, Some(0.12));
+    assert_eq!(health.max_cell_temp_spread, Some(2.5));
+    assert_eq!((health.charge, health.discharge), (8.0, 7.2));
+    assert_eq!(health.efficiency, Some(90.0));
+    // 905 out of 1005 in
+    assert_eq!(health.lifetime_efficiency, Some(90.0));
+    // 20% -> 60% on 5 kWh in and 1 kWh out, then 60% -> 25% on 4 kWh out
+    assert_eq!(health.usable_capacity, Some(10.7));
+
+    // the new day starts afresh
+    assert_eq!(tracker.update(&inputs(2, 25, 3.33, 3.30)).health, None);
+}
+
+#[tokio::test]
+async fn publishes_cells_to_mqtt() {
+    common_setup();
+
+    let mut c = Factory::example_config();
+    c.mqtt.enabled = true;
+    c.battery_health = Factory::yaml("enabled: true");
+    for db in &mut c.databases {
+        db.enabled = false;
+    }
+    let channels = Channels::new();
+    let mut to_mqtt = channels.to_mqtt.subscribe();
+
+    let battery_health = BatteryHealth::new(ConfigWrapper::from_config(c), channels.clone(), None);
+
+    let tf = async {
+        while channels.from_coordinator.receiver_count() == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        channels
+            .from_coordinator
+            .send(coordinator::ChannelData::ReadInputAll(Box::new(inputs(1, 50, 3.35, 3.30))))?;
+
+        let mqtt::ChannelData::Message(message) = to_mqtt.recv().await? else {
+            panic!("expected a message");
+        };
+        assert_eq!(message.topic, "1234567890/battery/cells");
+        assert!(message.retain);
+        let json: serde_json::Value = serde_json::from_str(&message.payload)?;
+        assert_eq!(json["cell_spread"], 0.05);
+        assert_eq!(json["alarm"], false);
+
+        battery_health.stop();
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(battery_health.start(), tf).unwrap();
+}
@@ -0,0 +1,105 @@
+mod common;
+use common::*;
+
+use chrono::NaiveDate;
+use eg4_bridge::charge_planner::{forecast_for, Plan};
+use eg4_bridge::prelude::*;
+use std::io::Write;
+
+fn config() -> config::ChargePlanner {
+    serde_yaml::from_str(
+        r#"
+enabled: true
+forecast_file: forecast.json
+battery_capacity: 10.0
+charge_power: 2.5
+min_soc: 20
+max_soc: 95
+window_start: "23:00"
+window_end: "06:00"
+slot: 2
+"#,
+    )
+    .unwrap()
+}
+
+fn tomorrow() -> NaiveDate {
+    NaiveDate::from_ymd_opt(2026, 10, 20).unwrap()
+}
+
+#[test]
+fn forecast_from_local_file() {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        file,
+        r#"[{{"date": "2026-10-19", "pv_kwh": 3.0}}, {{"date": "2026-10-20", "pv_kwh": 18.4}}]"#
+    )
+    .unwrap();
+    let document = std::fs::read_to_string(file.path()).unwrap();
+
+    assert_eq!(forecast_for(&document, tomorrow()).unwrap(), 18.4);
+    assert!(forecast_for(&document, NaiveDate::from_ymd_opt(2026, 10, 22).unwrap()).is_err());
+    assert_eq!(forecast_for(r#"{"pv_kwh": 7.5}"#, tomorrow()).unwrap(), 7.5);
+    assert!(forecast_for("not json", tomorrow()).is_err());
+}
+
+#[test]
+fn plan_covers_shortfall_within_window() {
+    common_setup();
+
+    let inverter = Factory::example_config().inverters[0].clone();
+    let datalog = inverter.datalog().unwrap();
+
+    // 6 kWh short of 18 kWh consumption: 20% reserve + 60%, 5 kWh from 30%, 2h at 2.5 kW
+    let plan = Plan::new(&config(), datalog, tomorrow(), 12.0, 18.0, Some(30), 23 * 60);
+    assert_eq!(plan.target_soc, 80);
+    assert_eq!(plan.charge_kwh, 5.0);
+    assert_eq!((plan.start.as_str(), plan.end.as_str()), ("04:00", "06:00"));
+
+    let commands = plan.commands(inverter.clone());
+    assert_eq!(commands[0], Command::AcChargeSocLimit(inverter.clone(), 80));
+    assert_eq!(commands[1], Command::SetAcChargeTime(inverter.clone(), 2, [4, 0, 6, 0]));
+
+    // a sunny day needs no more than the reserve; a dull one is capped at max_soc and the window
+    let plan = Plan::new(&config(), datalog, tomorrow(), 30.0, 18.0, Some(50), 23 * 60);
+    assert_eq!((plan.target_soc, plan.charge_kwh), (20, 0.0));
+    assert_eq!(plan.start, plan.end);
+
+    let mut slow = config();
+    slow.charge_power = 1.0;
+    let plan = Plan::new(&slow, datalog, tomorrow(), 0.0, 40.0, None, 23 * 60);
+    assert_eq!((plan.target_soc, plan.charge_kwh), (95, 7.5));
+    assert_eq!((plan.start.as_str(), plan.end.as_str()), ("23:00", "06:00"));
+}
+
+#[test]
+fn plan_starts_from_soc_at_window_start() {
+    common_setup();
+
+    let inverter = Factory::example_config().inverters[0].clone();
+    let datalog = inverter.datalog().unwrap();
+
+    // 6h to 23:00 at 18 kWh/day drains 4.5 kWh, 90% down to 45%: 3.5 kWh to reach 80%
+    let plan = Plan::new(&config(), datalog, tomorrow(), 12.0, 18.0, Some(90), 17 * 60);
+    assert_eq!((plan.soc_at_window_start, plan.target_soc, plan.charge_kwh), (45, 80, 3.5));
+
+    // the drain stops at the reserve, and wraps past midnight
+    let plan = Plan::new(&config(), datalog, tomorrow(), 12.0, 18.0, Some(30), 17 * 60);
+    assert_eq!((plan.soc_at_window_start, plan.charge_kwh), (20, 6.0));
+    let plan = Plan::new(&config(), datalog, tomorrow(), 12.0, 18.0, Some(30), 22 * 60 + 59);
+    assert_eq!(plan.soc_at_window_start, 29);
+}
+
+#[test]
+fn cancel_topic_is_a_command() {
+    common_setup();
+
+    let inverter = Factory::example_config().inverters[0].clone();
+    let message = mqtt::Message {
+        topic: format!("cmd/{}/charge_plan/cancel", inverter.datalog().unwrap()),
+        retain: false,
+        payload: "".to_owned(),
+    };
+
+    assert_eq!(message.to_command(inverter.clone()).unwrap(), Command::CancelChargePlan(inverter));
+}
@@ -64,6 +64,66 @@ databases: []
     );
 }
 
+#[test]
+fn config_rejects_invalid_tariff_rate() {
+    let mut temp = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        temp,
+        r#"
+read_only: false
+inverters: []
+mqtt:
+  enabled: false
+  host: localhost
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+tariff:
+  enabled: true
+  rates:
+    - days: [mon, funday]
+      import: 0.30
+"#
+    )
+    .unwrap();
+
+    let err = Config::new(temp.path().to_string_lossy().to_string()).unwrap_err();
+    assert!(err.to_string().contains("funday"), "got: {err}");
+}
+
+#[test]
+fn config_rejects_invalid_rule() {
+    let mut temp = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        temp,
+        r#"
+read_only: false
+inverters: []
+mqtt:
+  enabled: false
+  host: localhost
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+rules:
+  enabled: true
+  rules:
+    - name: night
+      start_time: "22:00"
+      end_time: "25:00"
+      actions:
+        - command: ac_charge
+          enable: true
+"#
+    )
+    .unwrap();
+
+    let err = Config::new(temp.path().to_string_lossy().to_string()).unwrap_err();
+    assert!(err.to_string().contains("25:00"), "got: {err}");
+}
+
 #[test]
 fn inverter_defaults() {
     let input =
@@ -108,6 +168,86 @@ fn mqtt_defaults() {
     assert!(mqtt.enabled());
     assert_eq!(mqtt.port(), 1883);
     assert_eq!(mqtt.namespace(), "lxp");
+    assert_eq!(mqtt.client_id(), "lxp-bridge");
+    assert_eq!(mqtt.keepalive(), 60);
+    assert!(mqtt.clean_session());
+    assert!(mqtt.tls().is_none());
+    assert_eq!(mqtt.connect_port(), 1883);
+    assert_eq!(mqtt.publish().inputs.qos(), 1);
+    assert!(mqtt.publish().holds.retain(true));
+    assert_eq!(mqtt.protocol(), "3.1.1");
+    assert!(!mqtt.v5());
+    assert!(!mqtt.json_results());
+    assert!(mqtt.outbox().enabled());
+    assert_eq!(mqtt.outbox().max_messages(), 10000);
+    assert!(!mqtt.outbox().latest_only());
+    assert!(mqtt.outbox().file().is_none());
+    assert!(!mqtt.deadband().enabled());
+    assert_eq!(mqtt.deadband().max_age(), 300);
+}
+
+#[test]
+fn mqtt_tls_uses_its_own_port() {
+    let input = json!({
+        "host": "host",
+        "tls": { "ca_file": "/etc/ssl/ca.pem" },
+        "publish": { "inputs": { "qos": 0, "retain": true } }
+    });
+    let mqtt: config::Mqtt = serde_json::from_value(input).unwrap();
+    assert_eq!(mqtt.tls().unwrap().ca_file(), Some("/etc/ssl/ca.pem"));
+    assert_eq!(mqtt.connect_port(), 8883);
+    assert_eq!(mqtt.publish().inputs.qos(), 0);
+    assert!(mqtt.publish().inputs.retain(false));
+}
+
+#[test]
+fn config_rejects_client_cert_without_key() {
+    let mut temp = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        temp,
+        r#"
+read_only: false
+inverters: []
+mqtt:
+  enabled: true
+  host: localhost
+  tls:
+    ca_file: /etc/ssl/ca.pem
+    cert_file: /etc/ssl/bridge.pem
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+"#
+    )
+    .unwrap();
+
+    let err = Config::new(temp.path().to_string_lossy().to_string()).unwrap_err();
+    assert!(err.to_string().contains("key_file"), "got: {err}");
+}
+
+#[test]
+fn config_rejects_unknown_mqtt_protocol() {
+    let mut temp = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        temp,
+        r#"
+read_only: false
+inverters: []
+mqtt:
+  enabled: true
+  host: localhost
+  protocol: "4"
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+"#
+    )
+    .unwrap();
+
+    let err = Config::new(temp.path().to_string_lossy().to_string()).unwrap_err();
+    assert!(err.to_string().contains("protocol"), "got: {err}");
 }
 
 #[test]
@@ -116,6 +256,8 @@ fn homeassistant_defaults() {
     let ha: config::HomeAssistant = serde_json::from_value(input).unwrap();
     assert!(ha.enabled());
     assert_eq!(ha.prefix(), "homeassistant");
+    assert_eq!(ha.status_topic(), "homeassistant/status");
+    assert!(!ha.remove_stale());
 }
 
 #[test]
@@ -136,6 +278,7 @@ fn enabled_inverters() {
             register_block_size: None,
             delay_ms: None,
             read_only: None,
+            dry_run: None,
             register_read_interval: None,
         },
         config::Inverter {
@@ -151,6 +294,7 @@ fn enabled_inverters() {
             register_block_size: None,
             delay_ms: None,
             read_only: None,
+            dry_run: None,
             register_read_interval: None,
         },
     ]);
@@ -176,6 +320,7 @@ fn inverters_for_message() {
             register_block_size: None,
             delay_ms: None,
             read_only: None,
+            dry_run: None,
             register_read_interval: None,
         },
         config::Inverter {
@@ -191,6 +336,7 @@ fn inverters_for_message() {
             register_block_size: None,
             delay_ms: None,
             read_only: None,
+            dry_run: None,
             register_read_interval: None,
         },
     ]);
@@ -231,12 +377,276 @@ fn enabled_databases() {
         config::Database {
             enabled: false,
             url: "sqlite://test.db".to_owned(),
+            batch_size: 1,
+            flush_interval: 10,
+            retention_days: None,
+            rollups: false,
+            maintenance_interval: 300,
         },
         config::Database {
             enabled: true,
             url: "sqlite://test.db".to_owned(),
+            batch_size: 1,
+            flush_interval: 10,
+            retention_days: None,
+            rollups: false,
+            maintenance_interval: 300,
         },
     ]);
 
     assert_eq!(config.enabled_databases().len(), 1);
 }
+
+#[test]
+fn config_rejects_backwards_write_policy_range() {
+    let mut temp = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        temp,
+        r#"
+read_only: false
+inverters: []
+mqtt:
+  enabled: false
+  host: localhost
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+write_policy:
+  enabled: true
+  deny: ["53-25"]
+"#
+    )
+    .unwrap();
+
+    let err = Config::new(temp.path().to_string_lossy().to_string()).unwrap_err();
+    assert!(format!("{err:#}").contains("backwards"), "got: {err:#}");
+}
+
+#[test]
+fn global_dry_run_applies_to_inverters_without_their_own() {
+    let mut temp = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        temp,
+        r#"
+read_only: false
+dry_run: true
+inverters:
+  - host: localhost
+    port: 8000
+    serial: "5555555555"
+    datalog: "2222222222"
+  - host: localhost
+    port: 8001
+    serial: "5555555556"
+    datalog: "2222222223"
+    dry_run: false
+mqtt:
+  enabled: false
+  host: localhost
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+"#
+    )
+    .unwrap();
+
+    let config = Config::new(temp.path().to_string_lossy().to_string()).unwrap();
+    assert!(config.inverters[0].dry_run());
+    assert!(!config.inverters[1].dry_run());
+}
+
+#[test]
+fn config_rejects_alerts_with_half_of_quiet_hours() {
+    let mut temp = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        temp,
+        r#"
+read_only: false
+inverters: []
+mqtt:
+  enabled: false
+  host: localhost
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+alerts:
+  enabled: true
+  quiet_start: "22:00"
+  notifiers:
+    - type: ntfy
+      url: https://ntfy.sh/inverter
+"#
+    )
+    .unwrap();
+
+    let err = Config::new(temp.path().to_string_lossy().to_string()).unwrap_err();
+    assert!(format!("{err:#}").contains("quiet_end"), "got: {err:#}");
+}
+
+#[test]
+fn config_rejects_smtp_login_without_tls() {
+    let mut temp = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        temp,
+        r#"
+read_only: false
+inverters: []
+mqtt:
+  enabled: false
+  host: localhost
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+alerts:
+  enabled: true
+  notifiers:
+    - type: smtp
+      host: localhost
+      tls: none
+      username: bridge
+      password: pw
+      from: bridge@example.com
+      to: [me@example.com]
+"#
+    )
+    .unwrap();
+
+    let err = Config::new(temp.path().to_string_lossy().to_string()).unwrap_err();
+    assert!(format!("{err:#}").contains("only logs in over TLS"), "got: {err:#}");
+}
+
+#[test]
+fn config_rejects_an_unknown_smtp_tls_mode() {
+    let notifier: Result<config::Notifier, _> =
+        serde_yaml::from_str("{type: smtp, host: localhost, tls: ssl, from: bridge@example.com, to: [me@example.com]}");
+    let err = notifier.unwrap_err().to_string();
+    assert!(err.contains("unknown variant `ssl`"), "got: {err}");
+
+    let notifier: config::Notifier =
+        serde_yaml::from_str("{type: smtp, host: localhost, from: bridge@example.com, to: [me@example.com]}").unwrap();
+    assert!(matches!(notifier.kind, config::NotifierKind::Smtp { tls: config::SmtpTls::Starttls, .. }));
+}
+
+#[test]
+fn config_rejects_battery_health_hysteresis_not_below_the_alarm() {
+    let mut temp = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        temp,
+        r#"
+read_only: false
+inverters: []
+mqtt:
+  enabled: false
+  host: localhost
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+battery_health:
+  enabled: true
+  cell_spread_alarm: 0.05
+  cell_spread_hysteresis: 0.05
+"#
+    )
+    .unwrap();
+
+    let err = Config::new(temp.path().to_string_lossy().to_string()).unwrap_err();
+    assert!(format!("{err:#}").contains("cell_spread_hysteresis"), "got: {err:#}");
+}
+
+fn config_with_group(members: &str) -> tempfile::NamedTempFile {
+    let mut temp = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        temp,
+        r#"
+read_only: false
+inverters:
+  - host: 192.168.0.10
+    port: 8000
+    serial: 5555555555
+    datalog: 2222222222
+  - host: 192.168.0.11
+    port: 8000
+    serial: 6666666666
+    datalog: 3333333333
+mqtt:
+  enabled: false
+  host: localhost
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+groups:
+  - name: house
+    members: {}
+"#,
+        members
+    )
+    .unwrap();
+    temp
+}
+
+#[test]
+fn group_commands_go_to_every_member_or_none() {
+    let temp = config_with_group("[2222222222, 3333333333]");
+    let config = config::ConfigWrapper::new(temp.path().to_string_lossy().to_string()).unwrap();
+
+    let message = |topic: &str| mqtt::Message {
+        topic: topic.to_string(),
+        retain: false,
+        payload: "true".to_string(),
+    };
+
+    let r = config.inverters_for_message(&message("cmd/house/set/ac_charge")).unwrap();
+    let datalogs: Vec<String> = r.iter().map(|i| i.datalog().unwrap().to_string()).collect();
+    assert_eq!(datalogs, vec!["2222222222", "3333333333"]);
+
+    let err = config.inverters_for_message(&message("cmd/garage/set/ac_charge")).unwrap_err();
+    assert!(err.to_string().contains("unknown inverter group garage"), "got: {err}");
+
+    let mut inverters = config.inverters();
+    inverters[1].enabled = false;
+    config.set_inverters(inverters);
+    let err = config.inverters_for_message(&message("cmd/house/set/ac_charge")).unwrap_err();
+    assert!(err.to_string().contains("3333333333 of group house is not enabled"), "got: {err}");
+}
+
+#[test]
+fn config_rejects_group_members_that_are_not_inverters() {
+    let temp = config_with_group("[2222222222, 4444444444]");
+
+    let err = Config::new(temp.path().to_string_lossy().to_string()).unwrap_err();
+    assert!(format!("{err:#}").contains("4444444444"), "got: {err:#}");
+}
+
+#[test]
+fn config_rejects_an_export_limit_register_not_in_watts_or_percent() {
+    for (register, error) in [("1", "not a hold register in W or %"), ("14", "rated_power")] {
+        let mut temp = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            temp,
+            r#"
+read_only: false
+inverters: []
+mqtt:
+  enabled: false
+  host: localhost
+influx:
+  enabled: false
+  url: http://localhost:8086
+  database: eg4
+export_limit:
+  enabled: true
+  register: {register}
+"#
+        )
+        .unwrap();
+
+        let err = Config::new(temp.path().to_string_lossy().to_string()).unwrap_err();
+        assert!(format!("{err:#}").contains(error), "got: {err:#}");
+    }
+}
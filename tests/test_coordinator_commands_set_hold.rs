@@ -3,9 +3,11 @@ mod common;
 use common::*;
 use eg4_bridge::coordinator;
 use eg4_bridge::coordinator::commands::set_hold::SetHold;
+use eg4_bridge::coordinator::commands::write_inverter::WriteInverter;
 use eg4_bridge::eg4;
 use eg4_bridge::eg4::packet::Packet;
 use eg4_bridge::prelude::*;
+use eg4_bridge::write_policy::WritePolicy;
 
 fn spawn_coordinator_forwarder(channels: &Channels) {
     let ch = channels.clone();
@@ -163,3 +165,50 @@ async fn inverter_not_receiving() {
 
     futures::try_join!(sf).unwrap();
 }
+
+#[tokio::test]
+async fn dry_run_publishes_frame_instead_of_sending() {
+    common_setup();
+
+    let inverter = config::Inverter {
+        dry_run: Some(true),
+        ..Factory::inverter()
+    };
+    let channels = Channels::new();
+    let mut to_coordinator = channels.to_coordinator.subscribe();
+    let mut to_mqtt = channels.to_mqtt.subscribe();
+
+    let subject = SetHold::new(channels.clone(), inverter.clone(), 5_u16, 10);
+    subject.run().await.unwrap();
+
+    assert!(to_coordinator.try_recv().is_err());
+    let mqtt::ChannelData::Message(message) = to_mqtt.try_recv().unwrap() else {
+        panic!("expected an MQTT message");
+    };
+    assert_eq!(message.topic, "dryrun/2222222222/hold/5");
+    let frame: serde_json::Value = serde_json::from_str(&message.payload).unwrap();
+    assert_eq!(frame["device_function"], "WriteSingle");
+    assert_eq!(frame["values"], serde_json::json!([10, 0]));
+    assert_eq!(frame["new_value"], 10);
+    // no register cache is running
+    assert!(frame["old_value"].is_null());
+}
+
+#[tokio::test]
+async fn dry_run_writes_do_not_use_up_the_write_policy_interval() {
+    common_setup();
+
+    let inverter = config::Inverter {
+        dry_run: Some(true),
+        ..Factory::inverter()
+    };
+    let mut c = Factory::example_config();
+    c.write_policy = serde_yaml::from_str("enabled: true\nmin_interval: 60").unwrap();
+    let config = ConfigWrapper::from_config(c);
+    let channels = Channels::new();
+    let policy = WritePolicy::new(config.clone(), channels.clone());
+
+    let subject = WriteInverter::new(channels.clone(), inverter, config, policy);
+    subject.set_hold(5_u16, 10).await.unwrap();
+    subject.set_hold(5_u16, 11).await.unwrap();
+}
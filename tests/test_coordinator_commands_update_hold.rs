@@ -42,6 +42,7 @@ async fn happy_path() {
         register,
         bit,
         enable,
+        None,
     );
 
     let sf = async {
@@ -120,6 +121,7 @@ async fn no_reply() {
         register,
         bit,
         enable,
+        None,
     );
 
     let sf = async {
@@ -0,0 +1,129 @@
+mod common;
+use common::*;
+
+use eg4_bridge::daily_summary::{DailySummary, Summary, Tracker};
+use eg4_bridge::eg4::packet::ReadInputAll;
+use eg4_bridge::prelude::*;
+
+fn inputs_with_day(pv: f64, import: f64, export: f64, chg: f64, dischg: f64) -> ReadInputAll {
+    let mut ria = Factory::read_input_all_at(chrono::DateTime::from_timestamp(1_760_000_000, 0).unwrap());
+    ria.e_pv_day = pv;
+    ria.e_inv_day = 12.0;
+    ria.e_rec_day = 1.0;
+    ria.e_chg_day = chg;
+    ria.e_dischg_day = dischg;
+    ria.e_eps_day = 0.0;
+    ria.e_to_grid_day = export;
+    ria.e_to_user_day = import;
+    ria
+}
+
+/// Like `inputs_with_day`, a day later.
+fn next_day(pv: f64, import: f64, export: f64, chg: f64, dischg: f64) -> ReadInputAll {
+    let mut ria = inputs_with_day(pv, import, export, chg, dischg);
+    ria.time = UnixTime(ria.time.0 + chrono::Duration::days(1));
+    ria
+}
+
+#[test]
+fn tracker_reports_previous_day_on_counter_reset() {
+    common_setup();
+
+    let mut tracker = Tracker::default();
+    let morning = inputs_with_day(2.0, 1.0, 0.0, 1.0, 0.0);
+    let evening = inputs_with_day(20.0, 4.0, 6.0, 8.0, 5.0);
+    let midnight = next_day(0.0, 0.1, 0.0, 0.0, 0.1);
+
+    assert_eq!(tracker.update(&morning), None);
+    assert_eq!(tracker.update(&evening), None);
+
+    let summary = tracker.update(&midnight).expect("rollover");
+    assert_eq!(summary.datalog, evening.datalog);
+    assert_eq!(summary.pv, 20.0);
+    // 12 inverted + 4 imported - 6 exported - 1 rectified
+    assert_eq!(summary.consumption, 9.0);
+    assert_eq!(summary.grid_import, 4.0);
+    assert_eq!(summary.grid_export, 6.0);
+    assert_eq!(summary.battery_throughput, 13.0);
+    assert_eq!(summary.net_import, -2.0);
+    assert_eq!(summary.self_consumption, Some(70.0));
+    assert_eq!(summary.self_sufficiency, Some(55.6));
+
+    // the new day carries on without another summary
+    assert_eq!(tracker.update(&next_day(1.0, 0.2, 0.0, 0.0, 0.1)), None);
+}
+
+#[test]
+fn tracker_ignores_a_single_glitched_frame() {
+    common_setup();
+
+    let mut tracker = Tracker::default();
+    let evening = inputs_with_day(20.0, 4.0, 6.0, 8.0, 5.0);
+    let glitch = inputs_with_day(0.0, 0.0, 0.0, 0.0, 0.0);
+
+    assert_eq!(tracker.update(&evening), None);
+    assert_eq!(tracker.update(&glitch), None);
+    assert_eq!(tracker.update(&inputs_with_day(20.1, 4.0, 6.0, 8.0, 5.0)), None);
+
+    // a drop that persists is a reset even without a date change, reporting the last good day
+    assert_eq!(tracker.update(&glitch), None);
+    let summary = tracker.update(&inputs_with_day(0.1, 0.0, 0.0, 0.0, 0.0)).expect("rollover");
+    assert_eq!(summary.pv, 20.1);
+    assert_eq!(tracker.update(&inputs_with_day(0.2, 0.0, 0.0, 0.0, 0.0)), None);
+}
+
+#[test]
+fn summary_without_pv_has_no_self_consumption() {
+    let summary = Summary::from_inputs(&inputs_with_day(0.0, 10.0, 0.0, 0.0, 0.0));
+    assert_eq!(summary.self_consumption, None);
+    // (12 inverted + 10 imported - 1 rectified - 10 imported) / 21 consumed
+    assert_eq!(summary.self_sufficiency, Some(52.4));
+}
+
+#[tokio::test]
+async fn publishes_daily_summary_to_mqtt() {
+    common_setup();
+
+    let mut c = Factory::example_config();
+    c.mqtt.enabled = true;
+    c.influx.enabled = false;
+    for db in &mut c.databases {
+        db.enabled = false;
+    }
+    let channels = Channels::new();
+    let mut to_mqtt = channels.to_mqtt.subscribe();
+
+    let daily_summary = DailySummary::new(ConfigWrapper::from_config(c), channels.clone());
+    let task = {
+        let daily_summary = daily_summary.clone();
+        tokio::spawn(async move { daily_summary.start().await })
+    };
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    let evening = inputs_with_day(20.0, 4.0, 6.0, 8.0, 5.0);
+    for ria in [evening.clone(), next_day(0.0, 0.0, 0.0, 0.0, 0.0)] {
+        channels
+            .from_coordinator
+            .send(coordinator::ChannelData::ReadInputAll(Box::new(ria)))
+            .unwrap();
+    }
+
+    let msg = tokio::time::timeout(std::time::Duration::from_secs(2), to_mqtt.recv())
+        .await
+        .expect("summary published")
+        .unwrap();
+    let mqtt::ChannelData::Message(message) = msg else {
+        panic!("expected mqtt message");
+    };
+    assert_eq!(message.topic, format!("{}/summary/daily", evening.datalog));
+    assert!(message.retain);
+    let payload: serde_json::Value = serde_json::from_str(&message.payload).unwrap();
+    assert_eq!(payload["pv"], 20.0);
+    assert_eq!(payload["battery_throughput"], 13.0);
+
+    // stopping it leaves every other subscriber running
+    let mut from_coordinator = channels.from_coordinator.subscribe();
+    daily_summary.stop();
+    task.await.unwrap().unwrap();
+    assert!(from_coordinator.try_recv().is_err());
+}
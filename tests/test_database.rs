@@ -1,6 +1,7 @@
-//! Integration test for `Database` insert path. Requires PostgreSQL (same as production).
+//! Integration tests for the `Database` insert path. The PostgreSQL test needs a server;
+//! the SQLite test uses a temporary file and always runs.
 //!
-//! Without a URL, the test exits successfully so `cargo test` stays usable offline.
+//! Without a URL, the PostgreSQL test exits successfully so `cargo test` stays usable offline.
 //! To run against a local or CI database:
 //!
 //! ```text
@@ -55,6 +56,11 @@ async fn postgres_read_input_all_insertion() {
     let config = config::Database {
         enabled: true,
         url,
+        batch_size: 1,
+        flush_interval: 10,
+        retention_days: None,
+        rollups: false,
+        maintenance_interval: 300,
     };
     let channels = Channels::new();
     let shared_stats = Arc::new(Mutex::new(PacketStats::default()));
@@ -111,3 +117,598 @@ async fn postgres_read_input_all_insertion() {
 
     futures::try_join!(database.start(), tf).unwrap();
 }
+
+#[tokio::test]
+async fn sqlite_batched_insert_rollup_and_retention() {
+    common_setup();
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}/eg4.db?mode=rwc", dir.path().display());
+
+    let config = config::Database {
+        enabled: true,
+        url,
+        batch_size: 3,
+        flush_interval: 3600,
+        retention_days: Some(1),
+        rollups: true,
+        maintenance_interval: 3600,
+    };
+    let channels = Channels::new();
+    let shared_stats = Arc::new(Mutex::new(PacketStats::default()));
+    let database = Database::new(config, channels.clone(), shared_stats.clone());
+
+    let now = chrono::Utc::now().timestamp();
+    let hour = now - now.rem_euclid(3600) - 3600;
+    let row = |time: i64, p_pv: u16, e_pv_all: f64| {
+        let mut ria = Factory::read_input_all();
+        ria.time = UnixTime(chrono::DateTime::from_timestamp(time, 0).unwrap());
+        ria.p_pv = p_pv;
+        ria.e_pv_all = e_pv_all;
+        database::ChannelData::ReadInputAll(Box::new(ria))
+    };
+
+    let tf = async {
+        let rows = [
+            row(hour + 60, 100, 10.0),
+            row(hour + 1800, 300, 12.5),
+            // outside the one day retention window
+            row(now - 3 * 86400, 50, 1.0),
+        ];
+
+        let mut retries = 0;
+        while channels.to_database.send(rows[0].clone()).is_err() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            retries += 1;
+            if retries > 50 {
+                panic!("database not ready for messages");
+            }
+        }
+        for r in &rows[1..] {
+            channels.to_database.send(r.clone()).unwrap();
+        }
+
+        // all three rows go out in a single batch
+        let mut retries = 0;
+        while shared_stats.lock().unwrap().database_writes < 3 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            retries += 1;
+            if retries > 500 {
+                panic!("rows not inserted");
+            }
+        }
+
+        database.maintain(hour + 1800).await?;
+
+        let pool = database.connection().await?;
+
+        let count: i64 = sqlx::query("SELECT COUNT(*) AS n FROM inputs")
+            .fetch_one(&pool)
+            .await?
+            .get("n");
+        assert_eq!(count, 2);
+
+        let hourly = sqlx::query("SELECT * FROM inputs_hourly WHERE bucket_start = $1")
+            .bind(hour)
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(hourly.get::<i64, _>("samples"), 2);
+        assert_f64_eq(hourly.get("p_pv_min"), 100.0);
+        assert_f64_eq(hourly.get("p_pv_avg"), 200.0);
+        assert_f64_eq(hourly.get("p_pv_max"), 300.0);
+        assert_f64_eq(hourly.get("e_pv_all_delta"), 2.5);
+        assert_str_eq(hourly.get("datalog"), "1234567890");
+
+        let daily: i64 = sqlx::query("SELECT samples FROM inputs_daily WHERE bucket_start = $1")
+            .bind(hour - hour.rem_euclid(86400))
+            .fetch_one(&pool)
+            .await?
+            .get("samples");
+        assert_eq!(daily, 2);
+
+        database.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(database.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn sqlite_caps_batches_at_the_bind_limit() {
+    common_setup();
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}/eg4.db?mode=rwc", dir.path().display());
+
+    // far more rows than one SQLite statement can bind
+    let config = config::Database {
+        enabled: true,
+        url,
+        batch_size: 10000,
+        flush_interval: 3600,
+        retention_days: None,
+        rollups: false,
+        maintenance_interval: 3600,
+    };
+    let channels = Channels::new();
+    let shared_stats = Arc::new(Mutex::new(PacketStats::default()));
+    let database = Database::new(config, channels.clone(), shared_stats.clone());
+
+    let row = database::ChannelData::ReadInputAll(Box::new(Factory::read_input_all()));
+
+    let tf = async {
+        let mut retries = 0;
+        while channels.to_database.send(row.clone()).is_err() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            retries += 1;
+            if retries > 50 {
+                panic!("database not ready for messages");
+            }
+        }
+        // two full batches of 360 rows
+        for _ in 1..720 {
+            channels.to_database.send(row.clone()).unwrap();
+        }
+
+        // full batches go out without waiting for the flush interval
+        let pool = database.connection().await?;
+        let mut retries = 0;
+        loop {
+            let count: i64 = sqlx::query("SELECT COUNT(*) AS n FROM inputs")
+                .fetch_one(&pool)
+                .await?
+                .get("n");
+            if count == 720 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            retries += 1;
+            if retries > 500 {
+                panic!("rows not inserted");
+            }
+        }
+        assert_eq!(shared_stats.lock().unwrap().database_errors, 0);
+
+        database.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(database.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn sqlite_records_hold_changes_params_and_events() {
+    common_setup();
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}/eg4.db?mode=rwc", dir.path().display());
+
+    let config = config::Database {
+        enabled: true,
+        url,
+        batch_size: 1,
+        flush_interval: 10,
+        retention_days: None,
+        rollups: false,
+        maintenance_interval: 300,
+    };
+    let channels = Channels::new();
+    let shared_stats = Arc::new(Mutex::new(PacketStats::default()));
+    let database = Database::new(config, channels.clone(), shared_stats.clone());
+    let datalog = Serial::from_str("2222222222").unwrap();
+
+    let tf = async {
+        let first = database::ChannelData::HoldData(datalog, vec![(21, 0x0001), (64, 100)]);
+        let mut retries = 0;
+        while channels.to_database.send(first.clone()).is_err() {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            retries += 1;
+            if retries > 50 {
+                panic!("database not ready for messages");
+            }
+        }
+
+        let mut ria = Factory::read_input_all();
+        ria.datalog = datalog;
+        ria.fault_code = 0;
+        ria.warning_code = 0;
+        let mut faulted = ria.clone();
+        faulted.fault_code = 1;
+
+        for msg in [
+            // 21 changes, 64 is unchanged
+            database::ChannelData::HoldData(datalog, vec![(21, 0x0081), (64, 100)]),
+            database::ChannelData::ParamData(datalog, vec![(7, 42)]),
+            database::ChannelData::ReadInputAll(Box::new(ria.clone())),
+            database::ChannelData::ReadInputAll(Box::new(faulted)),
+            database::ChannelData::ReadInputAll(Box::new(ria)),
+            database::ChannelData::Event(database::Event {
+                origin: Some(eg4_bridge::command::Origin::Mqtt),
+                ..database::Event::new(datalog, database::EventKind::Write, "2222222222/set/hold/21 = 129 ok".to_string())
+            }),
+        ] {
+            channels.to_database.send(msg).unwrap();
+        }
+
+        let pool = database.connection().await?;
+
+        let mut retries = 0;
+        loop {
+            let events: i64 = sqlx::query("SELECT COUNT(*) AS n FROM events")
+                .fetch_one(&pool)
+                .await?
+                .get("n");
+            if events == 3 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            retries += 1;
+            if retries > 500 {
+                panic!("events not inserted");
+            }
+        }
+
+        let holds = sqlx::query("SELECT register, old_value, new_value FROM holds ORDER BY id")
+            .fetch_all(&pool)
+            .await?;
+        let holds: Vec<(i64, Option<i64>, i64)> = holds
+            .iter()
+            .map(|r| (r.get("register"), r.get("old_value"), r.get("new_value")))
+            .collect();
+        assert_eq!(holds, vec![(21, None, 1), (64, None, 100), (21, Some(1), 0x81)]);
+
+        let param: i64 = sqlx::query("SELECT value FROM params WHERE register = 7")
+            .fetch_one(&pool)
+            .await?
+            .get("value");
+        assert_eq!(param, 42);
+
+        let events = sqlx::query("SELECT kind, code, origin FROM events ORDER BY id")
+            .fetch_all(&pool)
+            .await?;
+        let events: Vec<(String, Option<i64>, Option<String>)> = events
+            .iter()
+            .map(|r| (r.get("kind"), r.get("code"), r.get("origin")))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                ("fault".to_string(), Some(1), None),
+                ("fault".to_string(), Some(0), None),
+                ("write".to_string(), None, Some("mqtt".to_string())),
+            ]
+        );
+
+        database.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(database.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn sqlite_replaces_daily_summary_for_the_same_day() {
+    common_setup();
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}/eg4.db?mode=rwc", dir.path().display());
+
+    let config = config::Database {
+        enabled: true,
+        url,
+        batch_size: 1,
+        flush_interval: 10,
+        retention_days: None,
+        rollups: false,
+        maintenance_interval: 300,
+    };
+    let channels = Channels::new();
+    let shared_stats = Arc::new(Mutex::new(PacketStats::default()));
+    let database = Database::new(config, channels.clone(), shared_stats.clone());
+
+    let tf = async {
+        let mut ria = Factory::read_input_all();
+        ria.e_pv_day = 12.5;
+        let first = eg4_bridge::daily_summary::Summary::from_inputs(&ria);
+        let second = eg4_bridge::daily_summary::Summary {
+            pv: 20.0,
+            ..first.clone()
+        };
+
+        let mut retries = 0;
+        while channels
+            .to_database
+            .send(database::ChannelData::DailySummary(first.clone()))
+            .is_err()
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            retries += 1;
+            if retries > 50 {
+                panic!("database not ready for messages");
+            }
+        }
+        channels
+            .to_database
+            .send(database::ChannelData::DailySummary(second))
+            .unwrap();
+
+        let pool = database.connection().await?;
+
+        let mut retries = 0;
+        loop {
+            let rows = sqlx::query("SELECT day, pv FROM daily_summary")
+                .fetch_all(&pool)
+                .await?;
+            if rows.len() == 1 && rows[0].get::<f64, _>("pv") == 20.0 {
+                assert_eq!(rows[0].get::<String, _>("day"), first.date.to_string());
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            retries += 1;
+            if retries > 500 {
+                panic!("daily summary not replaced");
+            }
+        }
+
+        database.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(database.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn sqlite_stores_battery_health_and_reads_the_baseline() {
+    common_setup();
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}/eg4.db?mode=rwc", dir.path().display());
+
+    let config = config::Database {
+        enabled: true,
+        url,
+        batch_size: 1,
+        flush_interval: 10,
+        retention_days: None,
+        rollups: false,
+        maintenance_interval: 300,
+    };
+    let channels = Channels::new();
+    let shared_stats = Arc::new(Mutex::new(PacketStats::default()));
+    let database = Database::new(config, channels.clone(), shared_stats.clone());
+
+    let tf = async {
+        let datalog = Serial::from_str("2222222222").unwrap();
+        let first = eg4_bridge::battery_health::Health {
+            datalog,
+            date: chrono::NaiveDate::from_ymd_opt(2026, 10, 1).unwrap(),
+            soh: 100,
+            cycle_count: 150,
+            soh_per_100_cycles: None,
+            max_cell_spread: Some(0.02),
+            max_cell_temp_spread: Some(1.5),
+            charge: 10.0,
+            discharge: 9.1,
+            efficiency: Some(91.0),
+            lifetime_efficiency: Some(92.3),
+            usable_capacity: None,
+        };
+        let second = eg4_bridge::battery_health::Health {
+            date: chrono::NaiveDate::from_ymd_opt(2026, 10, 2).unwrap(),
+            soh: 99,
+            cycle_count: 151,
+            usable_capacity: Some(13.8),
+            ..first.clone()
+        };
+
+        let mut retries = 0;
+        while channels
+            .to_database
+            .send(database::ChannelData::BatteryHealth(second.clone()))
+            .is_err()
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            retries += 1;
+            if retries > 50 {
+                panic!("database not ready for messages");
+            }
+        }
+        for health in [first.clone(), second] {
+            channels
+                .to_database
+                .send(database::ChannelData::BatteryHealth(health))
+                .unwrap();
+        }
+
+        let pool = database.connection().await?;
+
+        let mut retries = 0;
+        loop {
+            let rows = sqlx::query("SELECT day, usable_capacity FROM battery_health ORDER BY day")
+                .fetch_all(&pool)
+                .await?;
+            if rows.len() == 2 {
+                assert_eq!(rows[0].get::<Option<f64>, _>("usable_capacity"), None);
+                assert_eq!(rows[1].get::<Option<f64>, _>("usable_capacity"), Some(13.8));
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            retries += 1;
+            if retries > 500 {
+                panic!("battery health not stored");
+            }
+        }
+
+        // the trend is measured from the earliest day
+        assert_eq!(database.battery_baseline(datalog).await?, Some((100, 150)));
+        assert_eq!(database.battery_baseline(Serial::from_str("3333333333").unwrap()).await?, None);
+
+        database.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(database.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn sqlite_keeps_one_tariff_row_per_period() {
+    common_setup();
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}/eg4.db?mode=rwc", dir.path().display());
+
+    let config = config::Database {
+        enabled: true,
+        url,
+        batch_size: 1,
+        flush_interval: 10,
+        retention_days: None,
+        rollups: false,
+        maintenance_interval: 300,
+    };
+    let channels = Channels::new();
+    let shared_stats = Arc::new(Mutex::new(PacketStats::default()));
+    let database = Database::new(config, channels.clone(), shared_stats.clone());
+
+    let tf = async {
+        let tariff: config::Tariff = serde_yaml::from_str("{enabled: true, rates: [{import: 0.25}]}")?;
+        let mut accounts = eg4_bridge::tariff::Accounts::new(tariff);
+        let mut ria = Factory::read_input_all();
+        let (day, month, _) = accounts.update(&ria);
+        ria.e_to_user_day += 4.0;
+        let (later_day, _, _) = accounts.update(&ria);
+
+        let mut retries = 0;
+        while channels
+            .to_database
+            .send(database::ChannelData::TariffCosts(day.clone()))
+            .is_err()
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            retries += 1;
+            if retries > 50 {
+                panic!("database not ready for messages");
+            }
+        }
+        for costs in [month, later_day] {
+            channels
+                .to_database
+                .send(database::ChannelData::TariffCosts(costs))
+                .unwrap();
+        }
+
+        let pool = database.connection().await?;
+
+        let mut retries = 0;
+        loop {
+            let rows = sqlx::query("SELECT period, import_cost FROM tariff_costs WHERE period = 'day'")
+                .fetch_all(&pool)
+                .await?;
+            if rows.len() == 1 && rows[0].get::<f64, _>("import_cost") == 1.0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            retries += 1;
+            if retries > 500 {
+                panic!("tariff row not replaced");
+            }
+        }
+        let periods: i64 = sqlx::query("SELECT COUNT(*) AS n FROM tariff_costs")
+            .fetch_one(&pool)
+            .await?
+            .get("n");
+        assert_eq!(periods, 2);
+
+        // read back to carry on after a restart
+        let period = eg4_bridge::tariff::Period::Day;
+        let stored = database
+            .tariff_costs(ria.datalog, period, &period.start_of(&ria))
+            .await?
+            .expect("stored day totals");
+        assert_eq!(stored.import_cost, 1.0);
+        assert!(database
+            .tariff_costs(ria.datalog, period, "2000-01-01")
+            .await?
+            .is_none());
+
+        database.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(database.start(), tf).unwrap();
+}
+
+#[tokio::test]
+async fn sqlite_stores_audited_writes() {
+    common_setup();
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("sqlite://{}/eg4.db?mode=rwc", dir.path().display());
+
+    let config = config::Database {
+        enabled: true,
+        url,
+        batch_size: 1,
+        flush_interval: 10,
+        retention_days: None,
+        rollups: false,
+        maintenance_interval: 300,
+    };
+    let channels = Channels::new();
+    let shared_stats = Arc::new(Mutex::new(PacketStats::default()));
+    let database = Database::new(config, channels.clone(), shared_stats.clone());
+
+    let tf = async {
+        let packet = Packet::TranslatedData(Factory::translated_data_with_values(vec![5, 0]));
+        let frame = eg4_bridge::coordinator::commands::dry_run::Frame::new(&packet, Some(4))?;
+        let source = eg4_bridge::command::Source::new(eg4_bridge::command::Origin::Mqtt, "cmd/2222222222/set/hold/0");
+        let record = eg4_bridge::audit::Record::new(frame, Some(source), "ok");
+
+        let mut retries = 0;
+        while channels
+            .to_database
+            .send(database::ChannelData::Write(Box::new(record.clone())))
+            .is_err()
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            retries += 1;
+            if retries > 50 {
+                panic!("database not ready for messages");
+            }
+        }
+
+        let pool = database.connection().await?;
+
+        let mut retries = 0;
+        let row = loop {
+            let rows = sqlx::query("SELECT old_value, new_value, origin, source, result FROM writes")
+                .fetch_all(&pool)
+                .await?;
+            if let Some(row) = rows.into_iter().next() {
+                break row;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            retries += 1;
+            if retries > 500 {
+                panic!("write not stored");
+            }
+        };
+        assert_eq!(row.get::<i64, _>("old_value"), 4);
+        assert_eq!(row.get::<i64, _>("new_value"), 5);
+        assert_eq!(row.get::<String, _>("origin"), "mqtt");
+        assert_eq!(row.get::<String, _>("source"), "cmd/2222222222/set/hold/0");
+        assert_eq!(row.get::<String, _>("result"), "ok");
+
+        database.stop();
+
+        Ok::<(), anyhow::Error>(())
+    };
+
+    futures::try_join!(database.start(), tf).unwrap();
+}
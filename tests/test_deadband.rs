@@ -0,0 +1,103 @@
+mod common;
+use common::*;
+
+use eg4_bridge::config::{Deadband, MqttDeadband};
+use eg4_bridge::deadband::Filter;
+use eg4_bridge::prelude::*;
+use eg4_bridge::register::RegisterParser;
+
+use std::time::{Duration, Instant};
+
+fn message(topic: &str, payload: &str) -> mqtt::Message {
+    mqtt::Message {
+        topic: topic.to_owned(),
+        retain: false,
+        payload: payload.to_owned(),
+    }
+}
+
+fn deadband() -> MqttDeadband {
+    MqttDeadband {
+        enabled: true,
+        max_age: 60,
+        power: Deadband { absolute: 50.0, percent: 0.0 },
+        voltage: Deadband { absolute: 1.0, percent: 0.0 },
+        energy: Deadband { absolute: 0.0, percent: 1.0 },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn deadband_thresholds() {
+    let band = Deadband { absolute: 5.0, percent: 10.0 };
+    // 10% of 100 beats the absolute 5
+    assert!(!band.exceeded(100.0, 109.0));
+    assert!(band.exceeded(100.0, 111.0));
+    // near zero the absolute band applies
+    assert!(!band.exceeded(1.0, 5.0));
+
+    let any = Deadband::default();
+    assert!(!any.exceeded(3.0, 3.0));
+    assert!(any.exceeded(3.0, 3.1));
+}
+
+#[test]
+fn single_registers_use_register_units() {
+    common_setup();
+
+    let registers = RegisterParser::new("doc/eg4_registers.json").unwrap();
+    let mut filter = Filter::new(deadband(), Some(&registers));
+    let t0 = Instant::now();
+    let mut publish = |topic: &str, payload: &str, secs: u64| {
+        filter.should_publish(&message(topic, payload), t0 + Duration::from_secs(secs))
+    };
+
+    // input 7 is a power register in W
+    assert!(publish("2222222222/input/7", "1000", 0));
+    assert!(!publish("2222222222/input/7", "1040", 1));
+    assert!(publish("2222222222/input/7", "1051", 2));
+    // input 1 is a voltage in 0.1V steps, so 5 raw is 0.5V
+    assert!(publish("2222222222/input/1", "2400", 0));
+    assert!(!publish("2222222222/input/1", "2405", 1));
+    assert!(publish("2222222222/input/1", "2411", 2));
+    // an unchanged value goes out again once max_age has passed
+    assert!(!publish("2222222222/input/7", "1051", 61));
+    assert!(publish("2222222222/input/7", "1051", 62));
+    // topics outside the register classes are never held back
+    assert!(publish("2222222222/summary/daily", "{}", 3));
+    assert!(publish("2222222222/summary/daily", "{}", 4));
+}
+
+#[test]
+fn json_topics_compare_fields() {
+    let mut filter = Filter::new(deadband(), None);
+    let t0 = Instant::now();
+    let mut publish = |payload: serde_json::Value, secs: u64| {
+        filter.should_publish(
+            &message("2222222222/inputs/all", &payload.to_string()),
+            t0 + Duration::from_secs(secs),
+        )
+    };
+
+    let inputs = |time: u64, p_pv: u64, e_pv_all: f64, status: u64| {
+        serde_json::json!({
+            "time": time, "runtime": time, "p_pv": p_pv, "e_pv_all": e_pv_all, "status": status
+        })
+    };
+
+    assert!(publish(inputs(0, 1000, 500.0, 16), 0));
+    // only the timestamp and small moves
+    assert!(!publish(inputs(10, 1020, 504.0, 16), 10));
+    // energy moved 1%
+    assert!(publish(inputs(20, 1020, 506.0, 16), 20));
+    // status changes always count
+    assert!(publish(inputs(30, 1020, 506.0, 32), 30));
+}
+
+#[test]
+fn disabled_publishes_everything() {
+    let mut filter = Filter::new(MqttDeadband::default(), None);
+    let now = Instant::now();
+    assert!(filter.should_publish(&message("2222222222/hold/21", "1"), now));
+    assert!(filter.should_publish(&message("2222222222/hold/21", "1"), now));
+}
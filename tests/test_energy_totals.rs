@@ -0,0 +1,78 @@
+mod common;
+use common::*;
+
+use eg4_bridge::energy_totals::Sanitizer;
+use eg4_bridge::eg4::packet::ReadInputAll;
+
+fn inputs_at(minutes: i64, grid_import: f64) -> ReadInputAll {
+    let mut ria =
+        Factory::read_input_all_at(chrono::DateTime::from_timestamp(1_760_000_000 + minutes * 60, 0).unwrap());
+    ria.e_to_user_all = grid_import;
+    ria.e_pv_all = 1500.0;
+    ria
+}
+
+#[test]
+fn sanitizer_carries_last_good_value_over_glitches() {
+    common_setup();
+
+    let mut sanitizer = Sanitizer::default();
+    assert_eq!(sanitizer.update(&inputs_at(0, 1000.0))["grid_import"], 1000.0);
+    assert_eq!(sanitizer.update(&inputs_at(1, 1000.1))["e_to_user_all"], 1000.1);
+
+    // dongle reconnect reads zero, then a wild spike
+    assert_eq!(sanitizer.update(&inputs_at(2, 0.0))["e_to_user_all"], 1000.1);
+    assert_eq!(sanitizer.update(&inputs_at(3, 65535.0))["e_to_user_all"], 1000.1);
+
+    // normal growth resumes
+    let totals = sanitizer.update(&inputs_at(4, 1000.2));
+    assert_eq!(totals["e_to_user_all"], 1000.2);
+    assert_eq!(totals["grid_import"], 1000.2);
+    assert_eq!(totals["solar"], 1500.0);
+}
+
+#[test]
+fn sanitizer_accepts_a_rise_that_persists() {
+    common_setup();
+
+    let mut sanitizer = Sanitizer::default();
+    sanitizer.update(&inputs_at(0, 1000.0));
+
+    // counters read again after a long outage the bridge didn't see
+    for minute in 1..5 {
+        assert_eq!(sanitizer.update(&inputs_at(minute, 2000.0))["e_to_user_all"], 1000.0);
+    }
+    assert_eq!(sanitizer.update(&inputs_at(5, 2000.0))["e_to_user_all"], 2000.0);
+    assert_eq!(sanitizer.update(&inputs_at(6, 2000.1))["e_to_user_all"], 2000.1);
+}
+
+#[test]
+fn sanitizer_holds_a_zero_glitch_over_many_readings() {
+    common_setup();
+
+    let mut sanitizer = Sanitizer::default();
+    sanitizer.update(&inputs_at(0, 1000.0));
+
+    for minute in 1..=10 {
+        assert_eq!(sanitizer.update(&inputs_at(minute, 0.0))["e_to_user_all"], 1000.0);
+    }
+    assert_eq!(sanitizer.update(&inputs_at(11, 1000.1))["e_to_user_all"], 1000.1);
+
+    // a later glitch is timed from its own start
+    assert_eq!(sanitizer.update(&inputs_at(12 * 60, 0.0))["e_to_user_all"], 1000.1);
+    assert_eq!(sanitizer.update(&inputs_at(24 * 60 + 30, 0.0))["e_to_user_all"], 1000.1);
+}
+
+#[test]
+fn sanitizer_accepts_a_drop_that_lasts_a_day() {
+    common_setup();
+
+    let mut sanitizer = Sanitizer::default();
+    sanitizer.update(&inputs_at(0, 1000.0));
+
+    // the counter really was reset, e.g. a replaced inverter
+    assert_eq!(sanitizer.update(&inputs_at(1, 2.0))["e_to_user_all"], 1000.0);
+    assert_eq!(sanitizer.update(&inputs_at(24 * 60, 2.0))["e_to_user_all"], 1000.0);
+    assert_eq!(sanitizer.update(&inputs_at(24 * 60 + 1, 2.0))["e_to_user_all"], 2.0);
+    assert_eq!(sanitizer.update(&inputs_at(24 * 60 + 2, 2.1))["e_to_user_all"], 2.1);
+}
@@ -0,0 +1,119 @@
+mod common;
+use common::*;
+
+use eg4_bridge::controllers::export_limit::{Action, Limiter};
+use eg4_bridge::eg4::packet::ReadInputAll;
+use eg4_bridge::register::RegisterParser;
+
+use std::io::Write;
+
+use std::time::{Duration, Instant};
+
+fn inputs(p_to_grid: u16, p_to_user: u16, p_inv: u16) -> ReadInputAll {
+    let mut ria = Factory::read_input_all();
+    ria.p_to_grid = p_to_grid;
+    ria.p_to_user = p_to_user;
+    ria.p_inv = p_inv;
+    ria.p_charge = 0;
+    ria
+}
+
+#[test]
+fn power_limit_follows_export_and_restores_original() {
+    common_setup();
+
+    let registers = RegisterParser::new("doc/eg4_registers.json").unwrap();
+    let mut limiter = Limiter::new(
+        Factory::yaml("enabled: true\nmax_export: 1000\nmargin: 100\nregister: 3\nmin_write_interval: 60"),
+        Some(&registers),
+    );
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    // under the ceiling nothing happens
+    assert_eq!(limiter.update(&inputs(800, 0, 3000), at(0)), None);
+
+    // over it, with the setting unknown, the register is read first
+    let datalog = Factory::read_input_all().datalog;
+    assert_eq!(limiter.update(&inputs(1500, 0, 3000), at(1)), Some(Action::Read(datalog)));
+    limiter.observe(datalog, 8000);
+
+    // writes are rate limited; then the limit aims for 900W of export
+    assert_eq!(limiter.update(&inputs(1500, 0, 3000), at(30)), None);
+    assert_eq!(limiter.update(&inputs(1500, 0, 3000), at(61)), Some(Action::Write(datalog, 2400)));
+    assert!(limiter.status(datalog).unwrap().limiting);
+
+    // our own write echoed back is not taken as the original
+    limiter.observe(datalog, 2400);
+    assert_eq!(limiter.status(datalog).unwrap().original, Some(8000));
+
+    // changes smaller than min_step are not written
+    assert_eq!(limiter.update(&inputs(950, 0, 2450), at(200)), None);
+
+    // once the load takes everything the original setting comes back
+    assert_eq!(limiter.update(&inputs(0, 6000, 2400), at(300)), Some(Action::Write(datalog, 8000)));
+    assert!(!limiter.status(datalog).unwrap().limiting);
+}
+
+#[test]
+fn charge_rate_method_raises_rate_within_bounds() {
+    common_setup();
+
+    let mut limiter = Limiter::new(
+        Factory::yaml("enabled: true\nmax_export: 0\nmargin: 0\nmethod: charge_rate\nmax_charge_power: 4000\nmin_write_interval: 0"),
+        None,
+    );
+    let datalog = Factory::read_input_all().datalog;
+    limiter.observe(datalog, 20);
+
+    // 1000W export soaked up by 1000W more charging, 25% of 4000W
+    assert_eq!(limiter.update(&inputs(1000, 0, 3000), Instant::now()), Some(Action::Write(datalog, 25)));
+    // never beyond 100%
+    assert_eq!(limiter.update(&inputs(9000, 0, 3000), Instant::now()), Some(Action::Write(datalog, 100)));
+}
+
+#[test]
+fn fails_safe_on_data_loss_and_shutdown() {
+    common_setup();
+
+    let mut limiter = Limiter::new(
+        Factory::yaml("enabled: true\nmax_export: 0\nregister: 3\nmin_write_interval: 0\ndata_timeout: 30"),
+        None,
+    );
+    let datalog = Factory::read_input_all().datalog;
+    let start = Instant::now();
+    limiter.observe(datalog, 5000);
+    assert_eq!(limiter.update(&inputs(500, 0, 3000), start), Some(Action::Write(datalog, 2400)));
+
+    assert!(limiter.expire(start + Duration::from_secs(10)).is_empty());
+    assert_eq!(limiter.expire(start + Duration::from_secs(30)), vec![Action::Write(datalog, 5000)]);
+    assert!(limiter.restore_all(start + Duration::from_secs(31)).is_empty());
+
+    assert!(limiter.update(&inputs(500, 0, 3000), start + Duration::from_secs(40)).is_some());
+    assert_eq!(limiter.restore_all(start + Duration::from_secs(41)), vec![Action::Write(datalog, 5000)]);
+}
+
+#[test]
+fn power_limit_register_is_scaled_by_its_unit() {
+    common_setup();
+
+    // a limit in tenths of a percent of the rated output
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    write!(
+        file,
+        r#"{{"registers": [{{"register_type": "hold", "register_map": [{{
+            "register_number": 103, "name": "Active Power Percent", "datatype": "uint16",
+            "unit": "%", "unit_scale": 0.1, "description": "", "read_only": false}}]}}]}}"#
+    )
+    .unwrap();
+    let registers = RegisterParser::new(&file.path().to_string_lossy()).unwrap();
+    let mut limiter = Limiter::new(
+        Factory::yaml("enabled: true\nmax_export: 0\nmargin: 0\nregister: 103\nrated_power: 8000\nmin_write_interval: 0"),
+        Some(&registers),
+    );
+    let datalog = Factory::read_input_all().datalog;
+    limiter.observe(datalog, 1000);
+
+    // 3000W out, 1000W of it exported: 2000W is 25.0% of 8000W
+    assert_eq!(limiter.update(&inputs(1000, 0, 3000), Instant::now()), Some(Action::Write(datalog, 250)));
+}
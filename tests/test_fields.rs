@@ -0,0 +1,67 @@
+mod common;
+use common::*;
+
+use eg4_bridge::fields;
+use eg4_bridge::prelude::*;
+use eg4_bridge::register::RegisterParser;
+
+#[test]
+fn input_fields_get_their_own_topics() {
+    common_setup();
+
+    let mut input = Factory::read_input_all();
+    input.soc = 87;
+    input.v_bat = Some(52.3);
+    input.v_pv_3 = None;
+    let messages = fields::for_inputs(&input).unwrap();
+
+    let find = |field: &str| {
+        messages
+            .iter()
+            .find(|m| m.topic == format!("{}/inputs/{}", input.datalog, field))
+            .map(|m| m.payload.as_str())
+    };
+    assert_eq!(find("soc"), Some("87"));
+    assert_eq!(find("v_bat"), Some("52.3"));
+    assert_eq!(find("v_pv_3"), None);
+    assert_eq!(find("time"), None);
+    assert_eq!(find("datalog"), None);
+    assert!(messages.iter().all(|m| !m.retain));
+}
+
+#[test]
+fn named_holds_are_scaled() {
+    let registers = RegisterParser::new("doc/eg4_registers.json").unwrap();
+    let td = eg4::packet::TranslatedData {
+        device_function: eg4::packet::DeviceFunction::ReadHold,
+        register: 1,
+        // hold 1 (output voltage setpoint, 0.1V) = 2405, hold 2 = 16
+        values: vec![0x65, 0x09, 16, 0],
+        ..Factory::translated_data()
+    };
+
+    let messages = fields::for_holds(&td, &registers);
+    assert_eq!(
+        messages[0],
+        mqtt::Message {
+            topic: "2222222222/hold/output_voltage_setpoint".to_owned(),
+            retain: true,
+            payload: "240.5".to_owned(),
+        }
+    );
+    assert_eq!(messages.len(), 2);
+}
+
+#[test]
+fn units_for_field_topics() {
+    let registers = RegisterParser::new("doc/eg4_registers.json").unwrap();
+    let units = fields::Units::new(Some(&registers));
+
+    assert_eq!(units.for_topic("2222222222/inputs/soc"), Some("%"));
+    assert_eq!(units.for_topic("2222222222/inputs/p_pv"), Some("W"));
+    assert_eq!(units.for_topic("2222222222/inputs/e_pv_all"), Some("kWh"));
+    assert_eq!(units.for_topic("2222222222/hold/output_voltage_setpoint"), Some("V"));
+    assert_eq!(units.for_topic("2222222222/inputs/status"), None);
+    assert_eq!(units.for_topic("2222222222/inputs/all"), None);
+    assert_eq!(units.for_topic("2222222222/hold/21"), None);
+}
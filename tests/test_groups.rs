@@ -0,0 +1,168 @@
+mod common;
+use common::*;
+
+use eg4_bridge::eg4::packet::ReadInputAll;
+use eg4_bridge::energy_totals::Sanitizer;
+use eg4_bridge::groups::{self, Tracker};
+use eg4_bridge::prelude::*;
+
+use std::time::{Duration, Instant};
+
+fn group() -> config::Group {
+    Factory::yaml("{name: house, members: ['2222222222', '3333333333']}")
+}
+
+fn inputs(datalog: &str, soc: i8, p_pv: u16, p_grid: i32, e_pv_day: f64, v_ac_r: f64) -> ReadInputAll {
+    let mut ria = Factory::read_input_all();
+    ria.datalog = Serial::from_str(datalog).unwrap();
+    ria.soc = soc;
+    ria.p_pv = p_pv;
+    ria.p_grid = p_grid;
+    ria.e_pv_day = e_pv_day;
+    ria.v_ac_r = v_ac_r;
+    ria.v_bat = Some(52.0);
+    ria.fault_code = 0;
+    ria
+}
+
+#[test]
+fn aggregates_members_into_one_inverter() {
+    common_setup();
+
+    let mut first = inputs("2222222222", 60, 4000, -500, 12.3, 240.2);
+    let mut second = inputs("3333333333", 55, 3500, 200, 10.1, 239.8);
+    second.v_bat = None;
+    second.fault_code = 1 << 4;
+    first.time = UnixTime(chrono::DateTime::from_timestamp(1_760_000_000, 0).unwrap());
+    second.time = UnixTime(chrono::DateTime::from_timestamp(1_760_000_030, 0).unwrap());
+
+    let total = groups::aggregate(&[first.clone(), second.clone()]).unwrap();
+    assert_eq!(total.p_pv, 7500);
+    assert_eq!(total.p_grid, -300);
+    assert_eq!(total.e_pv_day, 22.4);
+    assert_eq!(total.v_ac_r, 240.0);
+    // only members reporting a value are averaged
+    assert_eq!(total.v_bat, Some(52.0));
+    assert_eq!(total.soc, 55);
+    assert_eq!(total.fault_code, 1 << 4);
+    assert_eq!(total.time.0, second.time.0);
+    // settings come from the first member
+    assert_eq!(total.datalog, first.datalog);
+
+    // powers saturate rather than overflow
+    let mut big = first.clone();
+    big.p_pv = 40000;
+    assert_eq!(groups::aggregate(&[big.clone(), big]).unwrap().p_pv, u16::MAX);
+
+    assert_eq!(groups::aggregate(&[]), None);
+}
+
+#[test]
+fn publishes_once_every_member_has_reported() {
+    common_setup();
+
+    let now = Instant::now();
+    let mut tracker = Tracker::new(vec![group()], now);
+    let first = inputs("2222222222", 60, 4000, 0, 1.0, 240.0);
+    let second = inputs("3333333333", 50, 1000, 0, 1.0, 240.0);
+
+    assert!(tracker.update(&first, now).is_empty());
+    // a second reading from the same member still waits for the other
+    assert!(tracker.update(&first, now).is_empty());
+    let r = tracker.update(&second, now);
+    assert_eq!(r.len(), 1);
+    assert_eq!(r[0].0.name(), "house");
+    assert_eq!(r[0].1.p_pv, 5000);
+    assert!(r[0].2.is_empty());
+
+    // the next round starts afresh
+    assert!(tracker.update(&second, now).is_empty());
+    assert_eq!(tracker.update(&first, now)[0].1.soc, 50);
+
+    // inverters outside the group are ignored
+    assert!(tracker.update(&inputs("4444444444", 10, 0, 0, 0.0, 0.0), now).is_empty());
+}
+
+#[test]
+fn publishes_without_members_that_stopped_reporting() {
+    common_setup();
+
+    let start = Instant::now();
+    let mut tracker = Tracker::new(vec![group()], start);
+    let first = inputs("2222222222", 60, 4000, 0, 1.0, 240.0);
+    let second = inputs("3333333333", 50, 1000, 0, 1.0, 240.0);
+
+    // a member not heard from since startup is waited for, until it goes stale
+    assert!(tracker.update(&first, start + Duration::from_secs(60)).is_empty());
+    let r = tracker.update(&first, start + Duration::from_secs(301));
+    assert_eq!(r[0].1.p_pv, 4000);
+    assert_eq!(r[0].2, vec![second.datalog]);
+
+    // it is back in as soon as it reports again
+    let late = start + Duration::from_secs(400);
+    assert!(tracker.update(&second, late).is_empty());
+    let r = tracker.update(&first, late);
+    assert_eq!(r[0].1.p_pv, 5000);
+    assert!(r[0].2.is_empty());
+
+    // and dropped again once silent for longer than stale_after
+    let r = tracker.update(&first, late + Duration::from_secs(301));
+    assert_eq!((r[0].1.p_pv, r[0].2.clone()), (4000, vec![second.datalog]));
+}
+
+#[test]
+fn lifetime_totals_keep_members_that_stopped_reporting() {
+    common_setup();
+
+    let start = Instant::now();
+    let mut tracker = Tracker::new(vec![group()], start);
+    let mut sanitizer = Sanitizer::default();
+    let mut first = inputs("2222222222", 60, 4000, 0, 1.0, 240.0);
+    let mut second = inputs("3333333333", 50, 1000, 0, 1.0, 240.0);
+    first.e_pv_all = 1000.0;
+    second.e_pv_all = 500.0;
+
+    tracker.update(&first, start);
+    let r = tracker.update(&second, start);
+    assert_eq!(r[0].1.e_pv_all, 1500.0);
+    assert_eq!(sanitizer.update_as("house", &r[0].1)["solar"], 1500.0);
+
+    // the second member drops out; its last lifetime counters stay in the sum
+    first.e_pv_all = 1000.5;
+    let r = tracker.update(&first, start + Duration::from_secs(301));
+    assert_eq!(r[0].2, vec![second.datalog]);
+    assert_eq!(r[0].1.p_pv, 4000);
+    assert_eq!(r[0].1.e_pv_all, 1500.5);
+    assert_eq!(sanitizer.update_as("house", &r[0].1)["solar"], 1500.5);
+
+    // and it comes back with what it made meanwhile
+    let back = start + Duration::from_secs(400);
+    second.e_pv_all = 500.2;
+    tracker.update(&second, back);
+    let r = tracker.update(&first, back);
+    assert!(r[0].2.is_empty());
+    assert_eq!(r[0].1.e_pv_all, 1500.7);
+    assert_eq!(sanitizer.update_as("house", &r[0].1)["solar"], 1500.7);
+}
+
+#[test]
+fn message_is_published_under_the_group_name() {
+    common_setup();
+
+    let total = groups::aggregate(&[inputs("2222222222", 60, 4000, 0, 1.0, 240.0)]).unwrap();
+    let missing = vec![Serial::from_str("3333333333").unwrap()];
+    let message = groups::mqtt_message(&group(), &total, &missing).unwrap();
+    assert_eq!(message.topic, "house/inputs/all");
+    assert!(!message.retain);
+
+    let json: serde_json::Value = serde_json::from_str(&message.payload).unwrap();
+    assert_eq!(json["datalog"], "house");
+    assert_eq!(
+        json["members"],
+        serde_json::json!([
+            {"datalog": "2222222222", "missing": false},
+            {"datalog": "3333333333", "missing": true},
+        ])
+    );
+    assert_eq!(json["p_pv"], 4000);
+}
@@ -4,3 +4,242 @@
 #[tokio::test]
 #[ignore = "refresh golden MQTT discovery JSON against current home_assistant::Config"]
 async fn discovery_payloads_placeholder() {}
+
+mod common;
+use common::*;
+
+use eg4_bridge::home_assistant;
+use eg4_bridge::prelude::*;
+
+const REGISTER_MAP: &str = r#"{
+  "registers": [
+    {
+      "register_type": "hold",
+      "register_map": [
+        {"register_number": 21, "name": "Function Enable", "shortname": "function_enable", "datatype": "uint16",
+         "description": "", "display_as": "flags", "read_only": false,
+         "flags": [{"bit": 7, "name": "ac_charge_en", "description": "AC Charge Enable"}]},
+        {"register_number": 99, "name": "Charge Voltage", "shortname": "charge_voltage", "datatype": "uint16",
+         "description": "", "unit": "V", "unit_scale": 0.1, "min": 50.0, "max": 59.0, "read_only": false},
+        {"register_number": 120, "name": "Work Mode", "shortname": "work_mode", "datatype": "uint16",
+         "description": "", "display_as": "enum", "read_only": false,
+         "enum_values": [{"value": 0, "name": "self_use"}, {"value": 1, "name": "backup"}]}
+      ]
+    },
+    {
+      "register_type": "input",
+      "register_map": [
+        {"register_number": 5, "name": "Battery Status", "shortname": "battery_status", "datatype": "uint16",
+         "description": "", "display_as": "fields", "read_only": true,
+         "fields": [{"name": "soc", "description": "State of Charge", "unit": "%", "byte": 0}]},
+        {"register_number": 10, "name": "Battery Power", "shortname": "battery_power", "datatype": "int16",
+         "description": "", "unit": "W", "read_only": true},
+        {"register_number": 40, "name": "PV Energy Total", "shortname": "e_pv_all", "datatype": "uint32",
+         "description": "", "unit": "kWh", "read_only": true}
+      ]
+    }
+  ]
+}"#;
+
+fn discovery(publish_individual_input: bool) -> Vec<mqtt::Message> {
+    let mut file = tempfile::NamedTempFile::new().unwrap();
+    file.write_all(REGISTER_MAP.as_bytes()).unwrap();
+
+    let mut c = Factory::example_config();
+    c.homeassistant_enabled = true;
+    c.mqtt.publish_individual_input = Some(publish_individual_input);
+    c.register_file = Some(file.path().to_string_lossy().to_string());
+    let config = ConfigWrapper::from_config(c);
+    let inverter = config.inverters()[0].clone();
+
+    home_assistant::Config::new(&inverter, &config.mqtt(), &config)
+        .registers()
+        .unwrap()
+}
+
+fn payload(messages: &[mqtt::Message], topic_suffix: &str) -> serde_json::Value {
+    let message = messages
+        .iter()
+        .find(|m| m.topic.ends_with(topic_suffix))
+        .unwrap_or_else(|| panic!("no discovery topic ending {}", topic_suffix));
+    serde_json::from_str(&message.payload).unwrap()
+}
+
+#[test]
+fn generates_hold_entities_from_register_map() {
+    common_setup();
+
+    let messages = discovery(false);
+    // inputs are only published per register with publish_individual_input
+    assert_eq!(messages.len(), 3);
+
+    let number = payload(&messages, "/hold_charge_voltage/config");
+    assert!(messages[1].topic.starts_with("homeassistant/number/"));
+    assert_eq!(number["min"], 50.0);
+    assert_eq!(number["max"], 59.0);
+    assert_eq!(number["step"], 0.1);
+    assert_eq!(number["unit_of_measurement"], "V");
+    assert_eq!(number["device_class"], "voltage");
+    assert_eq!(number["entity_category"], "config");
+    assert!(number["command_topic"].as_str().unwrap().ends_with("/set/hold/99"));
+    assert_eq!(number["command_template"], "{{ (value / 0.1) | round | int }}");
+
+    let select = payload(&messages, "/hold_work_mode/config");
+    assert!(messages[2].topic.starts_with("homeassistant/select/"));
+    assert_eq!(select["options"], serde_json::json!(["self_use", "backup"]));
+    assert_eq!(select["command_template"], "{{ {'self_use': 0, 'backup': 1}[value] }}");
+
+    let bit = payload(&messages, "/hold_function_enable_ac_charge_en/config");
+    assert!(messages[0].topic.starts_with("homeassistant/binary_sensor/"));
+    assert!(bit["value_template"].as_str().unwrap().contains("bitwise_and(128)"));
+    assert!(bit.get("command_topic").is_none());
+}
+
+#[test]
+fn generates_input_sensors_from_register_map() {
+    common_setup();
+
+    let messages = discovery(true);
+    // 32-bit e_pv_all has no single-register topic and is skipped
+    assert_eq!(messages.len(), 5);
+    assert!(!messages.iter().any(|m| m.topic.contains("e_pv_all")));
+
+    let soc = payload(&messages, "/input_battery_status_soc/config");
+    assert!(soc["state_topic"].as_str().unwrap().ends_with("/input/5"));
+    assert!(soc["value_template"].as_str().unwrap().ends_with("{{ v % 256 }}"));
+
+    let power = payload(&messages, "/input_battery_power/config");
+    assert_eq!(power["device_class"], "power");
+    assert_eq!(power["state_class"], "measurement");
+    assert!(power["value_template"].as_str().unwrap().contains("v - 65536"));
+    assert!(power.get("entity_category").is_none());
+}
+
+#[test]
+fn only_discovery_we_published_is_stale() {
+    use home_assistant::Published;
+
+    let record = Published::message("lxp", &["2222222222".to_string(), "3333333333".to_string()]).unwrap();
+    assert_eq!(record.topic, "lxp/discovery");
+    assert!(record.retain);
+
+    let mut published = Published::default();
+    published.read(record.payload.as_bytes());
+    published.read(b"");
+    let running = vec!["2222222222".to_string()];
+    let config = |datalog: &str| format!("homeassistant/sensor/lxp_{}/soc/config", datalog);
+
+    // ours and no longer running
+    assert!(published.is_stale("homeassistant", &config("3333333333"), &running));
+    // ours and still running
+    assert!(!published.is_stale("homeassistant", &config("2222222222"), &running));
+    // another bridge's on the same broker
+    assert!(!published.is_stale("homeassistant", &config("4444444444"), &running));
+    assert!(!published.is_stale("homeassistant", "homeassistant/status", &running));
+
+    // nothing is stale before a record has been read back
+    assert!(!Published::default().is_stale("homeassistant", &config("3333333333"), &running));
+}
+
+#[test]
+fn discovery_datalog_matches_only_our_config_topics() {
+    use home_assistant::discovery_datalog;
+
+    assert_eq!(
+        discovery_datalog("homeassistant", "homeassistant/sensor/lxp_2222222222/soc/config"),
+        Some("2222222222")
+    );
+    assert_eq!(
+        discovery_datalog("homeassistant", "homeassistant/sensor/other_device/soc/config"),
+        None
+    );
+    assert_eq!(discovery_datalog("homeassistant", "homeassistant/status"), None);
+    assert_eq!(
+        discovery_datalog("ha", "homeassistant/sensor/lxp_2222222222/soc/config"),
+        None
+    );
+}
+
+#[test]
+fn sensors_are_grouped_into_sub_devices() {
+    common_setup();
+
+    let config = ConfigWrapper::from_config(Factory::example_config());
+    let inverter = config.inverters()[0].clone();
+    let datalog = inverter.datalog().unwrap().to_string();
+    let sensors = home_assistant::Config::new(&inverter, &config.mqtt(), &config).sensors();
+    let device = |key: &str| payload(&sensors, &format!("/{}/config", key))["device"].clone();
+
+    let inverter_device = device("p_pv");
+    assert_eq!(inverter_device["identifiers"][0], format!("lxp_{}", datalog));
+    assert!(inverter_device.get("via_device").is_none());
+
+    for (key, suffix) in [
+        ("soc", "battery"),
+        ("cycle_count", "battery"),
+        ("max_cell_temp", "battery"),
+        ("p_gen", "generator"),
+        ("e_gen_day", "generator"),
+        ("v_eps_l1", "eps"),
+    ] {
+        let d = device(key);
+        assert_eq!(d["identifiers"][0], format!("lxp_{}_{}", datalog, suffix), "{}", key);
+        assert_eq!(d["via_device"], format!("lxp_{}", datalog), "{}", key);
+    }
+}
+
+#[test]
+fn lifetime_energy_sensors_read_sanitized_topic() {
+    common_setup();
+
+    let config = ConfigWrapper::from_config(Factory::example_config());
+    let inverter = config.inverters()[0].clone();
+    let datalog = inverter.datalog().unwrap().to_string();
+    let sensors = home_assistant::Config::new(&inverter, &config.mqtt(), &config).sensors();
+
+    let energy_topic = format!("lxp/{}/energy", datalog);
+    for key in ["e_pv_all", "grid_import", "grid_export", "solar", "battery_in", "battery_out"] {
+        let sensor = payload(&sensors, &format!("/{}/config", key));
+        assert_eq!(sensor["state_topic"], energy_topic, "{}", key);
+        assert_eq!(sensor["device_class"], "energy", "{}", key);
+        assert_eq!(sensor["state_class"], "total_increasing", "{}", key);
+        assert_eq!(sensor["value_template"], format!("{{{{ value_json.{} }}}}", key));
+    }
+
+    // daily counters still come straight from inputs/all
+    let day = payload(&sensors, "/e_pv_day/config");
+    assert_eq!(day["state_topic"], format!("lxp/{}/inputs/all", datalog));
+}
+
+#[test]
+fn groups_get_a_device_reading_their_aggregated_inputs() {
+    common_setup();
+
+    let mut c = Factory::example_config();
+    c.homeassistant_enabled = true;
+    let config = ConfigWrapper::from_config(c);
+    let group: config::Group = serde_yaml::from_str("{name: house, members: ['2222222222', '3333333333']}").unwrap();
+    let messages = home_assistant::Config::for_group(&group, &config.mqtt(), &config)
+        .all()
+        .unwrap();
+
+    // sensors only; there is no group hold state for switches or numbers
+    assert!(messages.iter().all(|m| m.topic.starts_with("homeassistant/sensor/lxp_house/")));
+    for message in &messages {
+        let sensor: serde_json::Value = serde_json::from_str(&message.payload).unwrap();
+        let state_topic = sensor["state_topic"].as_str().unwrap();
+        assert!(["lxp/house/inputs/all", "lxp/house/energy"].contains(&state_topic), "{}", message.topic);
+    }
+    assert!(!messages.iter().any(|m| m.topic.contains("/fault_code/")));
+
+    let soc = payload(&messages, "/soc/config");
+    assert_eq!(soc["unique_id"], "lxp_house_soc");
+    assert_eq!(soc["device"]["via_device"], "lxp_house");
+
+    // lifetime sensors read the group's sanitized energy topic
+    for key in ["e_pv_all", "solar", "grid_import"] {
+        let sensor = payload(&messages, &format!("/{}/config", key));
+        assert_eq!(sensor["state_topic"], "lxp/house/energy", "{}", key);
+        assert_eq!(sensor["value_template"], format!("{{{{ value_json.{} }}}}", key));
+    }
+}
@@ -56,3 +56,177 @@ async fn sends_http_request() {
 
     mock.assert();
 }
+
+#[tokio::test]
+async fn sends_v2_write_with_token_and_gzip() {
+    setup_log();
+
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("POST", "/api/v2/write")
+        .match_query(Matcher::AllOf(vec![
+            Matcher::UrlEncoded("org".to_owned(), "home".to_owned()),
+            Matcher::UrlEncoded("bucket".to_owned(), "solar".to_owned()),
+            Matcher::UrlEncoded("precision".to_owned(), "ms".to_owned()),
+        ]))
+        .match_header("authorization", "Token secret-token")
+        .match_header("content-encoding", "gzip")
+        .match_body(Matcher::Any)
+        .with_status(204)
+        .expect(2)
+        .create();
+
+    let mut cfg = config::Config::new("config.yaml.example".to_string()).unwrap();
+    cfg.influx.url = server.url();
+    cfg.influx.api_version = 2;
+    cfg.influx.org = Some("home".to_string());
+    cfg.influx.bucket = Some("solar".to_string());
+    cfg.influx.token = Some("secret-token".to_string());
+    cfg.influx.precision = "ms".to_string();
+    cfg.influx.gzip = true;
+    cfg.register_file = None;
+    let config = ConfigWrapper::from_config(cfg);
+    let channels = Channels::new();
+    let stats = Arc::new(Mutex::new(PacketStats::default()));
+
+    let influx = influx::Influx::new(config, channels.clone(), stats);
+
+    influx.start().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let json = json!({
+        "time": 1000_i64,
+        "serial": "5555555555",
+        "datalog": "BA12345678",
+        "raw_data": { "0": "00fa" }
+    });
+    channels
+        .to_influx
+        .send(ChannelData::InputData(json))
+        .unwrap();
+    channels.to_influx.send(ChannelData::Shutdown).unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    mock.assert();
+}
+
+#[tokio::test]
+async fn batches_points_with_extra_tags() {
+    setup_log();
+
+    let mut server = mockito::Server::new_async().await;
+    // connection test
+    let test_mock = server
+        .mock("POST", "/write")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::Regex("connection_test".to_owned()))
+        .with_status(204)
+        .expect(1)
+        .create();
+    // both messages arrive in a single write, tagged with the site
+    let batch_mock = server
+        .mock("POST", "/write")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::AllOf(vec![
+            Matcher::Regex("site=home".to_owned()),
+            Matcher::Regex("1000\n(.|\n)*2000\n".to_owned()),
+        ]))
+        .with_status(204)
+        .expect(1)
+        .create();
+
+    let mut cfg = config::Config::new("config.yaml.example".to_string()).unwrap();
+    cfg.influx.url = server.url();
+    cfg.influx.batch_size = 2;
+    cfg.influx.flush_interval = 3600;
+    cfg.influx.tags.insert("site".to_string(), "home".to_string());
+    cfg.register_file = None;
+    let config = ConfigWrapper::from_config(cfg);
+    let channels = Channels::new();
+    let stats = Arc::new(Mutex::new(PacketStats::default()));
+
+    let influx = influx::Influx::new(config, channels.clone(), stats.clone());
+
+    influx.start().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    for time in [1000_i64, 2000_i64] {
+        let json = json!({
+            "time": time,
+            "serial": "5555555555",
+            "datalog": "BA12345678",
+            "raw_data": { "0": "00fa" }
+        });
+        channels
+            .to_influx
+            .send(ChannelData::InputData(json))
+            .unwrap();
+    }
+
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    test_mock.assert();
+    batch_mock.assert();
+    assert_eq!(stats.lock().unwrap().influx_writes, 1);
+
+    channels.to_influx.send(ChannelData::Shutdown).unwrap();
+}
+
+#[tokio::test]
+async fn writes_measurement_as_single_point() {
+    setup_log();
+
+    let mut server = mockito::Server::new_async().await;
+    let test_mock = server
+        .mock("POST", "/write")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::Regex("connection_test".to_owned()))
+        .with_status(204)
+        .expect(1)
+        .create();
+    let summary_mock = server
+        .mock("POST", "/write")
+        .match_query(Matcher::Any)
+        .match_body(Matcher::AllOf(vec![
+            Matcher::Regex("^eg4_daily_summary,datalog=BA12345678 ".to_owned()),
+            Matcher::Regex("pv=20".to_owned()),
+            Matcher::Regex(" 86400\n$".to_owned()),
+        ]))
+        .with_status(204)
+        .expect(1)
+        .create();
+
+    let mut cfg = config::Config::new("config.yaml.example".to_string()).unwrap();
+    cfg.influx.url = server.url();
+    cfg.register_file = None;
+    let config = ConfigWrapper::from_config(cfg);
+    let channels = Channels::new();
+    let stats = Arc::new(Mutex::new(PacketStats::default()));
+
+    let influx = influx::Influx::new(config, channels.clone(), stats.clone());
+
+    influx.start().await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    channels
+        .to_influx
+        .send(ChannelData::Measurement(
+            "eg4_daily_summary".to_string(),
+            json!({
+                "time": 86400,
+                "datalog": "BA12345678",
+                "date": "1970-01-02",
+                "pv": 20.0,
+                "self_consumption": null,
+            }),
+        ))
+        .unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    test_mock.assert();
+    summary_mock.assert();
+
+    channels.to_influx.send(ChannelData::Shutdown).unwrap();
+}
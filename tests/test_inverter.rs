@@ -30,6 +30,7 @@ async fn serials_fixed_in_outgoing_packets() {
         register_block_size: None,
         delay_ms: None,
         read_only: None,
+        dry_run: None,
         register_read_interval: None,
     };
     let channels = Channels::new();
@@ -125,6 +126,7 @@ async fn test_replies_to_heartbeats() {
         register_block_size: None,
         delay_ms: None,
         read_only: None,
+        dry_run: None,
         register_read_interval: None,
     };
     let channels = Channels::new();
@@ -0,0 +1,87 @@
+mod common;
+use common::*;
+
+use eg4_bridge::json_command;
+use eg4_bridge::prelude::*;
+use eg4_bridge::register::RegisterParser;
+
+fn message(payload: &str) -> mqtt::Message {
+    mqtt::Message {
+        topic: "cmd/all/json".to_owned(),
+        retain: false,
+        payload: payload.to_owned(),
+    }
+}
+
+#[test]
+fn batch_of_typed_commands() {
+    common_setup();
+
+    let inverter = Factory::example_config().inverters[0].clone();
+    let registers = RegisterParser::new("doc/eg4_registers.json").unwrap();
+    let message = message(
+        r#"[
+            {"command": "read_hold", "register": 64, "count": 3},
+            {"command": "read_input", "register": "pv1_voltage"},
+            {"command": "set_ac_charge", "slot": 1, "start": "01:30", "end": "05:00"},
+            {"command": "ac_charge", "enable": true},
+            {"command": "charge_rate_pct", "value": 80}
+        ]"#,
+    );
+
+    assert!(message.is_json_command());
+    let commands = message.to_commands(inverter, Some(&registers)).unwrap();
+    assert_eq!(commands.len(), 5);
+    assert!(matches!(commands[0], Command::ReadHold(_, 64, 3)));
+    assert!(matches!(commands[1], Command::ReadInput(_, 1, 1)));
+    assert!(matches!(commands[2], Command::SetAcChargeTime(_, 1, [1, 30, 5, 0])));
+    assert!(matches!(commands[3], Command::AcCharge(_, true)));
+    assert!(matches!(commands[4], Command::ChargeRate(_, 80)));
+}
+
+#[test]
+fn rejects_invalid_documents() {
+    let inverter = Factory::example_config().inverters[0].clone();
+    let err = |payload: &str| {
+        message(payload)
+            .to_commands(inverter.clone(), None)
+            .unwrap_err()
+            .to_string()
+    };
+
+    assert!(err(r#"{"command": "ac_charge", "enable": "yes"}"#).contains("invalid type"));
+    assert!(err(r#"{"command": "ac_charge", "enable": true, "extra": 1}"#).contains("unknown field"));
+    assert!(err(r#"{"command": "reboot"}"#).contains("unknown variant"));
+    assert!(err(r#"{"command": "charge_rate_pct", "value": 150}"#).contains("0-100"));
+    assert!(err(r#"{"command": "set_ac_first", "slot": 1, "start": "25:00", "end": "05:00"}"#)
+        .contains("out of range"));
+    assert!(err(r#"[{"command": "read_inputs", "block": 1}, {"command": "read_hold"}]"#)
+        .starts_with("command 1:"));
+    assert!(err(r#"{"command": "set_hold", "register": "pv1_voltage", "value": 1}"#)
+        .contains("no register map"));
+    assert!(err("[]").contains("empty"));
+}
+
+#[test]
+fn schema_lists_every_command() {
+    let schema = json_command::schema();
+    let commands = schema["$defs"]["command"]["oneOf"].as_array().unwrap();
+
+    // every command the schema describes parses with its required arguments filled in
+    for command in commands {
+        let name = command["properties"]["command"]["const"].as_str().unwrap();
+        let mut doc = serde_json::json!({ "command": name });
+        for arg in command["required"].as_array().unwrap() {
+            let arg = arg.as_str().unwrap();
+            doc[arg] = match arg {
+                "command" => continue,
+                "start" | "end" => "01:00".into(),
+                "enable" => true.into(),
+                "token" => "secret".into(),
+                _ => 1.into(),
+            };
+        }
+        json_command::parse(&doc.to_string()).unwrap_or_else(|e| panic!("{}: {}", name, e));
+    }
+    assert_eq!(commands.len(), 23);
+}
@@ -2,6 +2,7 @@
 //! etc.). **No MQTT broker or daemon** is involved; there is no network I/O.
 
 use eg4_bridge::{eg4, mqtt};
+use serde_json::json;
 
 mod common;
 use common::*;
@@ -368,3 +369,86 @@ async fn for_input_ignore_127_254() {
 
     assert_eq!(mqtt::Message::for_input(packet, false).unwrap(), vec![]);
 }
+
+#[test]
+fn state_cache_keeps_latest_retained_message_per_topic() {
+    let mut cache = mqtt::StateCache::default();
+    let message = |topic: &str, retain: bool, payload: &str| mqtt::Message {
+        topic: topic.to_owned(),
+        retain,
+        payload: payload.to_owned(),
+    };
+
+    cache.record(&message("2222222222/hold/64", true, "100"));
+    cache.record(&message("2222222222/hold/21", true, "1"));
+    cache.record(&message("2222222222/inputs/all", false, "{}"));
+    cache.record(&message("2222222222/hold/64", true, "50"));
+
+    assert_eq!(
+        cache.messages(),
+        vec![
+            message("2222222222/hold/21", true, "1"),
+            message("2222222222/hold/64", true, "50"),
+        ]
+    );
+}
+
+#[test]
+fn publish_policy_by_message_class() {
+    let policy = eg4_bridge::config::MqttPublishPolicy {
+        inputs: eg4_bridge::config::MqttPublish { qos: 0, retain: None },
+        holds: eg4_bridge::config::MqttPublish { qos: 2, retain: None },
+        discovery: Default::default(),
+        other: eg4_bridge::config::MqttPublish { qos: 1, retain: Some(false) },
+    };
+    let class = |topic: &str| {
+        let message = mqtt::Message {
+            topic: topic.to_owned(),
+            retain: true,
+            payload: String::new(),
+        };
+        message.publish_policy(&policy).qos()
+    };
+
+    assert_eq!(class("2222222222/inputs/all"), 0);
+    assert_eq!(class("2222222222/input/5"), 0);
+    assert_eq!(class("2222222222/hold/21/bits"), 2);
+    assert_eq!(class("2222222222/param/0"), 2);
+    assert_eq!(class("2222222222/ac_charge/1"), 2);
+    assert_eq!(class("2222222222/summary/daily"), 1);
+}
+
+#[test]
+fn take_envelope_id_unwraps_payload() {
+    let mut message = mqtt::Message {
+        topic: "cmd/all/set/ac_charge".to_owned(),
+        retain: false,
+        payload: r#"{"id":7,"payload":"1"}"#.to_owned(),
+    };
+    assert_eq!(message.take_envelope_id(), Some(json!(7)));
+    assert_eq!(message.payload, "1");
+
+    message.payload = r#"{"id":"abc","payload":{"start":"01:00","end":"05:00"}}"#.to_owned();
+    assert_eq!(message.take_envelope_id(), Some(json!("abc")));
+    assert_eq!(message.payload, r#"{"end":"05:00","start":"01:00"}"#);
+
+    // a bare command payload is left alone
+    message.payload = r#"{"start":"01:00","end":"05:00"}"#.to_owned();
+    assert_eq!(message.take_envelope_id(), None);
+    assert_eq!(message.payload, r#"{"start":"01:00","end":"05:00"}"#);
+}
+
+#[test]
+fn command_result_json() {
+    let ok = mqtt::CommandResult::new(Some(json!(7)), &Ok(vec![(12, 1558), (13, 0)]));
+    assert_eq!(
+        serde_json::to_value(&ok).unwrap(),
+        json!({ "status": "ok", "error": null, "values": { "12": 1558, "13": 0 }, "id": 7 })
+    );
+
+    let err = mqtt::CommandResult::new(None, &Err(anyhow::anyhow!("timeout")));
+    assert_eq!(
+        serde_json::to_value(&err).unwrap(),
+        json!({ "status": "error", "error": "timeout", "values": {} })
+    );
+}
@@ -0,0 +1,100 @@
+mod common;
+use common::*;
+
+use eg4_bridge::outbox::Outbox;
+use eg4_bridge::prelude::*;
+
+fn message(topic: &str, retain: bool, payload: &str) -> mqtt::Message {
+    mqtt::Message {
+        topic: topic.to_owned(),
+        retain,
+        payload: payload.to_owned(),
+    }
+}
+
+fn outbox_config(max_messages: usize, policy: &str, file: Option<String>) -> config::MqttOutbox {
+    config::MqttOutbox {
+        enabled: true,
+        max_messages,
+        policy: policy.to_owned(),
+        file,
+    }
+}
+
+fn drain(outbox: &mut Outbox) -> Vec<String> {
+    std::iter::from_fn(|| outbox.pop()).map(|m| m.payload).collect()
+}
+
+#[test]
+fn bounded_in_order_and_counts_drops() {
+    common_setup();
+
+    let mut outbox = Outbox::new(&outbox_config(3, "all", None));
+    for i in 1..=5 {
+        outbox.push(message("2222222222/inputs/1", false, &i.to_string()));
+    }
+    // retained messages keep only the newest per topic under either policy
+    outbox.push(message("2222222222/hold/21", true, "a"));
+    outbox.push(message("2222222222/hold/21", true, "b"));
+
+    assert_eq!(outbox.len(), 3);
+    assert_eq!(outbox.dropped(), 3);
+    assert_eq!(drain(&mut outbox), vec!["4", "5", "b"]);
+}
+
+#[test]
+fn latest_policy_keeps_newest_per_topic() {
+    let mut outbox = Outbox::new(&outbox_config(100, "latest", None));
+    outbox.push(message("2222222222/inputs/1", false, "1"));
+    outbox.push(message("2222222222/inputs/2", false, "2"));
+    outbox.push(message("2222222222/inputs/1", false, "3"));
+
+    assert_eq!(outbox.dropped(), 0);
+    assert_eq!(drain(&mut outbox), vec!["2", "3"]);
+}
+
+#[test]
+fn backlog_survives_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("outbox.jsonl").to_string_lossy().to_string();
+    let config = outbox_config(100, "all", Some(file.clone()));
+
+    let mut outbox = Outbox::new(&config);
+    for i in 1..=3 {
+        outbox.push(message("2222222222/inputs/1", false, &i.to_string()));
+    }
+    assert_eq!(outbox.front().unwrap().payload, "1");
+    drop(outbox);
+
+    let mut outbox = Outbox::new(&config);
+    assert_eq!(drain(&mut outbox), vec!["1", "2", "3"]);
+
+    // once replayed nothing is left for the next start
+    assert!(Outbox::new(&config).is_empty());
+}
+
+#[test]
+fn restart_during_replay_resumes_after_what_was_sent() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("outbox.jsonl").to_string_lossy().to_string();
+    let config = outbox_config(100, "all", Some(file.clone()));
+
+    let mut outbox = Outbox::new(&config);
+    for i in 1..=5 {
+        outbox.push(message("2222222222/inputs/1", false, &i.to_string()));
+    }
+    // a retained message replaced later leaves a stale line in the file
+    outbox.push(message("2222222222/hold/21", true, "a"));
+    outbox.push(message("2222222222/hold/21", true, "b"));
+    assert_eq!(outbox.pop().unwrap().payload, "1");
+    assert_eq!(outbox.pop().unwrap().payload, "2");
+    drop(outbox);
+
+    let mut outbox = Outbox::new(&config);
+    assert_eq!(outbox.pop().unwrap().payload, "3");
+    drop(outbox);
+
+    let mut outbox = Outbox::new(&config);
+    assert_eq!(drain(&mut outbox), vec!["4", "5", "b"]);
+    assert!(Outbox::new(&config).is_empty());
+}
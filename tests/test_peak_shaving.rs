@@ -0,0 +1,198 @@
+mod common;
+use common::*;
+
+use eg4_bridge::controllers::peak_shaving::{Action, Change, Shaver};
+use eg4_bridge::coordinator::commands::write_inverter::DISCHARGE_RATE_REGISTER;
+use eg4_bridge::eg4::packet::ReadInputAll;
+use eg4_bridge::prelude::*;
+
+use std::time::{Duration, Instant};
+
+fn config(max_writes_per_hour: u32) -> config::PeakShaving {
+    let mut config: config::PeakShaving = Factory::yaml(
+        r#"
+enabled: true
+approach: 500
+hysteresis: 500
+windows:
+  - days: [mon, tue, wed, thu, fri]
+    start_time: "16:00"
+    end_time: "21:00"
+    threshold: 5000
+recharge:
+  - start_time: "23:00"
+    end_time: "06:00"
+"#,
+    );
+    config.max_writes_per_hour = max_writes_per_hour;
+    config
+}
+
+/// Monday 2026-10-19 at a local hour/minute.
+fn inputs_at(hour: u32, min: u32, p_to_user: u16, p_discharge: u16) -> ReadInputAll {
+    let mut ria = Factory::read_input_all_at(Factory::local_time(19, hour, min, 0));
+    ria.p_to_user = p_to_user;
+    ria.p_discharge = p_discharge;
+    ria
+}
+
+const AC_CHARGE: u16 = 1 << 7;
+const FORCED_DISCHARGE: u16 = 1 << 10;
+
+fn datalog() -> Serial {
+    Factory::read_input_all().datalog
+}
+
+/// The changes among `actions`, each answered by the inverter as written.
+fn made(shaver: &mut Shaver, actions: Vec<Action>, register_21: u16) -> Vec<Change> {
+    let mut r = Vec::new();
+    for action in actions {
+        let Action::Change(datalog, change) = action else {
+            panic!("expected a change, got {:?}", action);
+        };
+        match change {
+            Change::Shave(true) => shaver.observe(datalog, DISCHARGE_RATE_REGISTER, 100),
+            Change::Shave(false) => {
+                let original = shaver.status(datalog).unwrap().original_discharge_rate.unwrap();
+                shaver.observe(datalog, DISCHARGE_RATE_REGISTER, original)
+            }
+            Change::AcCharge(enable) => {
+                shaver.observe(datalog, 21, if enable { register_21 | AC_CHARGE } else { register_21 })
+            }
+        }
+        r.push(change);
+    }
+    r
+}
+
+#[test]
+fn shaves_near_threshold_with_hysteresis() {
+    common_setup();
+
+    let mut shaver = Shaver::new(config(10));
+    let now = Instant::now();
+    let update = |shaver: &mut Shaver, hour, min, p_to_user, p_discharge| {
+        let actions = shaver.update(&inputs_at(hour, min, p_to_user, p_discharge), now);
+        made(shaver, actions, 0)
+    };
+
+    // outside the window import is not limited
+    assert!(update(&mut shaver, 15, 0, 6000, 0).is_empty());
+    // inside it, approaching the threshold reads the discharge rate first...
+    assert!(update(&mut shaver, 16, 0, 4000, 0).is_empty());
+    assert_eq!(
+        shaver.update(&inputs_at(16, 5, 4600, 0), now),
+        vec![Action::Read(datalog(), DISCHARGE_RATE_REGISTER)]
+    );
+    shaver.observe(datalog(), DISCHARGE_RATE_REGISTER, 40);
+    // ...then starts shaving
+    assert_eq!(update(&mut shaver, 16, 6, 4600, 0), vec![Change::Shave(true)]);
+    assert_eq!(shaver.status(datalog()).unwrap().original_discharge_rate, Some(40));
+    // import falls because the battery is covering it; the load is still near the threshold
+    assert!(update(&mut shaver, 16, 10, 500, 3500).is_empty());
+    // once the load drops clear of the band shaving stops
+    assert_eq!(update(&mut shaver, 16, 15, 500, 3400), vec![Change::Shave(false)]);
+    // and the window closing stops it too
+    shaver.observe(datalog(), DISCHARGE_RATE_REGISTER, 40);
+    assert_eq!(update(&mut shaver, 17, 0, 4800, 0), vec![Change::Shave(true)]);
+    assert_eq!(update(&mut shaver, 21, 0, 4800, 0), vec![Change::Shave(false)]);
+}
+
+#[test]
+fn recharges_off_peak_within_write_budget() {
+    common_setup();
+
+    let mut shaver = Shaver::new(config(4));
+    let now = Instant::now();
+    shaver.observe(datalog(), 21, 0);
+
+    let actions = shaver.update(&inputs_at(23, 0, 0, 0), now);
+    assert_eq!(made(&mut shaver, actions, 0), vec![Change::AcCharge(true)]);
+    let actions = shaver.update(&inputs_at(6, 0, 0, 0), now);
+    assert_eq!(made(&mut shaver, actions, 0), vec![Change::AcCharge(false)]);
+
+    // a change the inverter never shows is sent again
+    assert_eq!(
+        shaver.update(&inputs_at(23, 0, 0, 0), now),
+        vec![Action::Change(datalog(), Change::AcCharge(true))]
+    );
+    assert_eq!(
+        shaver.update(&inputs_at(23, 5, 0, 0), now),
+        vec![Action::Change(datalog(), Change::AcCharge(true))]
+    );
+
+    // budget spent: the change waits until a write ages out
+    assert!(shaver.update(&inputs_at(23, 30, 0, 0), now + Duration::from_secs(60)).is_empty());
+    assert_eq!(shaver.status(datalog()).unwrap().writes_last_hour, 4);
+    assert_eq!(
+        shaver.update(&inputs_at(23, 30, 0, 0), now + Duration::from_secs(3600)),
+        vec![Action::Change(datalog(), Change::AcCharge(true))]
+    );
+
+    // restoring undoes only what is in force
+    shaver.observe(datalog(), 21, AC_CHARGE);
+    assert_eq!(shaver.restore_all(), vec![(datalog(), Change::AcCharge(false))]);
+    assert!(shaver.restore_all().is_empty());
+}
+
+#[test]
+fn restores_the_settings_found_before_the_first_change() {
+    common_setup();
+
+    let mut config = config(10);
+    config.method = "forced_discharge".to_string();
+    let mut shaver = Shaver::new(config);
+    let now = Instant::now();
+    let inverter = config::Inverter {
+        datalog: Some(datalog()),
+        ..Factory::inverter()
+    };
+
+    // AC charging was already on, forced discharge off
+    assert_eq!(
+        shaver.update(&inputs_at(23, 0, 0, 0), now),
+        vec![Action::Read(datalog(), 21)]
+    );
+    shaver.observe(datalog(), 21, AC_CHARGE | 1);
+    assert_eq!(
+        shaver.update(&inputs_at(23, 1, 0, 0), now),
+        vec![Action::Change(datalog(), Change::AcCharge(true))]
+    );
+    shaver.observe(datalog(), 21, AC_CHARGE | 1);
+
+    // the window ends: AC charging stays as it was found
+    assert_eq!(
+        shaver.update(&inputs_at(6, 0, 0, 0), now),
+        vec![Action::Change(datalog(), Change::AcCharge(false))]
+    );
+    assert_eq!(
+        shaver.command(inverter.clone(), Change::AcCharge(false)),
+        Some(Command::AcCharge(inverter.clone(), true))
+    );
+    shaver.observe(datalog(), 21, AC_CHARGE | 1);
+
+    // shaving enables forced discharge, and shutdown puts it back off
+    assert_eq!(
+        shaver.update(&inputs_at(16, 0, 4800, 0), now),
+        vec![Action::Change(datalog(), Change::Shave(true))]
+    );
+    assert_eq!(shaver.status(datalog()).unwrap().original_forced_discharge, Some(false));
+    // a read while shaving is not taken as the original
+    shaver.observe(datalog(), 21, FORCED_DISCHARGE | AC_CHARGE);
+    assert_eq!(shaver.status(datalog()).unwrap().original_forced_discharge, Some(false));
+    assert_eq!(shaver.restore_all(), vec![(datalog(), Change::Shave(false))]);
+    assert_eq!(
+        shaver.command(inverter.clone(), Change::Shave(false)),
+        Some(Command::ForcedDischarge(inverter, false))
+    );
+
+    // until the restore is seen the original is kept, and sent again
+    shaver.observe(datalog(), 21, FORCED_DISCHARGE | AC_CHARGE);
+    assert_eq!(shaver.status(datalog()).unwrap().original_forced_discharge, Some(false));
+    assert_eq!(
+        shaver.update(&inputs_at(12, 0, 0, 0), now),
+        vec![Action::Change(datalog(), Change::Shave(false))]
+    );
+    shaver.observe(datalog(), 21, AC_CHARGE);
+    assert!(shaver.update(&inputs_at(12, 1, 0, 0), now).is_empty());
+}
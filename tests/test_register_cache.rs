@@ -0,0 +1,39 @@
+mod common;
+use common::*;
+
+use eg4_bridge::prelude::*;
+
+#[tokio::test]
+async fn registers_are_cached_per_inverter() {
+    common_setup();
+
+    let channels = Channels::new();
+    let cache = std::sync::Arc::new(RegisterCache::new(channels.clone()));
+    let task = tokio::spawn({
+        let cache = cache.clone();
+        async move { cache.start().await }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    let first = Serial::from_str("2222222222").unwrap();
+    let second = Serial::from_str("3333333333").unwrap();
+
+    channels
+        .to_register_cache
+        .send(register_cache::ChannelData::RegisterData(first, 64, 100))
+        .unwrap();
+    channels
+        .to_register_cache
+        .send(register_cache::ChannelData::RegisterData(second, 64, 50))
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    assert_eq!(RegisterCache::cached(&channels, first, 64).await, Some(100));
+    assert_eq!(RegisterCache::cached(&channels, second, 64).await, Some(50));
+    assert_eq!(RegisterCache::cached(&channels, second, 65).await, None);
+    assert_eq!(RegisterCache::get(&channels, second, 65).await, 0);
+
+    channels.read_register_cache.send(register_cache::ChannelData::Shutdown).unwrap();
+    channels.to_register_cache.send(register_cache::ChannelData::Shutdown).unwrap();
+    task.await.unwrap().unwrap();
+}
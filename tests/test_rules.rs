@@ -0,0 +1,118 @@
+mod common;
+use common::*;
+
+use eg4_bridge::eg4::packet::ReadInputAll;
+use eg4_bridge::rules::{Engine, Outcome};
+
+/// Monday 2026-10-19 at a local hour/minute/second.
+fn inputs_at(hour: u32, min: u32, sec: u32, soc: i8) -> ReadInputAll {
+    let mut ria = Factory::read_input_all_at(Factory::local_time(19, hour, min, sec));
+    ria.soc = soc;
+    ria
+}
+
+fn outcomes(engine: &mut Engine, samples: &[(u32, u32, u32, i8)]) -> Vec<Outcome> {
+    samples
+        .iter()
+        .map(|&(h, m, s, soc)| engine.evaluate(&inputs_at(h, m, s, soc))[0].outcome)
+        .collect()
+}
+
+#[test]
+fn hysteresis_holds_rule_until_value_clears_band() {
+    common_setup();
+
+    let mut engine = Engine::new(Factory::yaml(
+        r#"
+enabled: true
+rules:
+  - name: low_battery
+    conditions:
+      - field: soc
+        below: 20
+    hysteresis: 5
+    actions:
+      - command: ac_charge
+        enable: true
+    release_actions:
+      - command: ac_charge
+        enable: false
+"#,
+    ));
+
+    let outcomes = outcomes(
+        &mut engine,
+        &[(12, 0, 0, 25), (12, 1, 0, 19), (12, 2, 0, 22), (12, 3, 0, 24), (12, 4, 0, 25)],
+    );
+    assert_eq!(
+        outcomes,
+        vec![Outcome::Idle, Outcome::Fired, Outcome::Held, Outcome::Held, Outcome::Released]
+    );
+
+    let fired = &engine.evaluate(&inputs_at(12, 5, 0, 10))[0];
+    assert_eq!(fired.outcome, Outcome::Fired);
+    assert_eq!(fired.values["soc"], Some(10.0));
+    assert_eq!(fired.actions.len(), 1);
+}
+
+#[test]
+fn cooldown_delays_refiring() {
+    common_setup();
+
+    let mut engine = Engine::new(Factory::yaml(
+        r#"
+enabled: true
+rules:
+  - name: low_battery
+    conditions:
+      - field: soc
+        below: 20
+    cooldown: 300
+    actions:
+      - command: ac_charge
+        enable: true
+"#,
+    ));
+
+    let outcomes = outcomes(
+        &mut engine,
+        &[(12, 0, 0, 10), (12, 1, 0, 30), (12, 2, 0, 10), (12, 5, 0, 10)],
+    );
+    assert_eq!(
+        outcomes,
+        vec![Outcome::Fired, Outcome::Released, Outcome::CoolingDown, Outcome::Fired]
+    );
+}
+
+#[test]
+fn time_window_wraps_midnight_and_honours_dry_run() {
+    common_setup();
+
+    let mut engine = Engine::new(Factory::yaml(
+        r#"
+enabled: true
+dry_run: true
+rules:
+  - name: overnight
+    days: [mon]
+    start_time: "22:00"
+    end_time: "06:00"
+    actions:
+      - command: forced_discharge
+        enable: false
+"#,
+    ));
+
+    let evaluation = &engine.evaluate(&inputs_at(21, 59, 0, 50))[0];
+    assert!(!evaluation.in_window);
+    assert_eq!(evaluation.outcome, Outcome::Idle);
+
+    let evaluation = &engine.evaluate(&inputs_at(23, 0, 0, 50))[0];
+    assert!(evaluation.in_window);
+    assert_eq!(evaluation.outcome, Outcome::Fired);
+    assert!(evaluation.dry_run);
+
+    let evaluation = &engine.evaluate(&inputs_at(5, 59, 0, 50))[0];
+    assert!(evaluation.in_window);
+    assert_eq!(evaluation.outcome, Outcome::Held);
+}
@@ -0,0 +1,139 @@
+mod common;
+use common::*;
+
+use eg4_bridge::eg4::packet::ReadInputAll;
+use eg4_bridge::prelude::*;
+use eg4_bridge::tariff::{Accounts, Period};
+
+fn tariff() -> config::Tariff {
+    Factory::yaml(
+        r#"
+enabled: true
+fixed_daily: 1.0
+fixed_monthly: 10.0
+rates:
+  - days: [mon, tue, wed, thu, fri]
+    start_hour: 16
+    end_hour: 21
+    import: 0.5
+    export: 0.1
+  - import: 0.2
+    export: 0.05
+"#,
+    )
+}
+
+/// Monday 2026-10-19 at a local hour/minute, or the following Tuesday with `day = 20`.
+fn inputs_at(day: u32, hour: u32, min: u32, import: f64, export: f64, inv: f64) -> ReadInputAll {
+    let mut ria = Factory::read_input_all_at(Factory::local_time(day, hour, min, 0));
+    ria.e_to_user_day = import;
+    ria.e_to_grid_day = export;
+    ria.e_inv_day = inv;
+    ria.e_rec_day = 0.0;
+    ria.p_to_user = 1000;
+    ria.p_to_grid = 0;
+    ria
+}
+
+#[test]
+fn prices_follow_weekday_and_hour() {
+    let accounts = Accounts::new(tariff());
+    assert_eq!(accounts.prices_at(chrono::Weekday::Mon, 16), (0.5, 0.1));
+    assert_eq!(accounts.prices_at(chrono::Weekday::Mon, 21), (0.2, 0.05));
+    assert_eq!(accounts.prices_at(chrono::Weekday::Sat, 17), (0.2, 0.05));
+}
+
+#[test]
+fn accounts_price_energy_deltas_per_day_and_month() {
+    common_setup();
+
+    let mut accounts = Accounts::new(tariff());
+
+    // first sample only sets the baseline counters
+    let (day, month, _) = accounts.update(&inputs_at(19, 15, 0, 1.0, 0.0, 5.0));
+    assert_eq!(day.period, Period::Day);
+    assert_eq!(day.period_start, "2026-10-19");
+    assert_eq!(day.import_kwh, 0.0);
+    assert_eq!(day.net_cost, 1.0);
+    assert_eq!(month.period_start, "2026-10");
+    assert_eq!(month.fixed_charges, 11.0);
+
+    // +2 kWh import, +1 kWh export and +5 kWh consumption at the peak rate
+    let (day, month, now) = accounts.update(&inputs_at(19, 16, 30, 3.0, 1.0, 9.0));
+    assert_eq!(day.import_cost, 1.0);
+    assert_eq!(day.export_revenue, 0.1);
+    assert_eq!(day.consumption_cost, 2.5);
+    assert_eq!(day.net_cost, 1.9);
+    assert_eq!(day.baseline_cost, 3.5);
+    assert_eq!(day.savings, 1.6);
+    assert_eq!(month.net_cost, 11.9);
+    assert_eq!(month.savings, 1.6);
+    assert_eq!(now.import_price, 0.5);
+    assert_eq!(now.cost_per_hour, 0.5);
+
+    // the inverter's counters reset overnight; the new day starts with its fixed charge
+    let (day, month, _) = accounts.update(&inputs_at(20, 0, 30, 0.2, 0.0, 0.3));
+    assert_eq!(day.period_start, "2026-10-20");
+    assert_eq!(day.import_kwh, 0.2);
+    assert_eq!(day.import_cost, 0.04);
+    assert_eq!(day.net_cost, 1.04);
+    assert_eq!(month.fixed_charges, 12.0);
+    assert_eq!(month.import_kwh, 2.2);
+    assert_eq!(month.consumption_kwh, 5.5);
+}
+
+#[test]
+fn ignores_a_day_counter_that_reads_zero_for_one_set() {
+    common_setup();
+
+    let mut accounts = Accounts::new(tariff());
+    accounts.update(&inputs_at(19, 10, 0, 2.0, 0.0, 5.0));
+
+    // a glitched set reading zero mid-morning is not the inverter's midnight
+    let (day, _, _) = accounts.update(&inputs_at(19, 10, 5, 0.0, 0.0, 0.0));
+    assert_eq!(day.import_kwh, 0.0);
+
+    let (day, month, _) = accounts.update(&inputs_at(19, 10, 10, 3.0, 0.0, 6.0));
+    assert_eq!(day.import_kwh, 1.0);
+    assert_eq!(day.consumption_kwh, 2.0);
+    assert_eq!(month.import_kwh, 1.0);
+
+    // a drop still there in the next set is a reset
+    accounts.update(&inputs_at(19, 10, 15, 0.5, 0.0, 0.5));
+    let (day, _, _) = accounts.update(&inputs_at(19, 10, 20, 1.0, 0.0, 1.0));
+    assert_eq!(day.import_kwh, 2.0);
+}
+
+#[test]
+fn carries_on_from_stored_totals_after_a_restart() {
+    common_setup();
+
+    let mut before = Accounts::new(tariff());
+    before.update(&inputs_at(19, 15, 0, 1.0, 0.0, 5.0));
+    let (day, month, _) = before.update(&inputs_at(19, 16, 30, 3.0, 1.0, 9.0));
+    assert_eq!(day.net_cost, 1.9);
+    assert_eq!(month.net_cost, 11.9);
+
+    // restarted later the same day: the first set only sets the baseline counters
+    let mut after = Accounts::new(tariff());
+    after.seed(day);
+    after.seed(month.clone());
+    let (day, month, _) = after.update(&inputs_at(19, 17, 0, 3.0, 1.0, 9.0));
+    assert_eq!(day.net_cost, 1.9);
+    assert_eq!(month.net_cost, 11.9);
+    assert_eq!(month.fixed_charges, 11.0);
+
+    let (day, month, _) = after.update(&inputs_at(19, 17, 30, 4.0, 1.0, 10.0));
+    assert_eq!(day.import_kwh, 3.0);
+    assert_eq!(day.import_cost, 1.5);
+    assert_eq!(month.import_cost, 1.5);
+
+    // restarted the next day: the month carries on and gains the new day's fixed charge
+    let mut next_day = Accounts::new(tariff());
+    next_day.seed(month);
+    let (day, month, _) = next_day.update(&inputs_at(20, 9, 0, 0.0, 0.0, 0.0));
+    assert_eq!(day.period_start, "2026-10-20");
+    assert_eq!(day.net_cost, 1.0);
+    assert_eq!(month.fixed_charges, 12.0);
+    assert_eq!(month.import_kwh, 3.0);
+}
@@ -0,0 +1,152 @@
+mod common;
+use common::*;
+
+use eg4_bridge::prelude::*;
+use eg4_bridge::register::RegisterParser;
+use eg4_bridge::audit;
+use eg4_bridge::command::{Origin, Source};
+use eg4_bridge::write_policy::{Policy, WritePolicy};
+
+use std::time::{Duration, Instant};
+
+fn datalog() -> Serial {
+    Serial::from_str("2222222222").unwrap()
+}
+
+#[test]
+fn allow_deny_and_bounds() {
+    common_setup();
+
+    let registers = RegisterParser::new("doc/eg4_registers.json").unwrap();
+    let policy = Policy::new(
+        Factory::yaml(
+            r#"
+enabled: true
+allow: ["0-20", 64, 0x0100]
+deny: [5]
+limits:
+  - register: 64
+    min: 10
+    max: 90
+    step: 5
+"#,
+        ),
+        Some(&registers),
+    );
+    let now = Instant::now();
+    let check = |policy: &Policy, register, value| policy.check(datalog(), register, value, now);
+
+    assert!(check(&policy, 3, Some(5000)).is_ok());
+    assert!(check(&policy, 5, Some(1)).unwrap_err().to_string().contains("may not be written"));
+    assert!(check(&policy, 21, None).is_err());
+
+    // SOC setpoint bounds come from the register map
+    assert!(check(&policy, 14, Some(100)).is_ok());
+    assert!(check(&policy, 14, Some(101)).unwrap_err().to_string().contains("outside"));
+
+    // and from the config
+    assert!(check(&policy, 64, Some(15)).is_ok());
+    assert!(check(&policy, 64, Some(17)).unwrap_err().to_string().contains("multiple of 5"));
+    assert!(check(&policy, 64, Some(95)).is_err());
+    assert!(check(&policy, 0x0100, Some(95)).is_ok());
+}
+
+#[test]
+fn dangerous_registers_need_confirmation() {
+    common_setup();
+
+    let mut policy = Policy::new(Factory::yaml("enabled: true\nconfirm_token: s3cret\nconfirm_timeout: 30"), None);
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    assert!(policy.check(datalog(), 25, Some(2640), at(0)).unwrap_err().to_string().contains("confirmation"));
+    assert!(policy.confirm(datalog(), 25, "guess", at(1)).is_err());
+    assert!(policy.confirm(datalog(), 3, "s3cret", at(1)).is_err());
+
+    // one confirmation, one write
+    policy.confirm(datalog(), 25, "s3cret", at(2)).unwrap();
+    assert!(policy.check(datalog(), 25, Some(2640), at(3)).is_ok());
+    // a write that never went out keeps its confirmation
+    assert!(policy.check(datalog(), 25, Some(2640), at(3)).is_ok());
+    policy.record(datalog(), 25, at(3));
+    assert!(policy.check(datalog(), 25, Some(2640), at(4)).is_err());
+
+    // confirmations expire
+    policy.confirm(datalog(), 53, "s3cret", at(10)).unwrap();
+    assert!(policy.check(datalog(), 53, Some(1), at(40)).unwrap_err().to_string().contains("expired"));
+
+    // without a configured token nothing dangerous can be written
+    let mut policy = Policy::new(Factory::yaml("enabled: true"), None);
+    assert!(policy.confirm(datalog(), 25, "", at(0)).is_err());
+    assert!(policy.check(datalog(), 25, Some(1), at(0)).is_err());
+}
+
+#[test]
+fn rate_limits_writes_per_register() {
+    common_setup();
+
+    let mut policy = Policy::new(Factory::yaml("enabled: true\nmin_interval: 60"), None);
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+
+    assert!(policy.check(datalog(), 3, Some(1), at(0)).is_ok());
+    // only acknowledged writes start the interval
+    assert!(policy.check(datalog(), 3, Some(1), at(10)).is_ok());
+    policy.record(datalog(), 3, at(10));
+    assert!(policy.check(datalog(), 3, Some(2), at(40)).unwrap_err().to_string().contains("minimum interval"));
+    assert!(policy.check(datalog(), 4, Some(2), at(40)).is_ok());
+    assert!(policy.check(Serial::from_str("1111111111").unwrap(), 3, Some(2), at(40)).is_ok());
+    assert!(policy.check(datalog(), 3, Some(2), at(70)).is_ok());
+
+    // a disabled policy allows everything
+    let mut policy = Policy::new(Factory::yaml("min_interval: 60"), None);
+    assert!(policy.check(datalog(), 25, Some(1), at(0)).is_ok());
+    policy.record(datalog(), 25, at(0));
+    assert!(policy.check(datalog(), 25, Some(1), at(0)).is_ok());
+}
+
+#[tokio::test]
+async fn restores_skip_the_interval_and_confirmation() {
+    common_setup();
+
+    let mut c = Factory::example_config();
+    c.write_policy = Factory::yaml("enabled: true\nmin_interval: 60\nconfirm_token: s3cret\ndeny: [5]");
+    let policy = WritePolicy::new(ConfigWrapper::from_config(c), Channels::new());
+
+    // a controller limits the register...
+    let limit = Source::new(Origin::Controller, "export_limit");
+    audit::scope(limit.clone(), async { policy.check(datalog(), 3, Some(10)) }).await.unwrap();
+    policy.record(datalog(), 3).unwrap();
+    assert!(audit::scope(limit, async { policy.check(datalog(), 3, Some(20)) }).await.is_err());
+
+    // ...and puts it back straight away
+    let restore = Source::restore(Origin::Controller, "export_limit");
+    audit::scope(restore.clone(), async { policy.check(datalog(), 3, Some(20)) }).await.unwrap();
+    audit::scope(restore.clone(), async { policy.check(datalog(), 25, Some(1)) }).await.unwrap();
+    // but only to registers it may write
+    assert!(audit::scope(restore, async { policy.check(datalog(), 5, Some(1)) }).await.is_err());
+}
+
+#[test]
+fn bounds_rates_soc_limits_and_time_slots() {
+    common_setup();
+
+    let registers = RegisterParser::new("doc/eg4_registers.json").unwrap();
+    let policy = Policy::new(Factory::yaml("enabled: true"), Some(&registers));
+    let now = Instant::now();
+    let check = |register, value| policy.check(datalog(), register, Some(value), now);
+
+    // charge and discharge rates and SOC limits are percentages
+    for register in 0x0100..=0x0104 {
+        assert!(check(register, 100).is_ok());
+        assert!(check(register, 101).unwrap_err().to_string().contains("outside"));
+    }
+
+    // time slots are an hour and a minute
+    assert!(check(68, u16::from_le_bytes([22, 30])).is_ok());
+    assert!(check(89, u16::from_le_bytes([23, 59])).is_ok());
+    assert!(check(68, u16::from_le_bytes([24, 0])).unwrap_err().to_string().contains("time of day"));
+    assert!(check(153, u16::from_le_bytes([6, 60])).is_err());
+    // register 74 is no time slot
+    assert!(check(74, u16::from_le_bytes([24, 0])).is_ok());
+}